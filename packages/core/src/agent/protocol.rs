@@ -0,0 +1,171 @@
+//! ssh-agent wire format (draft-miller-ssh-agent).
+//!
+//! Every message is a big-endian `u32` length followed by that many bytes: a
+//! type byte, then SSH strings (`u32` length + bytes) and integers. Frames
+//! handled here keep their length prefix.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Largest message accepted in either direction, as in OpenSSH.
+pub(crate) const MAX_MESSAGE: usize = 256 * 1024;
+
+pub(crate) const SSH_AGENT_FAILURE: u8 = 5;
+pub(crate) const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+pub(crate) const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+pub(crate) const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+pub(crate) const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+
+/// Sign-request flags selecting the RSA signature hash.
+pub(crate) const SSH_AGENT_RSA_SHA2_256: u32 = 2;
+pub(crate) const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+/// Prefix `body` with its length.
+pub(crate) fn frame(body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(4 + body.len());
+    put_u32(&mut out, body.len() as u32);
+    out.extend_from_slice(body);
+    out
+}
+
+/// The `SSH_AGENT_FAILURE` reply.
+pub(crate) fn failure() -> Vec<u8> {
+    frame(&[SSH_AGENT_FAILURE])
+}
+
+/// Split one message off the front of `buf`, if it has arrived in full.
+pub(crate) fn take_frame(buf: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+    let Some(prefix) = buf.get(..4) else {
+        return Ok(None);
+    };
+    let len = frame_len(prefix)?;
+    if buf.len() < 4 + len {
+        return Ok(None);
+    }
+    Ok(Some(buf.drain(..4 + len).collect()))
+}
+
+/// Read one message from `stream`. `None` on a clean end of stream.
+pub(crate) async fn read_frame<S>(stream: &mut S) -> io::Result<Option<Vec<u8>>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut frame = vec![0u8; 4];
+    match stream.read_exact(&mut frame).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = frame_len(&frame)?;
+    frame.resize(4 + len, 0);
+    stream.read_exact(&mut frame[4..]).await?;
+    Ok(Some(frame))
+}
+
+fn frame_len(prefix: &[u8]) -> io::Result<usize> {
+    let len = Reader::new(prefix).u32()? as usize;
+    if len > MAX_MESSAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("agent message of {len} bytes exceeds {MAX_MESSAGE}"),
+        ));
+    }
+    Ok(len)
+}
+
+pub(crate) fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub(crate) fn put_string(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_u32(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+/// Cursor over a message body.
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf }
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() < n {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated agent message",
+            ));
+        }
+        let (head, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    pub fn string(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_split_only_when_complete() {
+        let mut buf = frame(&[11]);
+        buf.extend_from_slice(&frame(&[13, 1, 2])[..5]);
+
+        assert_eq!(take_frame(&mut buf).unwrap(), Some(frame(&[11])));
+        assert_eq!(take_frame(&mut buf).unwrap(), None);
+        buf.extend_from_slice(&[1, 2]);
+        assert_eq!(take_frame(&mut buf).unwrap(), Some(frame(&[13, 1, 2])));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        let mut buf = u32::MAX.to_be_bytes().to_vec();
+        assert!(take_frame(&mut buf).is_err());
+    }
+
+    #[test]
+    fn reader_walks_strings_and_integers() {
+        let mut body = vec![SSH_AGENTC_SIGN_REQUEST];
+        put_string(&mut body, b"blob");
+        put_string(&mut body, b"data");
+        put_u32(&mut body, SSH_AGENT_RSA_SHA2_256);
+
+        let mut r = Reader::new(&body);
+        assert_eq!(r.u8().unwrap(), SSH_AGENTC_SIGN_REQUEST);
+        assert_eq!(r.string().unwrap(), b"blob");
+        assert_eq!(r.string().unwrap(), b"data");
+        assert_eq!(r.u32().unwrap(), SSH_AGENT_RSA_SHA2_256);
+        assert!(r.u8().is_err());
+    }
+
+    #[tokio::test]
+    async fn read_frame_stops_cleanly_at_eof() {
+        let mut input: &[u8] = &[0, 0, 0, 1, 11];
+        assert_eq!(read_frame(&mut input).await.unwrap(), Some(frame(&[11])));
+        assert_eq!(read_frame(&mut input).await.unwrap(), None);
+    }
+}
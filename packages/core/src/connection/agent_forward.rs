@@ -0,0 +1,302 @@
+//! SSH agent forwarding (`ssh -A`).
+//!
+//! With [`SshSettings::forward_agent`](crate::profile::types::SshSettings)
+//! set, [`SshAdapter`](super::ssh::SshAdapter) sends
+//! `auth-agent-req@openssh.com` on its shell and exec channels. The server
+//! then opens an `auth-agent@openssh.com` channel whenever a remote program
+//! (`git`, `ssh`) talks to `$SSH_AUTH_SOCK`, and [`AgentForwarder`] relays
+//! each request to a local agent socket and writes the reply back.
+//!
+//! The local agent is the one at `SSH_AUTH_SOCK` unless
+//! [`SshConnectOptions::agent_socket`](super::ssh::SshConnectOptions) names
+//! another, e.g. a vault-backed agent.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::agent::protocol::{failure, read_frame, take_frame};
+
+#[cfg(unix)]
+type AgentStream = tokio::net::UnixStream;
+#[cfg(not(unix))]
+type AgentStream = tokio::io::DuplexStream;
+
+/// How long the local agent gets to answer one request. A key that needs
+/// confirmation on the agent side waits here, so this is generous.
+pub(crate) const AGENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where one channel's relayed replies go: the SSH channel, or a test's
+/// queue.
+#[async_trait]
+pub(crate) trait AgentReplies: Send + Sync {
+    /// Write `reply` back. `false` once the session is gone.
+    async fn reply(&self, reply: Vec<u8>) -> bool;
+}
+
+/// Relays forwarded-agent channels to a local agent socket.
+///
+/// russh delivers agent-channel data through the session handler rather than
+/// a [`russh::Channel`], so the handler owns one of these and hands it each
+/// chunk. Every channel is relayed by its own task, which writes replies
+/// through [`AgentReplies`]; the handler never waits on the local agent.
+pub(crate) struct AgentForwarder {
+    /// `None` means `SSH_AUTH_SOCK`, looked up per connection.
+    socket: Option<PathBuf>,
+    /// Data for each channel's relay task.
+    channels: HashMap<u32, mpsc::UnboundedSender<Vec<u8>>>,
+}
+
+impl AgentForwarder {
+    pub fn new(socket: Option<PathBuf>) -> Self {
+        AgentForwarder {
+            socket,
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Start relaying a channel the server opened for agent forwarding,
+    /// writing its replies to `replies`.
+    pub fn open(&mut self, channel: u32, replies: Box<dyn AgentReplies>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(relay_channel(channel, self.socket.clone(), rx, replies));
+        self.channels.insert(channel, tx);
+    }
+
+    /// `true` for channels registered with [`AgentForwarder::open`].
+    pub fn owns(&self, channel: u32) -> bool {
+        self.channels.contains_key(&channel)
+    }
+
+    /// Stop relaying `channel`; its task finishes the request in hand.
+    pub fn close(&mut self, channel: u32) {
+        self.channels.remove(&channel);
+    }
+
+    /// Hand `data` from `channel` to its relay task without waiting.
+    ///
+    /// Returns `false` when the relay has given up on the channel (an
+    /// oversized request), which the caller should then close.
+    pub fn data(&mut self, channel: u32, data: &[u8]) -> bool {
+        let Some(relay) = self.channels.get(&channel) else {
+            return false;
+        };
+        if relay.send(data.to_vec()).is_err() {
+            self.channels.remove(&channel);
+            return false;
+        }
+        true
+    }
+}
+
+/// Relay one channel: frame the requests in `data` and answer each in order.
+///
+/// Agent errors never fail the session: an unreachable or slow agent answers
+/// `SSH_AGENT_FAILURE`, and an oversized request ends the relay.
+async fn relay_channel(
+    channel: u32,
+    socket: Option<PathBuf>,
+    mut data: mpsc::UnboundedReceiver<Vec<u8>>,
+    replies: Box<dyn AgentReplies>,
+) {
+    let mut pending = Vec::new();
+    let mut agent = None;
+    while let Some(chunk) = data.recv().await {
+        pending.extend_from_slice(&chunk);
+        loop {
+            let request = match take_frame(&mut pending) {
+                Ok(Some(request)) => request,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("forwarded agent channel {channel}: {e}");
+                    return;
+                }
+            };
+            let reply = relay(&mut agent, socket.as_ref(), &request).await;
+            if !replies.reply(reply).await {
+                return;
+            }
+        }
+    }
+}
+
+/// Send one request to the local agent, connecting first if needed. Any
+/// failure drops the connection and yields `SSH_AGENT_FAILURE`.
+async fn relay(
+    agent: &mut Option<AgentStream>,
+    socket: Option<&PathBuf>,
+    request: &[u8],
+) -> Vec<u8> {
+    if agent.is_none() {
+        match connect_agent(socket).await {
+            Ok(stream) => *agent = Some(stream),
+            Err(e) => {
+                warn!("cannot reach local SSH agent for forwarding: {e}");
+                return failure();
+            }
+        }
+    }
+    let Some(stream) = agent.as_mut() else {
+        return failure();
+    };
+    match round_trip(stream, request, AGENT_TIMEOUT).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            warn!("local SSH agent request failed: {e}");
+            *agent = None;
+            failure()
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn connect_agent(socket: Option<&PathBuf>) -> io::Result<AgentStream> {
+    let path = match socket {
+        Some(path) => path.clone(),
+        None => std::env::var_os("SSH_AUTH_SOCK")
+            .map(PathBuf::from)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "SSH_AUTH_SOCK is not set"))?,
+    };
+    tokio::net::UnixStream::connect(path).await
+}
+
+#[cfg(not(unix))]
+async fn connect_agent(_socket: Option<&PathBuf>) -> io::Result<AgentStream> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "agent forwarding needs a Unix socket agent",
+    ))
+}
+
+/// Write a framed request and read the framed reply, giving up after
+/// `limit`. A timed-out stream is left mid-reply and must not be reused.
+async fn round_trip<S>(agent: &mut S, request: &[u8], limit: Duration) -> io::Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let exchange = async {
+        agent.write_all(request).await?;
+        agent.flush().await?;
+        read_frame(agent).await?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "agent closed the connection")
+        })
+    };
+    tokio::time::timeout(limit, exchange)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "agent did not answer in time"))?
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::agent::protocol::frame;
+
+    #[async_trait]
+    impl AgentReplies for mpsc::UnboundedSender<Vec<u8>> {
+        async fn reply(&self, reply: Vec<u8>) -> bool {
+            self.send(reply).is_ok()
+        }
+    }
+
+    /// Open `channel` on `forwarder`, returning the replies it writes.
+    fn open(forwarder: &mut AgentForwarder, channel: u32) -> mpsc::UnboundedReceiver<Vec<u8>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        forwarder.open(channel, Box::new(tx));
+        rx
+    }
+
+    #[tokio::test]
+    async fn round_trip_reads_one_reply() {
+        let (mut client, mut agent) = tokio::io::duplex(64);
+        let server = tokio::spawn(async move {
+            let mut request = vec![0u8; 5];
+            agent.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, frame(&[11]));
+            agent.write_all(&frame(&[12, 0, 0, 0, 0])).await.unwrap();
+        });
+
+        let reply = round_trip(&mut client, &frame(&[11]), AGENT_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(reply, frame(&[12, 0, 0, 0, 0]));
+        server.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_agent_times_out() {
+        let (mut client, _agent) = tokio::io::duplex(64);
+        let err = round_trip(&mut client, &frame(&[11]), AGENT_TIMEOUT)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unreachable_agent_answers_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut forwarder = AgentForwarder::new(Some(dir.path().join("missing.sock")));
+        let mut replies = open(&mut forwarder, 7);
+
+        assert!(forwarder.data(7, &frame(&[11])));
+        assert_eq!(replies.recv().await, Some(failure()));
+        assert!(!forwarder.data(8, &frame(&[11])));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn relays_requests_to_the_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.sock");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 5];
+            while stream.read_exact(&mut request).await.is_ok() {
+                stream.write_all(&frame(&[12, 0, 0, 0, 0])).await.unwrap();
+            }
+        });
+
+        let mut forwarder = AgentForwarder::new(Some(path));
+        let mut replies = open(&mut forwarder, 3);
+        // Two requests, the second split across chunks.
+        let mut data = frame(&[11]);
+        data.extend_from_slice(&[0, 0]);
+        assert!(forwarder.data(3, &data));
+        assert!(forwarder.data(3, &[0, 1, 11]));
+        for _ in 0..2 {
+            assert_eq!(replies.recv().await, Some(frame(&[12, 0, 0, 0, 0])));
+        }
+
+        forwarder.close(3);
+        assert!(!forwarder.owns(3));
+        assert_eq!(replies.recv().await, None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn oversized_request_ends_the_relay() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut forwarder = AgentForwarder::new(Some(dir.path().join("missing.sock")));
+        let _replies = open(&mut forwarder, 5);
+
+        assert!(forwarder.data(5, &u32::MAX.to_be_bytes()));
+        // The relay task stops on the bad length; the next chunk notices.
+        while forwarder.data(5, &[0]) {
+            tokio::task::yield_now().await;
+        }
+        assert!(!forwarder.owns(5));
+    }
+}
@@ -0,0 +1,533 @@
+//! SSH algorithm preferences and negotiation reporting.
+//!
+//! [`preferred`] turns a profile's [`AlgorithmPreferences`] into the lists
+//! russh offers. russh does not say what was agreed, so [`KexInitTap`] reads
+//! the server's `SSH_MSG_KEXINIT` as it passes (it is sent in the clear) and
+//! [`negotiate`] repeats the RFC 4253 §7.1 choice — the first client
+//! algorithm the server also lists — to produce [`NegotiatedAlgorithms`].
+
+use std::borrow::Cow;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+
+use russh::keys::key;
+use russh::{cipher, compression, kex, mac, Preferred};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::ConnectionError;
+use crate::profile::types::AlgorithmPreferences;
+
+/// Host-key algorithms russh can verify. `key::Name::try_from` does not know
+/// `ssh-ed25519`, so the list is spelled out.
+const HOST_KEY_ALGORITHMS: &[key::Name] = &[
+    key::ED25519,
+    key::ECDSA_SHA2_NISTP256,
+    key::ECDSA_SHA2_NISTP384,
+    key::ECDSA_SHA2_NISTP521,
+    key::RSA_SHA2_256,
+    key::RSA_SHA2_512,
+    key::SSH_RSA,
+];
+
+/// Pseudo-algorithms that announce extensions rather than name a key
+/// exchange. Always offered, so user lists keep `server-sig-algs` and the
+/// strict-kex (Terrapin) countermeasure.
+const KEX_EXTENSIONS: &[kex::Name] = &[
+    kex::EXTENSION_SUPPORT_AS_CLIENT,
+    kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT,
+];
+
+/// Ciphers with built-in integrity; no MAC is negotiated alongside them.
+const AEAD_CIPHERS: &[&str] = &[
+    "chacha20-poly1305@openssh.com",
+    "aes256-gcm@openssh.com",
+    "aes128-gcm@openssh.com",
+];
+
+// ---------------------------------------------------------------------------
+// Preferences
+// ---------------------------------------------------------------------------
+
+/// The algorithm lists to offer. Empty lists keep russh's defaults; an
+/// unknown name is an error.
+pub(crate) fn preferred(prefs: &AlgorithmPreferences) -> Result<Preferred, ConnectionError> {
+    let mut preferred = Preferred::default();
+    if !prefs.kex.is_empty() {
+        let mut names = lookup("key exchange", &prefs.kex, |n| kex::Name::try_from(n).ok())?;
+        for extension in KEX_EXTENSIONS {
+            if !names.contains(extension) {
+                names.push(*extension);
+            }
+        }
+        preferred.kex = Cow::Owned(names);
+    }
+    if !prefs.host_key.is_empty() {
+        preferred.key = Cow::Owned(lookup("host key", &prefs.host_key, |n| {
+            HOST_KEY_ALGORITHMS.iter().find(|k| k.0 == n).copied()
+        })?);
+    }
+    if !prefs.cipher.is_empty() {
+        preferred.cipher = Cow::Owned(lookup("cipher", &prefs.cipher, |n| {
+            cipher::Name::try_from(n).ok()
+        })?);
+    }
+    if !prefs.mac.is_empty() {
+        preferred.mac = Cow::Owned(lookup("MAC", &prefs.mac, |n| mac::Name::try_from(n).ok())?);
+    }
+    if !prefs.compression.is_empty() {
+        preferred.compression = Cow::Owned(lookup("compression", &prefs.compression, |n| {
+            compression::Name::try_from(n).ok()
+        })?);
+    }
+    Ok(preferred)
+}
+
+fn lookup<T>(
+    kind: &str,
+    names: &[String],
+    find: impl Fn(&str) -> Option<T>,
+) -> Result<Vec<T>, ConnectionError> {
+    names
+        .iter()
+        .map(|name| {
+            find(name).ok_or_else(|| ConnectionError::UnsupportedAlgorithm {
+                kind: kind.to_owned(),
+                name: name.clone(),
+            })
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Negotiated algorithms
+// ---------------------------------------------------------------------------
+
+/// What a session agreed on with its server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedAlgorithms {
+    pub kex: String,
+    pub host_key: String,
+    /// Client-to-server cipher.
+    pub cipher: String,
+    /// Client-to-server MAC; `None` with an AEAD cipher, which needs none.
+    pub mac: Option<String>,
+    pub compression: String,
+}
+
+impl NegotiatedAlgorithms {
+    /// The agreed algorithms that are considered weak, in the order above.
+    pub fn weak(&self) -> Vec<&str> {
+        [
+            Some(self.kex.as_str()),
+            Some(self.host_key.as_str()),
+            Some(self.cipher.as_str()),
+            self.mac.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|name| is_weak(name))
+        .collect()
+    }
+}
+
+/// `true` for algorithms kept only for old hosts: SHA-1 key exchange and
+/// signatures, CBC and RC4 ciphers, SHA-1 and MD5 MACs, and `none`.
+pub fn is_weak(name: &str) -> bool {
+    matches!(
+        name,
+        "diffie-hellman-group1-sha1"
+            | "diffie-hellman-group14-sha1"
+            | "diffie-hellman-group-exchange-sha1"
+            | "ssh-rsa"
+            | "ssh-dss"
+            | "none"
+    ) || name.ends_with("-cbc")
+        || name.starts_with("arcfour")
+        || name.starts_with("hmac-sha1")
+        || name.starts_with("hmac-md5")
+}
+
+/// The algorithm name-lists of a server's `SSH_MSG_KEXINIT`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct KexInit {
+    kex: Vec<String>,
+    host_key: Vec<String>,
+    cipher: Vec<String>,
+    mac: Vec<String>,
+    compression: Vec<String>,
+}
+
+const MSG_KEXINIT: u8 = 20;
+
+impl KexInit {
+    /// Parse a KEXINIT payload, message number included.
+    fn parse(payload: &[u8]) -> Option<Self> {
+        let (&msg, rest) = payload.split_first()?;
+        if msg != MSG_KEXINIT {
+            return None;
+        }
+        let mut rest = rest.get(16..)?; // cookie
+        let mut lists = Vec::with_capacity(8);
+        for _ in 0..8 {
+            let len = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+            let list = std::str::from_utf8(rest.get(4..4 + len)?).ok()?;
+            lists.push(
+                list.split(',')
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>(),
+            );
+            rest = &rest[4 + len..];
+        }
+        let mut lists = lists.into_iter();
+        let mut next = || lists.next().unwrap_or_default();
+        let kex = next();
+        let host_key = next();
+        let cipher = next();
+        let _cipher_server_to_client = next();
+        let mac = next();
+        let _mac_server_to_client = next();
+        let compression = next();
+        Some(KexInit {
+            kex,
+            host_key,
+            cipher,
+            mac,
+            compression,
+        })
+    }
+}
+
+/// Choose as the client does: the first of ours the server also offers.
+pub(crate) fn negotiate(ours: &Preferred, server: &KexInit) -> Option<NegotiatedAlgorithms> {
+    fn pick<N: AsRef<str>>(ours: &[N], theirs: &[String]) -> Option<String> {
+        ours.iter()
+            .map(AsRef::as_ref)
+            .find(|name| theirs.iter().any(|t| t == name))
+            .map(str::to_owned)
+    }
+    let kex: Vec<&kex::Name> = ours
+        .kex
+        .iter()
+        .filter(|k| !KEX_EXTENSIONS.contains(k))
+        .collect();
+    let cipher = pick(&ours.cipher, &server.cipher)?;
+    let mac = if AEAD_CIPHERS.contains(&cipher.as_str()) {
+        None
+    } else {
+        Some(pick(&ours.mac, &server.mac)?)
+    };
+    Some(NegotiatedAlgorithms {
+        kex: pick(&kex, &server.kex)?,
+        host_key: pick(&ours.key, &server.host_key)?,
+        cipher,
+        mac,
+        compression: pick(&ours.compression, &server.compression)?,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// KEXINIT tap
+// ---------------------------------------------------------------------------
+
+/// Lines before the version string and packets before KEXINIT are bounded so
+/// a misbehaving server cannot make the tap buffer without limit.
+const MAX_LINE: usize = 8 * 1024;
+const MAX_PACKET: usize = 256 * 1024;
+
+/// Where the tap leaves the server's KEXINIT.
+pub(crate) type KexInitSlot = Arc<Mutex<Option<KexInit>>>;
+
+#[derive(Debug)]
+enum TapState {
+    /// Reading lines up to and including `SSH-…`.
+    Version,
+    /// Reading binary packets until KEXINIT.
+    Packets,
+    Done,
+}
+
+/// Watches the first bytes a server sends for its KEXINIT, passing
+/// everything through unchanged.
+pub(crate) struct KexInitTap<S> {
+    inner: S,
+    state: TapState,
+    buf: Vec<u8>,
+    slot: KexInitSlot,
+}
+
+impl<S> KexInitTap<S> {
+    pub fn new(inner: S, slot: KexInitSlot) -> Self {
+        KexInitTap {
+            inner,
+            state: TapState::Version,
+            buf: Vec::new(),
+            slot,
+        }
+    }
+
+    fn observe(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+        loop {
+            match self.state {
+                TapState::Version => {
+                    let Some(end) = self.buf.iter().position(|&b| b == b'\n') else {
+                        if self.buf.len() > MAX_LINE {
+                            self.finish(None);
+                        }
+                        return;
+                    };
+                    let is_version = self.buf.starts_with(b"SSH-");
+                    self.buf.drain(..=end);
+                    if is_version {
+                        self.state = TapState::Packets;
+                    }
+                }
+                TapState::Packets => {
+                    let Some(len) = self.buf.get(..4) else {
+                        return;
+                    };
+                    let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+                    if !(2..=MAX_PACKET).contains(&len) {
+                        self.finish(None);
+                        return;
+                    }
+                    if self.buf.len() < 4 + len {
+                        return;
+                    }
+                    let padding = usize::from(self.buf[4]);
+                    let payload = self.buf.get(5..(4 + len).saturating_sub(padding));
+                    match payload.map(|p| (p.first() == Some(&MSG_KEXINIT), p)) {
+                        Some((true, payload)) => {
+                            let parsed = KexInit::parse(payload);
+                            self.finish(parsed);
+                            return;
+                        }
+                        Some((false, _)) => {
+                            self.buf.drain(..4 + len);
+                        }
+                        None => {
+                            self.finish(None);
+                            return;
+                        }
+                    }
+                }
+                TapState::Done => return,
+            }
+        }
+    }
+
+    fn finish(&mut self, kexinit: Option<KexInit>) {
+        *self.slot.lock().unwrap_or_else(PoisonError::into_inner) = kexinit;
+        self.state = TapState::Done;
+        self.buf = Vec::new();
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for KexInitTap<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if matches!(this.state, TapState::Done) {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            this.observe(&buf.filled()[before..]);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for KexInitTap<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    fn name_list(out: &mut Vec<u8>, names: &str) {
+        out.extend_from_slice(&(names.len() as u32).to_be_bytes());
+        out.extend_from_slice(names.as_bytes());
+    }
+
+    fn kexinit_payload(kex: &str, host_key: &str, cipher: &str, mac: &str) -> Vec<u8> {
+        let mut payload = vec![MSG_KEXINIT];
+        payload.extend_from_slice(&[7; 16]);
+        for list in [
+            kex, host_key, cipher, cipher, mac, mac, "none", "none", "", "",
+        ] {
+            name_list(&mut payload, list);
+        }
+        payload.extend_from_slice(&[0, 0, 0, 0, 0]);
+        payload
+    }
+
+    fn packet(payload: &[u8]) -> Vec<u8> {
+        let padding = 8 - (payload.len() + 5) % 8 + 4;
+        let mut out = ((payload.len() + padding + 1) as u32)
+            .to_be_bytes()
+            .to_vec();
+        out.push(padding as u8);
+        out.extend_from_slice(payload);
+        out.extend(std::iter::repeat_n(0, padding));
+        out
+    }
+
+    fn legacy_server() -> KexInit {
+        KexInit::parse(&kexinit_payload(
+            "diffie-hellman-group14-sha1",
+            "ssh-rsa",
+            "aes128-cbc,aes128-ctr",
+            "hmac-sha1",
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn preferences_replace_the_defaults_and_keep_extensions() {
+        let prefs = AlgorithmPreferences {
+            kex: vec!["diffie-hellman-group14-sha1".into()],
+            host_key: vec!["ssh-ed25519".into(), "ssh-rsa".into()],
+            ..AlgorithmPreferences::default()
+        };
+        let preferred = preferred(&prefs).unwrap();
+        assert_eq!(preferred.kex[0], kex::DH_G14_SHA1);
+        assert!(preferred
+            .kex
+            .contains(&kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT));
+        assert_eq!(&preferred.key[..], &[key::ED25519, key::SSH_RSA]);
+        assert_eq!(preferred.cipher, Preferred::default().cipher);
+    }
+
+    #[test]
+    fn unknown_algorithms_are_rejected() {
+        let prefs = AlgorithmPreferences {
+            cipher: vec!["rot13".into()],
+            ..AlgorithmPreferences::default()
+        };
+        assert!(matches!(
+            preferred(&prefs),
+            Err(ConnectionError::UnsupportedAlgorithm { kind, name })
+                if kind == "cipher" && name == "rot13"
+        ));
+    }
+
+    #[test]
+    fn defaults_cannot_agree_with_a_legacy_server() {
+        assert_eq!(negotiate(&Preferred::default(), &legacy_server()), None);
+    }
+
+    #[test]
+    fn legacy_preferences_negotiate_and_are_flagged_weak() {
+        let prefs = AlgorithmPreferences {
+            kex: vec!["diffie-hellman-group14-sha1".into()],
+            host_key: vec!["ssh-rsa".into()],
+            cipher: vec!["aes128-ctr".into(), "aes128-cbc".into()],
+            mac: vec!["hmac-sha1".into()],
+            ..AlgorithmPreferences::default()
+        };
+        let negotiated = negotiate(&preferred(&prefs).unwrap(), &legacy_server()).unwrap();
+        assert_eq!(negotiated.cipher, "aes128-ctr");
+        assert_eq!(negotiated.mac.as_deref(), Some("hmac-sha1"));
+        assert_eq!(
+            negotiated.weak(),
+            ["diffie-hellman-group14-sha1", "ssh-rsa", "hmac-sha1"]
+        );
+    }
+
+    #[test]
+    fn aead_ciphers_need_no_mac() {
+        let server = KexInit::parse(&kexinit_payload(
+            "curve25519-sha256",
+            "ssh-ed25519",
+            "chacha20-poly1305@openssh.com",
+            "",
+        ))
+        .unwrap();
+        let negotiated = negotiate(&Preferred::default(), &server).unwrap();
+        assert_eq!(negotiated.mac, None);
+        assert!(negotiated.weak().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tap_captures_kexinit_and_passes_bytes_through() {
+        let mut stream = b"banner line\r\nSSH-2.0-OpenSSH_7.4\r\n".to_vec();
+        stream.extend(packet(&[2, 0, 0, 0, 0])); // SSH_MSG_IGNORE
+        stream.extend(packet(&kexinit_payload(
+            "diffie-hellman-group14-sha1",
+            "ssh-rsa",
+            "aes128-cbc,aes128-ctr",
+            "hmac-sha1",
+        )));
+
+        let slot = KexInitSlot::default();
+        // Tiny reads exercise reassembly across calls.
+        let reader = tokio_test::io::Builder::new()
+            .read(&stream[..7])
+            .read(&stream[7..40])
+            .read(&stream[40..])
+            .build();
+        let mut tap = KexInitTap::new(reader, Arc::clone(&slot));
+        let mut out = Vec::new();
+        tap.read_to_end(&mut out).await.unwrap();
+
+        assert_eq!(out, stream);
+        assert_eq!(slot.lock().unwrap().clone(), Some(legacy_server()));
+    }
+
+    #[tokio::test]
+    async fn tap_stops_buffering_once_kexinit_is_captured() {
+        let mut stream = b"SSH-2.0-OpenSSH_7.4\r\n".to_vec();
+        stream.extend(packet(&kexinit_payload(
+            "curve25519-sha256",
+            "ssh-ed25519",
+            "aes128-ctr",
+            "hmac-sha2-256",
+        )));
+        let after = vec![9u8; 64 * 1024];
+
+        let slot = KexInitSlot::default();
+        let reader = tokio_test::io::Builder::new()
+            .read(&stream)
+            .read(&after)
+            .build();
+        let mut tap = KexInitTap::new(reader, Arc::clone(&slot));
+        let mut out = vec![0; stream.len()];
+        tap.read_exact(&mut out).await.unwrap();
+        assert!(matches!(tap.state, TapState::Done));
+
+        let mut rest = Vec::new();
+        tap.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, after);
+        assert!(tap.buf.is_empty());
+        assert!(slot.lock().unwrap().is_some());
+    }
+}
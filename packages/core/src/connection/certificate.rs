@@ -0,0 +1,205 @@
+//! OpenSSH user certificates.
+//!
+//! A certificate (`id_ed25519-cert.pub`) is a public key signed by a CA,
+//! limited to a set of principals and a validity window. Pass it alongside
+//! the private key in [`Credential::PublicKey`](super::Credential::PublicKey)
+//! or store it on [`SshKey::certificate`](crate::profile::types::SshKey).
+//!
+//! [`CertificateInfo`] summarises a certificate for display, so the UI can
+//! warn about an expired or not-yet-valid certificate before connecting.
+
+use chrono::{DateTime, Utc};
+use ssh_key::{Certificate, HashAlg};
+
+use super::ConnectionError;
+
+/// Where a certificate stands relative to its validity window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertificateValidity {
+    NotYetValid,
+    Valid,
+    Expired,
+}
+
+/// The parts of an OpenSSH certificate a user cares about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateInfo {
+    /// Certificate type, e.g. `ssh-ed25519-cert-v01@openssh.com`.
+    pub algorithm: String,
+    /// Free-form identifier chosen by the CA; shows up in server logs.
+    pub key_id: String,
+    pub serial: u64,
+    /// Users the certificate may log in as. Empty means any.
+    pub principals: Vec<String>,
+    pub valid_after: DateTime<Utc>,
+    /// `None` for certificates that never expire.
+    pub valid_before: Option<DateTime<Utc>>,
+    /// SHA-256 fingerprint of the signing CA's key.
+    pub ca_fingerprint: String,
+    /// Granted extensions, e.g. `permit-pty`.
+    pub extensions: Vec<String>,
+}
+
+impl CertificateInfo {
+    /// Parse an OpenSSH certificate (the contents of a `-cert.pub` file). A
+    /// malformed or host certificate is a [`ConnectionError::Config`] error.
+    pub fn parse(openssh: &str) -> Result<Self, ConnectionError> {
+        let cert = parse_certificate(openssh)?;
+        if !cert.cert_type().is_user() {
+            return Err(ConnectionError::Config(
+                "OpenSSH certificate is a host certificate, not a user certificate".to_owned(),
+            ));
+        }
+        Ok(CertificateInfo {
+            algorithm: cert.algorithm().to_certificate_type(),
+            key_id: cert.key_id().to_owned(),
+            serial: cert.serial(),
+            principals: cert.valid_principals().to_vec(),
+            valid_after: timestamp(cert.valid_after()).unwrap_or_default(),
+            valid_before: timestamp(cert.valid_before()),
+            ca_fingerprint: cert
+                .signature_key()
+                .fingerprint(HashAlg::Sha256)
+                .to_string(),
+            extensions: cert.extensions().keys().cloned().collect(),
+        })
+    }
+
+    /// Validity at `now`.
+    pub fn validity_at(&self, now: DateTime<Utc>) -> CertificateValidity {
+        if now < self.valid_after {
+            CertificateValidity::NotYetValid
+        } else if self.valid_before.is_some_and(|end| now >= end) {
+            CertificateValidity::Expired
+        } else {
+            CertificateValidity::Valid
+        }
+    }
+
+    /// Validity right now.
+    pub fn validity(&self) -> CertificateValidity {
+        self.validity_at(Utc::now())
+    }
+
+    /// `true` if `username` is among the principals (or there are none).
+    pub fn allows_principal(&self, username: &str) -> bool {
+        self.principals.is_empty() || self.principals.iter().any(|p| p == username)
+    }
+}
+
+pub(crate) fn parse_certificate(openssh: &str) -> Result<Certificate, ConnectionError> {
+    Certificate::from_openssh(openssh.trim())
+        .map_err(|e| ConnectionError::Config(format!("invalid OpenSSH certificate: {e}")))
+}
+
+/// Certificate times are Unix seconds; OpenSSH uses `u64::MAX` for "forever".
+fn timestamp(secs: u64) -> Option<DateTime<Utc>> {
+    i64::try_from(secs)
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// `ssh-keygen -s ca -I alice-2026-10 -n alice,deploy -z 42
+    ///  -V 20261001000000:20261101000000 id_ed25519.pub`
+    const CERT: &str = concat!(
+        "ssh-ed25519-cert-v01@openssh.com ",
+        "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIEaB2MVTERh+MIquZZTlhtqX",
+        "1yPVhOB3UXaho2QpQbTvAAAAIOeOM4E7db3MOPdgOs0nv/B9U5lxy/pUW31gpBqkH8fWAAAAAAAA",
+        "ACoAAAABAAAADWFsaWNlLTIwMjYtMTAAAAATAAAABWFsaWNlAAAABmRlcGxveQAAAABqvaKAAAAA",
+        "AGrmgQAAAAAAAAAAggAAABVwZXJtaXQtWDExLWZvcndhcmRpbmcAAAAAAAAAF3Blcm1pdC1hZ2Vu",
+        "dC1mb3J3YXJkaW5nAAAAAAAAABZwZXJtaXQtcG9ydC1mb3J3YXJkaW5nAAAAAAAAAApwZXJtaXQt",
+        "cHR5AAAAAAAAAA5wZXJtaXQtdXNlci1yYwAAAAAAAAAAAAAAMwAAAAtzc2gtZWQyNTUxOQAAACDC",
+        "080XGL/2BGSNUAs9yYnWG/IEOR6RglXb8sVPm5h/QgAAAFMAAAALc3NoLWVkMjU1MTkAAABAF96h",
+        "qVQrpFfxXgCycNKU5aZBJpTsfdxKxazB7g22FHCfIyOLa95vMZG6N8mRX3Os2gEs9ROdYs+6ePcK",
+        "KUwpBg==",
+        " alice@example.com",
+    );
+
+    #[test]
+    fn parses_user_certificate_fields() {
+        let info = CertificateInfo::parse(CERT).unwrap();
+        assert_eq!(info.algorithm, "ssh-ed25519-cert-v01@openssh.com");
+        assert_eq!(info.key_id, "alice-2026-10");
+        assert_eq!(info.serial, 42);
+        assert_eq!(info.principals, ["alice", "deploy"]);
+        assert_eq!(
+            info.valid_after,
+            Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            info.valid_before,
+            Some(Utc.with_ymd_and_hms(2026, 11, 1, 0, 0, 0).unwrap())
+        );
+        assert_eq!(
+            info.ca_fingerprint,
+            "SHA256:P1L3buFWLFPFSQ8R28OfEiefzpLasrga75aunCy1q+4"
+        );
+        assert!(info.extensions.iter().any(|e| e == "permit-pty"));
+    }
+
+    #[test]
+    fn validity_window_is_half_open() {
+        let info = CertificateInfo::parse(CERT).unwrap();
+        let at = |m, d| Utc.with_ymd_and_hms(2026, m, d, 0, 0, 0).unwrap();
+        assert_eq!(
+            info.validity_at(at(9, 30)),
+            CertificateValidity::NotYetValid
+        );
+        assert_eq!(info.validity_at(at(10, 1)), CertificateValidity::Valid);
+        assert_eq!(info.validity_at(at(10, 31)), CertificateValidity::Valid);
+        assert_eq!(info.validity_at(at(11, 1)), CertificateValidity::Expired);
+    }
+
+    #[test]
+    fn principals_restrict_usernames() {
+        let mut info = CertificateInfo::parse(CERT).unwrap();
+        assert!(info.allows_principal("deploy"));
+        assert!(!info.allows_principal("root"));
+        info.principals.clear();
+        assert!(info.allows_principal("root"));
+    }
+
+    #[test]
+    fn forever_certificates_have_no_end() {
+        assert_eq!(timestamp(u64::MAX), None);
+        assert!(timestamp(0).is_some());
+    }
+
+    #[test]
+    fn rejects_host_certificates() {
+        // `ssh-keygen -s ca -h -I web-host -n web.example.com host.pub`
+        let host = concat!(
+            "ssh-ed25519-cert-v01@openssh.com ",
+            "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIPJgtd/xmcAogCLKF0G/jHST",
+            "5EPAkp+Di8J+cmhstaYPAAAAIICb4iYAjReBY2YQSRTkKga5L+939IrIca5i/F5xuZNeAAAAAAAA",
+            "AAAAAAACAAAACHdlYi1ob3N0AAAAEwAAAA93ZWIuZXhhbXBsZS5jb20AAAAAaVW5AAAAAAB8JF8A",
+            "AAAAAAAAAAAAAAAAAAAAMwAAAAtzc2gtZWQyNTUxOQAAACBhBvVBUbbc6vNC7dhgHXMPd48WiMiG",
+            "9wXCUG3TnetdWgAAAFMAAAALc3NoLWVkMjU1MTkAAABAk2qYsudeCGyQ1LTx1RTrWgsekRTzgags",
+            "ZrOuc04mhm5xDuqw5ej4TbLtItC/R0jxOMp/6aOR0kOC+xk8kB+VBQ==",
+        );
+        assert!(matches!(
+            CertificateInfo::parse(host),
+            Err(ConnectionError::Config(msg)) if msg.contains("host certificate")
+        ));
+    }
+
+    #[test]
+    fn rejects_plain_public_keys() {
+        let plain =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOeOM4E7db3MOPdgOs0nv/B9U5lxy/pUW31gpBqkH8fW";
+        assert!(matches!(
+            CertificateInfo::parse(plain),
+            Err(ConnectionError::Config(msg)) if msg.starts_with("invalid OpenSSH certificate")
+        ));
+    }
+}
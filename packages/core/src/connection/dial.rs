@@ -0,0 +1,268 @@
+//! Name resolution and TCP connect for SSH sessions.
+//!
+//! [`dial`] resolves the host, alternates its IPv6 and IPv4 addresses and
+//! races connection attempts "happy eyeballs" style (RFC 8305): each attempt
+//! gets [`ATTEMPT_DELAY`] to itself before the next address is tried, a
+//! failed attempt starts the next one at once, and the first connection wins.
+//! A host whose IPv6 route is broken then costs a quarter second rather than
+//! a full TCP timeout.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+use super::{ConnectStage, ConnectionError};
+
+/// Head start given to each attempt before the next address is tried.
+pub(crate) const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Resolve `host` and connect to `port` on the first address that answers.
+/// `timeout` bounds resolution and connection together.
+pub(crate) async fn dial(
+    host: &str,
+    port: u16,
+    timeout: Option<Duration>,
+) -> Result<TcpStream, ConnectionError> {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let expired = |stage| ConnectionError::ConnectTimeout {
+        stage,
+        timeout: timeout.unwrap_or_default(),
+    };
+    let dns_error = |reason: String| ConnectionError::Dns {
+        host: host.to_owned(),
+        reason,
+    };
+
+    let addrs = until(deadline, tokio::net::lookup_host((host, port)))
+        .await
+        .ok_or_else(|| expired(ConnectStage::Dns))?
+        .map_err(|e| dns_error(e.to_string()))?;
+    let addrs = interleave(addrs.collect());
+    if addrs.is_empty() {
+        return Err(dns_error("no addresses found".to_owned()));
+    }
+
+    until(deadline, race(addrs, ATTEMPT_DELAY, TcpStream::connect))
+        .await
+        .ok_or_else(|| expired(ConnectStage::Tcp))?
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::ConnectionRefused => ConnectionError::Refused {
+                host: host.to_owned(),
+                port,
+            },
+            _ => ConnectionError::Unreachable {
+                host: host.to_owned(),
+                port,
+                source: e,
+            },
+        })
+}
+
+/// Wait for `fut`, giving up at `deadline`.
+async fn until<F: Future>(deadline: Option<Instant>, fut: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, fut).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
+/// Alternate address families, starting with the one the resolver listed
+/// first; order within a family is kept.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addrs.first().is_some_and(SocketAddr::is_ipv6);
+    let (preferred, other): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv6() == first_v6);
+
+    let mut out = Vec::with_capacity(preferred.len() + other.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return out,
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Start `connect` on each address in turn, `delay` apart or as soon as an
+/// attempt fails, and return the first connection. Losing attempts are
+/// aborted. When every attempt fails, a refusal is reported over other
+/// errors, since it shows the host is up.
+async fn race<C, F, S>(addrs: Vec<SocketAddr>, delay: Duration, connect: C) -> io::Result<S>
+where
+    C: Fn(SocketAddr) -> F,
+    F: Future<Output = io::Result<S>> + Send + 'static,
+    S: Send + 'static,
+{
+    let mut addrs = addrs.into_iter().peekable();
+    let mut attempts = JoinSet::new();
+    let mut error: Option<io::Error> = None;
+
+    match addrs.next() {
+        Some(addr) => attempts.spawn(connect(addr)),
+        None => return Err(io::Error::new(io::ErrorKind::NotFound, "no addresses")),
+    };
+
+    loop {
+        tokio::select! {
+            Some(joined) = attempts.join_next() => {
+                let e = match joined {
+                    Ok(Ok(stream)) => return Ok(stream),
+                    Ok(Err(e)) => e,
+                    Err(e) => io::Error::other(e),
+                };
+                // Keep the first refusal: it says more than a later timeout.
+                if error
+                    .as_ref()
+                    .is_none_or(|prev| prev.kind() != io::ErrorKind::ConnectionRefused)
+                {
+                    error = Some(e);
+                }
+                match addrs.next() {
+                    Some(addr) => {
+                        attempts.spawn(connect(addr));
+                    }
+                    None if attempts.is_empty() => {
+                        return Err(error.unwrap_or_else(|| io::ErrorKind::NotFound.into()));
+                    }
+                    None => {}
+                }
+            }
+            _ = tokio::time::sleep(delay), if addrs.peek().is_some() => {
+                if let Some(addr) = addrs.next() {
+                    attempts.spawn(connect(addr));
+                }
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn interleave_alternates_families() {
+        let addrs = vec![
+            addr("[2001:db8::1]:22"),
+            addr("[2001:db8::2]:22"),
+            addr("192.0.2.1:22"),
+            addr("[2001:db8::3]:22"),
+            addr("192.0.2.2:22"),
+        ];
+        assert_eq!(
+            interleave(addrs),
+            [
+                addr("[2001:db8::1]:22"),
+                addr("192.0.2.1:22"),
+                addr("[2001:db8::2]:22"),
+                addr("192.0.2.2:22"),
+                addr("[2001:db8::3]:22"),
+            ]
+        );
+
+        let v4_first = vec![addr("192.0.2.1:22"), addr("[2001:db8::1]:22")];
+        assert_eq!(interleave(v4_first.clone()), v4_first);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_address_loses_to_the_next_after_the_delay() {
+        let stalled = addr("[2001:db8::1]:22");
+        let started = Instant::now();
+        let winner = race(
+            vec![stalled, addr("192.0.2.1:22")],
+            ATTEMPT_DELAY,
+            move |a| async move {
+                if a == stalled {
+                    std::future::pending::<()>().await;
+                }
+                Ok(a)
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(winner, addr("192.0.2.1:22"));
+        assert_eq!(started.elapsed(), ATTEMPT_DELAY);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_attempt_starts_the_next_at_once() {
+        let broken = addr("[2001:db8::1]:22");
+        let started = Instant::now();
+        let winner = race(
+            vec![broken, addr("192.0.2.1:22")],
+            ATTEMPT_DELAY,
+            move |a| async move {
+                if a == broken {
+                    return Err(io::ErrorKind::NetworkUnreachable.into());
+                }
+                Ok(a)
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(winner, addr("192.0.2.1:22"));
+        assert!(started.elapsed() < ATTEMPT_DELAY);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refusal_is_reported_over_other_failures() {
+        let refused = addr("192.0.2.1:22");
+        let err = race(
+            vec![refused, addr("[2001:db8::1]:22")],
+            ATTEMPT_DELAY,
+            move |a| async move {
+                Err::<(), _>(if a == refused {
+                    io::ErrorKind::ConnectionRefused.into()
+                } else {
+                    io::Error::from(io::ErrorKind::NetworkUnreachable)
+                })
+            },
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[tokio::test]
+    async fn dial_connects_and_reports_refusal() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        dial("127.0.0.1", port, Some(Duration::from_secs(5)))
+            .await
+            .unwrap();
+
+        drop(listener);
+        let err = dial("127.0.0.1", port, Some(Duration::from_secs(5)))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ConnectionError::Refused { .. }), "{err}");
+        assert_eq!(err.stage(), Some(ConnectStage::Tcp));
+    }
+
+    #[tokio::test]
+    async fn unknown_host_fails_at_dns() {
+        // `.invalid` never resolves (RFC 2606); without a resolver the
+        // lookup may instead time out, which is still the DNS stage.
+        let err = dial("tacoshell.invalid", 22, Some(Duration::from_secs(5)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.stage(), Some(ConnectStage::Dns), "{err}");
+    }
+}
@@ -0,0 +1,442 @@
+//! Streaming command execution.
+//!
+//! [`TerminalAdapter::exec_stream`](super::TerminalAdapter::exec_stream)
+//! starts an [`ExecRequest`] and returns an [`ExecHandle`] straight away.
+//! stdout and stderr arrive on separate receivers while the command runs;
+//! stdin, signals and PTY resizes go the other way through the handle.
+//!
+//! Output is bounded per stream: nothing is dropped, but once
+//! [`OUTPUT_LIMIT`](super::output::OUTPUT_LIMIT) bytes of stdout or stderr go
+//! unread the channel is closed and the command fails with
+//! [`ConnectionError::OutputOverflow`]. Drop a receiver you do not care about
+//! rather than leaving it unread; its output is then discarded.
+
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+
+use super::{ConnectionError, ExecResult};
+
+// ---------------------------------------------------------------------------
+// Request
+// ---------------------------------------------------------------------------
+
+/// A command to run with [`exec_stream`](super::TerminalAdapter::exec_stream).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecRequest {
+    pub command: String,
+    /// Variables to set before the command starts. Servers only honour the
+    /// names they allow (OpenSSH: `AcceptEnv`) and silently ignore the rest.
+    pub env: Vec<(String, String)>,
+    /// Allocate a PTY for the command. stderr is merged into stdout by the
+    /// remote terminal when set.
+    pub pty: Option<ExecPty>,
+    /// Give up after this long, failing with [`ConnectionError::Timeout`].
+    pub timeout: Option<Duration>,
+}
+
+impl ExecRequest {
+    pub fn new(command: impl Into<String>) -> Self {
+        ExecRequest {
+            command: command.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// PTY settings for an [`ExecRequest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecPty {
+    pub term: String,
+    pub cols: u16,
+    pub rows: u16,
+}
+
+impl Default for ExecPty {
+    fn default() -> Self {
+        ExecPty {
+            term: "xterm-256color".to_owned(),
+            cols: 80,
+            rows: 24,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Signals and exit status
+// ---------------------------------------------------------------------------
+
+/// A signal that can be delivered to a remote process (RFC 4254 §6.10).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signal {
+    Abrt,
+    Alrm,
+    Fpe,
+    Hup,
+    Ill,
+    Int,
+    Kill,
+    Pipe,
+    Quit,
+    Segv,
+    Term,
+    Usr1,
+    /// Any other signal, by name without the `SIG` prefix.
+    Other(String),
+}
+
+impl Signal {
+    /// Name as sent on the wire, without the `SIG` prefix.
+    pub fn name(&self) -> &str {
+        match self {
+            Signal::Abrt => "ABRT",
+            Signal::Alrm => "ALRM",
+            Signal::Fpe => "FPE",
+            Signal::Hup => "HUP",
+            Signal::Ill => "ILL",
+            Signal::Int => "INT",
+            Signal::Kill => "KILL",
+            Signal::Pipe => "PIPE",
+            Signal::Quit => "QUIT",
+            Signal::Segv => "SEGV",
+            Signal::Term => "TERM",
+            Signal::Usr1 => "USR1",
+            Signal::Other(name) => name,
+        }
+    }
+
+    /// Parse a signal name, with or without the `SIG` prefix.
+    pub fn from_name(name: &str) -> Self {
+        let bare = name.strip_prefix("SIG").unwrap_or(name);
+        match bare {
+            "ABRT" => Signal::Abrt,
+            "ALRM" => Signal::Alrm,
+            "FPE" => Signal::Fpe,
+            "HUP" => Signal::Hup,
+            "ILL" => Signal::Ill,
+            "INT" => Signal::Int,
+            "KILL" => Signal::Kill,
+            "PIPE" => Signal::Pipe,
+            "QUIT" => Signal::Quit,
+            "SEGV" => Signal::Segv,
+            "TERM" => Signal::Term,
+            "USR1" => Signal::Usr1,
+            other => Signal::Other(other.to_owned()),
+        }
+    }
+}
+
+impl std::fmt::Display for Signal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SIG{}", self.name())
+    }
+}
+
+/// Reported when a remote command was terminated by a signal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitSignal {
+    pub signal: Signal,
+    pub core_dumped: bool,
+    /// Optional explanation from the server; often empty.
+    pub error_message: String,
+}
+
+/// How a remote command finished. Servers send either an exit code or an
+/// exit signal; both are `None` if the channel closed without either.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExitStatus {
+    pub code: Option<u32>,
+    pub signal: Option<ExitSignal>,
+}
+
+impl ExitStatus {
+    /// `true` if the command exited with status 0.
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Handle
+// ---------------------------------------------------------------------------
+
+/// Requests from an [`ExecHandle`] to the task driving the command.
+#[derive(Debug)]
+pub(crate) enum ExecCmd {
+    Stdin(Vec<u8>),
+    CloseStdin,
+    Signal(Signal),
+    Resize { cols: u16, rows: u16 },
+}
+
+/// Channels connecting an [`ExecHandle`] to the adapter task that runs the
+/// command.
+pub(crate) struct ExecParts {
+    pub stdout: mpsc::Receiver<Vec<u8>>,
+    pub stderr: mpsc::Receiver<Vec<u8>>,
+    pub control: mpsc::Sender<ExecCmd>,
+    pub exit: oneshot::Receiver<Result<ExitStatus, ConnectionError>>,
+    pub cancel: CancellationToken,
+}
+
+/// A running remote command.
+///
+/// Dropping the handle cancels the command; call [`ExecHandle::wait`] or
+/// [`ExecHandle::collect`] to let it finish.
+pub struct ExecHandle {
+    stdout: Option<mpsc::Receiver<Vec<u8>>>,
+    stderr: Option<mpsc::Receiver<Vec<u8>>>,
+    stdin: ExecStdin,
+    exit: oneshot::Receiver<Result<ExitStatus, ConnectionError>>,
+    cancel: CancellationToken,
+}
+
+impl ExecHandle {
+    pub(crate) fn new(parts: ExecParts) -> Self {
+        ExecHandle {
+            stdout: Some(parts.stdout),
+            stderr: Some(parts.stderr),
+            stdin: ExecStdin {
+                control: parts.control,
+            },
+            exit: parts.exit,
+            cancel: parts.cancel,
+        }
+    }
+
+    /// Take the stdout receiver. Returns `None` after the first call.
+    pub fn stdout(&mut self) -> Option<mpsc::Receiver<Vec<u8>>> {
+        self.stdout.take()
+    }
+
+    /// Take the stderr receiver. Returns `None` after the first call.
+    pub fn stderr(&mut self) -> Option<mpsc::Receiver<Vec<u8>>> {
+        self.stderr.take()
+    }
+
+    /// A writer for the command's stdin. Can be cloned and moved to another
+    /// task.
+    pub fn stdin(&self) -> ExecStdin {
+        self.stdin.clone()
+    }
+
+    /// Deliver `signal` to the remote process. Servers that do not support
+    /// signals ignore it.
+    pub async fn signal(&self, signal: Signal) -> Result<(), ConnectionError> {
+        self.stdin.send(ExecCmd::Signal(signal)).await
+    }
+
+    /// Resize the command's PTY. Ignored by the server if no PTY was
+    /// requested.
+    pub async fn resize(&self, cols: u16, rows: u16) -> Result<(), ConnectionError> {
+        self.stdin.send(ExecCmd::Resize { cols, rows }).await
+    }
+
+    /// Abort the command. [`ExecHandle::wait`] then fails with
+    /// [`ConnectionError::Cancelled`].
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Wait for the command to finish. Output not yet read stays available on
+    /// receivers that were taken.
+    pub async fn wait(mut self) -> Result<ExitStatus, ConnectionError> {
+        // Release the receivers we still own so unread output cannot stall
+        // the command.
+        self.stdout = None;
+        self.stderr = None;
+        (&mut self.exit)
+            .await
+            .map_err(|_| ConnectionError::Protocol("exec task ended unexpectedly".to_owned()))?
+    }
+
+    /// Wait for the command to finish, buffering whatever output has not been
+    /// taken.
+    pub async fn collect(mut self) -> Result<ExecResult, ConnectionError> {
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let mut stdout_rx = self.stdout.take();
+        let mut stderr_rx = self.stderr.take();
+
+        // Both streams must be drained together: a full stderr would
+        // otherwise stall a command still writing to stdout.
+        while stdout_rx.is_some() || stderr_rx.is_some() {
+            tokio::select! {
+                chunk = recv(&mut stdout_rx), if stdout_rx.is_some() => match chunk {
+                    Some(chunk) => stdout.extend_from_slice(&chunk),
+                    None => stdout_rx = None,
+                },
+                chunk = recv(&mut stderr_rx), if stderr_rx.is_some() => match chunk {
+                    Some(chunk) => stderr.extend_from_slice(&chunk),
+                    None => stderr_rx = None,
+                },
+            }
+        }
+
+        let status = self.wait().await?;
+        Ok(ExecResult {
+            stdout,
+            stderr,
+            exit_code: status.code,
+            exit_signal: status.signal,
+        })
+    }
+}
+
+impl Drop for ExecHandle {
+    fn drop(&mut self) {
+        // A no-op once the command has finished.
+        self.cancel.cancel();
+    }
+}
+
+async fn recv(rx: &mut Option<mpsc::Receiver<Vec<u8>>>) -> Option<Vec<u8>> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => None,
+    }
+}
+
+/// Writer for a running command's stdin.
+#[derive(Clone)]
+pub struct ExecStdin {
+    control: mpsc::Sender<ExecCmd>,
+}
+
+impl ExecStdin {
+    /// Write `data` to the command's stdin.
+    pub async fn write(&self, data: &[u8]) -> Result<(), ConnectionError> {
+        self.send(ExecCmd::Stdin(data.to_vec())).await
+    }
+
+    /// Send EOF on stdin. Further writes are an error on the server side.
+    pub async fn close(&self) -> Result<(), ConnectionError> {
+        self.send(ExecCmd::CloseStdin).await
+    }
+
+    async fn send(&self, cmd: ExecCmd) -> Result<(), ConnectionError> {
+        self.control
+            .send(cmd)
+            .await
+            .map_err(|_| ConnectionError::Protocol("command has finished".to_owned()))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Remote {
+        stdout: mpsc::Sender<Vec<u8>>,
+        stderr: mpsc::Sender<Vec<u8>>,
+        control: mpsc::Receiver<ExecCmd>,
+        exit: oneshot::Sender<Result<ExitStatus, ConnectionError>>,
+        cancel: CancellationToken,
+    }
+
+    fn handle() -> (ExecHandle, Remote) {
+        let (stdout_tx, stdout) = mpsc::channel(4);
+        let (stderr_tx, stderr) = mpsc::channel(4);
+        let (control, control_rx) = mpsc::channel(4);
+        let (exit_tx, exit) = oneshot::channel();
+        let cancel = CancellationToken::new();
+        let handle = ExecHandle::new(ExecParts {
+            stdout,
+            stderr,
+            control,
+            exit,
+            cancel: cancel.clone(),
+        });
+        let remote = Remote {
+            stdout: stdout_tx,
+            stderr: stderr_tx,
+            control: control_rx,
+            exit: exit_tx,
+            cancel,
+        };
+        (handle, remote)
+    }
+
+    #[test]
+    fn signal_names_round_trip() {
+        for signal in [Signal::Int, Signal::Term, Signal::Kill, Signal::Usr1] {
+            assert_eq!(Signal::from_name(signal.name()), signal);
+        }
+        assert_eq!(Signal::from_name("SIGHUP"), Signal::Hup);
+        assert_eq!(Signal::from_name("WINCH"), Signal::Other("WINCH".into()));
+        assert_eq!(Signal::Term.to_string(), "SIGTERM");
+    }
+
+    #[tokio::test]
+    async fn collect_drains_both_streams_and_reports_exit() {
+        let (handle, remote) = handle();
+        let task = tokio::spawn(async move {
+            // More chunks than either channel holds, interleaved.
+            for _ in 0..10 {
+                remote.stderr.send(b"e".to_vec()).await.unwrap();
+                remote.stdout.send(b"o".to_vec()).await.unwrap();
+            }
+            drop(remote.stdout);
+            drop(remote.stderr);
+            let status = ExitStatus {
+                code: None,
+                signal: Some(ExitSignal {
+                    signal: Signal::Segv,
+                    core_dumped: true,
+                    error_message: String::new(),
+                }),
+            };
+            remote.exit.send(Ok(status)).unwrap();
+        });
+
+        let result = handle.collect().await.unwrap();
+        task.await.unwrap();
+        assert_eq!(result.stdout, b"oooooooooo");
+        assert_eq!(result.stderr, b"eeeeeeeeee");
+        assert_eq!(result.exit_code, None);
+        assert_eq!(result.exit_signal.unwrap().signal, Signal::Segv);
+    }
+
+    #[tokio::test]
+    async fn stdin_and_signals_reach_the_task() {
+        let (handle, mut remote) = handle();
+        let stdin = handle.stdin();
+        stdin.write(b"input").await.unwrap();
+        stdin.close().await.unwrap();
+        handle.signal(Signal::Int).await.unwrap();
+
+        assert!(matches!(remote.control.recv().await, Some(ExecCmd::Stdin(d)) if d == b"input"));
+        assert!(matches!(
+            remote.control.recv().await,
+            Some(ExecCmd::CloseStdin)
+        ));
+        assert!(matches!(
+            remote.control.recv().await,
+            Some(ExecCmd::Signal(Signal::Int))
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropping_the_handle_cancels() {
+        let (handle, remote) = handle();
+        assert!(!remote.cancel.is_cancelled());
+        drop(handle);
+        assert!(remote.cancel.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn writes_after_the_task_exits_fail() {
+        let (handle, remote) = handle();
+        drop(remote);
+        assert!(handle.stdin().write(b"x").await.is_err());
+        assert!(matches!(
+            handle.wait().await,
+            Err(ConnectionError::Protocol(_))
+        ));
+    }
+}
@@ -0,0 +1,204 @@
+//! Remote file metadata shared by the file-transfer adapters.
+//!
+//! [`FileEntry`] is what [`FileTransferAdapter`](super::FileTransferAdapter)
+//! returns for listings and `stat`, whatever the protocol. Remote paths are
+//! POSIX strings, handled with the helpers here rather than
+//! [`std::path::Path`], whose separators depend on the local platform.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// What a directory entry is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+    /// Devices, sockets, FIFOs, or a type the server did not report.
+    Other,
+}
+
+impl FileKind {
+    /// The type bits (`S_IFMT`) of a POSIX mode.
+    pub fn from_mode(mode: u32) -> Self {
+        match mode & 0o170_000 {
+            0o100_000 => FileKind::File,
+            0o040_000 => FileKind::Dir,
+            0o120_000 => FileKind::Symlink,
+            _ => FileKind::Other,
+        }
+    }
+}
+
+/// One file, directory or link on the remote side.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// The last path component.
+    pub name: String,
+    /// The full remote path.
+    pub path: String,
+    pub kind: FileKind,
+    pub size: u64,
+    /// Permission bits, e.g. `0o755` (type bits are in [`kind`](Self::kind)).
+    pub permissions: u32,
+    pub modified_at: Option<DateTime<Utc>>,
+    pub accessed_at: Option<DateTime<Utc>>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Owner and group names, when the server reports them (SFTP does so
+    /// only in directory listings).
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    /// Where a symlink points, as stored in the link.
+    pub symlink_target: Option<String>,
+    /// What a symlink resolves to; `None` if it is dangling.
+    pub target_kind: Option<FileKind>,
+}
+
+impl FileEntry {
+    /// A directory, or a symlink to one.
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Dir || self.target_kind == Some(FileKind::Dir)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind == FileKind::Symlink
+    }
+
+    /// `ls -l` style mode, e.g. `drwxr-xr-x`.
+    pub fn mode_string(&self) -> String {
+        let kind = match self.kind {
+            FileKind::File => '-',
+            FileKind::Dir => 'd',
+            FileKind::Symlink => 'l',
+            FileKind::Other => '?',
+        };
+        let mut mode = String::with_capacity(10);
+        mode.push(kind);
+        for shift in [6, 3, 0] {
+            let bits = self.permissions >> shift;
+            mode.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+            mode.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+            mode.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+        }
+        mode
+    }
+}
+
+/// The account a session is logged in to. Sessions with equal identities
+/// see the same files with the same permissions, whatever host name or
+/// address each one dialled.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ServerIdentity {
+    /// SHA-256 fingerprint of the server's host key.
+    pub host_key: String,
+    pub username: String,
+}
+
+/// `dir/name`, without doubling a trailing `/`.
+pub fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_owned()
+    } else if dir.ends_with('/') {
+        format!("{dir}{name}")
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+/// The last component of `path`, ignoring trailing slashes; `/` for the
+/// root.
+pub fn file_name(path: &str) -> &str {
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.is_empty() && path.starts_with('/') {
+        return "/";
+    }
+    trimmed.rsplit('/').next().unwrap_or(trimmed)
+}
+
+/// The directory containing `path`: `/` for top-level absolute paths, `.`
+/// for a bare name.
+pub fn parent(path: &str) -> &str {
+    match path.trim_end_matches('/').rsplit_once('/') {
+        Some(("", _)) => "/",
+        Some((dir, _)) => dir,
+        None => ".",
+    }
+}
+
+/// Quote `path` for a POSIX shell on the server.
+pub(crate) fn shell_quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', r"'\''"))
+}
+
+/// Seconds since the epoch as a timestamp.
+pub(crate) fn timestamp(secs: u32) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(i64::from(secs), 0)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(kind: FileKind, permissions: u32) -> FileEntry {
+        FileEntry {
+            name: "x".into(),
+            path: "/x".into(),
+            kind,
+            size: 0,
+            permissions,
+            modified_at: None,
+            accessed_at: None,
+            uid: None,
+            gid: None,
+            owner: None,
+            group: None,
+            symlink_target: None,
+            target_kind: None,
+        }
+    }
+
+    #[test]
+    fn kinds_come_from_the_mode_type_bits() {
+        assert_eq!(FileKind::from_mode(0o100_644), FileKind::File);
+        assert_eq!(FileKind::from_mode(0o040_755), FileKind::Dir);
+        assert_eq!(FileKind::from_mode(0o120_777), FileKind::Symlink);
+        assert_eq!(FileKind::from_mode(0o020_620), FileKind::Other);
+        assert_eq!(FileKind::from_mode(0o644), FileKind::Other);
+    }
+
+    #[test]
+    fn mode_strings_look_like_ls() {
+        assert_eq!(entry(FileKind::Dir, 0o755).mode_string(), "drwxr-xr-x");
+        assert_eq!(entry(FileKind::File, 0o640).mode_string(), "-rw-r-----");
+        assert_eq!(entry(FileKind::Symlink, 0o777).mode_string(), "lrwxrwxrwx");
+    }
+
+    #[test]
+    fn symlinks_to_directories_count_as_directories() {
+        let mut link = entry(FileKind::Symlink, 0o777);
+        assert!(!link.is_dir());
+        link.target_kind = Some(FileKind::Dir);
+        assert!(link.is_dir());
+        assert!(link.is_symlink());
+    }
+
+    #[test]
+    fn paths_join_and_split_on_slashes() {
+        assert_eq!(join("/srv", "app"), "/srv/app");
+        assert_eq!(join("/", "etc"), "/etc");
+        assert_eq!(join("", "rel"), "rel");
+        assert_eq!(file_name("/srv/app/"), "app");
+        assert_eq!(file_name("notes.txt"), "notes.txt");
+        assert_eq!(file_name("/"), "/");
+        assert_eq!(parent("/srv/app/"), "/srv");
+        assert_eq!(parent("/etc"), "/");
+        assert_eq!(parent("notes.txt"), ".");
+        assert_eq!(shell_quote("it's here"), r"'it'\''s here'");
+    }
+}
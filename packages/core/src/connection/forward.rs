@@ -0,0 +1,425 @@
+//! Building blocks for SSH port forwarding.
+//!
+//! [`SshAdapter`](super::ssh::SshAdapter) implements
+//! [`PortForwardAdapter`](super::PortForwardAdapter) on top of these:
+//!
+//! - [`PortForwardHandle`] — what callers get back from starting a forward;
+//!   cheap to clone, exposes live byte counts.
+//! - [`RemoteForwardRegistry`] — maps server-side listeners (`ssh -R`) to the
+//!   local endpoint their connections should reach. Shared between the
+//!   adapter and its russh handler, which receives the incoming channels.
+//! - [`pipe`] and the SOCKS5 helpers used by dynamic forwards (`ssh -D`).
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+
+use crate::profile::types::PortForwardSpec;
+
+// ---------------------------------------------------------------------------
+// PortForwardHandle
+// ---------------------------------------------------------------------------
+
+/// Live counters for one port forward.
+#[derive(Debug, Default)]
+pub(crate) struct ForwardCounters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    active_connections: AtomicU64,
+    total_connections: AtomicU64,
+}
+
+/// Snapshot of a forward's traffic.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortForwardStats {
+    /// Bytes carried from the listening side to the far side.
+    pub bytes_sent: u64,
+    /// Bytes carried from the far side back to the listening side.
+    pub bytes_received: u64,
+    pub active_connections: u64,
+    pub total_connections: u64,
+}
+
+/// A running port forward.
+///
+/// Stop it with [`PortForwardAdapter::stop_forward`](super::PortForwardAdapter::stop_forward);
+/// dropping the handle does not stop the forward.
+#[derive(Debug, Clone)]
+pub struct PortForwardHandle {
+    pub id: String,
+    pub spec: PortForwardSpec,
+    /// Port actually listened on. Differs from the spec's `bind_port` when
+    /// that was `0`.
+    pub bound_port: u16,
+    counters: Arc<ForwardCounters>,
+    cancel: CancellationToken,
+}
+
+impl PortForwardHandle {
+    pub(crate) fn new(spec: PortForwardSpec, bound_port: u16) -> Self {
+        PortForwardHandle {
+            id: uuid::Uuid::new_v4().to_string(),
+            spec,
+            bound_port,
+            counters: Arc::new(ForwardCounters::default()),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Current byte and connection counts.
+    pub fn stats(&self) -> PortForwardStats {
+        PortForwardStats {
+            bytes_sent: self.counters.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.counters.bytes_received.load(Ordering::Relaxed),
+            active_connections: self.counters.active_connections.load(Ordering::Relaxed),
+            total_connections: self.counters.total_connections.load(Ordering::Relaxed),
+        }
+    }
+
+    /// `false` once the forward has been stopped.
+    pub fn is_active(&self) -> bool {
+        !self.cancel.is_cancelled()
+    }
+
+    pub(crate) fn counters(&self) -> &Arc<ForwardCounters> {
+        &self.counters
+    }
+
+    pub(crate) fn cancel_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    pub(crate) fn stop(&self) {
+        self.cancel.cancel();
+    }
+}
+
+// ---------------------------------------------------------------------------
+// RemoteForwardRegistry
+// ---------------------------------------------------------------------------
+
+/// Where connections arriving on a server-side listener should go.
+#[derive(Debug, Clone)]
+pub(crate) struct RemoteForwardTarget {
+    pub local_host: String,
+    pub local_port: u16,
+    pub counters: Arc<ForwardCounters>,
+    pub cancel: CancellationToken,
+}
+
+/// Server-side listeners registered with `tcpip-forward`, keyed by the
+/// address and port the server reports in `forwarded-tcpip` channel opens.
+#[derive(Debug, Default)]
+pub(crate) struct RemoteForwardRegistry {
+    targets: Mutex<HashMap<(String, u32), RemoteForwardTarget>>,
+}
+
+impl RemoteForwardRegistry {
+    pub fn insert(&self, address: &str, port: u32, target: RemoteForwardTarget) {
+        self.lock().insert((address.to_owned(), port), target);
+    }
+
+    pub fn remove(&self, address: &str, port: u32) {
+        self.lock().remove(&(address.to_owned(), port));
+    }
+
+    /// Look up the target for an incoming channel. Servers do not always echo
+    /// the bind address verbatim (e.g. `localhost` vs `127.0.0.1`), so fall
+    /// back to a port-only match.
+    pub fn lookup(&self, address: &str, port: u32) -> Option<RemoteForwardTarget> {
+        let targets = self.lock();
+        targets
+            .get(&(address.to_owned(), port))
+            .or_else(|| {
+                targets
+                    .iter()
+                    .find(|((_, p), _)| *p == port)
+                    .map(|(_, target)| target)
+            })
+            .cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(String, u32), RemoteForwardTarget>> {
+        self.targets.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Piping
+// ---------------------------------------------------------------------------
+
+/// Copy bytes in both directions between `near` (the side that accepted the
+/// connection) and `far` until both directions finish or `cancel` fires.
+pub(crate) async fn pipe<N, F>(
+    near: N,
+    far: F,
+    counters: &ForwardCounters,
+    cancel: &CancellationToken,
+) where
+    N: AsyncRead + AsyncWrite + Unpin,
+    F: AsyncRead + AsyncWrite + Unpin,
+{
+    counters.active_connections.fetch_add(1, Ordering::Relaxed);
+    counters.total_connections.fetch_add(1, Ordering::Relaxed);
+
+    let (mut near_read, mut near_write) = tokio::io::split(near);
+    let (mut far_read, mut far_write) = tokio::io::split(far);
+    let outbound = copy_counting(&mut near_read, &mut far_write, &counters.bytes_sent);
+    let inbound = copy_counting(&mut far_read, &mut near_write, &counters.bytes_received);
+
+    tokio::select! {
+        _ = async { tokio::join!(outbound, inbound) } => {}
+        _ = cancel.cancelled() => {}
+    }
+
+    counters.active_connections.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Copy until EOF, counting bytes, then shut the writer down so the peer sees
+/// the half-close.
+async fn copy_counting<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    counter: &AtomicU64,
+) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; 16 * 1024];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n]).await?;
+        counter.fetch_add(n as u64, Ordering::Relaxed);
+    }
+    writer.shutdown().await
+}
+
+// ---------------------------------------------------------------------------
+// SOCKS5 (RFC 1928), CONNECT only, no authentication
+// ---------------------------------------------------------------------------
+
+/// SOCKS5 reply codes used by the dynamic forwarder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum SocksReply {
+    Succeeded = 0x00,
+    HostUnreachable = 0x04,
+    CommandNotSupported = 0x07,
+    AddressTypeNotSupported = 0x08,
+}
+
+/// Run the SOCKS5 greeting and read the CONNECT request, returning the
+/// destination host and port. On protocol errors the appropriate failure
+/// reply has already been sent.
+pub(crate) async fn socks5_accept<S>(stream: &mut S) -> std::io::Result<(String, u16)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+    if header[0] != 0x05 {
+        return Err(invalid("not a SOCKS5 client"));
+    }
+    let mut methods = vec![0u8; header[1] as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&0x00) {
+        // No acceptable authentication method.
+        stream.write_all(&[0x05, 0xff]).await?;
+        return Err(invalid("SOCKS5 client does not offer no-auth"));
+    }
+    stream.write_all(&[0x05, 0x00]).await?;
+
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await?;
+    let [version, command, _reserved, address_type] = request;
+    if version != 0x05 {
+        return Err(invalid("bad SOCKS5 request version"));
+    }
+
+    let host = match address_type {
+        0x01 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets).await?;
+            Ipv4Addr::from(octets).to_string()
+        }
+        0x03 => {
+            let len = stream.read_u8().await? as usize;
+            let mut name = vec![0u8; len];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8(name).map_err(|_| invalid("SOCKS5 host name is not UTF-8"))?
+        }
+        0x04 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets).await?;
+            Ipv6Addr::from(octets).to_string()
+        }
+        _ => {
+            socks5_reply(stream, SocksReply::AddressTypeNotSupported).await?;
+            return Err(invalid("unsupported SOCKS5 address type"));
+        }
+    };
+    let port = stream.read_u16().await?;
+
+    if command != 0x01 {
+        socks5_reply(stream, SocksReply::CommandNotSupported).await?;
+        return Err(invalid("only SOCKS5 CONNECT is supported"));
+    }
+    Ok((host, port))
+}
+
+/// Send a SOCKS5 reply. The bound address is always reported as `0.0.0.0:0`;
+/// clients do not need it for CONNECT.
+pub(crate) async fn socks5_reply<S>(stream: &mut S, reply: SocksReply) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    stream
+        .write_all(&[0x05, reply as u8, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+fn invalid(msg: &'static str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn socks5_accept_parses_domain_connect() {
+        let (mut client, mut server) = tokio::io::duplex(256);
+        let accept = tokio::spawn(async move {
+            let target = socks5_accept(&mut server).await;
+            (target, server)
+        });
+
+        client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut choice = [0u8; 2];
+        client.read_exact(&mut choice).await.unwrap();
+        assert_eq!(choice, [0x05, 0x00]);
+
+        let mut request = vec![0x05, 0x01, 0x00, 0x03, 11];
+        request.extend_from_slice(b"example.com");
+        request.extend_from_slice(&443u16.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+
+        let (target, _server) = accept.await.unwrap();
+        assert_eq!(target.unwrap(), ("example.com".to_owned(), 443));
+    }
+
+    #[tokio::test]
+    async fn socks5_accept_rejects_bind_command() {
+        let (mut client, mut server) = tokio::io::duplex(256);
+        let accept = tokio::spawn(async move { socks5_accept(&mut server).await });
+
+        client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut choice = [0u8; 2];
+        client.read_exact(&mut choice).await.unwrap();
+        client
+            .write_all(&[0x05, 0x02, 0x00, 0x01, 10, 0, 0, 1, 0, 80])
+            .await
+            .unwrap();
+
+        let mut reply = [0u8; 10];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[1], SocksReply::CommandNotSupported as u8);
+        assert!(accept.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn socks5_accept_requires_no_auth_method() {
+        let (mut client, mut server) = tokio::io::duplex(256);
+        let accept = tokio::spawn(async move { socks5_accept(&mut server).await });
+
+        // Offer only username/password.
+        client.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+        let mut choice = [0u8; 2];
+        client.read_exact(&mut choice).await.unwrap();
+        assert_eq!(choice, [0x05, 0xff]);
+        assert!(accept.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn pipe_counts_bytes_both_ways() {
+        let (mut near_client, near) = tokio::io::duplex(64);
+        let (far, mut far_server) = tokio::io::duplex(64);
+        let handle = PortForwardHandle::new(
+            PortForwardSpec::Dynamic {
+                bind_address: "127.0.0.1".into(),
+                bind_port: 0,
+            },
+            1080,
+        );
+        let counters = Arc::clone(handle.counters());
+        let cancel = handle.cancel_token().clone();
+        let task = tokio::spawn(async move { pipe(near, far, &counters, &cancel).await });
+
+        near_client.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        far_server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        far_server.write_all(b"hi").await.unwrap();
+        let mut buf = [0u8; 2];
+        near_client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hi");
+
+        drop(near_client);
+        drop(far_server);
+        task.await.unwrap();
+
+        let stats = handle.stats();
+        assert_eq!(stats.bytes_sent, 5);
+        assert_eq!(stats.bytes_received, 2);
+        assert_eq!(stats.total_connections, 1);
+        assert_eq!(stats.active_connections, 0);
+    }
+
+    #[test]
+    fn registry_falls_back_to_port_match() {
+        let registry = RemoteForwardRegistry::default();
+        let target = RemoteForwardTarget {
+            local_host: "127.0.0.1".into(),
+            local_port: 3000,
+            counters: Arc::new(ForwardCounters::default()),
+            cancel: CancellationToken::new(),
+        };
+        registry.insert("localhost", 8080, target);
+
+        assert!(registry.lookup("localhost", 8080).is_some());
+        assert!(registry.lookup("127.0.0.1", 8080).is_some());
+        assert!(registry.lookup("localhost", 9090).is_none());
+
+        registry.remove("localhost", 8080);
+        assert!(registry.lookup("localhost", 8080).is_none());
+    }
+
+    #[test]
+    fn stopping_a_handle_marks_it_inactive() {
+        let handle = PortForwardHandle::new(
+            PortForwardSpec::Dynamic {
+                bind_address: "127.0.0.1".into(),
+                bind_port: 0,
+            },
+            1080,
+        );
+        let clone = handle.clone();
+        assert!(clone.is_active());
+        handle.stop();
+        assert!(!clone.is_active());
+    }
+}
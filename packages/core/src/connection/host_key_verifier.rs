@@ -0,0 +1,279 @@
+//! Interactive host-key confirmation.
+//!
+//! A [`HostKeyVerifier`] is consulted by
+//! [`SshAdapter`](super::ssh::SshAdapter) whenever the server presents a key
+//! that the active [`HostKeyPolicy`](crate::profile::types::HostKeyPolicy)
+//! does not already trust. The verifier receives a [`HostKeyInfo`] with the
+//! fingerprints and randomart a user needs to make the decision, and answers
+//! asynchronously — typically after the UI has shown a confirmation prompt.
+//!
+//! Keys that conflict with an existing trust entry are never offered to the
+//! verifier; they always fail with
+//! [`ConnectionError::HostKeyMismatch`](super::ConnectionError::HostKeyMismatch).
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+
+use super::known_hosts::sha256_fingerprint;
+
+// ---------------------------------------------------------------------------
+// HostKeyInfo
+// ---------------------------------------------------------------------------
+
+/// Everything a user needs to decide whether to trust a host key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostKeyInfo {
+    pub host: String,
+    pub port: u16,
+    /// Key algorithm as it appears on the wire (e.g. `ssh-ed25519`).
+    pub algorithm: String,
+    /// Key size in bits, when it can be determined.
+    pub bits: Option<u32>,
+    /// `SHA256:<base64>` fingerprint, as printed by modern OpenSSH.
+    pub sha256_fingerprint: String,
+    /// `MD5:aa:bb:…` fingerprint, as printed by legacy OpenSSH.
+    pub md5_fingerprint: String,
+    /// OpenSSH "drunken bishop" visualisation of the SHA-256 fingerprint.
+    pub randomart: String,
+    /// Base64-encoded wire-format public key.
+    pub public_key: String,
+}
+
+impl HostKeyInfo {
+    /// Build the info for a wire-format public key presented by `host:port`.
+    pub fn new(host: &str, port: u16, key_bytes: &[u8]) -> Self {
+        let algorithm = wire_string(key_bytes, 0)
+            .and_then(|(s, _)| std::str::from_utf8(s).ok())
+            .unwrap_or("unknown")
+            .to_owned();
+        let bits = key_bits(key_bytes);
+        let digest = ring::digest::digest(&ring::digest::SHA256, key_bytes);
+        let title = randomart_title(&algorithm, bits);
+        HostKeyInfo {
+            host: host.to_owned(),
+            port,
+            algorithm,
+            bits,
+            sha256_fingerprint: sha256_fingerprint(key_bytes),
+            md5_fingerprint: md5_fingerprint(key_bytes),
+            randomart: randomart(&title, "SHA256", digest.as_ref()),
+            public_key: BASE64.encode(key_bytes),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// HostKeyDecision / HostKeyVerifier
+// ---------------------------------------------------------------------------
+
+/// The user's answer to a host-key prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostKeyDecision {
+    /// Trust the key for this connection only.
+    AcceptOnce,
+    /// Trust the key and record it so future connections skip the prompt.
+    AcceptAndRemember,
+    /// Abort the connection.
+    Reject,
+}
+
+/// Asynchronous callback that decides whether to trust an unknown host key.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait HostKeyVerifier: Send + Sync {
+    async fn verify(&self, info: HostKeyInfo) -> HostKeyDecision;
+}
+
+// ---------------------------------------------------------------------------
+// Fingerprint helpers
+// ---------------------------------------------------------------------------
+
+/// OpenSSH-style MD5 fingerprint (`MD5:aa:bb:…`) of a wire-format public key.
+pub fn md5_fingerprint(key_bytes: &[u8]) -> String {
+    let digest = md5::compute(key_bytes);
+    let hex: Vec<String> = digest.0.iter().map(|b| format!("{b:02x}")).collect();
+    format!("MD5:{}", hex.join(":"))
+}
+
+const FIELD_WIDTH: usize = 17;
+const FIELD_HEIGHT: usize = 9;
+const AUGMENTATION: &[u8] = b" .o+=*BOX@%&#/^SE";
+
+/// Render `digest` as OpenSSH randomart, framed by `title` on top and
+/// `[hash_name]` at the bottom.
+pub fn randomart(title: &str, hash_name: &str, digest: &[u8]) -> String {
+    // Values 0..=14 are visit counts; the last two symbols mark start/end.
+    let len = AUGMENTATION.len() - 1;
+    let mut field = [[0usize; FIELD_HEIGHT]; FIELD_WIDTH];
+    let mut x = FIELD_WIDTH / 2;
+    let mut y = FIELD_HEIGHT / 2;
+
+    for &byte in digest {
+        let mut input = byte;
+        for _ in 0..4 {
+            x = if input & 0x1 != 0 {
+                (x + 1).min(FIELD_WIDTH - 1)
+            } else {
+                x.saturating_sub(1)
+            };
+            y = if input & 0x2 != 0 {
+                (y + 1).min(FIELD_HEIGHT - 1)
+            } else {
+                y.saturating_sub(1)
+            };
+            if field[x][y] < len - 2 {
+                field[x][y] += 1;
+            }
+            input >>= 2;
+        }
+    }
+    field[FIELD_WIDTH / 2][FIELD_HEIGHT / 2] = len - 1;
+    field[x][y] = len;
+
+    let mut out = String::new();
+    out.push_str(&frame_line(&format!("[{title}]")));
+    out.push('\n');
+    for row in 0..FIELD_HEIGHT {
+        out.push('|');
+        for column in &field {
+            out.push(AUGMENTATION[column[row].min(len)] as char);
+        }
+        out.push_str("|\n");
+    }
+    out.push_str(&frame_line(&format!("[{hash_name}]")));
+    out
+}
+
+/// `+---[label]---+`, with `label` centred the way OpenSSH does it.
+fn frame_line(label: &str) -> String {
+    let label: String = label.chars().take(FIELD_WIDTH).collect();
+    let label_len = label.chars().count();
+    let left = (FIELD_WIDTH - label_len) / 2;
+    let right = FIELD_WIDTH - label_len - left;
+    format!("+{}{label}{}+", "-".repeat(left), "-".repeat(right))
+}
+
+/// `ED25519 256`, `RSA 3072`, … — the title OpenSSH puts above randomart.
+fn randomart_title(algorithm: &str, bits: Option<u32>) -> String {
+    let short = match algorithm {
+        "ssh-ed25519" => "ED25519",
+        "ssh-rsa" => "RSA",
+        "ssh-dss" => "DSA",
+        a if a.starts_with("ecdsa-sha2-") => "ECDSA",
+        a => a,
+    };
+    match bits {
+        Some(bits) => format!("{short} {bits}"),
+        None => short.to_owned(),
+    }
+}
+
+/// Key size in bits, derived from the wire encoding.
+fn key_bits(key_bytes: &[u8]) -> Option<u32> {
+    let (algorithm, offset) = wire_string(key_bytes, 0)?;
+    match algorithm {
+        b"ssh-ed25519" => Some(256),
+        b"ecdsa-sha2-nistp256" => Some(256),
+        b"ecdsa-sha2-nistp384" => Some(384),
+        b"ecdsa-sha2-nistp521" => Some(521),
+        b"ssh-rsa" => {
+            // string "ssh-rsa" || mpint e || mpint n
+            let (_, offset) = wire_string(key_bytes, offset)?;
+            let (modulus, _) = wire_string(key_bytes, offset)?;
+            let modulus = match modulus.iter().position(|&b| b != 0) {
+                Some(start) => &modulus[start..],
+                None => return Some(0),
+            };
+            let leading = modulus[0].leading_zeros();
+            Some(modulus.len() as u32 * 8 - leading)
+        }
+        _ => None,
+    }
+}
+
+/// Read one SSH wire `string` at `offset`, returning it and the next offset.
+fn wire_string(buf: &[u8], offset: usize) -> Option<(&[u8], usize)> {
+    let len_bytes = buf.get(offset..offset + 4)?;
+    let len = u32::from_be_bytes(len_bytes.try_into().ok()?) as usize;
+    let start = offset + 4;
+    let value = buf.get(start..start + len)?;
+    Some((value, start + len))
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wire(parts: &[&[u8]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for part in parts {
+            out.extend_from_slice(&(part.len() as u32).to_be_bytes());
+            out.extend_from_slice(part);
+        }
+        out
+    }
+
+    #[test]
+    fn md5_fingerprint_is_colon_separated_hex() {
+        // md5("") = d41d8cd98f00b204e9800998ecf8427e
+        assert_eq!(
+            md5_fingerprint(b""),
+            "MD5:d4:1d:8c:d9:8f:00:b2:04:e9:80:09:98:ec:f8:42:7e"
+        );
+    }
+
+    #[test]
+    fn randomart_has_openssh_shape() {
+        let art = randomart("ED25519 256", "SHA256", &[0u8; 32]);
+        let lines: Vec<&str> = art.lines().collect();
+        assert_eq!(lines.len(), FIELD_HEIGHT + 2);
+        assert_eq!(lines[0], "+--[ED25519 256]--+");
+        assert_eq!(lines[FIELD_HEIGHT + 1], "+----[SHA256]-----+");
+        let body = lines[1..=FIELD_HEIGHT].concat();
+        for line in &lines[1..=FIELD_HEIGHT] {
+            assert_eq!(line.chars().count(), FIELD_WIDTH + 2);
+        }
+        assert_eq!(body.matches('S').count(), 1, "start marker");
+        assert_eq!(body.matches('E').count(), 1, "end marker");
+    }
+
+    #[test]
+    fn randomart_all_zero_digest_walks_to_top_left() {
+        // Every step moves up-left, so the bishop ends in the corner.
+        let art = randomart("T", "H", &[0u8; 4]);
+        let first_row = art.lines().nth(1).unwrap();
+        assert_eq!(&first_row[..2], "|E");
+    }
+
+    #[test]
+    fn key_bits_reads_rsa_modulus() {
+        let mut modulus = vec![0x00, 0x80];
+        modulus.extend(std::iter::repeat_n(0xff, 255));
+        let key = wire(&[b"ssh-rsa", &[0x01, 0x00, 0x01], &modulus]);
+        assert_eq!(key_bits(&key), Some(2048));
+    }
+
+    #[test]
+    fn host_key_info_new_populates_all_fields() {
+        let key = wire(&[b"ssh-ed25519", &[7u8; 32]]);
+        let info = HostKeyInfo::new("host.example.com", 2222, &key);
+        assert_eq!(info.algorithm, "ssh-ed25519");
+        assert_eq!(info.bits, Some(256));
+        assert_eq!(info.sha256_fingerprint, sha256_fingerprint(&key));
+        assert!(info.md5_fingerprint.starts_with("MD5:"));
+        assert!(info.randomart.starts_with("+--[ED25519 256]--+"));
+        assert_eq!(BASE64.decode(&info.public_key).unwrap(), key);
+    }
+
+    #[test]
+    fn host_key_decision_serializes_snake_case() {
+        let json = serde_json::to_string(&HostKeyDecision::AcceptAndRemember).unwrap();
+        assert_eq!(json, "\"accept_and_remember\"");
+    }
+}
@@ -0,0 +1,189 @@
+//! ProxyJump support.
+//!
+//! [`SshSettings::jump_host_ids`](crate::profile::types::SshSettings) lists
+//! the profiles to hop through, in order. [`SshAdapter`](super::ssh::SshAdapter)
+//! asks a [`JumpHostResolver`] for each hop's profile and credential, since
+//! neither is available from the target profile alone.
+//!
+//! [`VaultJumpHostResolver`] looks both up in the vault, which is what the
+//! desktop app uses; tests and embedders can supply their own resolver.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use secrecy::SecretString;
+use tokio::sync::Mutex;
+
+use crate::profile::manager::ProfileManager;
+use crate::profile::types::{ConnectionProfile, VaultPayload};
+
+use super::keyboard_interactive::CredentialPromptResponder;
+use super::{ConnectionError, Credential};
+
+// ---------------------------------------------------------------------------
+// JumpHostResolver
+// ---------------------------------------------------------------------------
+
+/// Resolves a jump-host profile ID to the profile and credential to use for
+/// that hop.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait JumpHostResolver: Send + Sync {
+    async fn resolve(
+        &self,
+        profile_id: &str,
+    ) -> Result<(ConnectionProfile, Credential), ConnectionError>;
+}
+
+/// Resolver backed by the vault.
+///
+/// A hop's `credential_id` may point at a `Password` or an `SshKey` item;
+/// hops without a credential authenticate through the SSH agent. Passwords
+/// with a TOTP seed try keyboard-interactive first, answering both the
+/// password and the one-time-code prompts, then plain password.
+pub struct VaultJumpHostResolver {
+    manager: Arc<Mutex<ProfileManager>>,
+}
+
+impl VaultJumpHostResolver {
+    pub fn new(manager: Arc<Mutex<ProfileManager>>) -> Self {
+        VaultJumpHostResolver { manager }
+    }
+}
+
+#[async_trait]
+impl JumpHostResolver for VaultJumpHostResolver {
+    async fn resolve(
+        &self,
+        profile_id: &str,
+    ) -> Result<(ConnectionProfile, Credential), ConnectionError> {
+        let unresolvable = |err: String| {
+            ConnectionError::Protocol(format!("cannot resolve jump host {profile_id}: {err}"))
+        };
+
+        let manager = self.manager.lock().await;
+        let profile = manager
+            .get_profile(profile_id)
+            .map_err(|e| unresolvable(e.to_string()))?;
+
+        let credential = match &profile.credential_id {
+            None => Credential::SshAgent,
+            Some(id) => match manager.get(id).map_err(|e| unresolvable(e.to_string()))? {
+                VaultPayload::Password(p) if p.totp_secret.is_some() => {
+                    let responder = CredentialPromptResponder::from_password(&p)?;
+                    Credential::Chain(vec![
+                        Credential::KeyboardInteractive(Arc::new(responder)),
+                        Credential::Password(SecretString::new(p.password)),
+                    ])
+                }
+                VaultPayload::Password(p) => Credential::Password(SecretString::new(p.password)),
+                VaultPayload::SshKey(k) => Credential::PublicKey {
+                    private_key_pem: SecretString::new(k.private_key_pem),
+                    passphrase: None,
+                    certificate: k.certificate,
+                },
+                other => {
+                    return Err(unresolvable(format!(
+                        "credential {id} is a {}, not a password or SSH key",
+                        other.type_name()
+                    )))
+                }
+            },
+        };
+
+        Ok((profile, credential))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use secrecy::ExposeSecret;
+
+    use super::*;
+    use crate::crypto::vault::VaultFile;
+    use crate::profile::types::{KnownHost, Password, SshKey, SshKeyType};
+
+    fn resolver_with(manager: ProfileManager) -> VaultJumpHostResolver {
+        VaultJumpHostResolver::new(Arc::new(Mutex::new(manager)))
+    }
+
+    fn manager() -> ProfileManager {
+        ProfileManager::new(VaultFile::new(), [7u8; 32])
+    }
+
+    #[tokio::test]
+    async fn resolves_password_credential() {
+        let mut m = manager();
+        let password = Password::new("bastion pw", "ops", "s3cret");
+        let mut bastion = ConnectionProfile::new_ssh("bastion", "bastion.example.com", 22, "ops");
+        bastion.credential_id = Some(m.add_password(password).unwrap());
+        let id = m.add_profile(bastion.clone()).unwrap();
+
+        let (profile, credential) = resolver_with(m).resolve(&id).await.unwrap();
+        assert_eq!(profile, bastion);
+        match credential {
+            Credential::Password(secret) => assert_eq!(secret.expose_secret(), "s3cret"),
+            other => panic!("expected password, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn password_with_totp_seed_uses_keyboard_interactive() {
+        let mut m = manager();
+        let mut password = Password::new("mfa", "ops", "s3cret");
+        password.totp_secret = Some("GEZDGNBVGY3TQOJQ".to_owned());
+        let mut bastion = ConnectionProfile::new_ssh("bastion", "mfa.example.com", 22, "ops");
+        bastion.credential_id = Some(m.add_password(password).unwrap());
+        let id = m.add_profile(bastion).unwrap();
+
+        let (_, credential) = resolver_with(m).resolve(&id).await.unwrap();
+        match credential {
+            Credential::Chain(steps) => {
+                assert!(matches!(steps[0], Credential::KeyboardInteractive(_)));
+                assert!(matches!(steps[1], Credential::Password(_)));
+            }
+            other => panic!("expected a chain, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_ssh_key_and_agent_credentials() {
+        let mut m = manager();
+        let key = SshKey::new("key", "PEM", "ssh-ed25519 AAAA", SshKeyType::Ed25519);
+        let mut keyed = ConnectionProfile::new_ssh("keyed", "a.example.com", 22, "ops");
+        keyed.credential_id = Some(m.add_ssh_key(key).unwrap());
+        let keyed_id = m.add_profile(keyed).unwrap();
+        let agent_id = m
+            .add_profile(ConnectionProfile::new_ssh(
+                "agent",
+                "b.example.com",
+                22,
+                "ops",
+            ))
+            .unwrap();
+
+        let resolver = resolver_with(m);
+        let (_, credential) = resolver.resolve(&keyed_id).await.unwrap();
+        assert!(matches!(credential, Credential::PublicKey { .. }));
+        let (_, credential) = resolver.resolve(&agent_id).await.unwrap();
+        assert!(matches!(credential, Credential::SshAgent));
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_profile_and_wrong_credential_type() {
+        let mut m = manager();
+        let known = KnownHost::new("x", 22, "ssh-ed25519", "AAAA");
+        let mut odd = ConnectionProfile::new_ssh("odd", "c.example.com", 22, "ops");
+        odd.credential_id = Some(m.add_known_host(known).unwrap());
+        let odd_id = m.add_profile(odd).unwrap();
+
+        let resolver = resolver_with(m);
+        assert!(resolver.resolve("missing").await.is_err());
+        let err = resolver.resolve(&odd_id).await.unwrap_err();
+        assert!(err.to_string().contains("not a password or SSH key"));
+    }
+}
@@ -0,0 +1,389 @@
+//! Keyboard-interactive authentication (RFC 4256).
+//!
+//! Servers that use PAM-driven MFA (Duo, Google Authenticator, RSA) ask
+//! questions through keyboard-interactive rather than the password method.
+//! [`Credential::KeyboardInteractive`](super::Credential::KeyboardInteractive)
+//! hands each round of questions to a [`PromptResponder`]:
+//!
+//! - the desktop app implements one that shows the prompts to the user;
+//! - [`CredentialPromptResponder`] answers password and one-time-code
+//!   prompts on its own, from a vault [`Password`] and its TOTP seed, and
+//!   passes anything it does not recognise to an optional fallback.
+//!
+//! [`Totp`] implements RFC 6238 codes for the latter.
+
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::profile::types::Password;
+
+use super::ConnectionError;
+
+// ---------------------------------------------------------------------------
+// Challenge / PromptResponder
+// ---------------------------------------------------------------------------
+
+/// One question in a keyboard-interactive round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyboardInteractivePrompt {
+    pub prompt: String,
+    /// `false` for secrets the UI should mask.
+    pub echo: bool,
+}
+
+/// One round of questions from the server (`SSH_MSG_USERAUTH_INFO_REQUEST`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyboardInteractiveChallenge {
+    pub name: String,
+    pub instructions: String,
+    pub prompts: Vec<KeyboardInteractivePrompt>,
+}
+
+/// Answers keyboard-interactive prompts.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait PromptResponder: Send + Sync {
+    /// Return one answer per prompt, in order. Return
+    /// [`ConnectionError::Cancelled`] if the user dismisses the prompt.
+    async fn respond(
+        &self,
+        challenge: &KeyboardInteractiveChallenge,
+    ) -> Result<Vec<SecretString>, ConnectionError>;
+}
+
+// ---------------------------------------------------------------------------
+// Totp
+// ---------------------------------------------------------------------------
+
+/// RFC 6238 time-based one-time password generator (HMAC-SHA1, 30 s steps,
+/// 6 digits — what authenticator apps use).
+#[derive(Clone)]
+pub struct Totp {
+    key: SecretString,
+}
+
+impl std::fmt::Debug for Totp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Totp([redacted])")
+    }
+}
+
+const TOTP_STEP_SECS: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+
+impl Totp {
+    /// Parse a base32 seed as shown in `otpauth://` URIs. Case, spaces and
+    /// `=` padding are ignored.
+    pub fn from_base32(seed: &str) -> Result<Self, ConnectionError> {
+        let normalized: String = seed
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '=')
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        match base32_decode(&normalized) {
+            Some(key) if !key.is_empty() => Ok(Totp {
+                key: SecretString::new(normalized),
+            }),
+            _ => Err(ConnectionError::AuthFailed {
+                reason: "TOTP secret is not valid base32".to_owned(),
+                attempts: Vec::new(),
+            }),
+        }
+    }
+
+    /// The code for the current time.
+    pub fn now(&self) -> String {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.code_at(secs)
+    }
+
+    /// The code for `unix_secs` seconds since the epoch.
+    pub fn code_at(&self, unix_secs: u64) -> String {
+        // Validated in `from_base32`.
+        let key = base32_decode(self.key.expose_secret()).unwrap_or_default();
+        let counter = unix_secs / TOTP_STEP_SECS;
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &key);
+        let tag = ring::hmac::sign(&key, &counter.to_be_bytes());
+        let mac = tag.as_ref();
+
+        // RFC 4226 §5.3 dynamic truncation.
+        let offset = usize::from(mac[mac.len() - 1] & 0x0f);
+        let binary = u32::from_be_bytes([
+            mac[offset] & 0x7f,
+            mac[offset + 1],
+            mac[offset + 2],
+            mac[offset + 3],
+        ]);
+        let code = binary % 10u32.pow(TOTP_DIGITS);
+        format!("{code:0width$}", width = TOTP_DIGITS as usize)
+    }
+}
+
+/// Decode unpadded RFC 4648 base32 (upper case).
+fn base32_decode(input: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in input.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | u32::from(value);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
+
+// ---------------------------------------------------------------------------
+// CredentialPromptResponder
+// ---------------------------------------------------------------------------
+
+/// What a prompt is asking for, judged from its text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PromptKind {
+    OneTimeCode,
+    Password,
+    Other,
+}
+
+fn classify(prompt: &str) -> PromptKind {
+    const OTP_HINTS: &[&str] = &[
+        "verification code",
+        "one-time",
+        "one time",
+        "otp",
+        "totp",
+        "passcode",
+        "token",
+        "authenticator",
+        "2fa",
+        "mfa",
+    ];
+    let prompt = prompt.to_lowercase();
+    if OTP_HINTS.iter().any(|hint| prompt.contains(hint)) {
+        PromptKind::OneTimeCode
+    } else if prompt.contains("password") {
+        PromptKind::Password
+    } else {
+        PromptKind::Other
+    }
+}
+
+/// Answers password prompts with a stored password and one-time-code prompts
+/// with a [`Totp`] code. Rounds containing any other prompt go to the
+/// fallback responder, if there is one.
+#[derive(Clone, Default)]
+pub struct CredentialPromptResponder {
+    password: Option<SecretString>,
+    totp: Option<Totp>,
+    fallback: Option<Arc<dyn PromptResponder>>,
+}
+
+impl CredentialPromptResponder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer with a vault password item and its TOTP seed, if it has one.
+    pub fn from_password(item: &Password) -> Result<Self, ConnectionError> {
+        let responder = Self::new().with_password(SecretString::new(item.password.clone()));
+        match &item.totp_secret {
+            Some(seed) => Ok(responder.with_totp(Totp::from_base32(seed)?)),
+            None => Ok(responder),
+        }
+    }
+
+    pub fn with_password(mut self, password: SecretString) -> Self {
+        self.password = Some(password);
+        self
+    }
+
+    pub fn with_totp(mut self, totp: Totp) -> Self {
+        self.totp = Some(totp);
+        self
+    }
+
+    /// Ask `fallback` whenever a round has a prompt this responder cannot
+    /// answer (e.g. Duo's "Passcode or option (1-3)" menu without a seed).
+    pub fn with_fallback(mut self, fallback: Arc<dyn PromptResponder>) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    fn answer(&self, prompt: &KeyboardInteractivePrompt) -> Option<SecretString> {
+        match classify(&prompt.prompt) {
+            PromptKind::OneTimeCode => self.totp.as_ref().map(|t| SecretString::new(t.now())),
+            PromptKind::Password => self.password.clone(),
+            PromptKind::Other => None,
+        }
+    }
+}
+
+#[async_trait]
+impl PromptResponder for CredentialPromptResponder {
+    async fn respond(
+        &self,
+        challenge: &KeyboardInteractiveChallenge,
+    ) -> Result<Vec<SecretString>, ConnectionError> {
+        let answers: Option<Vec<SecretString>> =
+            challenge.prompts.iter().map(|p| self.answer(p)).collect();
+        match (answers, &self.fallback) {
+            (Some(answers), _) => Ok(answers),
+            (None, Some(fallback)) => fallback.respond(challenge).await,
+            (None, None) => {
+                let unanswered = challenge
+                    .prompts
+                    .iter()
+                    .find(|p| self.answer(p).is_none())
+                    .map(|p| p.prompt.trim())
+                    .unwrap_or_default();
+                Err(ConnectionError::AuthFailed {
+                    reason: format!("no stored answer for prompt {unanswered:?}"),
+                    attempts: Vec::new(),
+                })
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RFC 6238 appendix B seed ("12345678901234567890"), base32-encoded.
+    const RFC_SEED: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    fn prompt(text: &str) -> KeyboardInteractivePrompt {
+        KeyboardInteractivePrompt {
+            prompt: text.to_owned(),
+            echo: false,
+        }
+    }
+
+    fn challenge(prompts: &[&str]) -> KeyboardInteractiveChallenge {
+        KeyboardInteractiveChallenge {
+            prompts: prompts.iter().map(|p| prompt(p)).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn exposed(answers: &[SecretString]) -> Vec<&str> {
+        answers.iter().map(|a| a.expose_secret().as_str()).collect()
+    }
+
+    #[test]
+    fn base32_decodes_rfc4648_vectors() {
+        assert_eq!(base32_decode("").unwrap(), b"");
+        assert_eq!(base32_decode("MY").unwrap(), b"f");
+        assert_eq!(base32_decode("MZXW6YTBOI").unwrap(), b"foobar");
+        assert!(base32_decode("MZ1W").is_none());
+    }
+
+    #[test]
+    fn totp_matches_rfc6238_test_vectors() {
+        let totp = Totp::from_base32(RFC_SEED).unwrap();
+        // Appendix B lists 8-digit codes; 6-digit codes are their suffixes.
+        assert_eq!(totp.code_at(59), "287082");
+        assert_eq!(totp.code_at(1_111_111_109), "081804");
+        assert_eq!(totp.code_at(1_234_567_890), "005924");
+        assert_eq!(totp.code_at(20_000_000_000), "353130");
+    }
+
+    #[test]
+    fn totp_seed_formatting_is_forgiving() {
+        let spaced = Totp::from_base32("gezd gnbv gy3t qojq gezd gnbv gy3t qojq====").unwrap();
+        assert_eq!(spaced.code_at(59), "287082");
+        assert!(Totp::from_base32("not base32!").is_err());
+        assert!(Totp::from_base32("").is_err());
+        assert!(!format!("{spaced:?}").contains("GEZD"));
+    }
+
+    #[test]
+    fn prompts_are_classified_by_their_text() {
+        assert_eq!(classify("Password: "), PromptKind::Password);
+        assert_eq!(classify("alice@host's password:"), PromptKind::Password);
+        assert_eq!(classify("Verification code: "), PromptKind::OneTimeCode);
+        assert_eq!(
+            classify("Passcode or option (1-3): "),
+            PromptKind::OneTimeCode
+        );
+        assert_eq!(classify("Enter your OTP:"), PromptKind::OneTimeCode);
+        assert_eq!(classify("Continue? [y/n]"), PromptKind::Other);
+    }
+
+    #[tokio::test]
+    async fn answers_password_and_code_prompts_from_the_vault() {
+        let mut item = Password::new("bastion", "ops", "s3cret");
+        item.totp_secret = Some(RFC_SEED.to_owned());
+        let responder = CredentialPromptResponder::from_password(&item).unwrap();
+
+        let answers = responder
+            .respond(&challenge(&["Password: ", "Verification code: "]))
+            .await
+            .unwrap();
+        let answers = exposed(&answers);
+        assert_eq!(answers[0], "s3cret");
+        assert_eq!(answers[1].len(), 6);
+        assert!(answers[1].chars().all(|c| c.is_ascii_digit()));
+
+        // Rounds without prompts need no answers.
+        assert!(responder.respond(&challenge(&[])).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_prompts_go_to_the_fallback() {
+        let mut fallback = MockPromptResponder::new();
+        fallback
+            .expect_respond()
+            .withf(|c| c.prompts.len() == 2)
+            .times(1)
+            .returning(|_| {
+                Ok(vec![
+                    SecretString::new("pw".to_owned()),
+                    SecretString::new("push".to_owned()),
+                ])
+            });
+        let responder = CredentialPromptResponder::new()
+            .with_password(SecretString::new("pw".to_owned()))
+            .with_fallback(Arc::new(fallback));
+
+        let answers = responder
+            .respond(&challenge(&["Password: ", "Duo option: "]))
+            .await
+            .unwrap();
+        assert_eq!(exposed(&answers), ["pw", "push"]);
+    }
+
+    #[tokio::test]
+    async fn unanswerable_prompt_without_fallback_fails() {
+        let responder =
+            CredentialPromptResponder::new().with_password(SecretString::new("pw".to_owned()));
+        let err = responder
+            .respond(&challenge(&["Password: ", "Verification code: "]))
+            .await
+            .unwrap_err();
+        match err {
+            ConnectionError::AuthFailed { reason, .. } => {
+                assert!(reason.contains("Verification code"), "got {reason}")
+            }
+            other => panic!("expected AuthFailed, got {other:?}"),
+        }
+    }
+}
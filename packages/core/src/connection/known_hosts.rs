@@ -0,0 +1,395 @@
+//! Persistent host-key trust stores.
+//!
+//! [`SshAdapter`](super::ssh::SshAdapter) consults a [`KnownHostsStore`] when
+//! verifying the server's host key under
+//! [`HostKeyPolicy::StrictFirstConnect`](crate::profile::types::HostKeyPolicy).
+//! Three implementations are provided:
+//!
+//! - [`MemoryKnownHostsStore`] — process-local; the default when no store is
+//!   supplied. Trust is forgotten when the adapter is dropped.
+//! - [`VaultKnownHostsStore`] — stores each entry as a
+//!   [`VaultPayload::KnownHost`](crate::profile::types::VaultPayload) item, so
+//!   trust follows the user across machines via `SyncEngine`.
+//! - [`FileKnownHostsStore`] — a JSON file in the platform data directory, for
+//!   callers that do not have an unlocked vault.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use base64::{
+    engine::general_purpose::{STANDARD as BASE64, STANDARD_NO_PAD as BASE64_NO_PAD},
+    Engine,
+};
+use chrono::Utc;
+use tokio::sync::Mutex;
+
+use crate::profile::manager::{ProfileError, ProfileManager};
+use crate::profile::types::KnownHost;
+
+use super::ConnectionError;
+
+// ---------------------------------------------------------------------------
+// Fingerprints
+// ---------------------------------------------------------------------------
+
+/// OpenSSH-style SHA-256 fingerprint (`SHA256:<base64, no padding>`) of a
+/// wire-format public key.
+pub fn sha256_fingerprint(key_bytes: &[u8]) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, key_bytes);
+    format!("SHA256:{}", BASE64_NO_PAD.encode(digest.as_ref()))
+}
+
+/// SHA-256 fingerprint of a stored entry.
+///
+/// Falls back to the raw base64 key if the stored value is not valid base64,
+/// so a corrupt entry still produces a useful diagnostic.
+pub fn known_host_fingerprint(entry: &KnownHost) -> String {
+    match BASE64.decode(&entry.public_key) {
+        Ok(bytes) => sha256_fingerprint(&bytes),
+        Err(_) => entry.public_key.clone(),
+    }
+}
+
+fn store_key(host: &str, port: u16) -> String {
+    format!("{host}:{port}")
+}
+
+// ---------------------------------------------------------------------------
+// Trait
+// ---------------------------------------------------------------------------
+
+/// Storage for trusted SSH host keys, keyed by `host:port`.
+///
+/// Two SSH daemons on the same host but different ports are independent
+/// servers and have independent entries.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait KnownHostsStore: Send + Sync {
+    /// Return the trusted key for `host:port`, or `None` if the host is unknown.
+    async fn get(&self, host: &str, port: u16) -> Result<Option<KnownHost>, ConnectionError>;
+
+    /// Trust `entry`, replacing any key previously stored for the same `host:port`.
+    async fn insert(&self, entry: KnownHost) -> Result<(), ConnectionError>;
+
+    /// Forget the key for `host:port`. Returns `true` if an entry was removed.
+    async fn remove(&self, host: &str, port: u16) -> Result<bool, ConnectionError>;
+
+    /// Return every trusted entry (e.g., for a "known hosts" settings page).
+    async fn list(&self) -> Result<Vec<KnownHost>, ConnectionError>;
+}
+
+// ---------------------------------------------------------------------------
+// In-memory implementation
+// ---------------------------------------------------------------------------
+
+/// Process-local store. Trust lasts only as long as the store itself.
+#[derive(Default)]
+pub struct MemoryKnownHostsStore {
+    entries: Mutex<HashMap<String, KnownHost>>,
+}
+
+impl MemoryKnownHostsStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl KnownHostsStore for MemoryKnownHostsStore {
+    async fn get(&self, host: &str, port: u16) -> Result<Option<KnownHost>, ConnectionError> {
+        Ok(self
+            .entries
+            .lock()
+            .await
+            .get(&store_key(host, port))
+            .cloned())
+    }
+
+    async fn insert(&self, entry: KnownHost) -> Result<(), ConnectionError> {
+        let key = store_key(&entry.host, entry.port);
+        self.entries.lock().await.insert(key, entry);
+        Ok(())
+    }
+
+    async fn remove(&self, host: &str, port: u16) -> Result<bool, ConnectionError> {
+        Ok(self
+            .entries
+            .lock()
+            .await
+            .remove(&store_key(host, port))
+            .is_some())
+    }
+
+    async fn list(&self) -> Result<Vec<KnownHost>, ConnectionError> {
+        Ok(self.entries.lock().await.values().cloned().collect())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Vault-backed implementation
+// ---------------------------------------------------------------------------
+
+impl From<ProfileError> for ConnectionError {
+    fn from(err: ProfileError) -> Self {
+        ConnectionError::KnownHosts(err.to_string())
+    }
+}
+
+/// Store that persists entries as encrypted `KnownHost` vault items.
+///
+/// Each entry's item ID is derived from its `host:port` (see
+/// [`ProfileManager::known_host_id`]), so a lookup decrypts only that item.
+///
+/// Changes are applied to the shared [`ProfileManager`] in memory; the caller
+/// persists them with `SyncEngine::push` as for any other vault edit.
+pub struct VaultKnownHostsStore {
+    manager: Arc<Mutex<ProfileManager>>,
+}
+
+impl VaultKnownHostsStore {
+    pub fn new(manager: Arc<Mutex<ProfileManager>>) -> Self {
+        VaultKnownHostsStore { manager }
+    }
+}
+
+#[async_trait]
+impl KnownHostsStore for VaultKnownHostsStore {
+    async fn get(&self, host: &str, port: u16) -> Result<Option<KnownHost>, ConnectionError> {
+        Ok(self.manager.lock().await.find_known_host(host, port)?)
+    }
+
+    async fn insert(&self, mut entry: KnownHost) -> Result<(), ConnectionError> {
+        let mut manager = self.manager.lock().await;
+        entry.id = manager.known_host_id(&entry.host, entry.port);
+        match manager.find_known_host(&entry.host, entry.port)? {
+            Some(old) => {
+                // The ID stays stable so other devices see an update rather
+                // than a delete + add.
+                entry.created_at = old.created_at;
+                entry.updated_at = Utc::now();
+                manager.update_known_host(entry)?;
+            }
+            None => {
+                manager.add_known_host(entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, host: &str, port: u16) -> Result<bool, ConnectionError> {
+        let mut manager = self.manager.lock().await;
+        let id = manager.known_host_id(host, port);
+        Ok(manager.delete(&id))
+    }
+
+    async fn list(&self) -> Result<Vec<KnownHost>, ConnectionError> {
+        Ok(self.manager.lock().await.list_known_hosts()?)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// File-backed implementation
+// ---------------------------------------------------------------------------
+
+/// Store that keeps entries in a single JSON file.
+///
+/// Writes are atomic (write to a `.tmp` sibling, then rename), mirroring
+/// `FileCache`. The file is re-read on every lookup so several adapters can
+/// share it without coordinating.
+pub struct FileKnownHostsStore {
+    path: PathBuf,
+    /// Serialises read-modify-write cycles within this process.
+    lock: Mutex<()>,
+}
+
+impl FileKnownHostsStore {
+    const FILE_NAME: &'static str = "known_hosts.json";
+
+    /// Production constructor — `{data_dir}/tacoshell/known_hosts.json`.
+    pub fn new() -> Result<Self, ConnectionError> {
+        let dir = dirs::data_dir()
+            .ok_or_else(|| {
+                ConnectionError::KnownHosts("cannot determine platform data directory".into())
+            })?
+            .join("tacoshell");
+        Ok(Self::with_path(dir.join(Self::FILE_NAME)))
+    }
+
+    /// Use an explicit file path.
+    pub fn with_path(path: impl Into<PathBuf>) -> Self {
+        FileKnownHostsStore {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    async fn read_entries(&self) -> Result<Vec<KnownHost>, ConnectionError> {
+        let bytes = match tokio::fs::read(&self.path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_slice(&bytes)
+            .map_err(|e| ConnectionError::KnownHosts(format!("corrupt known-hosts file: {e}")))
+    }
+
+    async fn write_entries(&self, entries: &[KnownHost]) -> Result<(), ConnectionError> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let json = serde_json::to_vec_pretty(entries)
+            .map_err(|e| ConnectionError::KnownHosts(e.to_string()))?;
+        let tmp = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, &json).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl KnownHostsStore for FileKnownHostsStore {
+    async fn get(&self, host: &str, port: u16) -> Result<Option<KnownHost>, ConnectionError> {
+        let _guard = self.lock.lock().await;
+        Ok(self
+            .read_entries()
+            .await?
+            .into_iter()
+            .find(|h| h.host == host && h.port == port))
+    }
+
+    async fn insert(&self, mut entry: KnownHost) -> Result<(), ConnectionError> {
+        let _guard = self.lock.lock().await;
+        let mut entries = self.read_entries().await?;
+        if let Some(old) = entries
+            .iter()
+            .find(|h| h.host == entry.host && h.port == entry.port)
+        {
+            entry.id = old.id.clone();
+            entry.created_at = old.created_at;
+            entry.updated_at = Utc::now();
+        }
+        entries.retain(|h| !(h.host == entry.host && h.port == entry.port));
+        entries.push(entry);
+        self.write_entries(&entries).await
+    }
+
+    async fn remove(&self, host: &str, port: u16) -> Result<bool, ConnectionError> {
+        let _guard = self.lock.lock().await;
+        let mut entries = self.read_entries().await?;
+        let before = entries.len();
+        entries.retain(|h| !(h.host == host && h.port == port));
+        if entries.len() == before {
+            return Ok(false);
+        }
+        self.write_entries(&entries).await?;
+        Ok(true)
+    }
+
+    async fn list(&self) -> Result<Vec<KnownHost>, ConnectionError> {
+        let _guard = self.lock.lock().await;
+        self.read_entries().await
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::crypto::vault::{EncryptedItem, VaultFile};
+
+    const KEY: [u8; 32] = [0x42u8; 32];
+
+    fn entry(host: &str, port: u16, key: &str) -> KnownHost {
+        KnownHost::new(host, port, "ssh-ed25519", key)
+    }
+
+    #[test]
+    fn sha256_fingerprint_has_openssh_prefix_and_no_padding() {
+        let fp = sha256_fingerprint(b"some key bytes");
+        assert!(fp.starts_with("SHA256:"));
+        assert!(!fp.ends_with('='));
+    }
+
+    #[tokio::test]
+    async fn memory_store_insert_replaces_same_host_port() {
+        let store = MemoryKnownHostsStore::new();
+        store.insert(entry("h", 22, "AAAA")).await.unwrap();
+        store.insert(entry("h", 22, "BBBB")).await.unwrap();
+        store.insert(entry("h", 2222, "CCCC")).await.unwrap();
+
+        assert_eq!(
+            store.get("h", 22).await.unwrap().unwrap().public_key,
+            "BBBB"
+        );
+        assert_eq!(store.list().await.unwrap().len(), 2);
+        assert!(store.remove("h", 22).await.unwrap());
+        assert!(store.get("h", 22).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn vault_store_persists_entries_as_vault_items() {
+        let manager = Arc::new(Mutex::new(ProfileManager::new(VaultFile::new(), KEY)));
+        let store = VaultKnownHostsStore::new(Arc::clone(&manager));
+
+        store.insert(entry("h", 22, "AAAA")).await.unwrap();
+        let first_id = store.get("h", 22).await.unwrap().unwrap().id;
+
+        // Re-trusting the same host updates the existing item in place.
+        store.insert(entry("h", 22, "BBBB")).await.unwrap();
+        let updated = store.get("h", 22).await.unwrap().unwrap();
+        assert_eq!(updated.id, first_id);
+        assert_eq!(updated.public_key, "BBBB");
+
+        // Reloading the serialized vault with the same key recovers the entry.
+        let json = manager.lock().await.vault().to_json().unwrap();
+        let reloaded = ProfileManager::new(VaultFile::from_json(&json).unwrap(), KEY);
+        let hosts = reloaded.list_known_hosts().unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].public_key, "BBBB");
+    }
+
+    #[tokio::test]
+    async fn vault_store_lookups_decrypt_only_the_host_entry() {
+        // An item this key cannot decrypt fails any lookup that decrypts it.
+        let mut vault = VaultFile::new();
+        vault.add_item(EncryptedItem::encrypt(&[0x24u8; 32], b"{}").unwrap());
+        let manager = Arc::new(Mutex::new(ProfileManager::new(vault, KEY)));
+        let store = VaultKnownHostsStore::new(Arc::clone(&manager));
+
+        assert!(store.get("h", 22).await.unwrap().is_none());
+        store.insert(entry("h", 22, "AAAA")).await.unwrap();
+        store.insert(entry("h", 22, "BBBB")).await.unwrap();
+        assert_eq!(
+            store.get("h", 22).await.unwrap().unwrap().public_key,
+            "BBBB"
+        );
+        assert!(store.get("h", 2222).await.unwrap().is_none());
+        assert!(store.remove("h", 22).await.unwrap());
+        assert!(!store.remove("h", 22).await.unwrap());
+        assert!(store.list().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn file_store_survives_new_instance() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("nested").join("known_hosts.json");
+
+        let store = FileKnownHostsStore::with_path(&path);
+        assert!(store.get("h", 22).await.unwrap().is_none());
+        store.insert(entry("h", 22, "AAAA")).await.unwrap();
+
+        let reopened = FileKnownHostsStore::with_path(&path);
+        let got = reopened.get("h", 22).await.unwrap().unwrap();
+        assert_eq!(got.public_key, "AAAA");
+
+        assert!(reopened.remove("h", 22).await.unwrap());
+        assert!(!reopened.remove("h", 22).await.unwrap());
+    }
+}
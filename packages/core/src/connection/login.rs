@@ -0,0 +1,293 @@
+//! Post-login scripts.
+//!
+//! A profile's [`LoginAction`]s run against the primary shell once it has
+//! started: text is typed through the shell's input like a keystroke, and
+//! `wait_for` steps watch a copy of its output. The script stops at the first
+//! step that fails, and [`LoginStepFailed`] says which one and why. The
+//! output the script watched is still delivered to the shell's consumer.
+
+use std::time::Duration;
+
+use regex::bytes::Regex;
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+use super::shell::{ShellCmd, ShellControl};
+use crate::profile::types::{LoginAction, LoginActionKind};
+
+/// Time a step may take when its `timeout_secs` is unset.
+pub const DEFAULT_STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Output kept for matching; older bytes are discarded first.
+const MAX_BUFFERED: usize = 64 * 1024;
+
+/// The step a login script stopped at.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Login step {step} ({action}) failed: {reason}")]
+pub struct LoginStepFailed {
+    /// 1-based position of the step in `ConnectionProfile::login_actions`.
+    pub step: usize,
+    /// What the step does, e.g. `wait for /\$ $/`. Sent text is left out,
+    /// since it may be a password.
+    pub action: String,
+    pub reason: LoginFailure,
+}
+
+/// Why a login step failed.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LoginFailure {
+    #[error("timed out after {0:?}")]
+    TimedOut(Duration),
+    #[error("the shell closed")]
+    ShellClosed,
+    /// The pattern does not compile. Checked before any step runs.
+    #[error("invalid pattern: {0}")]
+    InvalidPattern(String),
+}
+
+/// Run `actions` against a shell. `output` receives a copy of everything the
+/// shell prints from the moment it started.
+pub(crate) async fn run_login_script(
+    actions: &[LoginAction],
+    input: &ShellControl,
+    output: &mut mpsc::UnboundedReceiver<Vec<u8>>,
+) -> Result<(), LoginStepFailed> {
+    let steps = compile(actions)?;
+    let mut buffer = Vec::new();
+    for (index, (action, step)) in actions.iter().zip(steps).enumerate() {
+        let fail = |reason| LoginStepFailed {
+            step: index + 1,
+            action: describe(&action.kind),
+            reason,
+        };
+        let limit = action
+            .timeout_secs
+            .map_or(DEFAULT_STEP_TIMEOUT, Duration::from_secs);
+        match step {
+            Step::Send(text) => within(limit, send(input, text)).await,
+            Step::WaitFor(pattern, text) => {
+                within(limit, async {
+                    wait_for(&pattern, output, &mut buffer).await?;
+                    send(input, text).await
+                })
+                .await
+            }
+            Step::Delay(pause) => {
+                tokio::time::sleep(pause).await;
+                Ok(())
+            }
+        }
+        .map_err(fail)?;
+    }
+    Ok(())
+}
+
+/// A [`LoginAction`] ready to run.
+enum Step<'a> {
+    Send(&'a str),
+    WaitFor(Regex, &'a str),
+    Delay(Duration),
+}
+
+/// Compile every `wait_for` pattern up front, so a typo fails the script
+/// before anything is typed.
+fn compile(actions: &[LoginAction]) -> Result<Vec<Step<'_>>, LoginStepFailed> {
+    actions
+        .iter()
+        .enumerate()
+        .map(|(index, action)| match &action.kind {
+            LoginActionKind::SendText { text } => Ok(Step::Send(text)),
+            LoginActionKind::WaitFor { pattern, text } => Regex::new(pattern)
+                .map(|pattern| Step::WaitFor(pattern, text))
+                .map_err(|e| LoginStepFailed {
+                    step: index + 1,
+                    action: describe(&action.kind),
+                    reason: LoginFailure::InvalidPattern(e.to_string()),
+                }),
+            LoginActionKind::Delay { millis } => Ok(Step::Delay(Duration::from_millis(*millis))),
+        })
+        .collect()
+}
+
+async fn within(
+    limit: Duration,
+    step: impl std::future::Future<Output = Result<(), LoginFailure>>,
+) -> Result<(), LoginFailure> {
+    tokio::time::timeout(limit, step)
+        .await
+        .unwrap_or(Err(LoginFailure::TimedOut(limit)))
+}
+
+async fn send(input: &ShellControl, text: &str) -> Result<(), LoginFailure> {
+    input
+        .send(ShellCmd::Data(text.as_bytes().to_vec()))
+        .await
+        .map_err(|_| LoginFailure::ShellClosed)
+}
+
+/// Read output until `pattern` matches, then drop everything up to the end
+/// of the match so the next step only sees newer output.
+async fn wait_for(
+    pattern: &Regex,
+    output: &mut mpsc::UnboundedReceiver<Vec<u8>>,
+    buffer: &mut Vec<u8>,
+) -> Result<(), LoginFailure> {
+    loop {
+        if let Some(found) = pattern.find(buffer) {
+            buffer.drain(..found.end());
+            return Ok(());
+        }
+        let chunk = output.recv().await.ok_or(LoginFailure::ShellClosed)?;
+        buffer.extend_from_slice(&chunk);
+        let excess = buffer.len().saturating_sub(MAX_BUFFERED);
+        buffer.drain(..excess);
+    }
+}
+
+fn describe(kind: &LoginActionKind) -> String {
+    match kind {
+        LoginActionKind::SendText { .. } => "send text".to_owned(),
+        LoginActionKind::WaitFor { pattern, .. } => format!("wait for /{pattern}/"),
+        LoginActionKind::Delay { millis } => format!("delay {millis}ms"),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn action(kind: LoginActionKind, timeout_secs: Option<u64>) -> LoginAction {
+        LoginAction { kind, timeout_secs }
+    }
+
+    fn send_text(text: &str) -> LoginActionKind {
+        LoginActionKind::SendText { text: text.into() }
+    }
+
+    fn wait_for(pattern: &str, text: &str) -> LoginActionKind {
+        LoginActionKind::WaitFor {
+            pattern: pattern.into(),
+            text: text.into(),
+        }
+    }
+
+    struct FakeShell {
+        control: ShellControl,
+        output: mpsc::UnboundedReceiver<Vec<u8>>,
+        /// Resolves to everything typed, once `control` is dropped.
+        typed: tokio::task::JoinHandle<Vec<Vec<u8>>>,
+    }
+
+    /// A shell that prints `banner`, then `script[i]` after its `i`-th input.
+    fn fake_shell(banner: &'static str, script: &'static [&'static str]) -> FakeShell {
+        let (tx, mut rx) = mpsc::channel(4);
+        let (out_tx, out_rx) = mpsc::unbounded_channel();
+        let control = ShellControl::new(tx, Arc::new(AtomicBool::new(true)));
+        let task = tokio::spawn(async move {
+            let _ = out_tx.send(banner.as_bytes().to_vec());
+            let mut typed = Vec::new();
+            while let Some(ShellCmd::Data(bytes)) = rx.recv().await {
+                typed.push(bytes);
+                if let Some(reply) = script.get(typed.len() - 1) {
+                    let _ = out_tx.send(reply.as_bytes().to_vec());
+                }
+            }
+            typed
+        });
+        FakeShell {
+            control,
+            output: out_rx,
+            typed: task,
+        }
+    }
+
+    #[tokio::test]
+    async fn steps_run_in_order_and_wait_for_their_prompts() {
+        let FakeShell {
+            control,
+            mut output,
+            typed,
+        } = fake_shell("Welcome\r\nuser@web:~$ ", &["\r\nroot@web:~# ", "", ""]);
+        let actions = [
+            action(wait_for(r"\$ $", "sudo -i\r"), None),
+            action(wait_for(r"# $", "cd /srv/app\r"), None),
+            action(LoginActionKind::Delay { millis: 1 }, None),
+            action(send_text("tmux attach\r"), None),
+        ];
+        run_login_script(&actions, &control, &mut output)
+            .await
+            .unwrap();
+        drop(control);
+        assert_eq!(
+            typed.await.unwrap(),
+            [&b"sudo -i\r"[..], b"cd /srv/app\r", b"tmux attach\r"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_prompt_that_never_comes_times_out_and_names_the_step() {
+        let FakeShell {
+            control,
+            mut output,
+            ..
+        } = fake_shell("$ ", &[]);
+        let actions = [
+            action(send_text("sudo -i\r"), None),
+            action(wait_for("# $", "id\r"), Some(3)),
+            action(send_text("never sent\r"), None),
+        ];
+        let err = run_login_script(&actions, &control, &mut output)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            LoginStepFailed {
+                step: 2,
+                action: "wait for /# $/".into(),
+                reason: LoginFailure::TimedOut(Duration::from_secs(3)),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn a_closed_shell_fails_the_waiting_step() {
+        let (tx, _rx) = mpsc::channel(1);
+        let control = ShellControl::new(tx, Arc::new(AtomicBool::new(true)));
+        let (out_tx, mut output) = mpsc::unbounded_channel();
+        out_tx.send(b"logout\r\n".to_vec()).unwrap();
+        drop(out_tx);
+        let actions = [action(wait_for(r"\$ $", "x"), None)];
+        let err = run_login_script(&actions, &control, &mut output)
+            .await
+            .unwrap_err();
+        assert_eq!(err.reason, LoginFailure::ShellClosed);
+    }
+
+    #[tokio::test]
+    async fn invalid_patterns_fail_before_anything_is_sent() {
+        let FakeShell {
+            control,
+            mut output,
+            typed,
+        } = fake_shell("$ ", &[]);
+        let actions = [
+            action(send_text("secret\r"), None),
+            action(wait_for("(unclosed", "x"), None),
+        ];
+        let err = run_login_script(&actions, &control, &mut output)
+            .await
+            .unwrap_err();
+        assert_eq!(err.step, 2);
+        assert!(matches!(err.reason, LoginFailure::InvalidPattern(_)));
+        assert!(!err.to_string().contains("secret"));
+        drop(control);
+        assert!(typed.await.unwrap().is_empty());
+    }
+}
@@ -1,23 +1,80 @@
 // Protocol adapter traits and implementations
 //
 // Each protocol adapter implements ConnectionAdapter plus one or more
-// capability traits (TerminalAdapter, FileTransferAdapter, KubernetesAdapter).
+// capability traits (TerminalAdapter, PortForwardAdapter, FileTransferAdapter,
+// KubernetesAdapter).
 //
 // Implementations live in sub-modules:
-//   ssh.rs   — SSH (implements TerminalAdapter)
+//   ssh.rs   — SSH (implements TerminalAdapter and PortForwardAdapter)
+//   agent_forward.rs — relays forwarded-agent channels to a local agent
+//   algorithms.rs — algorithm preferences and negotiation reporting for ssh.rs
+//   certificate.rs — OpenSSH user certificate inspection for ssh.rs
+//   dial.rs  — DNS resolution and happy-eyeballs TCP connect for ssh.rs
+//   exec.rs  — streaming exec handles returned by TerminalAdapter::exec_stream
+//   files.rs — FileEntry and remote-path helpers for FileTransferAdapter
+//   shell.rs — handles for additional interactive shells on an SSH session
+//   known_hosts.rs — host-key trust stores consulted by ssh.rs
+//   host_key_verifier.rs — interactive host-key confirmation for ssh.rs
+//   openssh_known_hosts.rs — OpenSSH known_hosts file parsing for ssh.rs
+//   jump.rs  — ProxyJump profile/credential resolution for ssh.rs
+//   forward.rs — port-forward handles, counters and SOCKS5 for ssh.rs
+//   keyboard_interactive.rs — keyboard-interactive prompts and TOTP for ssh.rs
+//   login.rs — post-login scripts run in the shell by ssh.rs
+//   output.rs — flow-controlled terminal output delivery for ssh.rs
+//   pty.rs   — PTY, terminal-mode and environment requests for ssh.rs
+//   reconnect.rs — SshSupervisor: automatic reconnect around ssh.rs
+//   remote_copy.rs — RemoteCopy: streams files between two FileTransferAdapters
 //   sftp.rs  — SFTP (implements FileTransferAdapter, built on top of SSH)
+//   sync.rs  — rsync-style directory sync on top of transfer.rs
+//   testing.rs — test doubles shared by the unit tests (test builds only)
+//   transfer.rs — TransferManager: queued, resumable FileTransferAdapter copies
+//   watch.rs — RemoteWatcher: change notifications by polling or inotifywait
 //   ftp.rs   — FTP/FTPS (implements FileTransferAdapter)
 //   k8s.rs   — Kubernetes (implements KubernetesAdapter)
 
+use std::io::SeekFrom;
+use std::path::Path;
 use std::time::Duration;
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 use tokio::sync::mpsc;
 
-use crate::profile::types::{ConnectionProfile, Protocol};
+use crate::profile::types::{ConnectionProfile, PortForwardSpec, Protocol};
 
+use self::exec::{ExecHandle, ExecRequest, ExitSignal};
+use self::files::{FileEntry, ServerIdentity};
+use self::forward::PortForwardHandle;
+use self::keyboard_interactive::PromptResponder;
+use self::transfer::OnProgress;
+
+mod agent_forward;
+pub mod algorithms;
+pub mod certificate;
+mod dial;
+pub mod exec;
+pub mod files;
+pub mod forward;
+pub mod host_key_verifier;
+pub mod jump;
+pub mod keyboard_interactive;
+pub mod known_hosts;
+pub mod login;
+pub mod openssh_known_hosts;
+pub mod output;
+mod pty;
+pub mod reconnect;
+pub mod remote_copy;
+pub mod sftp;
+pub mod shell;
 pub mod ssh;
+pub mod sync;
+#[cfg(test)]
+mod testing;
+pub mod transfer;
+pub mod watch;
 
 // ---------------------------------------------------------------------------
 // Credential
@@ -32,12 +89,22 @@ pub enum Credential {
     /// Username/password authentication.
     Password(secrecy::SecretString),
     /// OpenSSH private key (PEM-encoded). Supply `passphrase` for encrypted keys.
+    /// With `certificate` (the contents of an `-cert.pub` file), the key is
+    /// presented as that OpenSSH certificate.
     PublicKey {
         private_key_pem: secrecy::SecretString,
         passphrase: Option<secrecy::SecretString>,
+        certificate: Option<String>,
     },
     /// Delegate signing to the SSH agent at `SSH_AUTH_SOCK`.
     SshAgent,
+    /// Keyboard-interactive authentication; each round of server prompts is
+    /// passed to the responder.
+    KeyboardInteractive(std::sync::Arc<dyn PromptResponder>),
+    /// Several methods tried in order, for servers that require more than
+    /// one (e.g. `AuthenticationMethods publickey,keyboard-interactive`).
+    /// Authentication succeeds as soon as the server accepts the session.
+    Chain(Vec<Credential>),
 }
 
 impl std::fmt::Debug for Credential {
@@ -46,6 +113,8 @@ impl std::fmt::Debug for Credential {
             Credential::Password(_) => write!(f, "Credential::Password([redacted])"),
             Credential::PublicKey { .. } => write!(f, "Credential::PublicKey([redacted])"),
             Credential::SshAgent => write!(f, "Credential::SshAgent"),
+            Credential::KeyboardInteractive(_) => write!(f, "Credential::KeyboardInteractive"),
+            Credential::Chain(steps) => f.debug_tuple("Credential::Chain").field(steps).finish(),
         }
     }
 }
@@ -57,31 +126,214 @@ impl std::fmt::Debug for Credential {
 /// Typed error returned by all connection-layer operations.
 #[derive(Debug, Error)]
 pub enum ConnectionError {
+    #[error("Could not resolve {host}: {reason}")]
+    Dns { host: String, reason: String },
+
     #[error("Connection refused: {host}:{port}")]
     Refused { host: String, port: u16 },
 
+    #[error("Could not reach {host}:{port}: {source}")]
+    Unreachable {
+        host: String,
+        port: u16,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Key exchange with {host}:{port} failed: {reason}")]
+    KeyExchange {
+        host: String,
+        port: u16,
+        reason: String,
+    },
+
     #[error("Authentication failed: {reason}")]
-    AuthFailed { reason: String },
+    AuthFailed {
+        reason: String,
+        /// Every method and key offered before giving up, in order.
+        attempts: Vec<AuthAttempt>,
+    },
+
+    #[error(
+        "Host key verification failed for {host}:{port} \
+         (expected {expected_fingerprint}, server presented {presented_fingerprint})"
+    )]
+    HostKeyMismatch {
+        host: String,
+        port: u16,
+        /// SHA-256 fingerprint of the key we previously trusted.
+        expected_fingerprint: String,
+        /// SHA-256 fingerprint of the key the server presented this time.
+        presented_fingerprint: String,
+    },
+
+    #[error("Host {host}:{port} is not in known_hosts (server presented {fingerprint})")]
+    HostKeyUnknown {
+        host: String,
+        port: u16,
+        fingerprint: String,
+    },
 
-    #[error("Host key verification failed for {host}:{port}")]
-    HostKeyMismatch { host: String, port: u16 },
+    #[error("Host key {fingerprint} for {host}:{port} has been revoked")]
+    HostKeyRevoked {
+        host: String,
+        port: u16,
+        fingerprint: String,
+    },
+
+    #[error("Host key {fingerprint} for {host}:{port} was rejected")]
+    HostKeyRejected {
+        host: String,
+        port: u16,
+        fingerprint: String,
+    },
+
+    #[error("Jump host {hop} ({name}) failed: {source}")]
+    JumpHost {
+        /// 1-based position of the hop in `SshSettings::jump_host_ids`.
+        hop: usize,
+        /// `host:port` of the hop, or its profile ID if it could not be resolved.
+        name: String,
+        #[source]
+        source: Box<ConnectionError>,
+    },
+
+    #[error("Could not open a channel: {reason}")]
+    ChannelOpen { reason: String },
 
-    #[error("Connection timed out after {timeout:?}")]
+    #[error("Timed out after {timeout:?}")]
     Timeout { timeout: Duration },
 
+    #[error("Timed out during {stage} after {timeout:?}")]
+    ConnectTimeout {
+        stage: ConnectStage,
+        timeout: Duration,
+    },
+
     #[error("Protocol error: {0}")]
     Protocol(String),
 
+    /// The profile or options cannot work as given; retrying will not help.
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    /// A profile names an algorithm this client does not implement.
+    #[error("Unsupported {kind} algorithm {name}")]
+    UnsupportedAlgorithm { kind: String, name: String },
+
     #[error("Operation not supported by {protocol:?}")]
     NotSupported { protocol: Protocol },
 
     #[error("Transfer cancelled")]
     Cancelled,
 
+    /// A queued transfer stopped; `reason` is the error it stopped with.
+    #[error("Transfer failed: {reason}")]
+    TransferFailed { reason: String },
+
+    #[error("No such file: {path}")]
+    NotFound { path: String },
+
+    #[error("Permission denied: {path}")]
+    PermissionDenied { path: String },
+
+    #[error("Invalid glob {pattern:?}: {reason}")]
+    InvalidGlob { pattern: String, reason: String },
+
+    /// More than `limit` bytes of a channel's output went unread.
+    #[error("Output exceeded {limit} unread bytes")]
+    OutputOverflow { limit: usize },
+
+    /// A copy whose source and destination are the same file.
+    #[error("Cannot copy {path} onto itself")]
+    SameFile { path: String },
+
+    #[error("Known-hosts store error: {0}")]
+    KnownHosts(String),
+
     #[error(transparent)]
     Io(#[from] std::io::Error),
 }
 
+impl ConnectionError {
+    /// The connection stage this error comes from, if any, so the UI can
+    /// tell "host not found" apart from "key rejected".
+    pub fn stage(&self) -> Option<ConnectStage> {
+        match self {
+            ConnectionError::Dns { .. } => Some(ConnectStage::Dns),
+            ConnectionError::Refused { .. } | ConnectionError::Unreachable { .. } => {
+                Some(ConnectStage::Tcp)
+            }
+            ConnectionError::KeyExchange { .. }
+            | ConnectionError::HostKeyMismatch { .. }
+            | ConnectionError::HostKeyUnknown { .. }
+            | ConnectionError::HostKeyRevoked { .. }
+            | ConnectionError::HostKeyRejected { .. }
+            | ConnectionError::KnownHosts(_) => Some(ConnectStage::KeyExchange),
+            ConnectionError::AuthFailed { .. } => Some(ConnectStage::Auth),
+            ConnectionError::ChannelOpen { .. } => Some(ConnectStage::ChannelOpen),
+            ConnectionError::ConnectTimeout { stage, .. } => Some(*stage),
+            ConnectionError::JumpHost { source, .. } => source.stage(),
+            _ => None,
+        }
+    }
+}
+
+/// Step of connection setup, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectStage {
+    Dns,
+    Tcp,
+    KeyExchange,
+    Auth,
+    ChannelOpen,
+}
+
+impl std::fmt::Display for ConnectStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ConnectStage::Dns => "DNS lookup",
+            ConnectStage::Tcp => "TCP connect",
+            ConnectStage::KeyExchange => "key exchange",
+            ConnectStage::Auth => "authentication",
+            ConnectStage::ChannelOpen => "channel open",
+        })
+    }
+}
+
+/// One authentication method offered to the server, as reported in
+/// [`ConnectionError::AuthFailed`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthAttempt {
+    /// SSH method name: `publickey`, `password` or `keyboard-interactive`.
+    pub method: String,
+    /// The key offered, for `publickey`: its type and SHA-256 fingerprint.
+    pub identity: Option<String>,
+}
+
+impl AuthAttempt {
+    pub fn new(method: impl Into<String>) -> Self {
+        AuthAttempt {
+            method: method.into(),
+            identity: None,
+        }
+    }
+
+    pub fn with_identity(mut self, identity: impl Into<String>) -> Self {
+        self.identity = Some(identity.into());
+        self
+    }
+}
+
+impl std::fmt::Display for AuthAttempt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.identity {
+            Some(identity) => write!(f, "{} ({identity})", self.method),
+            None => f.write_str(&self.method),
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // ExecResult
 // ---------------------------------------------------------------------------
@@ -92,6 +344,8 @@ pub struct ExecResult {
     pub stdout: Vec<u8>,
     pub stderr: Vec<u8>,
     pub exit_code: Option<u32>,
+    /// Set instead of `exit_code` when the command was killed by a signal.
+    pub exit_signal: Option<ExitSignal>,
 }
 
 impl ExecResult {
@@ -151,4 +405,193 @@ pub trait TerminalAdapter: ConnectionAdapter {
 
     /// Execute a one-shot command and collect its stdout / stderr / exit code.
     async fn exec(&self, command: &str) -> Result<ExecResult, ConnectionError>;
+
+    /// Start a command and stream its output as it runs.
+    async fn exec_stream(&self, request: ExecRequest) -> Result<ExecHandle, ConnectionError>;
+}
+
+/// Extended trait for adapters that can tunnel TCP connections (SSH).
+#[async_trait]
+pub trait PortForwardAdapter: ConnectionAdapter {
+    /// Listen on `bind_address:bind_port` locally and connect each accepted
+    /// connection to `remote_host:remote_port` from the server (`ssh -L`).
+    async fn forward_local(
+        &self,
+        bind_address: &str,
+        bind_port: u16,
+        remote_host: &str,
+        remote_port: u16,
+    ) -> Result<PortForwardHandle, ConnectionError>;
+
+    /// Ask the server to listen on `bind_address:bind_port` and connect each
+    /// accepted connection to `local_host:local_port` from here (`ssh -R`).
+    async fn forward_remote(
+        &self,
+        bind_address: &str,
+        bind_port: u16,
+        local_host: &str,
+        local_port: u16,
+    ) -> Result<PortForwardHandle, ConnectionError>;
+
+    /// Run a SOCKS5 proxy on `bind_address:bind_port` whose connections exit
+    /// from the server (`ssh -D`).
+    async fn forward_dynamic(
+        &self,
+        bind_address: &str,
+        bind_port: u16,
+    ) -> Result<PortForwardHandle, ConnectionError>;
+
+    /// All forwards currently running on this connection.
+    fn list_forwards(&self) -> Vec<PortForwardHandle>;
+
+    /// Stop the forward with the given handle ID and close its connections.
+    async fn stop_forward(&self, id: &str) -> Result<(), ConnectionError>;
+
+    /// Start the forward described by `spec`.
+    async fn start_forward(
+        &self,
+        spec: &PortForwardSpec,
+    ) -> Result<PortForwardHandle, ConnectionError> {
+        match spec {
+            PortForwardSpec::Local {
+                bind_address,
+                bind_port,
+                remote_host,
+                remote_port,
+            } => {
+                self.forward_local(bind_address, *bind_port, remote_host, *remote_port)
+                    .await
+            }
+            PortForwardSpec::Remote {
+                bind_address,
+                bind_port,
+                local_host,
+                local_port,
+            } => {
+                self.forward_remote(bind_address, *bind_port, local_host, *local_port)
+                    .await
+            }
+            PortForwardSpec::Dynamic {
+                bind_address,
+                bind_port,
+            } => self.forward_dynamic(bind_address, *bind_port).await,
+        }
+    }
+}
+
+/// Extended trait for adapters that browse and transfer files (SFTP, FTP).
+///
+/// Remote paths are `/`-separated strings; relative ones are resolved
+/// against the login directory.
+#[async_trait]
+pub trait FileTransferAdapter: ConnectionAdapter {
+    /// The entries of directory `path`, by name, without `.` and `..`.
+    async fn list_dir(&self, path: &str) -> Result<Vec<FileEntry>, ConnectionError>;
+
+    /// Metadata for `path` itself; a symlink is described, not followed.
+    async fn stat(&self, path: &str) -> Result<FileEntry, ConnectionError>;
+
+    /// Copy local file `local` to `remote`, replacing it. Returns the bytes
+    /// written.
+    async fn upload(&self, local: &Path, remote: &str) -> Result<u64, ConnectionError> {
+        self.upload_from(local, remote, 0, &|_| {}).await
+    }
+
+    /// Copy `remote` to local file `local`, replacing it. Returns the bytes
+    /// read.
+    async fn download(&self, remote: &str, local: &Path) -> Result<u64, ConnectionError> {
+        self.download_from(remote, local, 0, &|_| {}).await
+    }
+
+    /// Like [`upload`](Self::upload), but keep the first `offset` bytes of
+    /// `remote` and send `local` from that position on. `on_progress` gets
+    /// the size of `remote` after each chunk. Returns the final size.
+    async fn upload_from(
+        &self,
+        local: &Path,
+        remote: &str,
+        offset: u64,
+        on_progress: &OnProgress<'_>,
+    ) -> Result<u64, ConnectionError> {
+        let mut file = tokio::fs::File::open(local).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        self.upload_stream(&mut file, remote, offset, on_progress)
+            .await
+    }
+
+    /// Like [`download`](Self::download), but keep the first `offset` bytes
+    /// of `local` and fetch `remote` from that position on.
+    async fn download_from(
+        &self,
+        remote: &str,
+        local: &Path,
+        offset: u64,
+        on_progress: &OnProgress<'_>,
+    ) -> Result<u64, ConnectionError> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(offset == 0)
+            .open(local)
+            .await?;
+        file.set_len(offset).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let read = self
+            .download_stream(remote, &mut file, offset, on_progress)
+            .await;
+        // Even after a failure, so a resume sees everything that arrived.
+        file.flush().await?;
+        read
+    }
+
+    /// Write what `source` yields to `remote`, starting at `offset`, and
+    /// cut `remote` there first. `on_progress` gets the size of `remote`
+    /// after each chunk. Returns the final size.
+    async fn upload_stream(
+        &self,
+        source: &mut (dyn AsyncRead + Send + Unpin),
+        remote: &str,
+        offset: u64,
+        on_progress: &OnProgress<'_>,
+    ) -> Result<u64, ConnectionError>;
+
+    /// Write `remote` from `offset` on to `sink`. `on_progress` gets the
+    /// position reached in `remote` after each chunk. Returns the final
+    /// position.
+    async fn download_stream(
+        &self,
+        remote: &str,
+        sink: &mut (dyn AsyncWrite + Send + Unpin),
+        offset: u64,
+        on_progress: &OnProgress<'_>,
+    ) -> Result<u64, ConnectionError>;
+
+    /// Remove a file, a symlink or an empty directory.
+    async fn delete(&self, path: &str) -> Result<(), ConnectionError>;
+
+    async fn mkdir(&self, path: &str) -> Result<(), ConnectionError>;
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), ConnectionError>;
+
+    /// Set the permission bits of `path` (e.g. `0o644`).
+    async fn chmod(&self, path: &str, mode: u32) -> Result<(), ConnectionError>;
+
+    /// Set the modification time of `path`.
+    async fn set_modified(&self, path: &str, time: DateTime<Utc>) -> Result<(), ConnectionError>;
+
+    /// Create a symlink at `link` pointing to `target`.
+    async fn symlink(&self, target: &str, link: &str) -> Result<(), ConnectionError>;
+
+    /// Where the symlink `path` points, as stored in the link.
+    async fn readlink(&self, path: &str) -> Result<String, ConnectionError>;
+
+    /// The absolute form of `path` as the server resolves it, with `.`,
+    /// `..` and symlinks followed.
+    async fn canonicalize(&self, path: &str) -> Result<String, ConnectionError>;
+
+    /// The account and server this session is logged in to, if the
+    /// protocol can tell.
+    fn server_identity(&self) -> Option<ServerIdentity> {
+        None
+    }
 }
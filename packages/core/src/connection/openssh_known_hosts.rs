@@ -0,0 +1,485 @@
+//! OpenSSH `known_hosts` file support.
+//!
+//! Used by [`HostKeyPolicy::KnownHostsFile`] and
+//! [`HostKeyPolicy::SystemKnownHosts`] so teams can keep verifying hosts
+//! against the curated files they already maintain for the OpenSSH client.
+//!
+//! Supported syntax (see `sshd(8)`, "SSH_KNOWN_HOSTS FILE FORMAT"):
+//!
+//! - comma-separated host patterns, including `*` / `?` wildcards and `!`
+//!   negations
+//! - `[host]:port` entries for servers on non-default ports
+//! - `|1|salt|hash` hashed host names (`HashKnownHosts yes`)
+//! - `@revoked` markers — a matching key is always rejected
+//! - `@cert-authority` markers — parsed, but never used to vouch for a plain
+//!   host key. `russh` does not negotiate host certificates, so such lines can
+//!   only ever matter for certificate-presenting servers; they are kept out of
+//!   plain-key matching rather than being misread as host entries.
+//!
+//! [`HostKeyPolicy::KnownHostsFile`]: crate::profile::types::HostKeyPolicy::KnownHostsFile
+//! [`HostKeyPolicy::SystemKnownHosts`]: crate::profile::types::HostKeyPolicy::SystemKnownHosts
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use tracing::warn;
+
+use super::known_hosts::sha256_fingerprint;
+
+// ---------------------------------------------------------------------------
+// Parsed representation
+// ---------------------------------------------------------------------------
+
+/// Optional marker at the start of a `known_hosts` line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnownHostsMarker {
+    /// `@cert-authority` — the key is a CA trusted to sign host certificates.
+    CertAuthority,
+    /// `@revoked` — the key must never be accepted.
+    Revoked,
+}
+
+/// One parsed, non-comment line of a `known_hosts` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownHostsLine {
+    /// 1-based line number in the source file (for diagnostics).
+    pub line: usize,
+    pub marker: Option<KnownHostsMarker>,
+    /// Raw comma-separated host-pattern field.
+    pub patterns: String,
+    /// Key type field (e.g., `ssh-ed25519`).
+    pub key_type: String,
+    /// Decoded wire-format public key.
+    pub key: Vec<u8>,
+}
+
+impl KnownHostsLine {
+    /// `true` if `host:port` is selected by this line's host patterns.
+    pub fn matches_host(&self, host: &str, port: u16) -> bool {
+        let candidate = host_field(host, port).to_ascii_lowercase();
+        let mut matched = false;
+        for pattern in self.patterns.split(',') {
+            if let Some(hashed) = pattern.strip_prefix("|1|") {
+                matched |= hashed_host_matches(hashed, &candidate);
+            } else if let Some(negated) = pattern.strip_prefix('!') {
+                if wildcard_match(&negated.to_ascii_lowercase(), &candidate) {
+                    // A matching negation excludes the host outright.
+                    return false;
+                }
+            } else {
+                matched |= wildcard_match(&pattern.to_ascii_lowercase(), &candidate);
+            }
+        }
+        matched
+    }
+}
+
+/// Outcome of checking a presented host key against `known_hosts` data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KnownHostsStatus {
+    /// A plain entry for this host lists exactly the presented key.
+    Trusted,
+    /// The presented key appears on an `@revoked` line.
+    Revoked,
+    /// The host is listed with a different key of the same type.
+    Changed { expected_fingerprint: String },
+    /// No applicable entry exists for this host and key type.
+    Unknown,
+}
+
+/// The parsed contents of one or more `known_hosts` files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KnownHostsFile {
+    pub lines: Vec<KnownHostsLine>,
+}
+
+impl KnownHostsFile {
+    /// Parse `known_hosts` text. Malformed lines are skipped with a warning,
+    /// matching OpenSSH's tolerant behaviour.
+    pub fn parse(text: &str) -> Self {
+        let lines = text
+            .lines()
+            .enumerate()
+            .filter_map(|(idx, raw)| parse_line(idx + 1, raw))
+            .collect();
+        KnownHostsFile { lines }
+    }
+
+    /// Read and parse `path`. A missing file is treated as empty.
+    pub fn load(path: &Path) -> Result<Self, std::io::Error> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Ok(Self::parse(&text)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Append the entries of `other` (e.g., the system-wide file).
+    pub fn extend(&mut self, other: KnownHostsFile) {
+        self.lines.extend(other.lines);
+    }
+
+    /// Check the wire-format `key` presented by `host:port`.
+    pub fn check(&self, host: &str, port: u16, key: &[u8]) -> KnownHostsStatus {
+        let applicable: Vec<&KnownHostsLine> = self
+            .lines
+            .iter()
+            .filter(|l| l.matches_host(host, port))
+            .collect();
+
+        // Revocation wins over everything else, as in OpenSSH.
+        if applicable
+            .iter()
+            .any(|l| l.marker == Some(KnownHostsMarker::Revoked) && l.key == key)
+        {
+            return KnownHostsStatus::Revoked;
+        }
+
+        let plain: Vec<&KnownHostsLine> = applicable
+            .into_iter()
+            .filter(|l| l.marker.is_none())
+            .collect();
+        if plain.iter().any(|l| l.key == key) {
+            return KnownHostsStatus::Trusted;
+        }
+
+        let presented_type = wire_key_type(key);
+        match plain
+            .iter()
+            .find(|l| presented_type.is_some() && wire_key_type(&l.key) == presented_type)
+        {
+            Some(stored) => KnownHostsStatus::Changed {
+                expected_fingerprint: sha256_fingerprint(&stored.key),
+            },
+            None => KnownHostsStatus::Unknown,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// File locations and writing
+// ---------------------------------------------------------------------------
+
+/// The user's `~/.ssh/known_hosts`, if a home directory can be determined.
+pub fn user_known_hosts_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ssh").join("known_hosts"))
+}
+
+/// The system-wide file consulted (read-only) by the "system default" policy.
+pub fn global_known_hosts_path() -> PathBuf {
+    PathBuf::from("/etc/ssh/ssh_known_hosts")
+}
+
+/// Append an unhashed entry for `host:port` to `path`, creating the file (and
+/// its parent directory) if necessary. The key type is taken from the
+/// wire-format `key` itself.
+pub fn append_known_host(
+    path: &Path,
+    host: &str,
+    port: u16,
+    key: &[u8],
+) -> Result<(), std::io::Error> {
+    let key_type = wire_key_type(key).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "public key has no algorithm name",
+        )
+    })?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let needs_newline = match std::fs::read(path) {
+        Ok(existing) => !existing.is_empty() && !existing.ends_with(b"\n"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+        Err(e) => return Err(e),
+    };
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    if needs_newline {
+        file.write_all(b"\n")?;
+    }
+    writeln!(
+        file,
+        "{} {} {}",
+        host_field(host, port),
+        key_type,
+        BASE64.encode(key)
+    )?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Private helpers
+// ---------------------------------------------------------------------------
+
+fn parse_line(line: usize, raw: &str) -> Option<KnownHostsLine> {
+    let trimmed = raw.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return None;
+    }
+
+    let mut fields = trimmed.split_whitespace();
+    let mut first = fields.next()?;
+    let marker = match first {
+        "@cert-authority" => Some(KnownHostsMarker::CertAuthority),
+        "@revoked" => Some(KnownHostsMarker::Revoked),
+        other if other.starts_with('@') => {
+            warn!("known_hosts line {line}: unknown marker {other}, skipping");
+            return None;
+        }
+        _ => None,
+    };
+    if marker.is_some() {
+        first = fields.next()?;
+    }
+
+    let (Some(key_type), Some(key_b64)) = (fields.next(), fields.next()) else {
+        warn!("known_hosts line {line}: missing key fields, skipping");
+        return None;
+    };
+    let Ok(key) = BASE64.decode(key_b64) else {
+        warn!("known_hosts line {line}: key is not valid base64, skipping");
+        return None;
+    };
+
+    Some(KnownHostsLine {
+        line,
+        marker,
+        patterns: first.to_owned(),
+        key_type: key_type.to_owned(),
+        key,
+    })
+}
+
+/// The host-name form OpenSSH uses for lookups: bare host on port 22,
+/// `[host]:port` otherwise.
+fn host_field(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_owned()
+    } else {
+        format!("[{host}]:{port}")
+    }
+}
+
+/// Match a `salt|hash` pair (the part after `|1|`) against `candidate`.
+fn hashed_host_matches(hashed: &str, candidate: &str) -> bool {
+    let Some((salt_b64, hash_b64)) = hashed.split_once('|') else {
+        return false;
+    };
+    let (Ok(salt), Ok(hash)) = (BASE64.decode(salt_b64), BASE64.decode(hash_b64)) else {
+        return false;
+    };
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &salt);
+    ring::hmac::verify(&key, candidate.as_bytes(), &hash).is_ok()
+}
+
+/// Glob match supporting `*` (any run) and `?` (any single character).
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Algorithm name embedded at the start of a wire-format public key.
+fn wire_key_type(key: &[u8]) -> Option<&str> {
+    let len = u32::from_be_bytes(key.get(..4)?.try_into().ok()?) as usize;
+    std::str::from_utf8(key.get(4..4 + len)?).ok()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Build a fake wire-format key: string(type) || string(body).
+    fn wire_key(key_type: &str, body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&(key_type.len() as u32).to_be_bytes());
+        out.extend_from_slice(key_type.as_bytes());
+        out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn line(hosts: &str, key: &[u8]) -> String {
+        format!("{hosts} ssh-ed25519 {}\n", BASE64.encode(key))
+    }
+
+    fn hashed(host: &str, salt: &[u8]) -> String {
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, salt);
+        let tag = ring::hmac::sign(&key, host.as_bytes());
+        format!("|1|{}|{}", BASE64.encode(salt), BASE64.encode(tag.as_ref()))
+    }
+
+    #[test]
+    fn plain_entry_is_trusted_and_other_key_is_changed() {
+        let good = wire_key("ssh-ed25519", b"good");
+        let bad = wire_key("ssh-ed25519", b"bad");
+        let file = KnownHostsFile::parse(&line("example.com,10.0.0.1", &good));
+
+        assert_eq!(
+            file.check("example.com", 22, &good),
+            KnownHostsStatus::Trusted
+        );
+        assert_eq!(file.check("10.0.0.1", 22, &good), KnownHostsStatus::Trusted);
+        assert_eq!(
+            file.check("example.com", 22, &bad),
+            KnownHostsStatus::Changed {
+                expected_fingerprint: sha256_fingerprint(&good)
+            }
+        );
+    }
+
+    #[test]
+    fn different_key_type_is_unknown_not_changed() {
+        let ed = wire_key("ssh-ed25519", b"k");
+        let rsa = wire_key("ssh-rsa", b"k");
+        let file = KnownHostsFile::parse(&line("example.com", &ed));
+        assert_eq!(
+            file.check("example.com", 22, &rsa),
+            KnownHostsStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn bracketed_port_entry_only_matches_that_port() {
+        let key = wire_key("ssh-ed25519", b"k");
+        let file = KnownHostsFile::parse(&line("[example.com]:2222", &key));
+        assert_eq!(
+            file.check("example.com", 2222, &key),
+            KnownHostsStatus::Trusted
+        );
+        assert_eq!(
+            file.check("example.com", 22, &key),
+            KnownHostsStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn hashed_entries_match_host_and_bracketed_port() {
+        let key = wire_key("ssh-ed25519", b"k");
+        let text = format!(
+            "{}{}",
+            line(&hashed("example.com", b"salt-one-0123456789"), &key),
+            line(&hashed("[db.internal]:2200", b"salt-two-0123456789"), &key),
+        );
+        let file = KnownHostsFile::parse(&text);
+        assert_eq!(
+            file.check("example.com", 22, &key),
+            KnownHostsStatus::Trusted
+        );
+        assert_eq!(
+            file.check("db.internal", 2200, &key),
+            KnownHostsStatus::Trusted
+        );
+        assert_eq!(file.check("other.com", 22, &key), KnownHostsStatus::Unknown);
+    }
+
+    #[test]
+    fn wildcards_and_negations() {
+        let key = wire_key("ssh-ed25519", b"k");
+        let file =
+            KnownHostsFile::parse(&line("*.prod.example.com,!bastion.prod.example.com", &key));
+        assert_eq!(
+            file.check("web1.prod.example.com", 22, &key),
+            KnownHostsStatus::Trusted
+        );
+        assert_eq!(
+            file.check("WEB2.PROD.example.com", 22, &key),
+            KnownHostsStatus::Trusted
+        );
+        assert_eq!(
+            file.check("bastion.prod.example.com", 22, &key),
+            KnownHostsStatus::Unknown
+        );
+        assert!(wildcard_match("web?", "web1"));
+        assert!(!wildcard_match("web?", "web12"));
+    }
+
+    #[test]
+    fn revoked_key_is_rejected_even_if_also_listed() {
+        let key = wire_key("ssh-ed25519", b"k");
+        let text = format!(
+            "{}@revoked * ssh-ed25519 {}\n",
+            line("example.com", &key),
+            BASE64.encode(&key)
+        );
+        let file = KnownHostsFile::parse(&text);
+        assert_eq!(
+            file.check("example.com", 22, &key),
+            KnownHostsStatus::Revoked
+        );
+    }
+
+    #[test]
+    fn cert_authority_lines_do_not_vouch_for_plain_keys() {
+        let ca = wire_key("ssh-ed25519", b"ca");
+        let text = format!(
+            "@cert-authority *.example.com ssh-ed25519 {}\n",
+            BASE64.encode(&ca)
+        );
+        let file = KnownHostsFile::parse(&text);
+        assert_eq!(file.lines[0].marker, Some(KnownHostsMarker::CertAuthority));
+        assert_eq!(
+            file.check("a.example.com", 22, &ca),
+            KnownHostsStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn comments_blank_and_malformed_lines_are_skipped() {
+        let key = wire_key("ssh-ed25519", b"k");
+        let text = format!(
+            "# comment\n\nbroken-line\nhost ssh-ed25519 !!!notbase64\n{}",
+            line("example.com", &key)
+        );
+        let file = KnownHostsFile::parse(&text);
+        assert_eq!(file.lines.len(), 1);
+        assert_eq!(file.lines[0].line, 5);
+    }
+
+    #[test]
+    fn append_then_load_round_trips() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".ssh").join("known_hosts");
+        let key = wire_key("ssh-ed25519", b"k");
+
+        assert!(KnownHostsFile::load(&path).unwrap().lines.is_empty());
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "# no trailing newline").unwrap();
+
+        append_known_host(&path, "example.com", 2222, &key).unwrap();
+        let file = KnownHostsFile::load(&path).unwrap();
+        assert_eq!(file.lines.len(), 1);
+        assert_eq!(file.lines[0].patterns, "[example.com]:2222");
+        assert_eq!(
+            file.check("example.com", 2222, &key),
+            KnownHostsStatus::Trusted
+        );
+    }
+}
@@ -0,0 +1,407 @@
+//! Bounded delivery of terminal output.
+//!
+//! The shell task in [`ssh`](super::ssh) pushes every chunk it reads from the
+//! SSH channel into an [`OutputSink`]; a separate pump task forwards chunks to
+//! the consumer's `mpsc` receiver. Channels are always read: russh re-opens
+//! the SSH window as packets arrive and queues unread channel data without
+//! limit, so leaving a channel unread would only move the backlog there. The
+//! sink bounds it instead, as the [`OutputMode`] says:
+//!
+//! - [`OutputMode::Backpressure`] (default) — nothing is dropped. Up to
+//!   [`OUTPUT_LIMIT`] bytes wait for the consumer. Past that the sink
+//!   overflows: it emits [`OutputEvent::Overflowed`], the channel's task
+//!   closes the channel, and the stream ends after what was buffered.
+//! - [`OutputMode::RingBuffer`] — undelivered output is kept in a bounded
+//!   buffer; when it overflows the oldest bytes are discarded and an
+//!   [`OutputEvent::Dropped`] is emitted.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use tokio::sync::{mpsc, Notify};
+use tokio_util::sync::CancellationToken;
+
+/// Bytes of output a backpressured channel may have waiting for its consumer
+/// before the channel is closed.
+pub const OUTPUT_LIMIT: usize = 16 * 1024 * 1024;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// How to handle a consumer that reads output more slowly than it arrives.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Never drop output; close the channel once [`OUTPUT_LIMIT`] bytes go
+    /// unread.
+    #[default]
+    Backpressure,
+    /// Keep at most `capacity` undelivered bytes, discarding the oldest.
+    RingBuffer { capacity: usize },
+}
+
+/// Out-of-band notifications about an output stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputEvent {
+    /// `bytes` were discarded because the consumer fell behind; `total` is
+    /// the running count for this stream.
+    Dropped { bytes: u64, total: u64 },
+    /// More than `limit` bytes went unread, so the channel was closed.
+    Overflowed { limit: usize },
+}
+
+// ---------------------------------------------------------------------------
+// OutputSink / pump
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Default)]
+struct Buffer {
+    chunks: VecDeque<Vec<u8>>,
+    len: usize,
+    /// Bytes the pump has taken but the consumer has not yet accepted.
+    in_flight: usize,
+    closed: bool,
+    /// Set once the consumer is gone, or a backpressured sink overflowed;
+    /// later chunks are discarded on arrival.
+    discard: bool,
+    overflowed: bool,
+}
+
+struct Shared {
+    mode: OutputMode,
+    buffer: Mutex<Buffer>,
+    ready: Notify,
+    /// Most undelivered bytes a backpressured sink holds.
+    limit: usize,
+    dropped: Arc<AtomicU64>,
+    consumer_gone: CancellationToken,
+    events: mpsc::Sender<OutputEvent>,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Producer side of an output stream. Never blocks.
+pub(crate) struct OutputSink {
+    shared: Arc<Shared>,
+}
+
+impl OutputSink {
+    /// Create a sink and spawn the pump that delivers to `output_tx`.
+    pub fn spawn(
+        mode: OutputMode,
+        output_tx: mpsc::Sender<Vec<u8>>,
+        events: mpsc::Sender<OutputEvent>,
+    ) -> Self {
+        Self::with_limit(mode, OUTPUT_LIMIT, output_tx, events)
+    }
+
+    fn with_limit(
+        mode: OutputMode,
+        limit: usize,
+        output_tx: mpsc::Sender<Vec<u8>>,
+        events: mpsc::Sender<OutputEvent>,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            mode,
+            buffer: Mutex::new(Buffer::default()),
+            ready: Notify::new(),
+            limit,
+            dropped: Arc::new(AtomicU64::new(0)),
+            consumer_gone: CancellationToken::new(),
+            events,
+        });
+        tokio::spawn(pump(Arc::clone(&shared), output_tx));
+        OutputSink { shared }
+    }
+
+    /// Queue a chunk for delivery. Discarded if the consumer has gone.
+    /// Returns `false` once a backpressured sink has overflowed; the caller
+    /// should close its channel.
+    #[must_use]
+    pub fn push(&self, data: Vec<u8>) -> bool {
+        let evicted = {
+            let mut buffer = self.shared.lock();
+            if buffer.discard {
+                return !buffer.overflowed;
+            }
+            if self.shared.mode == OutputMode::Backpressure
+                && buffer.len + buffer.in_flight + data.len() > self.shared.limit
+            {
+                // Keep what is buffered for the consumer, take nothing more.
+                buffer.discard = true;
+                buffer.overflowed = true;
+                buffer.closed = true;
+                drop(buffer);
+                let limit = self.shared.limit;
+                let _ = self
+                    .shared
+                    .events
+                    .try_send(OutputEvent::Overflowed { limit });
+                self.shared.ready.notify_one();
+                return false;
+            }
+            buffer.len += data.len();
+            buffer.chunks.push_back(data);
+            match self.shared.mode {
+                OutputMode::Backpressure => 0,
+                OutputMode::RingBuffer { capacity } => evict_oldest(&mut buffer, capacity),
+            }
+        };
+        if evicted > 0 {
+            let total = self.shared.dropped.fetch_add(evicted, Ordering::Relaxed) + evicted;
+            // If the event queue is full the next event's `total` still
+            // accounts for these bytes.
+            let _ = self.shared.events.try_send(OutputEvent::Dropped {
+                bytes: evicted,
+                total,
+            });
+        }
+        self.shared.ready.notify_one();
+        true
+    }
+
+    /// Deliver what is buffered, then end the stream.
+    pub fn close(&self) {
+        self.shared.lock().closed = true;
+        self.shared.ready.notify_one();
+    }
+
+    /// Cancelled when the consumer drops its receiver.
+    pub fn consumer_gone(&self) -> CancellationToken {
+        self.shared.consumer_gone.clone()
+    }
+
+    /// Running count of bytes discarded (ring-buffer mode only).
+    pub fn dropped_counter(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.shared.dropped)
+    }
+}
+
+impl Drop for OutputSink {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Drop bytes from the front of `buffer` until it fits in `capacity`.
+/// Returns the number of bytes dropped.
+fn evict_oldest(buffer: &mut Buffer, capacity: usize) -> u64 {
+    let mut evicted = 0usize;
+    while buffer.len > capacity {
+        let excess = buffer.len - capacity;
+        let Some(front) = buffer.chunks.front_mut() else {
+            break;
+        };
+        let n = if front.len() <= excess {
+            let n = front.len();
+            buffer.chunks.pop_front();
+            n
+        } else {
+            front.drain(..excess);
+            excess
+        };
+        buffer.len -= n;
+        evicted += n;
+    }
+    evicted as u64
+}
+
+async fn pump(shared: Arc<Shared>, output_tx: mpsc::Sender<Vec<u8>>) {
+    loop {
+        let next = {
+            let mut buffer = shared.lock();
+            match buffer.chunks.pop_front() {
+                Some(chunk) => {
+                    buffer.len -= chunk.len();
+                    buffer.in_flight = chunk.len();
+                    Some(Some(chunk))
+                }
+                None if buffer.closed => Some(None),
+                None => None,
+            }
+        };
+        let chunk = match next {
+            Some(Some(chunk)) => chunk,
+            Some(None) => break,
+            None => {
+                shared.ready.notified().await;
+                continue;
+            }
+        };
+
+        let delivered = output_tx.send(chunk).await.is_ok();
+        {
+            let mut buffer = shared.lock();
+            buffer.in_flight = 0;
+            if !delivered {
+                // Nobody is listening: drop what is buffered.
+                buffer.discard = true;
+                buffer.len = 0;
+                buffer.chunks.clear();
+            }
+        }
+        if !delivered {
+            shared.consumer_gone.cancel();
+            break;
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn backpressure_delivers_everything_in_order() {
+        let (output_tx, mut output_rx) = mpsc::channel(1);
+        let (events_tx, mut events_rx) = mpsc::channel(4);
+        let sink = OutputSink::spawn(OutputMode::Backpressure, output_tx, events_tx);
+
+        for i in 0..100u8 {
+            assert!(sink.push(vec![i; 10]));
+        }
+        sink.close();
+
+        let mut received = Vec::new();
+        while let Some(chunk) = output_rx.recv().await {
+            received.push(chunk[0]);
+        }
+        assert_eq!(received, (0..100u8).collect::<Vec<_>>());
+        assert!(
+            events_rx.try_recv().is_err(),
+            "no drops in backpressure mode"
+        );
+    }
+
+    #[tokio::test]
+    async fn ring_buffer_drops_oldest_and_reports() {
+        // Capacity-1 consumer channel that nobody reads: the pump holds one
+        // chunk in the channel and one in flight, the rest stays buffered.
+        let (output_tx, mut output_rx) = mpsc::channel(1);
+        let (events_tx, mut events_rx) = mpsc::channel(16);
+        let sink = OutputSink::spawn(OutputMode::RingBuffer { capacity: 8 }, output_tx, events_tx);
+        let dropped = sink.dropped_counter();
+
+        // Let the pump park on the full consumer channel.
+        assert!(sink.push(b"AAAA".to_vec()));
+        assert!(sink.push(b"BBBB".to_vec()));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert!(sink.push(b"cccc".to_vec()));
+        assert!(sink.push(b"dddd".to_vec()));
+        assert!(sink.push(b"eeee".to_vec()));
+
+        let event = events_rx.recv().await.unwrap();
+        assert_eq!(event, OutputEvent::Dropped { bytes: 4, total: 4 });
+        assert_eq!(dropped.load(Ordering::Relaxed), 4);
+
+        sink.close();
+        let mut received = Vec::new();
+        while let Some(chunk) = output_rx.recv().await {
+            received.extend(chunk);
+        }
+        assert_eq!(received, b"AAAABBBBddddeeee");
+    }
+
+    #[test]
+    fn evict_oldest_trims_partial_chunks() {
+        let mut buffer = Buffer::default();
+        buffer.chunks.push_back(b"abcdef".to_vec());
+        buffer.chunks.push_back(b"gh".to_vec());
+        buffer.len = 8;
+
+        assert_eq!(evict_oldest(&mut buffer, 5), 3);
+        assert_eq!(buffer.len, 5);
+        assert_eq!(buffer.chunks.front().unwrap(), b"def");
+    }
+
+    #[tokio::test]
+    async fn consumer_drop_is_signalled() {
+        let (output_tx, output_rx) = mpsc::channel(1);
+        let (events_tx, _events_rx) = mpsc::channel(1);
+        let sink = OutputSink::spawn(OutputMode::Backpressure, output_tx, events_tx);
+        let gone = sink.consumer_gone();
+
+        drop(output_rx);
+        assert!(sink.push(b"x".to_vec()));
+        tokio::time::timeout(Duration::from_secs(1), gone.cancelled())
+            .await
+            .expect("consumer_gone should fire");
+    }
+
+    #[tokio::test]
+    async fn stalled_consumers_overflow_instead_of_growing() {
+        let (output_tx, mut output_rx) = mpsc::channel(1);
+        let (events_tx, mut events_rx) = mpsc::channel(4);
+        let sink = OutputSink::with_limit(OutputMode::Backpressure, 64, output_tx, events_tx);
+
+        // Nobody reads: one chunk sits in the consumer channel, one is in
+        // flight, and the sink holds at most the limit.
+        let mut accepted = 0;
+        while sink.push(vec![accepted; 16]) {
+            let held = {
+                let buffer = sink.shared.lock();
+                buffer.len + buffer.in_flight
+            };
+            assert!(held <= 64);
+            accepted += 1;
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(
+            events_rx.recv().await,
+            Some(OutputEvent::Overflowed { limit: 64 })
+        );
+        assert!(!sink.push(vec![0; 1]), "an overflowed sink stays closed");
+
+        // What was accepted is still delivered, in order, then the stream ends.
+        let mut received = Vec::new();
+        while let Some(chunk) = output_rx.recv().await {
+            received.push(chunk[0]);
+        }
+        assert_eq!(received, (0..accepted).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn ring_buffers_never_overflow() {
+        let (output_tx, _output_rx) = mpsc::channel(1);
+        let (events_tx, _events_rx) = mpsc::channel(16);
+        let sink = OutputSink::with_limit(
+            OutputMode::RingBuffer { capacity: 8 },
+            4,
+            output_tx,
+            events_tx,
+        );
+        for _ in 0..4 {
+            assert!(sink.push(vec![0; 1024]), "ring buffers drop instead");
+            assert!(sink.shared.lock().len <= 8);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_dropped_consumer_never_overflows_the_sink() {
+        let (output_tx, output_rx) = mpsc::channel(1);
+        let (events_tx, _events_rx) = mpsc::channel(1);
+        let sink = OutputSink::with_limit(OutputMode::Backpressure, 64, output_tx, events_tx);
+
+        assert!(sink.push(vec![0; 32]));
+        drop(output_rx);
+        sink.consumer_gone().cancelled().await;
+
+        // Output is now discarded on arrival and never fills the sink.
+        for _ in 0..4 {
+            assert!(sink.push(vec![0; 64]));
+        }
+        assert_eq!(sink.shared.lock().len, 0);
+    }
+}
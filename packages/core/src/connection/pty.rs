@@ -0,0 +1,158 @@
+//! PTY and environment requests for shell channels.
+//!
+//! [`PtySettings`] names terminal modes the way RFC 4254 §8 does; this module
+//! maps them to russh's opcodes and sends the `env`, `pty-req` and `shell`
+//! requests in the order servers expect.
+
+use std::collections::BTreeMap;
+
+use russh::Pty;
+
+use super::ConnectionError;
+use crate::profile::types::{PtySettings, ShellMode};
+
+/// Every mode russh can encode, by RFC 4254 name.
+const TERMINAL_MODES: &[(&str, Pty)] = &[
+    ("VINTR", Pty::VINTR),
+    ("VQUIT", Pty::VQUIT),
+    ("VERASE", Pty::VERASE),
+    ("VKILL", Pty::VKILL),
+    ("VEOF", Pty::VEOF),
+    ("VEOL", Pty::VEOL),
+    ("VEOL2", Pty::VEOL2),
+    ("VSTART", Pty::VSTART),
+    ("VSTOP", Pty::VSTOP),
+    ("VSUSP", Pty::VSUSP),
+    ("VDSUSP", Pty::VDSUSP),
+    ("VREPRINT", Pty::VREPRINT),
+    ("VWERASE", Pty::VWERASE),
+    ("VLNEXT", Pty::VLNEXT),
+    ("VFLUSH", Pty::VFLUSH),
+    ("VSWTCH", Pty::VSWTCH),
+    ("VSTATUS", Pty::VSTATUS),
+    ("VDISCARD", Pty::VDISCARD),
+    ("IGNPAR", Pty::IGNPAR),
+    ("PARMRK", Pty::PARMRK),
+    ("INPCK", Pty::INPCK),
+    ("ISTRIP", Pty::ISTRIP),
+    ("INLCR", Pty::INLCR),
+    ("IGNCR", Pty::IGNCR),
+    ("ICRNL", Pty::ICRNL),
+    ("IUCLC", Pty::IUCLC),
+    ("IXON", Pty::IXON),
+    ("IXANY", Pty::IXANY),
+    ("IXOFF", Pty::IXOFF),
+    ("IMAXBEL", Pty::IMAXBEL),
+    ("IUTF8", Pty::IUTF8),
+    ("ISIG", Pty::ISIG),
+    ("ICANON", Pty::ICANON),
+    ("XCASE", Pty::XCASE),
+    ("ECHO", Pty::ECHO),
+    ("ECHOE", Pty::ECHOE),
+    ("ECHOK", Pty::ECHOK),
+    ("ECHONL", Pty::ECHONL),
+    ("NOFLSH", Pty::NOFLSH),
+    ("TOSTOP", Pty::TOSTOP),
+    ("IEXTEN", Pty::IEXTEN),
+    ("ECHOCTL", Pty::ECHOCTL),
+    ("ECHOKE", Pty::ECHOKE),
+    ("PENDIN", Pty::PENDIN),
+    ("OPOST", Pty::OPOST),
+    ("OLCUC", Pty::OLCUC),
+    ("ONLCR", Pty::ONLCR),
+    ("OCRNL", Pty::OCRNL),
+    ("ONOCR", Pty::ONOCR),
+    ("ONLRET", Pty::ONLRET),
+    ("CS7", Pty::CS7),
+    ("CS8", Pty::CS8),
+    ("PARENB", Pty::PARENB),
+    ("PARODD", Pty::PARODD),
+    ("TTY_OP_ISPEED", Pty::TTY_OP_ISPEED),
+    ("TTY_OP_OSPEED", Pty::TTY_OP_OSPEED),
+];
+
+/// Encode named modes for `pty-req`. Names are case-insensitive; an unknown
+/// one is an error rather than being dropped, so a typo does not go
+/// unnoticed.
+pub(crate) fn terminal_modes(
+    modes: &BTreeMap<String, u32>,
+) -> Result<Vec<(Pty, u32)>, ConnectionError> {
+    modes
+        .iter()
+        .map(|(name, value)| {
+            TERMINAL_MODES
+                .iter()
+                .find(|(known, _)| known.eq_ignore_ascii_case(name))
+                .map(|(_, mode)| (*mode, *value))
+                .ok_or_else(|| ConnectionError::Config(format!("unknown terminal mode {name}")))
+        })
+        .collect()
+}
+
+/// Set the environment, request a PTY unless `mode` is
+/// [`ShellMode::NoPty`], and start the shell.
+pub(crate) async fn request_shell(
+    channel: &russh::Channel<russh::client::Msg>,
+    settings: &PtySettings,
+    mode: ShellMode,
+) -> Result<(), ConnectionError> {
+    let modes = terminal_modes(&settings.modes)?;
+    for (name, value) in &settings.env {
+        channel
+            .set_env(false, name.as_str(), value.as_str())
+            .await?;
+    }
+    if mode == ShellMode::Pty {
+        channel
+            .request_pty(
+                false,
+                &settings.term,
+                u32::from(settings.cols),
+                u32::from(settings.rows),
+                settings.pixel_width,
+                settings.pixel_height,
+                &modes,
+            )
+            .await?;
+    }
+    channel.request_shell(false).await?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_are_encoded_by_name() {
+        let modes = BTreeMap::from([
+            ("ECHO".to_owned(), 0),
+            ("verase".to_owned(), 127),
+            ("IUTF8".to_owned(), 1),
+        ]);
+        let encoded = terminal_modes(&modes).unwrap();
+        let opcodes: Vec<(u8, u32)> = encoded.iter().map(|(m, v)| (*m as u8, *v)).collect();
+        // BTreeMap order: ECHO, IUTF8, verase.
+        assert_eq!(opcodes, [(53, 0), (42, 1), (3, 127)]);
+    }
+
+    #[test]
+    fn unknown_modes_are_rejected() {
+        let modes = BTreeMap::from([("ECHOO".to_owned(), 1)]);
+        assert!(matches!(
+            terminal_modes(&modes),
+            Err(ConnectionError::Config(msg)) if msg.contains("ECHOO")
+        ));
+    }
+
+    #[test]
+    fn every_mode_name_maps_to_its_opcode() {
+        for (name, mode) in TERMINAL_MODES {
+            assert_eq!(Pty::from_u8(*mode as u8), Some(*mode), "{name}");
+        }
+    }
+}
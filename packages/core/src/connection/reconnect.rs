@@ -0,0 +1,814 @@
+//! Automatic reconnection for SSH sessions.
+//!
+//! [`SshSupervisor`] wraps an [`SshAdapter`] and watches its primary shell.
+//! When the output stream ends because the transport dropped (a network
+//! failure, or russh giving up after unanswered keepalives), it reconnects
+//! with exponential backoff and jitter per [`ReconnectPolicy`] and swaps the
+//! new session in. [`SshSupervisor::output_stream`] carries on across
+//! reconnects, so the consumer keeps reading from the same receiver. A shell
+//! that exits while the transport is still up ends the session instead.
+//!
+//! State changes are published as [`ConnectionState`] on a `watch` channel.
+//! Only errors another attempt might fix are retried; rejected credentials,
+//! host keys and configuration mistakes fail the session at once.
+//!
+//! The supervisor also feeds the output through a [`TerminalState`], so the
+//! screen and scrollback survive reconnects and a client that re-attaches
+//! can redraw from [`SshSupervisor::snapshot`].
+
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::sync::{mpsc, watch, RwLock, RwLockReadGuard};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::profile::types::ConnectionProfile;
+use crate::recording::Recorder;
+use crate::terminal::{SearchMatch, Snapshot, TerminalState};
+
+use super::ssh::{SshAdapter, SshConnectOptions};
+use super::{ConnectionError, Credential};
+
+// ---------------------------------------------------------------------------
+// Policy
+// ---------------------------------------------------------------------------
+
+/// How long to wait between reconnect attempts, and how many to make.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Delay before the first attempt.
+    pub initial_delay: Duration,
+    /// Upper bound on the delay, before jitter.
+    pub max_delay: Duration,
+    /// Factor applied to the delay after each failed attempt.
+    pub multiplier: f64,
+    /// Random spread applied to each delay, as a fraction of it (`0.2`
+    /// means ±20%), so many clients dropped together do not retry in step.
+    pub jitter: f64,
+    /// Give up after this many attempts. `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: Some(10),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before attempt `attempt` (1-based). `sample` in `[0, 1)` picks
+    /// the point within the jitter range.
+    pub fn delay(&self, attempt: u32, sample: f64) -> Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let base = (self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_delay.as_secs_f64());
+        let spread = 1.0 + self.jitter * (2.0 * sample - 1.0);
+        Duration::try_from_secs_f64(base * spread).unwrap_or(self.max_delay)
+    }
+}
+
+/// A uniform sample in `[0, 1)` from the system RNG. Falls back to the
+/// middle of the jitter range if the RNG fails.
+fn jitter_sample() -> f64 {
+    let mut bytes = [0u8; 8];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return 0.5;
+    }
+    (u64::from_be_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Errors that another attempt might not hit. Rejected credentials, host
+/// keys and configuration will not fix themselves.
+fn is_transient(error: &ConnectionError) -> bool {
+    match error {
+        ConnectionError::Dns { .. }
+        | ConnectionError::Refused { .. }
+        | ConnectionError::Unreachable { .. }
+        | ConnectionError::KeyExchange { .. }
+        | ConnectionError::ChannelOpen { .. }
+        | ConnectionError::Timeout { .. }
+        | ConnectionError::ConnectTimeout { .. }
+        | ConnectionError::Protocol(_)
+        | ConnectionError::Io(_) => true,
+        ConnectionError::JumpHost { source, .. } => is_transient(source),
+        ConnectionError::AuthFailed { .. }
+        | ConnectionError::HostKeyMismatch { .. }
+        | ConnectionError::HostKeyUnknown { .. }
+        | ConnectionError::HostKeyRevoked { .. }
+        | ConnectionError::HostKeyRejected { .. }
+        | ConnectionError::NotSupported { .. }
+        | ConnectionError::Cancelled
+        | ConnectionError::TransferFailed { .. }
+        | ConnectionError::InvalidGlob { .. }
+        | ConnectionError::SameFile { .. }
+        | ConnectionError::OutputOverflow { .. }
+        | ConnectionError::NotFound { .. }
+        | ConnectionError::PermissionDenied { .. }
+        | ConnectionError::Config(_)
+        | ConnectionError::UnsupportedAlgorithm { .. }
+        | ConnectionError::KnownHosts(_) => false,
+    }
+}
+
+fn no_shell_output() -> ConnectionError {
+    ConnectionError::Config("the session has no shell output to supervise".to_owned())
+}
+
+// ---------------------------------------------------------------------------
+// Sessions
+// ---------------------------------------------------------------------------
+
+/// What the supervisor needs from a session: [`SshAdapter`], or a fake in
+/// tests.
+#[async_trait]
+pub(crate) trait SupervisedSession: Send + Sync + 'static {
+    fn output_stream(&mut self) -> Option<mpsc::Receiver<Vec<u8>>>;
+    async fn send_input(&self, data: &[u8]) -> Result<(), ConnectionError>;
+    async fn resize(&self, cols: u16, rows: u16) -> Result<(), ConnectionError>;
+    async fn record(&self, recorder: Option<Recorder>) -> Result<(), ConnectionError>;
+    /// `false` once the transport has dropped.
+    async fn is_connected(&self) -> bool;
+    async fn disconnect(&mut self) -> Result<(), ConnectionError>;
+}
+
+#[async_trait]
+impl SupervisedSession for SshAdapter {
+    fn output_stream(&mut self) -> Option<mpsc::Receiver<Vec<u8>>> {
+        super::TerminalAdapter::output_stream(self)
+    }
+
+    async fn send_input(&self, data: &[u8]) -> Result<(), ConnectionError> {
+        super::TerminalAdapter::send_input(self, data).await
+    }
+
+    async fn resize(&self, cols: u16, rows: u16) -> Result<(), ConnectionError> {
+        super::TerminalAdapter::resize(self, cols, rows).await
+    }
+
+    async fn record(&self, recorder: Option<Recorder>) -> Result<(), ConnectionError> {
+        SshAdapter::record(self, recorder).await
+    }
+
+    async fn is_connected(&self) -> bool {
+        SshAdapter::is_connected(self).await
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        super::ConnectionAdapter::disconnect(self).await
+    }
+}
+
+/// Opens sessions for the supervisor, the first one and every reconnect.
+#[async_trait]
+pub(crate) trait Connector<S>: Send + Sync {
+    async fn connect(&self) -> Result<S, ConnectionError>;
+}
+
+/// Connects with [`SshAdapter::connect_with`].
+struct SshConnector {
+    profile: ConnectionProfile,
+    credential: Credential,
+    options: SshConnectOptions,
+}
+
+#[async_trait]
+impl Connector<SshAdapter> for SshConnector {
+    async fn connect(&self) -> Result<SshAdapter, ConnectionError> {
+        SshAdapter::connect_with(&self.profile, self.credential.clone(), self.options.clone()).await
+    }
+}
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+/// Lifecycle of a supervised session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Opening a session.
+    Connecting,
+    Connected,
+    /// The connection dropped; waiting `delay` before attempt `attempt`.
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+    },
+    /// Gave up; the session will not come back.
+    Failed {
+        reason: String,
+    },
+    /// The shell exited or the session was disconnected on purpose.
+    Closed,
+}
+
+// ---------------------------------------------------------------------------
+// SshSupervisor
+// ---------------------------------------------------------------------------
+
+/// State shared between an [`SshSupervisor`] and its background task.
+struct Supervised<S> {
+    adapter: RwLock<S>,
+    connector: Box<dyn Connector<S>>,
+    policy: ReconnectPolicy,
+    /// Last PTY size requested, re-applied to new sessions.
+    size: Mutex<Option<(u16, u16)>>,
+    /// Active recording, carried over to new sessions.
+    recorder: Mutex<Option<Recorder>>,
+    /// The emulated screen and scrollback, fed with all output.
+    terminal: Mutex<TerminalState>,
+    state: watch::Sender<ConnectionState>,
+}
+
+/// An [`SshAdapter`] that reconnects itself when the connection drops.
+///
+/// Dropping the supervisor stops it; call [`SshSupervisor::disconnect`] to
+/// also close the session cleanly.
+pub struct SshSupervisor {
+    shared: Arc<Supervised<SshAdapter>>,
+    /// Taken once via [`SshSupervisor::output_stream`].
+    output: Option<mpsc::Receiver<Vec<u8>>>,
+    cancel: CancellationToken,
+}
+
+impl SshSupervisor {
+    /// Connect to `profile` and start supervising the session. The first
+    /// connection is not retried: its error is returned as is.
+    pub async fn connect(
+        profile: &ConnectionProfile,
+        credential: Credential,
+        options: SshConnectOptions,
+        policy: ReconnectPolicy,
+    ) -> Result<Self, ConnectionError> {
+        let settings = profile.ssh.clone().unwrap_or_default();
+        let terminal = TerminalState::new(
+            settings.pty.cols,
+            settings.pty.rows,
+            settings.scrollback_lines,
+        );
+        let connector = SshConnector {
+            profile: profile.clone(),
+            credential,
+            options,
+        };
+        let (shared, output, cancel) =
+            Supervised::start(Box::new(connector), policy, terminal).await?;
+
+        Ok(SshSupervisor {
+            shared,
+            output: Some(output),
+            cancel,
+        })
+    }
+
+    /// Take the output receiver. Returns `None` after the first call.
+    ///
+    /// The stream spans reconnects and ends only when the session is
+    /// closed or reconnecting fails.
+    pub fn output_stream(&mut self) -> Option<mpsc::Receiver<Vec<u8>>> {
+        self.output.take()
+    }
+
+    /// The current state.
+    pub fn state(&self) -> ConnectionState {
+        self.shared.state.borrow().clone()
+    }
+
+    /// Watch for state changes. A receiver that falls behind sees only the
+    /// latest state.
+    pub fn subscribe(&self) -> watch::Receiver<ConnectionState> {
+        self.shared.state.subscribe()
+    }
+
+    /// Write to the current session's shell. Fails while reconnecting.
+    pub async fn send_input(&self, data: &[u8]) -> Result<(), ConnectionError> {
+        self.shared.adapter.read().await.send_input(data).await
+    }
+
+    /// Resize the shell. The size is re-applied after a reconnect.
+    pub async fn resize(&self, cols: u16, rows: u16) -> Result<(), ConnectionError> {
+        *self.shared.lock_size() = Some((cols, rows));
+        self.shared.lock_terminal().resize(cols, rows);
+        self.shared.adapter.read().await.resize(cols, rows).await
+    }
+
+    /// Record the shell; `None` stops. The recording continues across
+    /// reconnects, with a `reconnected` marker at each one.
+    pub async fn record(&self, recorder: Option<Recorder>) -> Result<(), ConnectionError> {
+        *self.shared.lock_recorder() = recorder.clone();
+        self.shared.adapter.read().await.record(recorder).await
+    }
+
+    /// The screen plus up to `scrollback` lines of history, for redrawing a
+    /// re-attached client.
+    pub fn snapshot(&self, scrollback: usize) -> Snapshot {
+        self.shared.lock_terminal().snapshot(scrollback)
+    }
+
+    /// Search the scrollback and screen; see [`TerminalState::search`].
+    pub fn search(&self, needle: &str, ignore_case: bool) -> Vec<SearchMatch> {
+        self.shared.lock_terminal().search(needle, ignore_case)
+    }
+
+    /// The current session, for everything else (exec, forwards, shells).
+    /// Holding the guard delays a reconnect.
+    pub async fn adapter(&self) -> RwLockReadGuard<'_, SshAdapter> {
+        self.shared.adapter.read().await
+    }
+
+    /// Stop supervising and close the session.
+    pub async fn disconnect(&self) -> Result<(), ConnectionError> {
+        self.cancel.cancel();
+        let result = self.shared.adapter.write().await.disconnect().await;
+        self.shared.state.send_replace(ConnectionState::Closed);
+        result
+    }
+}
+
+impl Drop for SshSupervisor {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+impl<S: SupervisedSession> Supervised<S> {
+    /// Connect and start the supervising task. The first connection is not
+    /// retried. Returns the shared state, the consumer's output stream and
+    /// the token that stops the task.
+    async fn start(
+        connector: Box<dyn Connector<S>>,
+        policy: ReconnectPolicy,
+        terminal: TerminalState,
+    ) -> Result<(Arc<Self>, mpsc::Receiver<Vec<u8>>, CancellationToken), ConnectionError> {
+        let mut adapter = connector.connect().await?;
+        let Some(shell_output) = adapter.output_stream() else {
+            let _ = adapter.disconnect().await;
+            return Err(no_shell_output());
+        };
+
+        let (state, _) = watch::channel(ConnectionState::Connected);
+        let shared = Arc::new(Supervised {
+            adapter: RwLock::new(adapter),
+            connector,
+            policy,
+            size: Mutex::new(None),
+            recorder: Mutex::new(None),
+            terminal: Mutex::new(terminal),
+            state,
+        });
+        let (output_tx, output) = mpsc::channel(256);
+        let cancel = CancellationToken::new();
+        tokio::spawn(supervise(
+            Arc::clone(&shared),
+            shell_output,
+            output_tx,
+            cancel.clone(),
+        ));
+        Ok((shared, output, cancel))
+    }
+
+    fn lock_size(&self) -> std::sync::MutexGuard<'_, Option<(u16, u16)>> {
+        self.size.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_recorder(&self) -> std::sync::MutexGuard<'_, Option<Recorder>> {
+        self.recorder.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_terminal(&self) -> std::sync::MutexGuard<'_, TerminalState> {
+        self.terminal.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        self.state.send_replace(state);
+    }
+
+    /// Reconnect with backoff. Returns the new session's output, or `None`
+    /// after giving up or being cancelled.
+    async fn reconnect(&self, cancel: &CancellationToken) -> Option<mpsc::Receiver<Vec<u8>>> {
+        let mut attempt = 0u32;
+        loop {
+            attempt += 1;
+            if self.policy.max_attempts.is_some_and(|max| attempt > max) {
+                self.set_state(ConnectionState::Failed {
+                    reason: format!("gave up after {} attempts", attempt - 1),
+                });
+                return None;
+            }
+            let delay = self.policy.delay(attempt, jitter_sample());
+            self.set_state(ConnectionState::Reconnecting { attempt, delay });
+            tokio::select! {
+                _ = cancel.cancelled() => return None,
+                _ = tokio::time::sleep(delay) => {}
+            }
+
+            self.set_state(ConnectionState::Connecting);
+            let connected = self.connector.connect().await;
+            let mut adapter = match connected {
+                Ok(adapter) => adapter,
+                Err(e) if is_transient(&e) => {
+                    warn!("reconnect attempt {attempt} failed: {e}");
+                    continue;
+                }
+                Err(e) => {
+                    self.set_state(ConnectionState::Failed {
+                        reason: e.to_string(),
+                    });
+                    return None;
+                }
+            };
+
+            let size = *self.lock_size();
+            if let Some((cols, rows)) = size {
+                let _ = adapter.resize(cols, rows).await;
+            }
+            let recorder = self.lock_recorder().clone();
+            if let Some(recorder) = recorder {
+                recorder.marker("reconnected");
+                let _ = adapter.record(Some(recorder)).await;
+            }
+            let Some(output) = adapter.output_stream() else {
+                let _ = adapter.disconnect().await;
+                self.set_state(ConnectionState::Failed {
+                    reason: no_shell_output().to_string(),
+                });
+                return None;
+            };
+            let mut old = std::mem::replace(&mut *self.adapter.write().await, adapter);
+            let _ = old.disconnect().await;
+            if cancel.is_cancelled() {
+                return None;
+            }
+            self.set_state(ConnectionState::Connected);
+            return Some(output);
+        }
+    }
+}
+
+/// Forward shell output to the consumer, reconnecting whenever the
+/// transport drops.
+async fn supervise<S: SupervisedSession>(
+    shared: Arc<Supervised<S>>,
+    mut shell_output: mpsc::Receiver<Vec<u8>>,
+    output_tx: mpsc::Sender<Vec<u8>>,
+    cancel: CancellationToken,
+) {
+    loop {
+        loop {
+            let chunk = tokio::select! {
+                _ = cancel.cancelled() => return,
+                chunk = shell_output.recv() => chunk,
+            };
+            match chunk {
+                Some(chunk) => {
+                    shared.lock_terminal().feed(&chunk);
+                    if output_tx.send(chunk).await.is_err() {
+                        // Nobody is reading any more.
+                        let _ = shared.adapter.write().await.disconnect().await;
+                        shared.set_state(ConnectionState::Closed);
+                        return;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        if cancel.is_cancelled() {
+            return;
+        }
+        if shared.adapter.read().await.is_connected().await {
+            // The shell exited on its own; that ends the session.
+            shared.set_state(ConnectionState::Closed);
+            return;
+        }
+        match shared.reconnect(&cancel).await {
+            Some(output) => shell_output = output,
+            None => return,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, VecDeque};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn policy() -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_attempts: None,
+        }
+    }
+
+    #[test]
+    fn delay_grows_exponentially_up_to_the_cap() {
+        let p = policy();
+        // A sample of 0.5 sits in the middle of the jitter range.
+        let delays: Vec<_> = (1..=6).map(|n| p.delay(n, 0.5).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 10, 10]);
+        assert_eq!(p.delay(u32::MAX, 0.5), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn jitter_spreads_around_the_base_delay() {
+        let p = policy();
+        assert_eq!(p.delay(3, 0.0), Duration::from_secs(2));
+        assert_eq!(p.delay(3, 0.999_999).as_secs(), 5);
+        for _ in 0..100 {
+            let sample = jitter_sample();
+            assert!((0.0..1.0).contains(&sample));
+        }
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        assert!(is_transient(&ConnectionError::Refused {
+            host: "h".into(),
+            port: 22
+        }));
+        assert!(is_transient(&ConnectionError::Io(std::io::Error::from(
+            std::io::ErrorKind::ConnectionReset
+        ))));
+        assert!(!is_transient(&ConnectionError::AuthFailed {
+            reason: "no".into(),
+            attempts: Vec::new()
+        }));
+        assert!(!is_transient(&ConnectionError::JumpHost {
+            hop: 1,
+            name: "bastion".into(),
+            source: Box::new(ConnectionError::HostKeyRejected {
+                host: "h".into(),
+                port: 22,
+                fingerprint: "SHA256:x".into()
+            }),
+        }));
+        assert!(!is_transient(&ConnectionError::UnsupportedAlgorithm {
+            kind: "cipher".into(),
+            name: "rot13".into()
+        }));
+    }
+
+    /// A session whose output and transport the test drives through its
+    /// [`Remote`].
+    struct FakeSession {
+        output: Option<mpsc::Receiver<Vec<u8>>>,
+        connected: Arc<AtomicBool>,
+        resized: Arc<Mutex<Vec<(u16, u16)>>>,
+    }
+
+    struct Remote {
+        output: mpsc::Sender<Vec<u8>>,
+        connected: Arc<AtomicBool>,
+        resized: Arc<Mutex<Vec<(u16, u16)>>>,
+    }
+
+    impl Remote {
+        /// Lose the transport: the output ends and the session reports it.
+        fn drop_transport(self) {
+            self.connected.store(false, Ordering::SeqCst);
+        }
+
+        /// End the shell with the transport still up.
+        fn exit(self) {}
+    }
+
+    fn session() -> (FakeSession, Remote) {
+        let (tx, rx) = mpsc::channel(16);
+        let connected = Arc::new(AtomicBool::new(true));
+        let resized = Arc::new(Mutex::new(Vec::new()));
+        let session = FakeSession {
+            output: Some(rx),
+            connected: Arc::clone(&connected),
+            resized: Arc::clone(&resized),
+        };
+        let remote = Remote {
+            output: tx,
+            connected,
+            resized,
+        };
+        (session, remote)
+    }
+
+    #[async_trait]
+    impl SupervisedSession for FakeSession {
+        fn output_stream(&mut self) -> Option<mpsc::Receiver<Vec<u8>>> {
+            self.output.take()
+        }
+
+        async fn send_input(&self, _data: &[u8]) -> Result<(), ConnectionError> {
+            Ok(())
+        }
+
+        async fn resize(&self, cols: u16, rows: u16) -> Result<(), ConnectionError> {
+            self.resized.lock().unwrap().push((cols, rows));
+            Ok(())
+        }
+
+        async fn record(&self, _recorder: Option<Recorder>) -> Result<(), ConnectionError> {
+            Ok(())
+        }
+
+        async fn is_connected(&self) -> bool {
+            self.connected.load(Ordering::SeqCst)
+        }
+
+        async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+            self.connected.store(false, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    /// Hands out queued results, one per connect, then refuses.
+    struct FakeConnector {
+        results: Mutex<VecDeque<Result<FakeSession, ConnectionError>>>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Connector<FakeSession> for FakeConnector {
+        async fn connect(&self) -> Result<FakeSession, ConnectionError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            // A real connect waits on the network.
+            tokio::task::yield_now().await;
+            let next = self.results.lock().unwrap().pop_front();
+            next.unwrap_or_else(|| Err(refused()))
+        }
+    }
+
+    fn refused() -> ConnectionError {
+        ConnectionError::Refused {
+            host: "h".into(),
+            port: 22,
+        }
+    }
+
+    type Started = (
+        Arc<Supervised<FakeSession>>,
+        mpsc::Receiver<Vec<u8>>,
+        CancellationToken,
+    );
+
+    /// Start supervising with `results` queued on the connector. Returns the
+    /// connect-call counter too.
+    async fn start(
+        results: Vec<Result<FakeSession, ConnectionError>>,
+        max_attempts: Option<u32>,
+    ) -> Result<(Started, Arc<AtomicUsize>), ConnectionError> {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let connector = FakeConnector {
+            results: Mutex::new(results.into()),
+            calls: Arc::clone(&calls),
+        };
+        let policy = ReconnectPolicy {
+            jitter: 0.0,
+            max_attempts,
+            ..policy()
+        };
+        let terminal = TerminalState::new(80, 24, 100);
+        let started = Supervised::start(Box::new(connector), policy, terminal).await?;
+        Ok((started, calls))
+    }
+
+    /// Collect every state `shared` publishes from now on.
+    fn watch_states(shared: &Supervised<FakeSession>) -> Arc<Mutex<Vec<ConnectionState>>> {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut states = shared.state.subscribe();
+        let sink = Arc::clone(&seen);
+        tokio::spawn(async move {
+            while states.changed().await.is_ok() {
+                let state = states.borrow_and_update().clone();
+                sink.lock().unwrap().push(state);
+            }
+        });
+        seen
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn output_continues_across_a_reconnect() {
+        let (first, first_remote) = session();
+        let (second, second_remote) = session();
+        let ((shared, mut output, _cancel), calls) =
+            start(vec![Ok(first), Err(refused()), Ok(second)], None)
+                .await
+                .unwrap();
+        let states = watch_states(&shared);
+
+        first_remote.output.send(b"before".to_vec()).await.unwrap();
+        assert_eq!(output.recv().await.unwrap(), b"before");
+        *shared.lock_size() = Some((120, 40));
+        first_remote.drop_transport();
+
+        second_remote.output.send(b"after".to_vec()).await.unwrap();
+        assert_eq!(output.recv().await.unwrap(), b"after");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(*second_remote.resized.lock().unwrap(), [(120, 40)]);
+        assert_eq!(*shared.state.borrow(), ConnectionState::Connected);
+        assert!(!shared.lock_terminal().search("after", false).is_empty());
+
+        let states = states.lock().unwrap().clone();
+        let retries: Vec<_> = states
+            .iter()
+            .filter_map(|s| match s {
+                ConnectionState::Reconnecting { attempt, delay } => Some((*attempt, *delay)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            retries,
+            [(1, Duration::from_secs(1)), (2, Duration::from_secs(2))]
+        );
+        assert!(states.contains(&ConnectionState::Connecting));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn permanent_errors_fail_without_retrying() {
+        let (first, remote) = session();
+        let rejected = ConnectionError::AuthFailed {
+            reason: "no".into(),
+            attempts: Vec::new(),
+        };
+        let ((shared, mut output, _cancel), calls) =
+            start(vec![Ok(first), Err(rejected)], None).await.unwrap();
+
+        remote.drop_transport();
+        assert_eq!(output.recv().await, None);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(matches!(
+            &*shared.state.borrow(),
+            ConnectionState::Failed { reason } if reason.starts_with("Authentication failed")
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unknown_terminal_modes_fail_without_retrying() {
+        let (first, remote) = session();
+        let modes = BTreeMap::from([("ECHOO".to_owned(), 1)]);
+        let typo = crate::connection::pty::terminal_modes(&modes).unwrap_err();
+        let ((shared, mut output, _cancel), calls) =
+            start(vec![Ok(first), Err(typo)], None).await.unwrap();
+
+        remote.drop_transport();
+        assert_eq!(output.recv().await, None);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(matches!(
+            &*shared.state.borrow(),
+            ConnectionState::Failed { reason } if reason.contains("unknown terminal mode ECHOO")
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_max_attempts() {
+        let (first, remote) = session();
+        let ((shared, mut output, _cancel), calls) = start(vec![Ok(first)], Some(2)).await.unwrap();
+
+        remote.drop_transport();
+        assert_eq!(output.recv().await, None);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            *shared.state.borrow(),
+            ConnectionState::Failed {
+                reason: "gave up after 2 attempts".into()
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shell_exit_closes_the_session() {
+        let (first, remote) = session();
+        let ((shared, mut output, _cancel), calls) = start(vec![Ok(first)], None).await.unwrap();
+
+        remote.exit();
+        assert_eq!(output.recv().await, None);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(*shared.state.borrow(), ConnectionState::Closed);
+    }
+
+    #[tokio::test]
+    async fn sessions_without_output_are_a_configuration_error() {
+        let (mut first, _remote) = session();
+        first.output = None;
+        let err = match start(vec![Ok(first)], None).await {
+            Ok(_) => panic!("a session without shell output cannot be supervised"),
+            Err(e) => e,
+        };
+        assert!(matches!(err, ConnectionError::Config(_)));
+        assert!(!is_transient(&err));
+    }
+}
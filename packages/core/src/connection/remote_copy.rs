@@ -0,0 +1,591 @@
+//! Copies from one remote session to another.
+//!
+//! [`RemoteCopy`] streams a file or a directory tree from one
+//! [`FileTransferAdapter`] to another through an in-memory pipe, so nothing
+//! is written to local disk. Any pair of adapters works: SFTP to SFTP, or
+//! FTP or a pod's files to SFTP once those adapters exist. Progress is
+//! published as [`TransferProgress`], like a queued transfer's.
+//!
+//! When both sessions are logged in to the same account on the same server
+//! (equal [`ServerIdentity`](super::files::ServerIdentity)) and a shell
+//! there is supplied, the copy runs on the server as `cp -R -p` instead of
+//! passing every byte through this machine twice. Copying a path there onto
+//! itself, however it is spelled, is refused, since streaming it would
+//! truncate the source. Streamed copies keep modes and modification times
+//! too.
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Utc};
+
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use super::files::{file_name, join, parent, shell_quote, FileEntry, FileKind};
+use super::transfer::{eta, Meter, OnProgress, TransferProgress, TransferStatus};
+use super::{ConnectionError, FileTransferAdapter, TerminalAdapter};
+
+/// Bytes buffered between the reading and the writing session.
+const PIPE_BUFFER: usize = 256 * 1024;
+
+/// Copies between two sessions.
+pub struct RemoteCopy {
+    source: Arc<dyn FileTransferAdapter>,
+    dest: Arc<dyn FileTransferAdapter>,
+    shell: Option<Arc<dyn TerminalAdapter>>,
+    progress: watch::Sender<TransferProgress>,
+    cancel: CancellationToken,
+    /// The destination file being written, removed if the copy stops.
+    partial: Mutex<Option<String>>,
+}
+
+impl RemoteCopy {
+    pub fn new(source: Arc<dyn FileTransferAdapter>, dest: Arc<dyn FileTransferAdapter>) -> Self {
+        RemoteCopy {
+            source,
+            dest,
+            shell: None,
+            progress: watch::channel(TransferProgress::queued()).0,
+            cancel: CancellationToken::new(),
+            partial: Mutex::new(None),
+        }
+    }
+
+    /// A shell on the source's server, used when the destination turns out
+    /// to be the same account there.
+    pub fn with_server_shell(mut self, shell: Arc<dyn TerminalAdapter>) -> Self {
+        self.shell = Some(shell);
+        self
+    }
+
+    pub fn progress(&self) -> watch::Receiver<TransferProgress> {
+        self.progress.subscribe()
+    }
+
+    /// Cancelling this stops the copy with [`ConnectionError::Cancelled`].
+    /// Files already copied stay; the one being copied is removed.
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Copy `from` on the source to `to` on the destination. A directory is
+    /// copied with everything under it except symlinks, merging into `to`
+    /// if that exists. Modes and modification times are kept. Returns the
+    /// bytes copied.
+    ///
+    /// A file left part-written by a failure or a cancel is removed.
+    pub async fn run(&self, from: &str, to: &str) -> Result<u64, ConnectionError> {
+        self.set_status(TransferStatus::InProgress);
+        let result = tokio::select! {
+            biased;
+            _ = self.cancel.cancelled() => Err(ConnectionError::Cancelled),
+            result = self.copy(from, to) => result,
+        };
+        let partial = self.partial().take();
+        if let (Err(_), Some(path)) = (&result, partial) {
+            match self.dest.delete(&path).await {
+                Ok(()) | Err(ConnectionError::NotFound { .. }) => {}
+                Err(e) => warn!("cannot remove partial copy {path}: {e}"),
+            }
+        }
+        match &result {
+            Ok(size) => {
+                self.progress.send_modify(|p| p.bytes_transferred = *size);
+                self.set_status(TransferStatus::Completed);
+            }
+            Err(ConnectionError::Cancelled) => self.set_status(TransferStatus::Cancelled),
+            Err(e) => self.set_status(TransferStatus::Failed(e.to_string())),
+        }
+        result
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<u64, ConnectionError> {
+        if self.same_file(from, to).await? {
+            return Err(ConnectionError::SameFile { path: from.into() });
+        }
+        let plan = self.collect(from, to).await?;
+        let total = plan.files.iter().map(|file| file.size).sum();
+        self.progress.send_modify(|p| p.total_bytes = total);
+
+        if let Some(shell) = self.same_server_shell() {
+            self.copy_on_server(shell, from, to, plan.is_dir).await?;
+            return Ok(total);
+        }
+
+        for dir in &plan.dirs {
+            match self.dest.stat(&dir.to).await {
+                Ok(entry) if entry.is_dir() => {}
+                _ => self.dest.mkdir(&dir.to).await?,
+            }
+        }
+        let meter = Mutex::new(Meter::new(Instant::now(), 0));
+        let mut done = 0;
+        for file in &plan.files {
+            let base = done;
+            let on_progress = |n: u64| {
+                let bytes = base + n;
+                let speed = lock(&meter).record(Instant::now(), bytes);
+                self.progress.send_modify(|p| {
+                    p.bytes_transferred = bytes;
+                    p.speed_bps = speed;
+                    p.eta = eta(total.saturating_sub(bytes), speed);
+                });
+            };
+            *self.partial() = Some(file.to.clone());
+            done += stream(
+                self.source.as_ref(),
+                &file.from,
+                self.dest.as_ref(),
+                &file.to,
+                &on_progress,
+            )
+            .await?;
+            self.preserve(file).await?;
+            *self.partial() = None;
+        }
+        // Children first, since writing into a directory moves its mtime.
+        for dir in plan.dirs.iter().rev() {
+            self.preserve(dir).await?;
+        }
+        Ok(done)
+    }
+
+    /// Whether `from` on the source and `to` on the destination are the
+    /// same file, once `.`, `..` and symlinks are resolved. A `to` that
+    /// does not exist yet is resolved through its directory.
+    async fn same_file(&self, from: &str, to: &str) -> Result<bool, ConnectionError> {
+        if !self.same_server() {
+            return Ok(false);
+        }
+        let from = self.source.canonicalize(from).await?;
+        let to = match self.dest.canonicalize(to).await {
+            Ok(to) => to,
+            Err(ConnectionError::NotFound { .. }) => match self.dest.canonicalize(parent(to)).await
+            {
+                Ok(dir) => join(&dir, file_name(to)),
+                Err(ConnectionError::NotFound { .. }) => return Ok(false),
+                Err(e) => return Err(e),
+            },
+            Err(e) => return Err(e),
+        };
+        Ok(from == to)
+    }
+
+    /// Give `item` the source's mode and modification time, as `cp -p`
+    /// does. Either is skipped if the destination cannot set it.
+    async fn preserve(&self, item: &CopyItem) -> Result<(), ConnectionError> {
+        if let Some(mode) = item.permissions {
+            skip_unsupported(self.dest.chmod(&item.to, mode).await)?;
+        }
+        if let Some(time) = item.modified {
+            skip_unsupported(self.dest.set_modified(&item.to, time).await)?;
+        }
+        Ok(())
+    }
+
+    /// Whether both sessions are the same account on the same server.
+    fn same_server(&self) -> bool {
+        self.source
+            .server_identity()
+            .is_some_and(|source| self.dest.server_identity() == Some(source))
+    }
+
+    /// The shell to copy with, if both sessions are the same account on the
+    /// same server.
+    fn same_server_shell(&self) -> Option<&dyn TerminalAdapter> {
+        self.shell.as_deref().filter(|_| self.same_server())
+    }
+
+    fn partial(&self) -> MutexGuard<'_, Option<String>> {
+        lock(&self.partial)
+    }
+
+    async fn copy_on_server(
+        &self,
+        shell: &dyn TerminalAdapter,
+        from: &str,
+        to: &str,
+        is_dir: bool,
+    ) -> Result<(), ConnectionError> {
+        // `cp -R dir existing` would nest `dir` inside `existing`; copying
+        // `dir/.` merges the way streaming does.
+        let merge = is_dir && self.dest.stat(to).await.is_ok_and(|entry| entry.is_dir());
+        let source = if merge {
+            join(from, ".")
+        } else {
+            from.to_owned()
+        };
+        let command = format!("cp -R -p -- {} {}", shell_quote(&source), shell_quote(to));
+        let result = shell.exec(&command).await?;
+        if result.exit_code != Some(0) {
+            return Err(ConnectionError::Protocol(format!(
+                "cp failed: {}",
+                String::from_utf8_lossy(&result.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+
+    /// What to create and copy for `from` → `to`, parents first.
+    async fn collect(&self, from: &str, to: &str) -> Result<CopyPlan, ConnectionError> {
+        let root = self.source.stat(from).await?;
+        let mut plan = CopyPlan {
+            is_dir: root.is_dir(),
+            dirs: Vec::new(),
+            files: Vec::new(),
+        };
+        if !plan.is_dir {
+            plan.files.push(CopyItem::new(&root, from, to));
+            return Ok(plan);
+        }
+        let mut pending = vec![CopyItem::new(&root, from, to)];
+        while let Some(dir) = pending.pop() {
+            for entry in self.source.list_dir(&dir.from).await? {
+                let item = CopyItem::new(&entry, &entry.path, &join(&dir.to, &entry.name));
+                match entry.kind {
+                    FileKind::Dir => pending.push(item),
+                    FileKind::File => plan.files.push(item),
+                    FileKind::Symlink | FileKind::Other => {}
+                }
+            }
+            plan.dirs.push(dir);
+        }
+        Ok(plan)
+    }
+
+    fn set_status(&self, status: TransferStatus) {
+        self.progress.send_modify(|p| {
+            p.speed_bps = 0.0;
+            p.eta = None;
+            p.status = status;
+        });
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn skip_unsupported(result: Result<(), ConnectionError>) -> Result<(), ConnectionError> {
+    match result {
+        Err(ConnectionError::NotSupported { .. }) => Ok(()),
+        result => result,
+    }
+}
+
+struct CopyPlan {
+    is_dir: bool,
+    /// Directories, each after its parent.
+    dirs: Vec<CopyItem>,
+    files: Vec<CopyItem>,
+}
+
+struct CopyItem {
+    from: String,
+    to: String,
+    /// 0 for a symlink given as the root, whose target's size is unknown.
+    size: u64,
+    /// The source's mode and mtime; `None` for a symlink given as the root,
+    /// which describes the link rather than its target.
+    permissions: Option<u32>,
+    modified: Option<DateTime<Utc>>,
+}
+
+impl CopyItem {
+    fn new(entry: &FileEntry, from: &str, to: &str) -> Self {
+        let link = entry.kind == FileKind::Symlink;
+        CopyItem {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            size: if entry.kind == FileKind::File {
+                entry.size
+            } else {
+                0
+            },
+            permissions: (!link).then_some(entry.permissions),
+            modified: entry.modified_at.filter(|_| !link),
+        }
+    }
+}
+
+/// Pipe one file from `source` to `dest`, both sessions running at once.
+async fn stream(
+    source: &dyn FileTransferAdapter,
+    from: &str,
+    dest: &dyn FileTransferAdapter,
+    to: &str,
+    on_progress: &OnProgress<'_>,
+) -> Result<u64, ConnectionError> {
+    let (mut reader, mut writer) = tokio::io::duplex(PIPE_BUFFER);
+    let send = async move {
+        let sent = source
+            .download_stream(from, &mut writer, 0, on_progress)
+            .await;
+        // Closing the pipe ends the upload.
+        drop(writer);
+        sent
+    };
+    let receive = dest.upload_stream(&mut reader, to, 0, &|_| {});
+    let (sent, received) = tokio::try_join!(send, receive)?;
+    if sent != received {
+        return Err(ConnectionError::Protocol(format!(
+            "{to}: wrote {received} of {sent} bytes"
+        )));
+    }
+    Ok(received)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tokio::sync::Semaphore;
+
+    use super::*;
+    use crate::connection::files::ServerIdentity;
+    use crate::connection::testing::LocalFs;
+
+    fn identity(username: &str) -> Option<ServerIdentity> {
+        Some(ServerIdentity {
+            host_key: "SHA256:test".into(),
+            username: username.into(),
+        })
+    }
+
+    fn session(username: &str) -> Arc<LocalFs> {
+        Arc::new(LocalFs {
+            identity: identity(username),
+            ..LocalFs::default()
+        })
+    }
+
+    fn path(root: &Path, name: &str) -> String {
+        root.join(name).to_str().unwrap().to_owned()
+    }
+
+    fn write(root: &Path, name: &str, data: &str) {
+        let path = root.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, data).unwrap();
+    }
+
+    #[tokio::test]
+    async fn files_stream_between_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.txt", "hello");
+        let copy = RemoteCopy::new(session("alice"), session("bob"));
+        let progress = copy.progress();
+
+        let size = copy
+            .run(&path(dir.path(), "a.txt"), &path(dir.path(), "b.txt"))
+            .await
+            .unwrap();
+
+        assert_eq!(size, 5);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("b.txt")).unwrap(),
+            "hello"
+        );
+        let progress = progress.borrow();
+        assert_eq!(progress.status, TransferStatus::Completed);
+        assert_eq!((progress.bytes_transferred, progress.total_bytes), (5, 5));
+    }
+
+    #[tokio::test]
+    async fn trees_are_recreated_and_merged_into_an_existing_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "src/top.txt", "1");
+        write(dir.path(), "src/sub/deep/leaf.txt", "22");
+        write(dir.path(), "dst/kept.txt", "333");
+        let copy = RemoteCopy::new(session("alice"), session("bob"));
+
+        let size = copy
+            .run(&path(dir.path(), "src"), &path(dir.path(), "dst"))
+            .await
+            .unwrap();
+
+        assert_eq!(size, 3);
+        let read = |name| std::fs::read_to_string(dir.path().join("dst").join(name)).unwrap();
+        assert_eq!(read("top.txt"), "1");
+        assert_eq!(read("sub/deep/leaf.txt"), "22");
+        assert_eq!(read("kept.txt"), "333");
+    }
+
+    #[tokio::test]
+    async fn the_same_account_copies_on_the_server() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "src/sub/leaf.txt", "leaf");
+        std::fs::create_dir(dir.path().join("dst")).unwrap();
+        let shell = session("alice");
+        let copy =
+            RemoteCopy::new(session("alice"), session("alice")).with_server_shell(shell.clone());
+
+        let size = copy
+            .run(&path(dir.path(), "src"), &path(dir.path(), "dst"))
+            .await
+            .unwrap();
+
+        assert_eq!(size, 4);
+        let leaf = dir.path().join("dst/sub/leaf.txt");
+        assert_eq!(std::fs::read_to_string(leaf).unwrap(), "leaf");
+        let commands = shell.commands.lock().unwrap();
+        assert_eq!(commands.len(), 1);
+        assert!(commands[0].starts_with("cp -R -p -- "));
+        assert!(commands[0].contains("/src/.'"));
+    }
+
+    #[tokio::test]
+    async fn different_accounts_stream_even_with_a_shell() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.txt", "hello");
+        let shell = session("alice");
+        let copy =
+            RemoteCopy::new(session("alice"), session("bob")).with_server_shell(shell.clone());
+
+        copy.run(&path(dir.path(), "a.txt"), &path(dir.path(), "b.txt"))
+            .await
+            .unwrap();
+
+        assert!(shell.commands.lock().unwrap().is_empty());
+        assert!(dir.path().join("b.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn failed_server_copies_report_stderr() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.txt", "hello");
+        let copy =
+            RemoteCopy::new(session("alice"), session("alice")).with_server_shell(session("alice"));
+
+        let err = copy
+            .run(
+                &path(dir.path(), "a.txt"),
+                &path(dir.path(), "missing/b.txt"),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(matches!(&err, ConnectionError::Protocol(m) if m.starts_with("cp failed: ")));
+        assert!(matches!(
+            copy.progress().borrow().status,
+            TransferStatus::Failed(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn copies_onto_themselves_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.txt", "hello");
+        let a = path(dir.path(), "a.txt");
+        let copy = RemoteCopy::new(session("alice"), session("alice"));
+
+        let err = copy.run(&a, &a).await.unwrap_err();
+
+        assert!(matches!(err, ConnectionError::SameFile { ref path } if *path == a));
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "hello");
+        // Another account's file at the same path is a different file.
+        let other = RemoteCopy::new(session("alice"), session("bob"));
+        assert!(!matches!(
+            other.run(&a, &a).await,
+            Err(ConnectionError::SameFile { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn copies_onto_themselves_are_refused_however_spelled() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.txt", "hello");
+        std::os::unix::fs::symlink("a.txt", dir.path().join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("alias")).unwrap();
+        let a = path(dir.path(), "a.txt");
+        let copy = RemoteCopy::new(session("alice"), session("alice"));
+
+        for to in ["./a.txt", "sub/../a.txt", "link.txt", "alias/a.txt"] {
+            std::fs::create_dir_all(dir.path().join("sub")).unwrap();
+            let err = copy.run(&a, &path(dir.path(), to)).await.unwrap_err();
+            assert!(
+                matches!(err, ConnectionError::SameFile { .. }),
+                "{to}: {err}"
+            );
+        }
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn streamed_copies_keep_modes_and_modification_times() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::{Duration, SystemTime};
+
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "src/sub/run.sh", "#!/bin/sh");
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let set = |name: &str, mode: u32| {
+            let path = dir.path().join(name);
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+            std::fs::File::open(&path)
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        };
+        set("src/sub/run.sh", 0o751);
+        set("src/sub", 0o710);
+        set("src", 0o750);
+        let copy = RemoteCopy::new(session("alice"), session("bob"));
+
+        copy.run(&path(dir.path(), "src"), &path(dir.path(), "dst"))
+            .await
+            .unwrap();
+
+        for (name, mode) in [
+            ("dst", 0o750),
+            ("dst/sub", 0o710),
+            ("dst/sub/run.sh", 0o751),
+        ] {
+            let meta = std::fs::metadata(dir.path().join(name)).unwrap();
+            assert_eq!(meta.permissions().mode() & 0o7777, mode, "{name}");
+            assert_eq!(meta.modified().unwrap(), old, "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_streams_remove_the_partial_file() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.txt", "0123456789");
+        let failing = LocalFs {
+            fail_after: Some(1),
+            ..LocalFs::gated(&Arc::new(Semaphore::new(Semaphore::MAX_PERMITS)))
+        };
+        let copy = RemoteCopy::new(Arc::new(failing), session("bob"));
+
+        let err = copy
+            .run(&path(dir.path(), "a.txt"), &path(dir.path(), "b.txt"))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, ConnectionError::Io(_)), "{err}");
+        assert!(!dir.path().join("b.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn cancelled_copies_stop() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.txt", "hello");
+        let copy = RemoteCopy::new(session("alice"), session("bob"));
+        copy.cancel_token().cancel();
+
+        let err = copy
+            .run(&path(dir.path(), "a.txt"), &path(dir.path(), "b.txt"))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, ConnectionError::Cancelled));
+        assert_eq!(copy.progress().borrow().status, TransferStatus::Cancelled);
+        assert!(!dir.path().join("b.txt").exists());
+    }
+}
@@ -0,0 +1,858 @@
+//! SFTP, on an SSH session.
+//!
+//! [`SftpAdapter`] runs the `sftp` subsystem on a channel of an SSH session
+//! and implements [`FileTransferAdapter`] over it. The session is either its
+//! own — [`SftpAdapter::connect`] logs in with the profile but opens no
+//! shell — or borrowed from an [`SshAdapter`] with [`SftpAdapter::from_ssh`],
+//! so a terminal tab and its file browser share one login.
+//!
+//! Listings carry owner and group names taken from the server's `ls -l`
+//! style long names; `stat` has only the numeric ids, as SFTP v3 sends no
+//! names with attributes. Symlinks are described along with their target
+//! and what it resolves to.
+//!
+//! Transfers keep [`PIPELINE`] read or write requests in flight, so
+//! throughput is not capped at one chunk per round trip. File handles are
+//! closed even when a transfer is dropped half-way by a pause or cancel.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::stream::{FuturesOrdered, FuturesUnordered, StreamExt};
+use russh_sftp::client::error::Error as SftpError;
+use russh_sftp::client::RawSftpSession;
+use russh_sftp::protocol::{FileAttributes, OpenFlags, StatusCode};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::files::{file_name, join, timestamp, FileEntry, FileKind, ServerIdentity};
+use super::ssh::{open_subsystem, SessionHandle, SshAdapter, SshConnectOptions};
+use super::transfer::OnProgress;
+use super::{ConnectionAdapter, ConnectionError, Credential, FileTransferAdapter};
+use crate::profile::types::{ConnectionProfile, Protocol, ShellMode, SshSettings};
+
+/// How long russh-sftp waits for each response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bytes per read or write request. 32 KiB is the size every server must
+/// accept.
+const CHUNK: usize = 32 * 1024;
+
+/// Requests a transfer or listing keeps in flight at once. OpenSSH's `sftp`
+/// defaults to 64; 16 already fills most links.
+const PIPELINE: usize = 16;
+
+/// SFTP session adapter.
+pub struct SftpAdapter {
+    sftp: Arc<RawSftpSession>,
+    /// The SSH session the subsystem channel belongs to, kept alive for as
+    /// long as the adapter.
+    session: SessionHandle,
+    /// The connection opened by [`SftpAdapter::connect`]; `None` when the
+    /// session belongs to an [`SshAdapter`].
+    ssh: Option<SshAdapter>,
+    identity: Option<ServerIdentity>,
+}
+
+impl SftpAdapter {
+    /// Connect using caller-supplied [`SshConnectOptions`]. The profile's
+    /// shell and port forwards are skipped.
+    pub async fn connect_with(
+        profile: &ConnectionProfile,
+        credential: Credential,
+        options: SshConnectOptions,
+    ) -> Result<Self, ConnectionError> {
+        if !matches!(profile.protocol, Protocol::Sftp | Protocol::Ssh) {
+            return Err(ConnectionError::NotSupported {
+                protocol: profile.protocol.clone(),
+            });
+        }
+        let mut profile = profile.clone();
+        let settings = profile.ssh.get_or_insert_with(SshSettings::default);
+        settings.shell_mode = ShellMode::ExecOnly;
+        settings.port_forwards.clear();
+
+        let ssh = SshAdapter::connect_with(&profile, credential, options).await?;
+        let session = ssh.session();
+        let sftp = Arc::new(open_sftp(&session).await?);
+        Ok(SftpAdapter {
+            sftp,
+            session,
+            identity: ssh.server_identity(),
+            ssh: Some(ssh),
+        })
+    }
+
+    /// Open SFTP on `ssh`'s session, without logging in again. The session
+    /// stays up while either adapter holds it.
+    pub async fn from_ssh(ssh: &SshAdapter) -> Result<Self, ConnectionError> {
+        let session = ssh.session();
+        let sftp = Arc::new(open_sftp(&session).await?);
+        Ok(SftpAdapter {
+            sftp,
+            session,
+            ssh: None,
+            identity: ssh.server_identity(),
+        })
+    }
+
+    /// Open `path` with `flags`, closing the handle when it is dropped.
+    async fn open(&self, path: &str, flags: OpenFlags) -> Result<OpenHandle, ConnectionError> {
+        let handle = self
+            .sftp
+            .open(path, flags, FileAttributes::empty())
+            .await
+            .map_err(|e| sftp_error(path, e))?
+            .handle;
+        Ok(OpenHandle::new(&self.sftp, handle))
+    }
+}
+
+/// An open file or directory handle. [`OpenHandle::close`] closes it in
+/// line; dropping it unclosed, e.g. with a paused or cancelled transfer's
+/// future, closes it in the background.
+struct OpenHandle {
+    sftp: Arc<RawSftpSession>,
+    handle: Option<String>,
+}
+
+impl OpenHandle {
+    fn new(sftp: &Arc<RawSftpSession>, handle: String) -> Self {
+        OpenHandle {
+            sftp: Arc::clone(sftp),
+            handle: Some(handle),
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        self.handle.as_deref().unwrap_or_default()
+    }
+
+    async fn close(mut self) {
+        if let Some(handle) = self.handle.take() {
+            // Closing is best-effort: the transfer result is already known.
+            let _ = self.sftp.close(handle).await;
+        }
+    }
+}
+
+impl Drop for OpenHandle {
+    fn drop(&mut self) {
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let sftp = Arc::clone(&self.sftp);
+            runtime.spawn(async move {
+                let _ = sftp.close(handle).await;
+            });
+        }
+    }
+}
+
+/// Fill in a symlink's target and what it resolves to, asking for both at
+/// once.
+async fn resolve_link(sftp: &RawSftpSession, entry: &mut FileEntry) -> Result<(), ConnectionError> {
+    if entry.kind != FileKind::Symlink {
+        return Ok(());
+    }
+    let path = entry.path.as_str();
+    let (target, resolved) = tokio::join!(sftp.readlink(path), sftp.stat(path));
+    let target = target.map_err(|e| sftp_error(path, e))?;
+    entry.symlink_target = Some(first_name(path, target.files)?);
+    // A dangling link is not an error; it just resolves to nothing.
+    entry.target_kind = resolved.ok().map(|attrs| kind(&attrs.attrs));
+    Ok(())
+}
+
+/// Resolve the symlinks among `entries`, up to [`PIPELINE`] at a time.
+async fn resolve_links(
+    sftp: &RawSftpSession,
+    entries: &mut [FileEntry],
+) -> Result<(), ConnectionError> {
+    let mut entries = entries.iter_mut();
+    let mut pending = FuturesUnordered::new();
+    loop {
+        while pending.len() < PIPELINE {
+            // Anything but a symlink resolves at once.
+            let Some(entry) = entries.next() else {
+                break;
+            };
+            pending.push(resolve_link(sftp, entry));
+        }
+        match pending.next().await {
+            Some(resolved) => resolved?,
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Write `source` to `handle` from `offset` on, with up to [`PIPELINE`]
+/// writes in flight. Progress is reported as writes are acknowledged.
+async fn write_all(
+    sftp: &RawSftpSession,
+    handle: &str,
+    remote: &str,
+    source: &mut (dyn AsyncRead + Send + Unpin),
+    offset: u64,
+    on_progress: &OnProgress<'_>,
+) -> Result<u64, ConnectionError> {
+    let mut pending = FuturesOrdered::new();
+    let mut next = offset;
+    let mut drained = false;
+    loop {
+        while !drained && pending.len() < PIPELINE {
+            let mut buf = vec![0; CHUNK];
+            let n = source.read(&mut buf).await?;
+            if n == 0 {
+                drained = true;
+                break;
+            }
+            buf.truncate(n);
+            let at = next;
+            next += n as u64;
+            pending
+                .push_back(async move { sftp.write(handle, at, buf).await.map(|_| at + n as u64) });
+        }
+        match pending.next().await {
+            Some(Ok(written)) => on_progress(written),
+            Some(Err(e)) => return Err(sftp_error(remote, e)),
+            None => return Ok(next),
+        }
+    }
+}
+
+/// Read `handle` from `offset` on into `sink`, with up to [`PIPELINE`]
+/// reads in flight.
+async fn read_all(
+    sftp: &RawSftpSession,
+    handle: &str,
+    remote: &str,
+    sink: &mut (dyn AsyncWrite + Send + Unpin),
+    mut offset: u64,
+    on_progress: &OnProgress<'_>,
+) -> Result<u64, ConnectionError> {
+    let mut pending = FuturesOrdered::new();
+    let mut next = offset;
+    loop {
+        while pending.len() < PIPELINE {
+            let at = next;
+            next += CHUNK as u64;
+            pending.push_back(async move { sftp.read(handle, at, CHUNK as u32).await });
+        }
+        let Some(read) = pending.next().await else {
+            break;
+        };
+        match read {
+            Ok(data) if data.data.is_empty() => break,
+            Ok(data) => {
+                sink.write_all(&data.data).await?;
+                offset += data.data.len() as u64;
+                on_progress(offset);
+                if data.data.len() < CHUNK {
+                    // A short read leaves a gap before the requests already
+                    // in flight: drop them and carry on from here.
+                    pending = FuturesOrdered::new();
+                    next = offset;
+                }
+            }
+            Err(SftpError::Status(status)) if status.status_code == StatusCode::Eof => break,
+            Err(e) => return Err(sftp_error(remote, e)),
+        }
+    }
+    sink.flush().await?;
+    Ok(offset)
+}
+
+/// Start the `sftp` subsystem on a new channel of `session`.
+async fn open_sftp(session: &SessionHandle) -> Result<RawSftpSession, ConnectionError> {
+    let channel = open_subsystem(session, "sftp").await?;
+    let sftp = RawSftpSession::new(channel.into_stream());
+    sftp.set_timeout(REQUEST_TIMEOUT.as_secs()).await;
+    sftp.init()
+        .await
+        .map_err(|e| ConnectionError::Protocol(format!("SFTP subsystem failed to start: {e}")))?;
+    Ok(sftp)
+}
+
+/// Map an SFTP failure on `path` to a [`ConnectionError`].
+fn sftp_error(path: &str, err: SftpError) -> ConnectionError {
+    match err {
+        SftpError::Status(status) => match status.status_code {
+            StatusCode::NoSuchFile => ConnectionError::NotFound {
+                path: path.to_owned(),
+            },
+            StatusCode::PermissionDenied => ConnectionError::PermissionDenied {
+                path: path.to_owned(),
+            },
+            code if status.error_message.is_empty() => {
+                ConnectionError::Protocol(format!("{path}: {code}"))
+            }
+            _ => ConnectionError::Protocol(format!("{path}: {}", status.error_message)),
+        },
+        SftpError::Timeout => ConnectionError::Timeout {
+            timeout: REQUEST_TIMEOUT,
+        },
+        other => ConnectionError::Protocol(format!("{path}: {other}")),
+    }
+}
+
+fn first_name(
+    path: &str,
+    files: Vec<russh_sftp::protocol::File>,
+) -> Result<String, ConnectionError> {
+    files
+        .into_iter()
+        .next()
+        .map(|file| file.filename)
+        .ok_or_else(|| ConnectionError::Protocol(format!("{path}: empty reply from server")))
+}
+
+fn kind(attrs: &FileAttributes) -> FileKind {
+    attrs
+        .permissions
+        .map_or(FileKind::Other, FileKind::from_mode)
+}
+
+/// Build an entry from SFTP attributes. `longname` is the `ls -l` line a
+/// directory listing sends with each name.
+fn entry(path: String, attrs: &FileAttributes, longname: Option<&str>) -> FileEntry {
+    let (owner, group) = longname.and_then(owner_and_group).unzip();
+    FileEntry {
+        name: file_name(&path).to_owned(),
+        path,
+        kind: kind(attrs),
+        size: attrs.size.unwrap_or(0),
+        permissions: attrs.permissions.unwrap_or(0) & 0o7777,
+        modified_at: attrs.mtime.and_then(timestamp),
+        accessed_at: attrs.atime.and_then(timestamp),
+        uid: attrs.uid,
+        gid: attrs.gid,
+        owner,
+        group,
+        symlink_target: None,
+        target_kind: None,
+    }
+}
+
+/// Owner and group from an `ls -l` line:
+/// `-rw-r--r--    1 alice    staff        1234 Jan  1 12:00 notes.txt`.
+fn owner_and_group(longname: &str) -> Option<(String, String)> {
+    let mut fields = longname.split_whitespace();
+    let mode = fields.next()?;
+    // Servers that send no real long name send just the file name.
+    if mode.len() < 10 {
+        return None;
+    }
+    let _links = fields.next()?;
+    let owner = fields.next()?;
+    let group = fields.next()?;
+    Some((owner.to_owned(), group.to_owned()))
+}
+
+// ---------------------------------------------------------------------------
+// ConnectionAdapter impl
+// ---------------------------------------------------------------------------
+
+#[async_trait]
+impl ConnectionAdapter for SftpAdapter {
+    async fn connect(
+        profile: &ConnectionProfile,
+        credential: Credential,
+    ) -> Result<Self, ConnectionError> {
+        SftpAdapter::connect_with(profile, credential, SshConnectOptions::default()).await
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        // Best-effort, like SshAdapter::disconnect.
+        let _ = self.sftp.close_session();
+        if let Some(ssh) = &mut self.ssh {
+            ssh.disconnect().await?;
+        }
+        Ok(())
+    }
+
+    fn is_alive(&self) -> bool {
+        // A busy lock means someone is using the session.
+        !self.session.try_lock().is_ok_and(|h| h.is_closed())
+    }
+
+    /// Reconnect an adapter that has its own connection. One that borrows
+    /// an [`SshAdapter`]'s session can only restart the subsystem, and fails
+    /// once that session has closed.
+    async fn reconnect(&mut self) -> Result<(), ConnectionError> {
+        let _ = self.sftp.close_session();
+        if let Some(ssh) = &mut self.ssh {
+            ssh.reconnect().await?;
+            self.session = ssh.session();
+            self.identity = ssh.server_identity();
+        } else if !self.is_alive() {
+            return Err(ConnectionError::Protocol(
+                "the SSH session this SFTP adapter shared has closed".to_owned(),
+            ));
+        }
+        self.sftp = Arc::new(open_sftp(&self.session).await?);
+        Ok(())
+    }
+
+    fn protocol(&self) -> Protocol {
+        Protocol::Sftp
+    }
+}
+
+// ---------------------------------------------------------------------------
+// FileTransferAdapter impl
+// ---------------------------------------------------------------------------
+
+#[async_trait]
+impl FileTransferAdapter for SftpAdapter {
+    async fn list_dir(&self, path: &str) -> Result<Vec<FileEntry>, ConnectionError> {
+        let dir = self.canonicalize(path).await?;
+        let handle = self
+            .sftp
+            .opendir(dir.as_str())
+            .await
+            .map_err(|e| sftp_error(&dir, e))?
+            .handle;
+        let handle = OpenHandle::new(&self.sftp, handle);
+
+        let mut entries = Vec::new();
+        let listed = loop {
+            match self.sftp.readdir(handle.as_str()).await {
+                Ok(name) => entries.extend(
+                    name.files
+                        .into_iter()
+                        .filter(|f| f.filename != "." && f.filename != "..")
+                        .map(|f| entry(join(&dir, &f.filename), &f.attrs, Some(&f.longname))),
+                ),
+                Err(SftpError::Status(status)) if status.status_code == StatusCode::Eof => {
+                    break Ok(());
+                }
+                Err(e) => break Err(sftp_error(&dir, e)),
+            }
+        };
+        handle.close().await;
+        listed?;
+
+        resolve_links(&self.sftp, &mut entries).await?;
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    async fn stat(&self, path: &str) -> Result<FileEntry, ConnectionError> {
+        let attrs = self
+            .sftp
+            .lstat(path)
+            .await
+            .map_err(|e| sftp_error(path, e))?
+            .attrs;
+        let mut entry = entry(path.to_owned(), &attrs, None);
+        resolve_link(&self.sftp, &mut entry).await?;
+        Ok(entry)
+    }
+
+    async fn upload_stream(
+        &self,
+        source: &mut (dyn AsyncRead + Send + Unpin),
+        remote: &str,
+        offset: u64,
+        on_progress: &OnProgress<'_>,
+    ) -> Result<u64, ConnectionError> {
+        let mut flags = OpenFlags::WRITE | OpenFlags::CREATE;
+        if offset == 0 {
+            flags |= OpenFlags::TRUNCATE;
+        } else {
+            // Drop anything past the resume point, e.g. a half-written chunk.
+            let attrs = FileAttributes {
+                size: Some(offset),
+                ..FileAttributes::empty()
+            };
+            self.sftp
+                .setstat(remote, attrs)
+                .await
+                .map_err(|e| sftp_error(remote, e))?;
+        }
+        let handle = self.open(remote, flags).await?;
+        let written = write_all(
+            &self.sftp,
+            handle.as_str(),
+            remote,
+            source,
+            offset,
+            on_progress,
+        )
+        .await;
+        handle.close().await;
+        written
+    }
+
+    async fn download_stream(
+        &self,
+        remote: &str,
+        sink: &mut (dyn AsyncWrite + Send + Unpin),
+        offset: u64,
+        on_progress: &OnProgress<'_>,
+    ) -> Result<u64, ConnectionError> {
+        let handle = self.open(remote, OpenFlags::READ).await?;
+        let read = read_all(
+            &self.sftp,
+            handle.as_str(),
+            remote,
+            sink,
+            offset,
+            on_progress,
+        )
+        .await;
+        handle.close().await;
+        read
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), ConnectionError> {
+        let attrs = self
+            .sftp
+            .lstat(path)
+            .await
+            .map_err(|e| sftp_error(path, e))?
+            .attrs;
+        let removed = if kind(&attrs) == FileKind::Dir {
+            self.sftp.rmdir(path).await
+        } else {
+            self.sftp.remove(path).await
+        };
+        removed.map(drop).map_err(|e| sftp_error(path, e))
+    }
+
+    async fn mkdir(&self, path: &str) -> Result<(), ConnectionError> {
+        self.sftp
+            .mkdir(path, FileAttributes::empty())
+            .await
+            .map(drop)
+            .map_err(|e| sftp_error(path, e))
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), ConnectionError> {
+        self.sftp
+            .rename(from, to)
+            .await
+            .map(drop)
+            .map_err(|e| sftp_error(from, e))
+    }
+
+    async fn chmod(&self, path: &str, mode: u32) -> Result<(), ConnectionError> {
+        let attrs = FileAttributes {
+            permissions: Some(mode & 0o7777),
+            ..FileAttributes::empty()
+        };
+        self.sftp
+            .setstat(path, attrs)
+            .await
+            .map(drop)
+            .map_err(|e| sftp_error(path, e))
+    }
+
+    async fn set_modified(&self, path: &str, time: DateTime<Utc>) -> Result<(), ConnectionError> {
+        // SFTP v3 sets both times or neither.
+        let secs = time.timestamp().clamp(0, u32::MAX.into()) as u32;
+        let attrs = FileAttributes {
+            atime: Some(secs),
+            mtime: Some(secs),
+            ..FileAttributes::empty()
+        };
+        self.sftp
+            .setstat(path, attrs)
+            .await
+            .map(drop)
+            .map_err(|e| sftp_error(path, e))
+    }
+
+    async fn symlink(&self, target: &str, link: &str) -> Result<(), ConnectionError> {
+        // OpenSSH reads SSH_FXP_SYMLINK's arguments as (target, link), the
+        // reverse of the draft, and other servers followed it.
+        self.sftp
+            .symlink(target, link)
+            .await
+            .map(drop)
+            .map_err(|e| sftp_error(link, e))
+    }
+
+    fn server_identity(&self) -> Option<ServerIdentity> {
+        self.identity.clone()
+    }
+
+    async fn readlink(&self, path: &str) -> Result<String, ConnectionError> {
+        let name = self
+            .sftp
+            .readlink(path)
+            .await
+            .map_err(|e| sftp_error(path, e))?;
+        first_name(path, name.files)
+    }
+
+    async fn canonicalize(&self, path: &str) -> Result<String, ConnectionError> {
+        let name = self
+            .sftp
+            .realpath(path)
+            .await
+            .map_err(|e| sftp_error(path, e))?;
+        first_name(path, name.files)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use russh_sftp::protocol::{Data, Handle, Status};
+
+    use super::*;
+
+    /// An SFTP server holding one file in memory. Reads return at most
+    /// `max_read` bytes, as some servers do.
+    struct MemoryServer {
+        file: Arc<Mutex<Vec<u8>>>,
+        closed: Arc<AtomicUsize>,
+        max_read: usize,
+    }
+
+    impl russh_sftp::server::Handler for MemoryServer {
+        type Error = StatusCode;
+
+        fn unimplemented(&self) -> StatusCode {
+            StatusCode::OpUnsupported
+        }
+
+        async fn open(
+            &mut self,
+            id: u32,
+            _filename: String,
+            _pflags: OpenFlags,
+            _attrs: FileAttributes,
+        ) -> Result<Handle, StatusCode> {
+            Ok(Handle {
+                id,
+                handle: "file".to_owned(),
+            })
+        }
+
+        async fn close(&mut self, id: u32, _handle: String) -> Result<Status, StatusCode> {
+            self.closed.fetch_add(1, Ordering::SeqCst);
+            Ok(ok(id))
+        }
+
+        async fn read(
+            &mut self,
+            id: u32,
+            _handle: String,
+            offset: u64,
+            len: u32,
+        ) -> Result<Data, StatusCode> {
+            let file = self.file.lock().unwrap();
+            let start = usize::try_from(offset).unwrap();
+            if start >= file.len() {
+                return Err(StatusCode::Eof);
+            }
+            let end = file.len().min(start + self.max_read.min(len as usize));
+            Ok(Data {
+                id,
+                data: file[start..end].to_vec(),
+            })
+        }
+
+        async fn write(
+            &mut self,
+            id: u32,
+            _handle: String,
+            offset: u64,
+            data: Vec<u8>,
+        ) -> Result<Status, StatusCode> {
+            let mut file = self.file.lock().unwrap();
+            let start = usize::try_from(offset).unwrap();
+            if file.len() < start + data.len() {
+                file.resize(start + data.len(), 0);
+            }
+            file[start..start + data.len()].copy_from_slice(&data);
+            Ok(ok(id))
+        }
+    }
+
+    fn ok(id: u32) -> Status {
+        Status {
+            id,
+            status_code: StatusCode::Ok,
+            error_message: String::new(),
+            language_tag: "en-US".to_owned(),
+        }
+    }
+
+    struct Served {
+        sftp: Arc<RawSftpSession>,
+        file: Arc<Mutex<Vec<u8>>>,
+        closed: Arc<AtomicUsize>,
+    }
+
+    async fn serve(contents: Vec<u8>, max_read: usize) -> Served {
+        let (client, server) = tokio::io::duplex(1 << 20);
+        let file = Arc::new(Mutex::new(contents));
+        let closed = Arc::new(AtomicUsize::new(0));
+        let handler = MemoryServer {
+            file: Arc::clone(&file),
+            closed: Arc::clone(&closed),
+            max_read,
+        };
+        russh_sftp::server::run(server, handler).await;
+        let sftp = Arc::new(RawSftpSession::new(client));
+        sftp.init().await.unwrap();
+        Served { sftp, file, closed }
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn pipelined_reads_survive_short_replies() {
+        let contents = pattern(10 * CHUNK + 123);
+        let served = serve(contents.clone(), 10_000).await;
+
+        let mut sink = Vec::new();
+        let end = read_all(&served.sftp, "file", "/f", &mut sink, 0, &|_| {})
+            .await
+            .unwrap();
+        assert_eq!(end, contents.len() as u64);
+        assert_eq!(sink, contents);
+
+        let mut tail = Vec::new();
+        read_all(&served.sftp, "file", "/f", &mut tail, 300_000, &|_| {})
+            .await
+            .unwrap();
+        assert_eq!(tail, contents[300_000..]);
+    }
+
+    #[tokio::test]
+    async fn pipelined_writes_land_in_order() {
+        let contents = pattern(PIPELINE * 3 * CHUNK + 77);
+        let served = serve(Vec::new(), CHUNK).await;
+
+        let progress = Mutex::new(Vec::new());
+        let on_progress = |n| progress.lock().unwrap().push(n);
+        let end = write_all(
+            &served.sftp,
+            "file",
+            "/f",
+            &mut contents.as_slice(),
+            0,
+            &on_progress,
+        )
+        .await
+        .unwrap();
+        assert_eq!(end, contents.len() as u64);
+        assert_eq!(*served.file.lock().unwrap(), contents);
+        let progress = progress.into_inner().unwrap();
+        assert!(progress.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(progress.last(), Some(&end));
+    }
+
+    #[tokio::test]
+    async fn handles_close_when_a_transfer_is_dropped() {
+        let served = serve(pattern(100), CHUNK).await;
+
+        OpenHandle::new(&served.sftp, "file".to_owned())
+            .close()
+            .await;
+        assert_eq!(served.closed.load(Ordering::SeqCst), 1);
+
+        // A paused or cancelled transfer drops its future, handle and all.
+        drop(OpenHandle::new(&served.sftp, "file".to_owned()));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while served.closed.load(Ordering::SeqCst) < 2 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    fn status(code: StatusCode, message: &str) -> SftpError {
+        SftpError::Status(Status {
+            id: 1,
+            status_code: code,
+            error_message: message.to_owned(),
+            language_tag: String::new(),
+        })
+    }
+
+    #[test]
+    fn attributes_become_an_entry() {
+        let attrs = FileAttributes {
+            size: Some(1234),
+            uid: Some(1000),
+            gid: Some(50),
+            permissions: Some(0o100_644),
+            mtime: Some(1_700_000_000),
+            ..FileAttributes::empty()
+        };
+        let longname = "-rw-r--r--    1 alice    staff        1234 Nov 14 22:13 notes.txt";
+        let entry = entry("/home/alice/notes.txt".into(), &attrs, Some(longname));
+        assert_eq!(entry.name, "notes.txt");
+        assert_eq!(entry.kind, FileKind::File);
+        assert_eq!(entry.size, 1234);
+        assert_eq!(entry.permissions, 0o644);
+        assert_eq!(
+            entry.modified_at.map(|t| t.timestamp()),
+            Some(1_700_000_000)
+        );
+        assert_eq!(entry.accessed_at, None);
+        assert_eq!((entry.uid, entry.gid), (Some(1000), Some(50)));
+        assert_eq!(entry.owner.as_deref(), Some("alice"));
+        assert_eq!(entry.group.as_deref(), Some("staff"));
+    }
+
+    #[test]
+    fn missing_attributes_leave_gaps_rather_than_guesses() {
+        let entry = entry("/x".into(), &FileAttributes::empty(), Some("x"));
+        assert_eq!(entry.kind, FileKind::Other);
+        assert_eq!(entry.size, 0);
+        assert_eq!(entry.owner, None);
+        assert_eq!(entry.uid, None);
+    }
+
+    #[test]
+    fn owner_and_group_come_from_the_long_name() {
+        assert_eq!(
+            owner_and_group("drwxr-xr-x    2 root     root         4096 Jan  1  2024 etc"),
+            Some(("root".into(), "root".into()))
+        );
+        assert_eq!(owner_and_group("etc"), None);
+        assert_eq!(owner_and_group(""), None);
+    }
+
+    #[test]
+    fn status_codes_map_to_connection_errors() {
+        assert!(matches!(
+            sftp_error("/a", status(StatusCode::NoSuchFile, "No such file")),
+            ConnectionError::NotFound { path } if path == "/a"
+        ));
+        assert!(matches!(
+            sftp_error("/b", status(StatusCode::PermissionDenied, "")),
+            ConnectionError::PermissionDenied { path } if path == "/b"
+        ));
+        assert!(matches!(
+            sftp_error("/c", status(StatusCode::Failure, "")),
+            ConnectionError::Protocol(msg) if msg == "/c: Failure"
+        ));
+        assert!(matches!(
+            sftp_error("/d", status(StatusCode::Failure, "Directory not empty")),
+            ConnectionError::Protocol(msg) if msg == "/d: Directory not empty"
+        ));
+        assert!(matches!(
+            sftp_error("/e", SftpError::Timeout),
+            ConnectionError::Timeout { timeout } if timeout == REQUEST_TIMEOUT
+        ));
+    }
+}
@@ -0,0 +1,242 @@
+//! Interactive shell channels.
+//!
+//! [`SshAdapter`](super::ssh::SshAdapter) opens one shell when it connects
+//! and drives it through [`TerminalAdapter`](super::TerminalAdapter).
+//! [`SshAdapter::open_shell`](super::ssh::SshAdapter::open_shell) opens more
+//! on the same authenticated session — one per split pane, say — without
+//! logging in again. Each is a [`ShellHandle`] with its own input, output,
+//! resize and liveness, and closing one leaves the transport and the other
+//! shells running.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use super::output::OutputEvent;
+use super::ConnectionError;
+use crate::recording::Recorder;
+
+/// Commands sent from a [`ShellHandle`] to the task that owns its channel.
+#[derive(Debug)]
+pub(crate) enum ShellCmd {
+    /// Raw bytes to write to the remote PTY stdin.
+    Data(Vec<u8>),
+    /// PTY window-size change.
+    Resize { cols: u16, rows: u16 },
+    /// Start recording to the given recorder, or stop with `None`.
+    Record(Option<Recorder>),
+    /// Graceful close.
+    Close,
+}
+
+/// The input side of a shell, shared between its [`ShellHandle`] and the
+/// adapter's registry of open shells.
+#[derive(Debug, Clone)]
+pub(crate) struct ShellControl {
+    tx: mpsc::Sender<ShellCmd>,
+    /// Set to `false` by the shell task when the channel closes.
+    alive: Arc<AtomicBool>,
+}
+
+impl ShellControl {
+    pub fn new(tx: mpsc::Sender<ShellCmd>, alive: Arc<AtomicBool>) -> Self {
+        ShellControl { tx, alive }
+    }
+
+    pub fn is_alive(&self) -> bool {
+        // Acquire pairs with the Release store in the shell task so the false
+        // is visible without reordering on weakly-ordered architectures.
+        self.alive.load(Ordering::Acquire)
+    }
+
+    pub fn mark_closed(&self) {
+        self.alive.store(false, Ordering::Release);
+    }
+
+    pub async fn send(&self, cmd: ShellCmd) -> Result<(), ConnectionError> {
+        self.tx.send(cmd).await.map_err(|_| {
+            // Defensively mark dead in case the shell task's Release store
+            // hasn't propagated to this thread yet.
+            self.mark_closed();
+            ConnectionError::Protocol("shell channel closed".to_owned())
+        })
+    }
+
+    /// Ask the shell task to close the channel. Best-effort: the task may
+    /// already be gone.
+    pub async fn close(&self) {
+        let _ = self.tx.send(ShellCmd::Close).await;
+        self.mark_closed();
+    }
+}
+
+/// One interactive shell channel.
+///
+/// Dropping the handle closes the shell. Its output is flow-controlled on
+/// its own: an unread pane holds back only its own channel.
+pub struct ShellHandle {
+    name: String,
+    control: ShellControl,
+    /// Closes the shell when the handle is dropped.
+    _close_on_drop: DropGuard,
+    /// Taken once via [`ShellHandle::output_stream`].
+    output: Option<mpsc::Receiver<Vec<u8>>>,
+    /// Taken once via [`ShellHandle::output_events`].
+    events: Option<mpsc::Receiver<OutputEvent>>,
+    /// Bytes of output discarded so far.
+    dropped: Arc<AtomicU64>,
+}
+
+impl ShellHandle {
+    pub(crate) fn new(
+        name: impl Into<String>,
+        control: ShellControl,
+        output: mpsc::Receiver<Vec<u8>>,
+        events: mpsc::Receiver<OutputEvent>,
+        dropped: Arc<AtomicU64>,
+        close_on_drop: CancellationToken,
+    ) -> Self {
+        ShellHandle {
+            name: name.into(),
+            control,
+            _close_on_drop: close_on_drop.drop_guard(),
+            output: Some(output),
+            events: Some(events),
+            dropped,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Write raw bytes to the shell's PTY.
+    pub async fn send_input(&self, data: &[u8]) -> Result<(), ConnectionError> {
+        self.control.send(ShellCmd::Data(data.to_vec())).await
+    }
+
+    /// Take the output receiver. Returns `None` after the first call.
+    ///
+    /// Dropping the receiver closes the shell.
+    pub fn output_stream(&mut self) -> Option<mpsc::Receiver<Vec<u8>>> {
+        self.output.take()
+    }
+
+    /// Take the output event receiver. Returns `None` after the first call.
+    pub fn output_events(&mut self) -> Option<mpsc::Receiver<OutputEvent>> {
+        self.events.take()
+    }
+
+    /// Bytes of output discarded because the consumer fell behind. Always
+    /// `0` in [`OutputMode::Backpressure`](super::output::OutputMode).
+    pub fn dropped_output_bytes(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Send a PTY window-resize notification for this shell.
+    pub async fn resize(&self, cols: u16, rows: u16) -> Result<(), ConnectionError> {
+        self.control.send(ShellCmd::Resize { cols, rows }).await
+    }
+
+    /// Record this shell's output, resizes and (if the recorder captures it)
+    /// input from now on; `None` stops recording. The recorder is told the
+    /// current size straight away.
+    pub async fn record(&self, recorder: Option<Recorder>) -> Result<(), ConnectionError> {
+        self.control.send(ShellCmd::Record(recorder)).await
+    }
+
+    /// Returns `true` until the shell's channel closes.
+    pub fn is_alive(&self) -> bool {
+        self.control.is_alive()
+    }
+
+    /// Close this shell's channel. The SSH session stays up.
+    pub async fn close(&self) {
+        self.control.close().await;
+    }
+
+    pub(crate) fn control(&self) -> &ShellControl {
+        &self.control
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle_with(closed: CancellationToken) -> (ShellHandle, mpsc::Receiver<ShellCmd>) {
+        let (tx, rx) = mpsc::channel(4);
+        let (_output_tx, output) = mpsc::channel(1);
+        let (_events_tx, events) = mpsc::channel(1);
+        let control = ShellControl::new(tx, Arc::new(AtomicBool::new(true)));
+        let handle = ShellHandle::new("pane-1", control, output, events, Arc::default(), closed);
+        (handle, rx)
+    }
+
+    fn handle() -> (ShellHandle, mpsc::Receiver<ShellCmd>) {
+        handle_with(CancellationToken::new())
+    }
+
+    #[tokio::test]
+    async fn commands_reach_the_shell_task() {
+        let (handle, mut rx) = handle();
+        handle.send_input(b"ls\n").await.unwrap();
+        handle.resize(120, 40).await.unwrap();
+
+        assert!(matches!(rx.recv().await, Some(ShellCmd::Data(d)) if d == b"ls\n"));
+        assert!(matches!(
+            rx.recv().await,
+            Some(ShellCmd::Resize {
+                cols: 120,
+                rows: 40
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn close_marks_the_shell_dead() {
+        let (handle, mut rx) = handle();
+        assert_eq!(handle.name(), "pane-1");
+        assert!(handle.is_alive());
+
+        handle.close().await;
+        assert!(!handle.is_alive());
+        assert!(matches!(rx.recv().await, Some(ShellCmd::Close)));
+    }
+
+    #[test]
+    fn dropping_the_handle_closes_the_shell() {
+        let closed = CancellationToken::new();
+        let (handle, _rx) = handle_with(closed.clone());
+        let control = handle.control().clone();
+        drop(handle);
+        assert!(closed.is_cancelled());
+        assert!(
+            control.is_alive(),
+            "the task marks the shell dead once closed"
+        );
+    }
+
+    #[tokio::test]
+    async fn input_fails_once_the_task_is_gone() {
+        let (handle, rx) = handle();
+        drop(rx);
+        assert!(handle.send_input(b"x").await.is_err());
+        assert!(!handle.is_alive());
+    }
+
+    #[test]
+    fn output_receivers_are_taken_once() {
+        let (mut handle, _rx) = handle();
+        assert!(handle.output_stream().is_some());
+        assert!(handle.output_stream().is_none());
+        assert!(handle.output_events().is_some());
+        assert!(handle.output_events().is_none());
+    }
+}
@@ -8,47 +8,192 @@
 //! Pass a [`Credential`] to [`SshAdapter::connect`]:
 //!
 //! - [`Credential::Password`] — username/password
-//! - [`Credential::PublicKey`] — OpenSSH PEM private key (Ed25519 or RSA)
-//! - [`Credential::SshAgent`] — delegate to the SSH agent at `SSH_AUTH_SOCK`
+//! - [`Credential::PublicKey`] — OpenSSH PEM private key (Ed25519 or RSA),
+//!   optionally with an OpenSSH user certificate
+//!   (see [`certificate`](super::certificate))
+//! - [`Credential::SshAgent`] — delegate to the SSH agent at `SSH_AUTH_SOCK`,
+//!   offering each loaded identity in turn (the one named by
+//!   [`SshSettings::agent_fingerprint`] first)
+//! - [`Credential::KeyboardInteractive`] — server prompts (PAM, Duo, TOTP)
+//!   answered by a [`PromptResponder`]; see
+//!   [`keyboard_interactive`](super::keyboard_interactive)
+//! - [`Credential::Chain`] — several of the above in turn, for servers that
+//!   require multiple methods
+//!
+//! When nothing is accepted, [`ConnectionError::AuthFailed`] lists every
+//! method and key that was offered.
 //!
 //! # Host key verification (TOFU)
 //!
 //! With [`HostKeyPolicy::StrictFirstConnect`] (the default), the server's
-//! public key is accepted on the first connection and stored in memory.
-//! Subsequent connections that present a *different* key are rejected with
-//! [`ConnectionError::HostKeyMismatch`].
+//! public key is accepted on the first connection and recorded in a
+//! [`KnownHostsStore`]. Subsequent connections that present a *different* key
+//! are rejected with [`ConnectionError::HostKeyMismatch`], which carries the
+//! fingerprints of both the stored and the presented key.
+//!
+//! [`ConnectionAdapter::connect`] uses a fresh [`MemoryKnownHostsStore`], so
+//! trust only survives [`ConnectionAdapter::reconnect`] on the same adapter.
+//! To persist trust across restarts and devices, pass a
+//! [`VaultKnownHostsStore`](super::known_hosts::VaultKnownHostsStore) or
+//! [`FileKnownHostsStore`](super::known_hosts::FileKnownHostsStore) via
+//! [`SshConnectOptions`] and [`SshAdapter::connect_with`].
 //!
-//! TOFU state is kept in the `SshAdapter` instance (and survives calls to
-//! [`ConnectionAdapter::reconnect`] on that same instance). It is **not**
-//! shared across independent `SshAdapter::connect` calls; each new adapter
-//! starts with an empty known-key store.
+//! [`HostKeyPolicy::KnownHostsFile`] and [`HostKeyPolicy::SystemKnownHosts`]
+//! verify against OpenSSH `known_hosts` files instead (see
+//! [`openssh_known_hosts`](super::openssh_known_hosts)). A host listed with a
+//! different key yields [`ConnectionError::HostKeyMismatch`], a revoked key
+//! [`ConnectionError::HostKeyRevoked`], and an unlisted host
+//! [`ConnectionError::HostKeyUnknown`] unless `append_unknown` is set.
+//!
+//! Supplying a [`HostKeyVerifier`] in [`SshConnectOptions::verifier`] turns
+//! these silent decisions into a prompt: the verifier sees the fingerprints of
+//! any key the policy does not already trust and chooses to accept it once,
+//! accept and remember it, or reject it
+//! ([`ConnectionError::HostKeyRejected`]).
 //!
 //! With [`HostKeyPolicy::AcceptAll`], every key is accepted without
 //! comparison (useful for testing; not recommended in production).
 //!
+//! # ProxyJump
+//!
+//! When [`SshSettings::jump_host_ids`] is non-empty, each jump profile and
+//! its credential are resolved through [`SshConnectOptions::jump_resolver`]
+//! (e.g. [`VaultJumpHostResolver`](super::jump::VaultJumpHostResolver)). Every
+//! hop is reached over a `direct-tcpip` channel opened on the previous one,
+//! and its host key is verified under its own profile's policy. Failures
+//! along the chain surface as [`ConnectionError::JumpHost`], naming the hop.
+//!
+//! # Output flow control
+//!
+//! By default a consumer that reads [`TerminalAdapter::output_stream`] slowly
+//! loses no bytes, but once [`OUTPUT_LIMIT`](super::output::OUTPUT_LIMIT)
+//! bytes go unread its shell is closed, so memory stays bounded. Set
+//! [`SshConnectOptions::output_mode`] to [`OutputMode::RingBuffer`] to keep
+//! the session moving instead; discarded bytes are then reported through
+//! [`SshAdapter::output_events`]. See [`output`](super::output).
+//!
+//! # Multiple shells
+//!
+//! [`SshAdapter::open_shell`] opens further named PTY shells on the same
+//! authenticated session, so split panes to one host need only one login
+//! (and one MFA prompt). Each [`ShellHandle`] closes independently; the
+//! primary shell remains the one behind [`TerminalAdapter`].
+//!
+//! # PTY
+//!
+//! Shells get the PTY described by [`SshSettings::pty`]: `TERM`, size,
+//! terminal modes and environment (see [`pty`](super::pty)).
+//! [`SshSettings::shell_mode`] can drop the PTY, or open no shell at all for
+//! hosts that forbid one; the adapter then serves exec, SFTP and forwards
+//! and its terminal methods fail.
+//!
+//! # Login scripts
+//!
+//! A profile's [`ConnectionProfile::login_actions`] run in the primary shell
+//! as soon as it starts (see [`login`](super::login)). Connecting waits for
+//! the script; a step that fails or times out stops it but keeps the shell,
+//! and [`SshAdapter::login_failure`] reports the step.
+//!
+//! # Recording
+//!
+//! [`SshAdapter::record`] attaches a [`Recorder`] to the primary shell, which
+//! then writes an asciicast of the session (see
+//! [`recording`](crate::recording)).
+//!
+//! # Agent forwarding
+//!
+//! With [`SshSettings::forward_agent`], shells and exec channels request
+//! agent forwarding, and the server's agent channels are relayed to the local
+//! agent (see [`agent_forward`](super::agent_forward)). Jump hosts never get
+//! the agent.
+//!
+//! # Timeouts and diagnostics
+//!
+//! The host is resolved and its addresses raced IPv6/IPv4 (see
+//! [`dial`](super::dial)). [`SshSettings::connect_timeout_secs`],
+//! [`SshSettings::handshake_timeout_secs`] and
+//! [`SshSettings::auth_timeout_secs`] bound each step, so a black-holed host
+//! fails with [`ConnectionError::ConnectTimeout`] instead of hanging. The
+//! handshake clock stops while a [`HostKeyVerifier`] waits for the user.
+//! Every setup error reports its [`ConnectStage`] through
+//! [`ConnectionError::stage`].
+//!
+//! # Algorithms
+//!
+//! [`SshSettings::algorithms`] replaces the key-exchange, host-key, cipher,
+//! MAC and compression lists offered to the server, so old equipment that
+//! only speaks `diffie-hellman-group14-sha1` or `ssh-rsa` can be reached
+//! from its own profile while the defaults stay strict. What was agreed is
+//! reported by [`SshAdapter::algorithms`], and weak choices are logged (see
+//! [`algorithms`](super::algorithms)).
+//!
 //! # Keepalive
 //!
 //! [`SshSettings::keepalive_secs`] maps directly to
 //! `russh::client::Config::keepalive_interval`. The `russh` session loop
 //! sends SSH keepalive messages automatically.
+//!
+//! # Reconnecting
+//!
+//! An `SshAdapter` stays dead once its connection drops;
+//! [`reconnect`](super::reconnect) provides a supervisor that detects the
+//! drop and reconnects with backoff.
 
 use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock, PoisonError, Weak};
 use std::time::Duration;
 
 use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use secrecy::ExposeSecret;
-use tokio::sync::mpsc;
-
-use crate::profile::types::{ConnectionProfile, HostKeyPolicy, Protocol};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::profile::types::{
+    ConnectionProfile, HostKeyPolicy, KnownHost, PortForwardSpec, Protocol, PtySettings, ShellMode,
+    SshSettings,
+};
+use crate::recording::Recorder;
 // Brings `public_key_bytes()` into scope on `PublicKey` for TOFU comparisons.
 use russh::keys::PublicKeyBase64 as _;
 
-use super::{ConnectionError, Credential, ExecResult};
-
-// Re-export ConnectionAdapter and TerminalAdapter so callers only need this module.
-pub use super::{ConnectionAdapter, TerminalAdapter};
+use super::agent_forward::{AgentForwarder, AgentReplies};
+use super::algorithms::{negotiate, preferred, KexInitSlot, KexInitTap, NegotiatedAlgorithms};
+use super::certificate::parse_certificate;
+use super::dial::dial;
+use super::exec::{ExecCmd, ExecHandle, ExecParts, ExecRequest, ExitSignal, ExitStatus, Signal};
+use super::files::ServerIdentity;
+use super::forward::{
+    pipe, socks5_accept, socks5_reply, PortForwardHandle, RemoteForwardRegistry,
+    RemoteForwardTarget, SocksReply,
+};
+use super::host_key_verifier::{HostKeyDecision, HostKeyInfo, HostKeyVerifier};
+use super::jump::JumpHostResolver;
+use super::keyboard_interactive::{
+    KeyboardInteractiveChallenge, KeyboardInteractivePrompt, PromptResponder,
+};
+use super::known_hosts::{
+    known_host_fingerprint, sha256_fingerprint, KnownHostsStore, MemoryKnownHostsStore,
+};
+use super::login::{run_login_script, LoginStepFailed};
+use super::openssh_known_hosts::{
+    append_known_host, global_known_hosts_path, user_known_hosts_path, KnownHostsFile,
+    KnownHostsStatus,
+};
+use super::output::{OutputEvent, OutputMode, OutputSink, OUTPUT_LIMIT};
+use super::pty::request_shell;
+use super::shell::{ShellCmd, ShellControl, ShellHandle};
+use super::{AuthAttempt, ConnectStage, ConnectionError, Credential, ExecResult};
+
+// Re-export the adapter traits so callers only need this module.
+pub use super::{ConnectionAdapter, PortForwardAdapter, TerminalAdapter};
 
 // ---------------------------------------------------------------------------
 // Error conversions
@@ -69,46 +214,190 @@ impl From<russh::keys::Error> for ConnectionError {
     }
 }
 
-// ---------------------------------------------------------------------------
-// Internal shell-task command
-// ---------------------------------------------------------------------------
-
-/// Commands sent from `SshAdapter` to the background shell task.
-enum ShellCmd {
-    /// Raw bytes to write to the remote PTY stdin.
-    Data(Vec<u8>),
-    /// PTY window-size change.
-    Resize { cols: u16, rows: u16 },
-    /// Graceful close.
-    Close,
-}
-
 // ---------------------------------------------------------------------------
 // SshClientHandler — implements russh::client::Handler
 // ---------------------------------------------------------------------------
 
 /// russh client handler.
 ///
-/// Responsible for host-key verification (TOFU / AcceptAll). Lives only for
-/// the duration of the connection setup; after that, `SshAdapter` drives the
-/// session through the [`russh::client::Handle`].
-struct SshClientHandler {
+/// Responsible for host-key verification (policy plus optional verifier).
+/// Lives only for the duration of the connection setup; after that,
+/// `SshAdapter` drives the session through the [`russh::client::Handle`].
+pub(crate) struct SshClientHandler {
     host: String,
     port: u16,
     host_key_policy: HostKeyPolicy,
-    /// Shared map of `"host:port"` → wire-encoded public-key bytes.
+    /// Trusted host keys, keyed by `host:port`.
     ///
     /// Keyed by the full `host:port` pair so that two SSH daemons on the same
-    /// host but different ports are treated as independent servers.
-    /// Written on first connect; compared on subsequent connects within the
-    /// same [`SshAdapter`] lifetime (or across `reconnect()` calls, which
-    /// reuse this [`Arc`]).
-    known_keys: Arc<tokio::sync::Mutex<HashMap<String, Vec<u8>>>>,
+    /// host but different ports are treated as independent servers. Shared
+    /// with the owning [`SshAdapter`] so that `reconnect()` sees the same
+    /// trust decisions.
+    known_hosts: Arc<dyn KnownHostsStore>,
+    /// Asked about keys the policy does not already trust.
+    verifier: Option<Arc<dyn HostKeyVerifier>>,
+    /// State shared with the owning adapter.
+    session: SessionState,
+    /// Relays forwarded-agent channels; `None` unless the profile forwards
+    /// the agent.
+    agent: Option<AgentForwarder>,
+}
+
+/// Per-session state shared between an [`SshAdapter`] and its handler.
+#[derive(Clone, Default)]
+struct SessionState {
+    /// Destinations for `forwarded-tcpip` channels opened by the server.
+    remote_forwards: Arc<RemoteForwardRegistry>,
+    /// SHA-256 fingerprint of the host key the server presented.
+    host_key: Arc<OnceLock<String>>,
+    /// The session's own handle, set once connected, for writes that start
+    /// outside the handler. Weak, so the handler does not keep its session
+    /// alive.
+    handle: Arc<OnceLock<Weak<tokio::sync::Mutex<russh::client::Handle<SshClientHandler>>>>>,
+    /// Time spent on host-key prompts, kept off the key-exchange timeout.
+    prompts: Arc<PromptClock>,
+}
+
+/// Writes a forwarded-agent channel's replies through the session handle.
+struct AgentChannelReplies {
+    session: SessionState,
+    channel: russh::ChannelId,
+}
+
+#[async_trait]
+impl AgentReplies for AgentChannelReplies {
+    async fn reply(&self, reply: Vec<u8>) -> bool {
+        let Some(handle) = self.session.handle.get().and_then(Weak::upgrade) else {
+            return false;
+        };
+        let handle = handle.lock().await;
+        handle
+            .data(self.channel, russh::CryptoVec::from(reply))
+            .await
+            .is_ok()
+    }
 }
 
 impl SshClientHandler {
-    fn host_key(&self) -> String {
-        format!("{}:{}", self.host, self.port)
+    /// Ask the verifier about an untrusted key. `None` when no verifier is
+    /// configured, in which case the policy's own default applies.
+    async fn ask_verifier(
+        &self,
+        key_bytes: &[u8],
+    ) -> Result<Option<HostKeyDecision>, ConnectionError> {
+        let Some(verifier) = &self.verifier else {
+            return Ok(None);
+        };
+        let info = HostKeyInfo::new(&self.host, self.port, key_bytes);
+        self.session.prompts.open();
+        let decision = verifier.verify(info).await;
+        self.session.prompts.close();
+        match decision {
+            HostKeyDecision::Reject => Err(ConnectionError::HostKeyRejected {
+                host: self.host.clone(),
+                port: self.port,
+                fingerprint: sha256_fingerprint(key_bytes),
+            }),
+            decision => Ok(Some(decision)),
+        }
+    }
+
+    /// TOFU check against the known-hosts store.
+    async fn check_known_hosts(
+        &self,
+        server_public_key: &russh::keys::key::PublicKey,
+    ) -> Result<bool, ConnectionError> {
+        let key_bytes = server_public_key.public_key_bytes();
+        let key_base64 = BASE64.encode(&key_bytes);
+        match self.known_hosts.get(&self.host, self.port).await? {
+            None => {
+                if self.ask_verifier(&key_bytes).await? == Some(HostKeyDecision::AcceptOnce) {
+                    return Ok(true);
+                }
+                // Trust on first use — store the key.
+                self.known_hosts
+                    .insert(KnownHost::new(
+                        &self.host,
+                        self.port,
+                        server_public_key.name(),
+                        key_base64,
+                    ))
+                    .await?;
+                Ok(true)
+            }
+            Some(stored) if stored.public_key == key_base64 => Ok(true),
+            Some(stored) => Err(ConnectionError::HostKeyMismatch {
+                host: self.host.clone(),
+                port: self.port,
+                expected_fingerprint: known_host_fingerprint(&stored),
+                presented_fingerprint: sha256_fingerprint(&key_bytes),
+            }),
+        }
+    }
+
+    /// Check against OpenSSH `known_hosts` files. Unlisted hosts are
+    /// appended to `write_to` when the verifier says to remember them, or
+    /// when `append_unknown` is set and there is no verifier. The files are
+    /// read and written on the blocking pool, off russh's session task.
+    async fn check_openssh_known_hosts(
+        &self,
+        files: &[PathBuf],
+        write_to: Option<&Path>,
+        append_unknown: bool,
+        server_public_key: &russh::keys::key::PublicKey,
+    ) -> Result<bool, ConnectionError> {
+        let key_bytes = server_public_key.public_key_bytes();
+        let status = {
+            let (files, host, port) = (files.to_vec(), self.host.clone(), self.port);
+            let key_bytes = key_bytes.clone();
+            tokio::task::spawn_blocking(move || {
+                let mut known = KnownHostsFile::default();
+                for file in &files {
+                    known.extend(KnownHostsFile::load(file)?);
+                }
+                Ok(known.check(&host, port, &key_bytes))
+            })
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)))?
+        };
+        match status {
+            KnownHostsStatus::Trusted => Ok(true),
+            KnownHostsStatus::Revoked => Err(ConnectionError::HostKeyRevoked {
+                host: self.host.clone(),
+                port: self.port,
+                fingerprint: sha256_fingerprint(&key_bytes),
+            }),
+            KnownHostsStatus::Changed {
+                expected_fingerprint,
+            } => Err(ConnectionError::HostKeyMismatch {
+                host: self.host.clone(),
+                port: self.port,
+                expected_fingerprint,
+                presented_fingerprint: sha256_fingerprint(&key_bytes),
+            }),
+            KnownHostsStatus::Unknown => {
+                let remember = match self.ask_verifier(&key_bytes).await? {
+                    Some(decision) => decision == HostKeyDecision::AcceptAndRemember,
+                    None if append_unknown => true,
+                    None => {
+                        return Err(ConnectionError::HostKeyUnknown {
+                            host: self.host.clone(),
+                            port: self.port,
+                            fingerprint: sha256_fingerprint(&key_bytes),
+                        })
+                    }
+                };
+                if let (true, Some(path)) = (remember, write_to) {
+                    let (path, host, port) = (path.to_owned(), self.host.clone(), self.port);
+                    tokio::task::spawn_blocking(move || {
+                        append_known_host(&path, &host, port, &key_bytes)
+                    })
+                    .await
+                    .unwrap_or_else(|e| Err(std::io::Error::other(e)))?;
+                }
+                Ok(true)
+            }
+        }
     }
 }
 
@@ -120,54 +409,230 @@ impl russh::client::Handler for SshClientHandler {
         &mut self,
         server_public_key: &russh::keys::key::PublicKey,
     ) -> Result<bool, Self::Error> {
-        match self.host_key_policy {
+        let _ = self
+            .session
+            .host_key
+            .set(sha256_fingerprint(&server_public_key.public_key_bytes()));
+        match &self.host_key_policy {
             HostKeyPolicy::AcceptAll => Ok(true),
-            HostKeyPolicy::StrictFirstConnect => {
-                let key_bytes = server_public_key.public_key_bytes();
-                let host_key = self.host_key();
-                let mut known = self.known_keys.lock().await;
-                match known.get(&host_key) {
-                    None => {
-                        // Trust on first use — store the key.
-                        known.insert(host_key, key_bytes);
-                        Ok(true)
-                    }
-                    Some(stored) if stored == &key_bytes => Ok(true),
-                    Some(_) => Err(ConnectionError::HostKeyMismatch {
-                        host: self.host.clone(),
-                        port: self.port,
-                    }),
+            HostKeyPolicy::StrictFirstConnect => self.check_known_hosts(server_public_key).await,
+            HostKeyPolicy::KnownHostsFile {
+                path,
+                append_unknown,
+            } => {
+                self.check_openssh_known_hosts(
+                    std::slice::from_ref(path),
+                    Some(path.as_path()),
+                    *append_unknown,
+                    server_public_key,
+                )
+                .await
+            }
+            HostKeyPolicy::SystemKnownHosts { append_unknown } => {
+                let user_file = user_known_hosts_path();
+                let mut files: Vec<PathBuf> = user_file.iter().cloned().collect();
+                files.push(global_known_hosts_path());
+                self.check_openssh_known_hosts(
+                    &files,
+                    user_file.as_deref(),
+                    *append_unknown,
+                    server_public_key,
+                )
+                .await
+            }
+        }
+    }
+
+    async fn server_channel_open_forwarded_tcpip(
+        &mut self,
+        channel: russh::Channel<russh::client::Msg>,
+        connected_address: &str,
+        connected_port: u32,
+        _originator_address: &str,
+        _originator_port: u32,
+        _session: &mut russh::client::Session,
+    ) -> Result<(), Self::Error> {
+        let Some(target) = self
+            .session
+            .remote_forwards
+            .lookup(connected_address, connected_port)
+        else {
+            // Not one of ours (or already stopped) — refuse the connection.
+            let _ = channel.close().await;
+            return Ok(());
+        };
+
+        tokio::spawn(async move {
+            let local = (target.local_host.as_str(), target.local_port);
+            match TcpStream::connect(local).await {
+                Ok(stream) => {
+                    pipe(
+                        channel.into_stream(),
+                        stream,
+                        &target.counters,
+                        &target.cancel,
+                    )
+                    .await
+                }
+                Err(e) => {
+                    warn!(
+                        "remote forward: cannot reach {}:{}: {e}",
+                        target.local_host, target.local_port
+                    );
+                    let _ = channel.close().await;
                 }
             }
+        });
+        Ok(())
+    }
+
+    async fn server_channel_open_agent_forward(
+        &mut self,
+        channel: russh::ChannelId,
+        session: &mut russh::client::Session,
+    ) -> Result<(), Self::Error> {
+        match &mut self.agent {
+            Some(agent) => {
+                let replies = AgentChannelReplies {
+                    session: self.session.clone(),
+                    channel,
+                };
+                agent.open(u32::from(channel), Box::new(replies));
+            }
+            // We never asked for one.
+            None => session.close(channel),
+        }
+        Ok(())
+    }
+
+    async fn channel_close(
+        &mut self,
+        channel: russh::ChannelId,
+        _session: &mut russh::client::Session,
+    ) -> Result<(), Self::Error> {
+        if let Some(agent) = &mut self.agent {
+            agent.close(u32::from(channel));
+        }
+        Ok(())
+    }
+
+    async fn data(
+        &mut self,
+        channel: russh::ChannelId,
+        data: &[u8],
+        session: &mut russh::client::Session,
+    ) -> Result<(), Self::Error> {
+        // Agent channels have no `russh::Channel`; their data only arrives
+        // here, and is relayed by the channel's own task.
+        if let Some(agent) = self.agent.as_mut().filter(|a| a.owns(u32::from(channel))) {
+            if !agent.data(u32::from(channel), data) {
+                session.close(channel);
+            }
+            return Ok(());
+        }
+
+        // Other channels read their data from their `russh::Channel`, in
+        // their own task; waiting here would stall the whole session.
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// SshConnectOptions
+// ---------------------------------------------------------------------------
+
+/// Connection-time collaborators for [`SshAdapter::connect_with`].
+///
+/// Everything here is shared state owned by the caller rather than profile
+/// data, so it is not stored in [`ConnectionProfile`].
+#[derive(Clone)]
+pub struct SshConnectOptions {
+    /// Host-key trust store consulted under
+    /// [`HostKeyPolicy::StrictFirstConnect`].
+    pub known_hosts: Arc<dyn KnownHostsStore>,
+    /// Prompt for host keys the policy does not already trust. When `None`,
+    /// `StrictFirstConnect` trusts silently on first use and the
+    /// `known_hosts` policies follow their `append_unknown` flag.
+    pub verifier: Option<Arc<dyn HostKeyVerifier>>,
+    /// Resolves the profiles in [`SshSettings::jump_host_ids`]. Required
+    /// when the profile has a ProxyJump chain.
+    ///
+    /// [`SshSettings::jump_host_ids`]: crate::profile::types::SshSettings::jump_host_ids
+    pub jump_resolver: Option<Arc<dyn JumpHostResolver>>,
+    /// What to do when the output consumer falls behind.
+    pub output_mode: OutputMode,
+    /// Local agent socket that forwarded-agent requests are relayed to, for
+    /// profiles with [`SshSettings::forward_agent`] set. `None` uses
+    /// `SSH_AUTH_SOCK`; point it at a vault-backed agent to forward vault
+    /// keys instead.
+    ///
+    /// [`SshSettings::forward_agent`]: crate::profile::types::SshSettings::forward_agent
+    pub agent_socket: Option<PathBuf>,
+}
+
+impl Default for SshConnectOptions {
+    fn default() -> Self {
+        SshConnectOptions {
+            known_hosts: Arc::new(MemoryKnownHostsStore::new()),
+            verifier: None,
+            jump_resolver: None,
+            output_mode: OutputMode::default(),
+            agent_socket: None,
         }
     }
 }
 
+/// An authenticated session to one hop of a ProxyJump chain.
+struct JumpSession {
+    /// `host:port`, for error reporting.
+    name: String,
+    handle: russh::client::Handle<SshClientHandler>,
+}
+
 // ---------------------------------------------------------------------------
 // SshAdapter
 // ---------------------------------------------------------------------------
 
+/// Shared handle to a russh session, used by background forwarding tasks to
+/// open channels.
+pub(crate) type SessionHandle = Arc<tokio::sync::Mutex<russh::client::Handle<SshClientHandler>>>;
+
+/// Name of the shell opened at connect time.
+const PRIMARY_SHELL: &str = "main";
+
 /// SSH session adapter.
 ///
-/// An `SshAdapter` corresponds to one active SSH connection with one
-/// interactive PTY channel. Additional one-shot commands run in their own
-/// transient channels via [`TerminalAdapter::exec`].
+/// An `SshAdapter` corresponds to one active SSH connection with one primary
+/// interactive PTY channel, driven through [`TerminalAdapter`]. More shells
+/// can be opened on the same session with [`SshAdapter::open_shell`].
+/// One-shot commands run in their own transient channels via
+/// [`TerminalAdapter::exec`], and port forwards via [`PortForwardAdapter`].
 pub struct SshAdapter {
     /// Handle to the underlying russh session, used to open new channels.
-    handle: tokio::sync::Mutex<russh::client::Handle<SshClientHandler>>,
-    /// Sends commands to the background shell task.
-    shell_tx: mpsc::Sender<ShellCmd>,
-    /// Output bytes streamed from the interactive shell. Taken once via
-    /// [`TerminalAdapter::output_stream`].
-    output_rx: Option<mpsc::Receiver<Vec<u8>>>,
-    /// Set to `false` by the background task when the shell channel closes.
-    alive: Arc<AtomicBool>,
+    handle: SessionHandle,
+    /// The shell opened at connect time. Its liveness is the adapter's.
+    /// `None` for [`ShellMode::ExecOnly`] profiles.
+    shell: Option<ShellHandle>,
+    /// Additional shells opened with [`SshAdapter::open_shell`], by name.
+    shells: std::sync::Mutex<HashMap<String, ShellControl>>,
     /// The profile used to establish this connection (needed for reconnect).
     profile: ConnectionProfile,
     /// Credential used to authenticate (needed for reconnect).
     credential: Credential,
-    /// Persists host-key knowledge across reconnects.
-    known_keys: Arc<tokio::sync::Mutex<HashMap<String, Vec<u8>>>>,
+    /// Connect-time collaborators, reused on reconnect so host-key knowledge
+    /// persists.
+    options: SshConnectOptions,
+    /// Jump-host sessions carrying the tunnel, in chain order. Kept alive for
+    /// as long as the target session.
+    jumps: Vec<JumpSession>,
+    /// Running port forwards.
+    forwards: std::sync::Mutex<Vec<PortForwardHandle>>,
+    /// Shared with the session's handler.
+    session: SessionState,
+    /// What the key exchange agreed on, if the server's offer was readable.
+    algorithms: Option<NegotiatedAlgorithms>,
+    /// The login-script step that failed, if one did.
+    login_failure: Option<LoginStepFailed>,
 }
 
 // ---------------------------------------------------------------------------
@@ -175,49 +640,305 @@ pub struct SshAdapter {
 // ---------------------------------------------------------------------------
 
 /// Build a [`russh::client::Config`] from the connection profile.
-fn build_russh_config(profile: &ConnectionProfile) -> Arc<russh::client::Config> {
+fn build_russh_config(
+    profile: &ConnectionProfile,
+) -> Result<Arc<russh::client::Config>, ConnectionError> {
     let keepalive_interval = profile
         .ssh
         .as_ref()
         .and_then(|s| s.keepalive_secs)
         .map(Duration::from_secs);
 
-    Arc::new(russh::client::Config {
+    let preferred = match &profile.ssh {
+        Some(settings) => preferred(&settings.algorithms)?,
+        None => russh::Preferred::default(),
+    };
+
+    Ok(Arc::new(russh::client::Config {
         keepalive_interval,
         keepalive_max: 3,
+        preferred,
         ..Default::default()
-    })
+    }))
+}
+
+/// The profile's limit for `stage`, if any. Profiles without SSH settings
+/// get the defaults.
+fn stage_timeout(profile: &ConnectionProfile, stage: ConnectStage) -> Option<Duration> {
+    let defaults;
+    let settings = match &profile.ssh {
+        Some(settings) => settings,
+        None => {
+            defaults = SshSettings::default();
+            &defaults
+        }
+    };
+    let secs = match stage {
+        ConnectStage::Dns | ConnectStage::Tcp => settings.connect_timeout_secs,
+        ConnectStage::KeyExchange => settings.handshake_timeout_secs,
+        ConnectStage::Auth => settings.auth_timeout_secs,
+        ConnectStage::ChannelOpen => None,
+    };
+    secs.map(Duration::from_secs)
 }
 
-/// Authenticate the session using the provided credential.
+/// Time a handshake spends waiting for the user to answer a host-key
+/// prompt, which the key-exchange timeout does not count.
+#[derive(Default)]
+struct PromptClock {
+    state: std::sync::Mutex<PromptTime>,
+    /// Woken when a prompt is answered.
+    answered: tokio::sync::Notify,
+}
+
+#[derive(Default)]
+struct PromptTime {
+    /// When the open prompt was shown.
+    since: Option<Instant>,
+    /// Time spent in answered prompts.
+    total: Duration,
+}
+
+impl PromptClock {
+    fn open(&self) {
+        self.lock().since = Some(Instant::now());
+    }
+
+    fn close(&self) {
+        let mut time = self.lock();
+        if let Some(since) = time.since.take() {
+            time.total += since.elapsed();
+        }
+        drop(time);
+        self.answered.notify_waiters();
+    }
+
+    /// When a step started at `start` runs out of `limit`; `None` while a
+    /// prompt is open.
+    fn deadline(&self, start: Instant, limit: Duration) -> Option<Instant> {
+        let time = self.lock();
+        time.since.is_none().then(|| start + limit + time.total)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PromptTime> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Like [`within`] for the key exchange, but with the clock stopped while
+/// `prompts` has a host-key prompt open.
+async fn within_handshake<T>(
+    limit: Option<Duration>,
+    prompts: &PromptClock,
+    step: impl Future<Output = Result<T, ConnectionError>>,
+) -> Result<T, ConnectionError> {
+    let Some(timeout) = limit else {
+        return step.await;
+    };
+    let start = Instant::now();
+    tokio::pin!(step);
+    loop {
+        // Registered before reading the deadline, so an answer in between
+        // is not missed.
+        let answered = prompts.answered.notified();
+        tokio::pin!(answered);
+        answered.as_mut().enable();
+        let deadline = prompts.deadline(start, timeout);
+        tokio::select! {
+            result = &mut step => return result,
+            _ = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => answered.await,
+                }
+            } => {}
+        }
+        // A prompt may have opened, or been answered, while sleeping.
+        if prompts
+            .deadline(start, timeout)
+            .is_some_and(|deadline| deadline <= Instant::now())
+        {
+            return Err(ConnectionError::ConnectTimeout {
+                stage: ConnectStage::KeyExchange,
+                timeout,
+            });
+        }
+    }
+}
+
+/// Run one setup step, failing with [`ConnectionError::ConnectTimeout`] if
+/// it outlasts `limit`.
+async fn within<T>(
+    stage: ConnectStage,
+    limit: Option<Duration>,
+    step: impl Future<Output = Result<T, ConnectionError>>,
+) -> Result<T, ConnectionError> {
+    match limit {
+        Some(timeout) => tokio::time::timeout(timeout, step)
+            .await
+            .map_err(|_| ConnectionError::ConnectTimeout { stage, timeout })?,
+        None => step.await,
+    }
+}
+
+/// Attribute a failed handshake to the key exchange. Host-key verdicts and
+/// timeouts already say what went wrong and pass through.
+fn key_exchange_error(profile: &ConnectionProfile, err: ConnectionError) -> ConnectionError {
+    let reason = match err {
+        ConnectionError::Protocol(reason) => reason,
+        ConnectionError::Io(e) => e.to_string(),
+        other => return other,
+    };
+    ConnectionError::KeyExchange {
+        host: profile.host.clone(),
+        port: profile.port,
+        reason,
+    }
+}
+
+/// A channel the server would not open.
+fn channel_open_error(err: russh::Error) -> ConnectionError {
+    ConnectionError::ChannelOpen {
+        reason: err.to_string(),
+    }
+}
+
+/// Authenticate the session as `profile.username` using the provided
+/// credential.
 ///
 /// Returns `Ok(())` on success or a typed [`ConnectionError`] on failure.
 async fn authenticate(
     handle: &mut russh::client::Handle<SshClientHandler>,
-    username: &str,
+    profile: &ConnectionProfile,
     credential: &Credential,
 ) -> Result<(), ConnectionError> {
+    let mut methods = Vec::new();
+    flatten_credential(credential, &mut methods);
+    let preferred = profile
+        .ssh
+        .as_ref()
+        .and_then(|s| s.agent_fingerprint.as_deref());
+
+    // russh does not report partial success separately from failure, so a
+    // rejected step simply moves on: if the server still wants more, the
+    // next method continues the exchange; if not, it fails too.
+    let mut attempts = Vec::new();
+    for method in &methods {
+        // OpenSSH disconnects after `MaxAuthTries` failures; nothing more
+        // can be offered on this connection.
+        if handle.is_closed() {
+            break;
+        }
+        match try_method(handle, &profile.username, method, preferred, &mut attempts).await {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(_) if handle.is_closed() => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(ConnectionError::AuthFailed {
+        reason: auth_failure_reason(&attempts, handle.is_closed()),
+        attempts,
+    })
+}
+
+/// Summarise what was offered for [`ConnectionError::AuthFailed`].
+fn auth_failure_reason(attempts: &[AuthAttempt], closed: bool) -> String {
+    let tried = attempts
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    match (attempts.len(), closed) {
+        (0, _) => {
+            "nothing to offer (empty credential chain or SSH agent without identities)".to_owned()
+        }
+        (n, true) => format!(
+            "server closed the connection after {n} attempt{}, \
+             likely its MaxAuthTries limit (tried {tried})",
+            if n == 1 { "" } else { "s" }
+        ),
+        (_, false) => format!("server rejected {tried}"),
+    }
+}
+
+/// Expand [`Credential::Chain`]s (including nested ones) into the methods to
+/// try, in order.
+fn flatten_credential<'a>(credential: &'a Credential, out: &mut Vec<&'a Credential>) {
+    match credential {
+        Credential::Chain(steps) => {
+            for step in steps {
+                flatten_credential(step, out);
+            }
+        }
+        single => out.push(single),
+    }
+}
+
+/// SSH method name for a single (non-chain) credential.
+fn method_name(credential: &Credential) -> &'static str {
+    match credential {
+        Credential::Password(_) => "password",
+        Credential::PublicKey { .. } | Credential::SshAgent => "publickey",
+        Credential::KeyboardInteractive(_) => "keyboard-interactive",
+        Credential::Chain(_) => "chain",
+    }
+}
+
+/// Attempt one authentication method, recording what was offered in
+/// `attempts`. `Ok(false)` means the server did not accept the session (yet).
+async fn try_method(
+    handle: &mut russh::client::Handle<SshClientHandler>,
+    username: &str,
+    credential: &Credential,
+    preferred_fingerprint: Option<&str>,
+    attempts: &mut Vec<AuthAttempt>,
+) -> Result<bool, ConnectionError> {
     let ok = match credential {
-        Credential::Password(secret) => handle
-            .authenticate_password(username, secret.expose_secret().as_str())
-            .await
-            .map_err(ConnectionError::from)?,
+        Credential::Password(secret) => {
+            attempts.push(AuthAttempt::new(method_name(credential)));
+            handle
+                .authenticate_password(username, secret.expose_secret().as_str())
+                .await
+                .map_err(ConnectionError::from)?
+        }
         Credential::PublicKey {
             private_key_pem,
             passphrase,
+            certificate,
         } => {
             let pem = private_key_pem.expose_secret().as_str();
             let pass = passphrase.as_ref().map(|p| p.expose_secret().as_str());
             let key_pair =
-                russh::keys::decode_secret_key(pem, pass).map_err(ConnectionError::from)?;
-            handle
-                .authenticate_publickey(username, Arc::new(key_pair))
-                .await
-                .map_err(ConnectionError::from)?
+                Arc::new(russh::keys::decode_secret_key(pem, pass).map_err(ConnectionError::from)?);
+            let mut attempt = AuthAttempt::new(method_name(credential));
+            if let Ok(public) = key_pair.clone_public_key() {
+                attempt = attempt.with_identity(key_identity(&public));
+            }
+            match certificate {
+                Some(cert) => {
+                    let cert = parse_certificate(cert)?;
+                    attempt.identity = Some(match attempt.identity.take() {
+                        Some(key) => format!("{key}, certificate {}", cert.key_id()),
+                        None => format!("certificate {}", cert.key_id()),
+                    });
+                    attempts.push(attempt);
+                    handle
+                        .authenticate_openssh_cert(username, key_pair, cert)
+                        .await
+                        .map_err(ConnectionError::from)?
+                }
+                None => {
+                    attempts.push(attempt);
+                    handle
+                        .authenticate_publickey(username, key_pair)
+                        .await
+                        .map_err(ConnectionError::from)?
+                }
+            }
         }
         Credential::SshAgent => {
-            // Connect to the local SSH agent and authenticate via the first
-            // available identity.
             let mut agent = russh::keys::agent::client::AgentClient::connect_env()
                 .await
                 .map_err(|e| ConnectionError::Protocol(format!("SSH agent error: {e}")))?;
@@ -226,26 +947,307 @@ async fn authenticate(
                 .request_identities()
                 .await
                 .map_err(|e| ConnectionError::Protocol(format!("SSH agent identities: {e}")))?;
+            let identities = prefer_fingerprint(identities, preferred_fingerprint, |key| {
+                sha256_fingerprint(&key.public_key_bytes())
+            });
+
+            // Offer every identity in turn, like `ssh` does. With many keys
+            // loaded the server's MaxAuthTries may cut this short, which is
+            // what `SshSettings::agent_fingerprint` is for.
+            let mut accepted = false;
+            for key in identities {
+                attempts.push(
+                    AuthAttempt::new(method_name(credential)).with_identity(key_identity(&key)),
+                );
+                let (returned, result) = handle.authenticate_future(username, key, agent).await;
+                agent = returned;
+                accepted = result
+                    .map_err(|e| ConnectionError::Protocol(format!("SSH agent auth: {e}")))?;
+                if accepted || handle.is_closed() {
+                    break;
+                }
+            }
+            accepted
+        }
+        Credential::KeyboardInteractive(responder) => {
+            attempts.push(AuthAttempt::new(method_name(credential)));
+            keyboard_interactive(handle, username, responder.as_ref(), attempts).await?
+        }
+        // Flattened away by `authenticate`.
+        Credential::Chain(_) => false,
+    };
+    Ok(ok)
+}
+
+/// Key type and SHA-256 fingerprint, e.g. `ssh-ed25519 SHA256:…`.
+fn key_identity(key: &russh::keys::key::PublicKey) -> String {
+    format!(
+        "{} {}",
+        key.name(),
+        sha256_fingerprint(&key.public_key_bytes())
+    )
+}
+
+/// Move the item whose fingerprint matches `preferred` to the front, keeping
+/// the rest in order. The `SHA256:` prefix is optional on either side.
+fn prefer_fingerprint<T>(
+    mut items: Vec<T>,
+    preferred: Option<&str>,
+    fingerprint: impl Fn(&T) -> String,
+) -> Vec<T> {
+    let Some(preferred) = preferred else {
+        return items;
+    };
+    let bare = |f: &str| f.trim().trim_start_matches("SHA256:").to_owned();
+    let preferred = bare(preferred);
+    match items
+        .iter()
+        .position(|item| bare(&fingerprint(item)) == preferred)
+    {
+        Some(index) => items[..=index].rotate_right(1),
+        None => warn!("preferred SSH agent identity SHA256:{preferred} is not loaded"),
+    }
+    items
+}
+
+/// Upper bound on keyboard-interactive rounds, so a misbehaving server
+/// cannot keep us prompting forever.
+const MAX_KEYBOARD_INTERACTIVE_ROUNDS: usize = 16;
+
+/// Run a keyboard-interactive exchange, asking `responder` for each round
+/// that has prompts. `attempts` (everything tried so far) goes into the
+/// error if the rounds run out.
+async fn keyboard_interactive(
+    handle: &mut russh::client::Handle<SshClientHandler>,
+    username: &str,
+    responder: &dyn PromptResponder,
+    attempts: &[AuthAttempt],
+) -> Result<bool, ConnectionError> {
+    use russh::client::KeyboardInteractiveAuthResponse as Reply;
+
+    let mut reply = handle
+        .authenticate_keyboard_interactive_start(username, None)
+        .await?;
+    for _ in 0..MAX_KEYBOARD_INTERACTIVE_ROUNDS {
+        let (name, instructions, prompts) = match reply {
+            Reply::Success => return Ok(true),
+            Reply::Failure => return Ok(false),
+            Reply::InfoRequest {
+                name,
+                instructions,
+                prompts,
+            } => (name, instructions, prompts),
+        };
+        let challenge = KeyboardInteractiveChallenge {
+            name,
+            instructions,
+            prompts: prompts
+                .into_iter()
+                .map(|p| KeyboardInteractivePrompt {
+                    prompt: p.prompt,
+                    echo: p.echo,
+                })
+                .collect(),
+        };
+
+        // Servers often finish with an empty round that needs no answers.
+        let answers = if challenge.prompts.is_empty() {
+            Vec::new()
+        } else {
+            responder.respond(&challenge).await?
+        };
+        if answers.len() != challenge.prompts.len() {
+            return Err(ConnectionError::Protocol(format!(
+                "prompt responder gave {} answers to {} prompts",
+                answers.len(),
+                challenge.prompts.len()
+            )));
+        }
+
+        let answers = answers.iter().map(|a| a.expose_secret().clone()).collect();
+        reply = handle
+            .authenticate_keyboard_interactive_respond(answers)
+            .await?;
+    }
+
+    Err(ConnectionError::AuthFailed {
+        reason: format!(
+            "keyboard-interactive did not finish after {MAX_KEYBOARD_INTERACTIVE_ROUNDS} rounds"
+        ),
+        attempts: attempts.to_vec(),
+    })
+}
+
+/// Build the host-key-verifying handler for a session to `profile`.
+fn client_handler(
+    profile: &ConnectionProfile,
+    options: &SshConnectOptions,
+    session: SessionState,
+) -> SshClientHandler {
+    let host_key_policy = profile
+        .ssh
+        .as_ref()
+        .map(|s| s.host_key_policy.clone())
+        .unwrap_or_default();
 
-            let Some(pub_key) = identities.into_iter().next() else {
-                return Err(ConnectionError::AuthFailed {
-                    reason: "SSH agent has no identities".to_owned(),
-                });
-            };
+    SshClientHandler {
+        host: profile.host.clone(),
+        port: profile.port,
+        host_key_policy,
+        known_hosts: Arc::clone(&options.known_hosts),
+        verifier: options.verifier.clone(),
+        session,
+        agent: forwards_agent(profile).then(|| AgentForwarder::new(options.agent_socket.clone())),
+    }
+}
 
-            let (_, result) = handle.authenticate_future(username, pub_key, agent).await;
+/// `true` when the profile opts in to agent forwarding.
+fn forwards_agent(profile: &ConnectionProfile) -> bool {
+    profile.ssh.as_ref().is_some_and(|s| s.forward_agent)
+}
 
-            result.map_err(|e| ConnectionError::Protocol(format!("SSH agent auth: {e}")))?
+/// Send `auth-agent-req@openssh.com` on `channel` when the profile forwards
+/// the agent. Must precede the shell or exec request.
+async fn request_agent_forwarding(
+    channel: &russh::Channel<russh::client::Msg>,
+    profile: &ConnectionProfile,
+) -> Result<(), ConnectionError> {
+    if forwards_agent(profile) {
+        channel.agent_forward(false).await?;
+    }
+    Ok(())
+}
+
+/// Run the SSH handshake with `profile`, over `tunnel` when connecting
+/// through a jump host and over a fresh TCP connection otherwise.
+async fn open_session(
+    profile: &ConnectionProfile,
+    options: &SshConnectOptions,
+    tunnel: Option<russh::Channel<russh::client::Msg>>,
+    session: SessionState,
+) -> Result<
+    (
+        russh::client::Handle<SshClientHandler>,
+        Option<NegotiatedAlgorithms>,
+    ),
+    ConnectionError,
+> {
+    let config = build_russh_config(profile)?;
+    let prompts = Arc::clone(&session.prompts);
+    let handler = client_handler(profile, options, session);
+    let handshake = stage_timeout(profile, ConnectStage::KeyExchange);
+    let kexinit = KexInitSlot::default();
+
+    let handle = match tunnel {
+        Some(channel) => {
+            let stream = KexInitTap::new(channel.into_stream(), Arc::clone(&kexinit));
+            let kex = russh::client::connect_stream(Arc::clone(&config), stream, handler);
+            within_handshake(handshake, &prompts, kex).await
+        }
+        None => {
+            let limit = stage_timeout(profile, ConnectStage::Tcp);
+            let stream = dial(&profile.host, profile.port, limit).await?;
+            let stream = KexInitTap::new(stream, Arc::clone(&kexinit));
+            let kex = russh::client::connect_stream(Arc::clone(&config), stream, handler);
+            within_handshake(handshake, &prompts, kex).await
         }
     };
+    let handle = handle.map_err(|e| key_exchange_error(profile, e))?;
+
+    let server = kexinit
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    let negotiated = server.and_then(|server| negotiate(&config.preferred, &server));
+    if let Some(weak) = negotiated.as_ref().map(NegotiatedAlgorithms::weak) {
+        if !weak.is_empty() {
+            warn!(
+                "{}:{} negotiated weak SSH algorithms: {}",
+                profile.host,
+                profile.port,
+                weak.join(", ")
+            );
+        }
+    }
+    Ok((handle, negotiated))
+}
 
-    if ok {
-        Ok(())
-    } else {
-        Err(ConnectionError::AuthFailed {
-            reason: "server rejected the credential".to_owned(),
-        })
+/// Open a `direct-tcpip` channel from `jump` to `profile`'s host and port.
+///
+/// Failures are reported against the jump host, since it is the one that
+/// could not reach the next hop.
+async fn open_tunnel(
+    jump: &JumpSession,
+    hop: usize,
+    profile: &ConnectionProfile,
+) -> Result<russh::Channel<russh::client::Msg>, ConnectionError> {
+    jump.handle
+        .channel_open_direct_tcpip(
+            profile.host.clone(),
+            u32::from(profile.port),
+            "127.0.0.1",
+            0,
+        )
+        .await
+        .map_err(|e| jump_error(hop, jump.name.clone(), channel_open_error(e)))
+}
+
+fn jump_error(hop: usize, name: String, source: ConnectionError) -> ConnectionError {
+    ConnectionError::JumpHost {
+        hop,
+        name,
+        source: Box::new(source),
+    }
+}
+
+/// Connect and authenticate to every hop in `profile`'s ProxyJump chain,
+/// each one tunnelled through the previous. Returns an empty list when the
+/// profile has no jump hosts.
+async fn connect_jump_chain(
+    profile: &ConnectionProfile,
+    options: &SshConnectOptions,
+) -> Result<Vec<JumpSession>, ConnectionError> {
+    let jump_host_ids = profile
+        .ssh
+        .as_ref()
+        .map(|s| s.jump_host_ids.as_slice())
+        .unwrap_or_default();
+    if jump_host_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(resolver) = &options.jump_resolver else {
+        return Err(ConnectionError::Config(
+            "profile has jump hosts but no JumpHostResolver was supplied".to_owned(),
+        ));
+    };
+
+    let mut jumps: Vec<JumpSession> = Vec::with_capacity(jump_host_ids.len());
+    for (index, id) in jump_host_ids.iter().enumerate() {
+        let hop = index + 1;
+        let (jump_profile, jump_credential) = resolver
+            .resolve(id)
+            .await
+            .map_err(|e| jump_error(hop, id.clone(), e))?;
+        let name = format!("{}:{}", jump_profile.host, jump_profile.port);
+
+        let tunnel = match jumps.last() {
+            Some(previous) => Some(open_tunnel(previous, index, &jump_profile).await?),
+            None => None,
+        };
+        let handle = async {
+            let (mut handle, _) =
+                open_session(&jump_profile, options, tunnel, SessionState::default()).await?;
+            let limit = stage_timeout(&jump_profile, ConnectStage::Auth);
+            let auth = authenticate(&mut handle, &jump_profile, &jump_credential);
+            within(ConnectStage::Auth, limit, auth).await?;
+            Ok::<_, ConnectionError>(handle)
+        }
+        .await
+        .map_err(|e| jump_error(hop, name.clone(), e))?;
+
+        jumps.push(JumpSession { name, handle });
     }
+    Ok(jumps)
 }
 
 /// Inner connect helper shared by [`ConnectionAdapter::connect`] and
@@ -253,130 +1255,600 @@ async fn authenticate(
 async fn connect_inner(
     profile: ConnectionProfile,
     credential: Credential,
-    known_keys: Arc<tokio::sync::Mutex<HashMap<String, Vec<u8>>>>,
+    options: SshConnectOptions,
 ) -> Result<SshAdapter, ConnectionError> {
-    let config = build_russh_config(&profile);
+    let jumps = connect_jump_chain(&profile, &options).await?;
+    let tunnel = match jumps.last() {
+        Some(last) => Some(open_tunnel(last, jumps.len(), &profile).await?),
+        None => None,
+    };
 
-    let host_key_policy = profile
+    let session = SessionState::default();
+    let (mut handle, algorithms) =
+        open_session(&profile, &options, tunnel, session.clone()).await?;
+
+    let limit = stage_timeout(&profile, ConnectStage::Auth);
+    within(
+        ConnectStage::Auth,
+        limit,
+        authenticate(&mut handle, &profile, &credential),
+    )
+    .await?;
+    let handle = Arc::new(tokio::sync::Mutex::new(handle));
+    let _ = session.handle.set(Arc::downgrade(&handle));
+
+    // Open the interactive shell channel, watching its output if there is
+    // a login script to run.
+    let (pty, mode) = shell_settings(&profile);
+    let (mut tap, tapped) = if profile.login_actions.is_empty() {
+        (None, None)
+    } else {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Some(tx), Some(rx))
+    };
+    let shell = match mode {
+        ShellMode::ExecOnly => None,
+        ShellMode::Pty | ShellMode::NoPty => {
+            let channel = handle
+                .lock()
+                .await
+                .channel_open_session()
+                .await
+                .map_err(channel_open_error)?;
+            request_agent_forwarding(&channel, &profile).await?;
+            Some(
+                start_shell(
+                    channel,
+                    PRIMARY_SHELL,
+                    &pty,
+                    mode,
+                    options.output_mode,
+                    tap.take(),
+                )
+                .await?,
+            )
+        }
+    };
+
+    let login_failure = match (&shell, tapped) {
+        (Some(shell), Some(mut output)) => {
+            run_login_script(&profile.login_actions, shell.control(), &mut output)
+                .await
+                .err()
+        }
+        _ => None,
+    };
+    if let Some(failure) = &login_failure {
+        warn!("{}:{}: {failure}", profile.host, profile.port);
+    }
+
+    let adapter = SshAdapter {
+        handle,
+        shell,
+        shells: std::sync::Mutex::new(HashMap::new()),
+        profile,
+        credential,
+        options,
+        jumps,
+        forwards: std::sync::Mutex::new(Vec::new()),
+        session,
+        algorithms,
+        login_failure,
+    };
+
+    // Forwards configured on the profile are best-effort, like OpenSSH
+    // without ExitOnForwardFailure: a port that cannot be bound should not
+    // cost the user their shell.
+    let specs = adapter
+        .profile
         .ssh
         .as_ref()
-        .map(|s| s.host_key_policy.clone())
+        .map(|s| s.port_forwards.clone())
         .unwrap_or_default();
+    for spec in &specs {
+        if let Err(e) = adapter.start_forward(spec).await {
+            warn!("could not start port forward {spec:?}: {e}");
+        }
+    }
 
-    let handler = SshClientHandler {
-        host: profile.host.clone(),
-        port: profile.port,
-        host_key_policy,
-        known_keys: Arc::clone(&known_keys),
-    };
+    Ok(adapter)
+}
 
-    let addr = format!("{}:{}", profile.host, profile.port);
-    let mut handle = russh::client::connect(config, addr.as_str(), handler)
-        .await
-        .map_err(|e| match e {
-            ConnectionError::Io(ref io) if io.kind() == std::io::ErrorKind::ConnectionRefused => {
-                ConnectionError::Refused {
-                    host: profile.host.clone(),
-                    port: profile.port,
-                }
-            }
-            other => other,
-        })?;
-
-    authenticate(&mut handle, &profile.username, &credential).await?;
-
-    // Open the interactive shell channel.
-    let channel = handle.channel_open_session().await?;
-    channel
-        .request_pty(
-            false,
-            "xterm-256color",
-            80,
-            24,
-            0,
-            0,
-            &[], // terminal modes
-        )
-        .await?;
-    channel.request_shell(false).await?;
+impl SshAdapter {
+    /// Connect using caller-supplied [`SshConnectOptions`] (e.g., a persistent
+    /// [`KnownHostsStore`]).
+    pub async fn connect_with(
+        profile: &ConnectionProfile,
+        credential: Credential,
+        options: SshConnectOptions,
+    ) -> Result<Self, ConnectionError> {
+        connect_inner(profile.clone(), credential, options).await
+    }
+
+    /// Take the primary shell's output event receiver. Returns `None` after
+    /// the first call.
+    pub fn output_events(&mut self) -> Option<mpsc::Receiver<OutputEvent>> {
+        self.shell.as_mut()?.output_events()
+    }
+
+    /// Bytes of primary shell output discarded because the consumer fell
+    /// behind. Always `0` in [`OutputMode::Backpressure`].
+    pub fn dropped_output_bytes(&self) -> u64 {
+        self.shell
+            .as_ref()
+            .map_or(0, ShellHandle::dropped_output_bytes)
+    }
+
+    /// Open another interactive shell on this session, without
+    /// re-authenticating. `name` must not belong to a shell that is still
+    /// open. Fails for [`ShellMode::ExecOnly`] profiles.
+    pub async fn open_shell(&self, name: &str) -> Result<ShellHandle, ConnectionError> {
+        let (pty, mode) = shell_settings(&self.profile);
+        if mode == ShellMode::ExecOnly {
+            return Err(no_shell());
+        }
+        if self
+            .lock_shells()
+            .get(name)
+            .is_some_and(ShellControl::is_alive)
+        {
+            return Err(ConnectionError::Protocol(format!(
+                "a shell named {name} is already open"
+            )));
+        }
+
+        let channel = {
+            let handle = self.handle.lock().await;
+            handle
+                .channel_open_session()
+                .await
+                .map_err(channel_open_error)?
+        };
+        request_agent_forwarding(&channel, &self.profile).await?;
+        let shell = start_shell(channel, name, &pty, mode, self.options.output_mode, None).await?;
+
+        let mut shells = self.lock_shells();
+        shells.retain(|_, control| control.is_alive());
+        shells.insert(name.to_owned(), shell.control().clone());
+        Ok(shell)
+    }
+
+    /// Names of the additional shells that are still open.
+    pub fn shell_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .lock_shells()
+            .iter()
+            .filter(|(_, control)| control.is_alive())
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Close the additional shell called `name`. The session and every other
+    /// shell stay open.
+    pub async fn close_shell(&self, name: &str) -> Result<(), ConnectionError> {
+        let control = self
+            .lock_shells()
+            .remove(name)
+            .ok_or_else(|| ConnectionError::Protocol(format!("no shell named {name}")))?;
+        control.close().await;
+        Ok(())
+    }
+
+    /// Record the primary shell (see [`ShellHandle::record`]); `None` stops
+    /// recording. Shells from [`SshAdapter::open_shell`] are recorded through
+    /// their own handles.
+    pub async fn record(&self, recorder: Option<Recorder>) -> Result<(), ConnectionError> {
+        self.primary_shell()?.record(recorder).await
+    }
+
+    /// The algorithms negotiated with the server. `None` if its key-exchange
+    /// offer could not be read.
+    pub fn algorithms(&self) -> Option<&NegotiatedAlgorithms> {
+        self.algorithms.as_ref()
+    }
 
-    // Spawn the background task that owns the shell channel.
+    /// The step the profile's login script stopped at, if it failed.
+    pub fn login_failure(&self) -> Option<&LoginStepFailed> {
+        self.login_failure.as_ref()
+    }
+
+    /// The server's host key and the account logged in to. `None` if the
+    /// host key was never seen.
+    pub fn server_identity(&self) -> Option<ServerIdentity> {
+        Some(ServerIdentity {
+            host_key: self.session.host_key.get()?.clone(),
+            username: self.profile.username.clone(),
+        })
+    }
+
+    /// `true` while the SSH transport is up, even if every shell has
+    /// exited. Unlike [`ConnectionAdapter::is_alive`], this tells a remote
+    /// `exit` apart from a dropped connection.
+    pub async fn is_connected(&self) -> bool {
+        !self.handle.lock().await.is_closed()
+    }
+
+    /// The session, for adapters that open their own channels on it (see
+    /// [`SftpAdapter::from_ssh`](super::sftp::SftpAdapter::from_ssh)).
+    pub(crate) fn session(&self) -> SessionHandle {
+        Arc::clone(&self.handle)
+    }
+
+    fn primary_shell(&self) -> Result<&ShellHandle, ConnectionError> {
+        self.shell.as_ref().ok_or_else(no_shell)
+    }
+
+    fn lock_shells(&self) -> std::sync::MutexGuard<'_, HashMap<String, ShellControl>> {
+        self.shells.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_forwards(&self) -> std::sync::MutexGuard<'_, Vec<PortForwardHandle>> {
+        self.forwards.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn register_forward(&self, forward: &PortForwardHandle) {
+        self.lock_forwards().push(forward.clone());
+    }
+}
+
+/// The profile's PTY settings and shell mode.
+fn shell_settings(profile: &ConnectionProfile) -> (PtySettings, ShellMode) {
+    profile
+        .ssh
+        .as_ref()
+        .map(|s| (s.pty.clone(), s.shell_mode))
+        .unwrap_or_default()
+}
+
+fn no_shell() -> ConnectionError {
+    ConnectionError::Config("the profile is exec-only and has no shell".to_owned())
+}
+
+/// Request a PTY (per `shell_mode`) and shell on `channel` and spawn the
+/// task that drives it. Output is also copied to `tap` until its receiver
+/// is dropped.
+async fn start_shell(
+    channel: russh::Channel<russh::client::Msg>,
+    name: &str,
+    pty: &PtySettings,
+    shell_mode: ShellMode,
+    mode: OutputMode,
+    tap: Option<mpsc::UnboundedSender<Vec<u8>>>,
+) -> Result<ShellHandle, ConnectionError> {
+    request_shell(&channel, pty, shell_mode).await?;
+    let size = (pty.cols, pty.rows);
+
+    // Output goes through an OutputSink so the task never blocks on a slow
+    // consumer.
     let alive = Arc::new(AtomicBool::new(true));
-    let (shell_tx, mut shell_rx) = mpsc::channel::<ShellCmd>(64);
+    let (shell_tx, shell_rx) = mpsc::channel::<ShellCmd>(64);
     let (output_tx, output_rx) = mpsc::channel::<Vec<u8>>(256);
+    let (events_tx, events_rx) = mpsc::channel::<OutputEvent>(16);
+
+    let sink = OutputSink::spawn(mode, output_tx, events_tx);
+    let dropped = sink.dropped_counter();
+
+    let dropped_handle = CancellationToken::new();
+    let handle_gone = dropped_handle.clone();
     let alive_bg = Arc::clone(&alive);
+    tokio::spawn(async move {
+        run_shell(channel, sink, shell_rx, size, tap, handle_gone).await;
+        // Use Release ordering so the false store is visible to any thread
+        // that subsequently reads the flag with Acquire.
+        alive_bg.store(false, Ordering::Release);
+    });
+
+    let control = ShellControl::new(shell_tx, alive);
+    Ok(ShellHandle::new(
+        name,
+        control,
+        output_rx,
+        events_rx,
+        dropped,
+        dropped_handle,
+    ))
+}
+
+/// Pump a shell channel until it closes, its output consumer goes away or
+/// its handle asks it to close or is dropped.
+async fn run_shell(
+    mut ch: russh::Channel<russh::client::Msg>,
+    sink: OutputSink,
+    mut shell_rx: mpsc::Receiver<ShellCmd>,
+    mut size: (u16, u16),
+    mut tap: Option<mpsc::UnboundedSender<Vec<u8>>>,
+    handle_gone: CancellationToken,
+) {
+    let consumer_gone = sink.consumer_gone();
+    let mut recorder: Option<Recorder> = None;
+    loop {
+        tokio::select! {
+            msg = ch.wait() => {
+                match msg {
+                    Some(russh::ChannelMsg::Data { data }) => {
+                        // Recorded before the sink, so output a slow
+                        // consumer misses is still in the recording.
+                        if let Some(recorder) = &recorder {
+                            recorder.output(&data);
+                        }
+                        if tap.as_ref().is_some_and(|t| t.send(data.to_vec()).is_err()) {
+                            tap = None;
+                        }
+                        if !sink.push(data.to_vec()) {
+                            // The consumer fell too far behind.
+                            let _ = ch.close().await;
+                            break;
+                        }
+                    }
+                    Some(russh::ChannelMsg::ExitStatus { .. })
+                    | Some(russh::ChannelMsg::Eof)
+                    | Some(russh::ChannelMsg::Close)
+                    | None => break,
+                    _ => {}
+                }
+            }
+            _ = handle_gone.cancelled() => {
+                let _ = ch.close().await;
+                break;
+            }
+            _ = consumer_gone.cancelled() => {
+                // Output receiver was dropped; close the SSH channel so
+                // the server learns immediately.
+                let _ = ch.close().await;
+                break;
+            }
+            cmd = shell_rx.recv() => {
+                match cmd {
+                    Some(ShellCmd::Data(bytes)) => {
+                        if let Some(recorder) = &recorder {
+                            recorder.input(&bytes);
+                        }
+                        if ch.data(std::io::Cursor::new(bytes)).await.is_err() {
+                            // Broken pipe to the remote — close cleanly.
+                            let _ = ch.close().await;
+                            break;
+                        }
+                    }
+                    Some(ShellCmd::Resize { cols, rows }) => {
+                        size = (cols, rows);
+                        if let Some(recorder) = &recorder {
+                            recorder.resize(cols, rows);
+                        }
+                        if ch.window_change(cols as u32, rows as u32, 0, 0).await.is_err() {
+                            // Broken pipe to the remote — close cleanly.
+                            let _ = ch.close().await;
+                            break;
+                        }
+                    }
+                    Some(ShellCmd::Record(next)) => {
+                        if let Some(next) = &next {
+                            next.resize(size.0, size.1);
+                        }
+                        recorder = next;
+                    }
+                    Some(ShellCmd::Close) | None => {
+                        let _ = ch.close().await;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    // Buffered output is still delivered; the stream ends after it.
+    sink.close();
+}
 
+/// Open a channel running the `name` subsystem, e.g. `sftp`.
+pub(crate) async fn open_subsystem(
+    session: &SessionHandle,
+    name: &str,
+) -> Result<russh::Channel<russh::client::Msg>, ConnectionError> {
+    let channel = session
+        .lock()
+        .await
+        .channel_open_session()
+        .await
+        .map_err(channel_open_error)?;
+    channel.request_subsystem(true, name).await?;
+    Ok(channel)
+}
+
+/// Open a `direct-tcpip` channel to `host:port` on behalf of a client
+/// connected from `peer`.
+async fn open_direct_tcpip(
+    session: &SessionHandle,
+    host: &str,
+    port: u16,
+    peer: SocketAddr,
+) -> Result<russh::Channel<russh::client::Msg>, ConnectionError> {
+    let handle = session.lock().await;
+    handle
+        .channel_open_direct_tcpip(
+            host,
+            u32::from(port),
+            peer.ip().to_string(),
+            u32::from(peer.port()),
+        )
+        .await
+        .map_err(channel_open_error)
+}
+
+/// Accept connections on `listener` until `forward` is stopped, serving each
+/// one on its own task.
+fn spawn_accept_loop<F, Fut>(listener: TcpListener, forward: &PortForwardHandle, serve: F)
+where
+    F: Fn(TcpStream, SocketAddr) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let cancel = forward.cancel_token().clone();
     tokio::spawn(async move {
-        let mut ch = channel;
         loop {
             tokio::select! {
-                msg = ch.wait() => {
-                    match msg {
-                        Some(russh::ChannelMsg::Data { data }) => {
-                            use tokio::sync::mpsc::error::TrySendError;
-                            match output_tx.try_send(data.to_vec()) {
-                                Ok(()) => {}
-                                Err(TrySendError::Full(_)) => {
-                                    // Consumer is falling behind; drop this
-                                    // chunk to keep the session alive (same
-                                    // behaviour as a real terminal under
-                                    // backpressure — data is lost rather than
-                                    // blocking the task indefinitely).
-                                }
-                                Err(TrySendError::Closed(_)) => {
-                                    // Output receiver was dropped; close the
-                                    // SSH channel so the server learns
-                                    // immediately.
-                                    let _ = ch.close().await;
-                                    break;
-                                }
-                            }
-                        }
-                        Some(russh::ChannelMsg::ExitStatus { .. })
-                        | Some(russh::ChannelMsg::Eof)
-                        | Some(russh::ChannelMsg::Close)
-                        | None => break,
-                        _ => {}
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        tokio::spawn(serve(stream, peer));
+                    }
+                    Err(e) => {
+                        warn!("port forward listener failed: {e}");
+                        cancel.cancel();
+                        break;
                     }
+                },
+                _ = cancel.cancelled() => break,
+            }
+        }
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Exec task
+// ---------------------------------------------------------------------------
+
+/// Where an exec channel's output goes.
+struct ExecOutput {
+    stdout: OutputSink,
+    stderr: OutputSink,
+}
+
+/// Drive an exec channel until the command finishes, the timeout expires or
+/// the handle cancels it.
+async fn run_exec(
+    mut ch: russh::Channel<russh::client::Msg>,
+    output: ExecOutput,
+    mut control: mpsc::Receiver<ExecCmd>,
+    timeout: Option<Duration>,
+    cancel: CancellationToken,
+) -> Result<ExitStatus, ConnectionError> {
+    let deadline = async move {
+        match timeout {
+            Some(timeout) => {
+                tokio::time::sleep(timeout).await;
+                timeout
+            }
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(deadline);
+
+    let mut status = ExitStatus::default();
+    let mut exited = false;
+    let mut eof = false;
+    let mut control_open = true;
+    let mut overflowed = false;
+
+    let result = loop {
+        tokio::select! {
+            msg = ch.wait() => match msg {
+                Some(russh::ChannelMsg::Data { data }) => {
+                    overflowed = !output.stdout.push(data.to_vec());
                 }
-                cmd = shell_rx.recv() => {
-                    match cmd {
-                        Some(ShellCmd::Data(bytes)) => {
-                            if ch.data(std::io::Cursor::new(bytes)).await.is_err() {
-                                // Broken pipe to the remote — close cleanly.
-                                let _ = ch.close().await;
-                                break;
-                            }
-                        }
-                        Some(ShellCmd::Resize { cols, rows }) => {
-                            if ch.window_change(cols as u32, rows as u32, 0, 0).await.is_err() {
-                                // Broken pipe to the remote — close cleanly.
-                                let _ = ch.close().await;
-                                break;
-                            }
-                        }
-                        Some(ShellCmd::Close) | None => {
-                            let _ = ch.close().await;
-                            break;
-                        }
+                Some(russh::ChannelMsg::ExtendedData { data, ext: 1 }) => {
+                    overflowed = !output.stderr.push(data.to_vec());
+                }
+                Some(russh::ChannelMsg::ExitStatus { exit_status }) => {
+                    status.code = Some(exit_status);
+                    exited = true;
+                }
+                Some(russh::ChannelMsg::ExitSignal {
+                    signal_name,
+                    core_dumped,
+                    error_message,
+                    ..
+                }) => {
+                    status.signal = Some(ExitSignal {
+                        signal: signal_from_russh(&signal_name),
+                        core_dumped,
+                        error_message,
+                    });
+                    exited = true;
+                }
+                Some(russh::ChannelMsg::Eof) => {
+                    eof = true;
+                    output.stdout.close();
+                    output.stderr.close();
+                }
+                Some(russh::ChannelMsg::Close) | None => break Ok(status),
+                _ => {}
+            },
+            cmd = control.recv(), if control_open => {
+                let sent = match cmd {
+                    Some(ExecCmd::Stdin(bytes)) => ch.data(std::io::Cursor::new(bytes)).await,
+                    Some(ExecCmd::CloseStdin) => ch.eof().await,
+                    Some(ExecCmd::Signal(signal)) => ch.signal(signal_to_russh(signal)).await,
+                    Some(ExecCmd::Resize { cols, rows }) => {
+                        ch.window_change(u32::from(cols), u32::from(rows), 0, 0).await
+                    }
+                    None => {
+                        control_open = false;
+                        Ok(())
                     }
+                };
+                if let Err(e) = sent {
+                    break Err(e.into());
                 }
             }
+            timeout = &mut deadline => {
+                let _ = ch.signal(russh::Sig::KILL).await;
+                break Err(ConnectionError::Timeout { timeout });
+            }
+            _ = cancel.cancelled() => break Err(ConnectionError::Cancelled),
         }
-        // Use Release ordering so the false store is visible to any thread
-        // that subsequently reads the flag with Acquire.
-        alive_bg.store(false, Ordering::Release);
-    });
 
-    Ok(SshAdapter {
-        handle: tokio::sync::Mutex::new(handle),
-        shell_tx,
-        output_rx: Some(output_rx),
-        alive,
-        profile,
-        credential,
-        known_keys,
-    })
+        if overflowed {
+            break Err(ConnectionError::OutputOverflow {
+                limit: OUTPUT_LIMIT,
+            });
+        }
+        // Servers send EOF and the exit status in either order. Stop once
+        // both have arrived rather than waiting on a `Close` that may be
+        // delayed or omitted.
+        if eof && exited {
+            break Ok(status);
+        }
+    };
+
+    // Dropping the sinks ends both output streams after buffered data.
+    drop(output);
+    let _ = ch.close().await;
+    result
+}
+
+fn signal_to_russh(signal: Signal) -> russh::Sig {
+    match signal {
+        Signal::Abrt => russh::Sig::ABRT,
+        Signal::Alrm => russh::Sig::ALRM,
+        Signal::Fpe => russh::Sig::FPE,
+        Signal::Hup => russh::Sig::HUP,
+        Signal::Ill => russh::Sig::ILL,
+        Signal::Int => russh::Sig::INT,
+        Signal::Kill => russh::Sig::KILL,
+        Signal::Pipe => russh::Sig::PIPE,
+        Signal::Quit => russh::Sig::QUIT,
+        Signal::Segv => russh::Sig::SEGV,
+        Signal::Term => russh::Sig::TERM,
+        Signal::Usr1 => russh::Sig::USR1,
+        Signal::Other(name) => russh::Sig::Custom(name),
+    }
+}
+
+fn signal_from_russh(signal: &russh::Sig) -> Signal {
+    match signal {
+        russh::Sig::ABRT => Signal::Abrt,
+        russh::Sig::ALRM => Signal::Alrm,
+        russh::Sig::FPE => Signal::Fpe,
+        russh::Sig::HUP => Signal::Hup,
+        russh::Sig::ILL => Signal::Ill,
+        russh::Sig::INT => Signal::Int,
+        russh::Sig::KILL => Signal::Kill,
+        russh::Sig::PIPE => Signal::Pipe,
+        russh::Sig::QUIT => Signal::Quit,
+        russh::Sig::SEGV => Signal::Segv,
+        russh::Sig::TERM => Signal::Term,
+        russh::Sig::USR1 => Signal::Usr1,
+        russh::Sig::Custom(name) => Signal::Other(name.clone()),
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -389,38 +1861,54 @@ impl ConnectionAdapter for SshAdapter {
         profile: &ConnectionProfile,
         credential: Credential,
     ) -> Result<Self, ConnectionError> {
-        let known_keys = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
-        connect_inner(profile.clone(), credential, known_keys).await
+        connect_inner(profile.clone(), credential, SshConnectOptions::default()).await
     }
 
     async fn disconnect(&mut self) -> Result<(), ConnectionError> {
         // Ask the background task to close the shell channel.  The send and
         // the underlying SSH disconnect are best-effort: errors are
         // intentionally ignored because we are tearing down regardless.
-        let _ = self.shell_tx.send(ShellCmd::Close).await;
+        if let Some(shell) = &self.shell {
+            shell.close().await;
+        }
+        let named: Vec<ShellControl> = self.lock_shells().drain().map(|(_, c)| c).collect();
+        for shell in named {
+            shell.close().await;
+        }
         let handle = self.handle.lock().await;
         let _ = handle
             .disconnect(russh::Disconnect::ByApplication, "", "en-US")
             .await;
-        // Release ordering pairs with the Acquire load in is_alive().
-        self.alive.store(false, Ordering::Release);
+        for forward in self.lock_forwards().drain(..) {
+            forward.stop();
+        }
+        // Tear the tunnel down from the target outwards.
+        for jump in self.jumps.iter().rev() {
+            let _ = jump
+                .handle
+                .disconnect(russh::Disconnect::ByApplication, "", "en-US")
+                .await;
+        }
         Ok(())
     }
 
     fn is_alive(&self) -> bool {
-        // Acquire pairs with the Release store in the background task so the
-        // false is visible without reordering on weakly-ordered architectures.
-        self.alive.load(Ordering::Acquire)
+        match &self.shell {
+            Some(shell) => shell.is_alive(),
+            // Without a shell the transport is all there is. A busy lock
+            // means someone is using the session.
+            None => !self.handle.try_lock().is_ok_and(|h| h.is_closed()),
+        }
     }
 
     async fn reconnect(&mut self) -> Result<(), ConnectionError> {
         let profile = self.profile.clone();
         let credential = self.credential.clone();
-        let known_keys = Arc::clone(&self.known_keys);
+        let options = self.options.clone();
         // Disconnect errors are intentionally ignored: the old transport may
         // already be dead, and we are about to replace it anyway.
         let _ = self.disconnect().await;
-        let new = connect_inner(profile, credential, known_keys).await?;
+        let new = connect_inner(profile, credential, options).await?;
         *self = new;
         Ok(())
     }
@@ -430,6 +1918,167 @@ impl ConnectionAdapter for SshAdapter {
     }
 }
 
+// ---------------------------------------------------------------------------
+// PortForwardAdapter impl
+// ---------------------------------------------------------------------------
+
+#[async_trait]
+impl PortForwardAdapter for SshAdapter {
+    async fn forward_local(
+        &self,
+        bind_address: &str,
+        bind_port: u16,
+        remote_host: &str,
+        remote_port: u16,
+    ) -> Result<PortForwardHandle, ConnectionError> {
+        let listener = TcpListener::bind((bind_address, bind_port)).await?;
+        let spec = PortForwardSpec::Local {
+            bind_address: bind_address.to_owned(),
+            bind_port,
+            remote_host: remote_host.to_owned(),
+            remote_port,
+        };
+        let forward = PortForwardHandle::new(spec, listener.local_addr()?.port());
+
+        let session = Arc::clone(&self.handle);
+        let counters = Arc::clone(forward.counters());
+        let cancel = forward.cancel_token().clone();
+        let remote_host = remote_host.to_owned();
+        spawn_accept_loop(listener, &forward, move |stream, peer| {
+            let session = Arc::clone(&session);
+            let counters = Arc::clone(&counters);
+            let cancel = cancel.clone();
+            let remote_host = remote_host.clone();
+            async move {
+                match open_direct_tcpip(&session, &remote_host, remote_port, peer).await {
+                    Ok(channel) => pipe(stream, channel.into_stream(), &counters, &cancel).await,
+                    Err(e) => warn!("local forward to {remote_host}:{remote_port} failed: {e}"),
+                }
+            }
+        });
+
+        self.register_forward(&forward);
+        Ok(forward)
+    }
+
+    async fn forward_remote(
+        &self,
+        bind_address: &str,
+        bind_port: u16,
+        local_host: &str,
+        local_port: u16,
+    ) -> Result<PortForwardHandle, ConnectionError> {
+        let allocated = {
+            let mut handle = self.handle.lock().await;
+            handle
+                .tcpip_forward(bind_address, u32::from(bind_port))
+                .await?
+        };
+        // The server only reports a port when it picked one for us.
+        let bound_port = match bind_port {
+            0 => u16::try_from(allocated).map_err(|_| {
+                ConnectionError::Protocol(format!("server allocated invalid port {allocated}"))
+            })?,
+            port => port,
+        };
+
+        let spec = PortForwardSpec::Remote {
+            bind_address: bind_address.to_owned(),
+            bind_port,
+            local_host: local_host.to_owned(),
+            local_port,
+        };
+        let forward = PortForwardHandle::new(spec, bound_port);
+        self.session.remote_forwards.insert(
+            bind_address,
+            u32::from(bound_port),
+            RemoteForwardTarget {
+                local_host: local_host.to_owned(),
+                local_port,
+                counters: Arc::clone(forward.counters()),
+                cancel: forward.cancel_token().clone(),
+            },
+        );
+
+        self.register_forward(&forward);
+        Ok(forward)
+    }
+
+    async fn forward_dynamic(
+        &self,
+        bind_address: &str,
+        bind_port: u16,
+    ) -> Result<PortForwardHandle, ConnectionError> {
+        let listener = TcpListener::bind((bind_address, bind_port)).await?;
+        let spec = PortForwardSpec::Dynamic {
+            bind_address: bind_address.to_owned(),
+            bind_port,
+        };
+        let forward = PortForwardHandle::new(spec, listener.local_addr()?.port());
+
+        let session = Arc::clone(&self.handle);
+        let counters = Arc::clone(forward.counters());
+        let cancel = forward.cancel_token().clone();
+        spawn_accept_loop(listener, &forward, move |mut stream, peer| {
+            let session = Arc::clone(&session);
+            let counters = Arc::clone(&counters);
+            let cancel = cancel.clone();
+            async move {
+                let (host, port) = match socks5_accept(&mut stream).await {
+                    Ok(target) => target,
+                    Err(e) => {
+                        warn!("SOCKS5 handshake from {peer} failed: {e}");
+                        return;
+                    }
+                };
+                match open_direct_tcpip(&session, &host, port, peer).await {
+                    Ok(channel) => {
+                        if socks5_reply(&mut stream, SocksReply::Succeeded)
+                            .await
+                            .is_ok()
+                        {
+                            pipe(stream, channel.into_stream(), &counters, &cancel).await;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("dynamic forward to {host}:{port} failed: {e}");
+                        let _ = socks5_reply(&mut stream, SocksReply::HostUnreachable).await;
+                    }
+                }
+            }
+        });
+
+        self.register_forward(&forward);
+        Ok(forward)
+    }
+
+    fn list_forwards(&self) -> Vec<PortForwardHandle> {
+        self.lock_forwards().clone()
+    }
+
+    async fn stop_forward(&self, id: &str) -> Result<(), ConnectionError> {
+        let forward = {
+            let mut forwards = self.lock_forwards();
+            let index = forwards
+                .iter()
+                .position(|f| f.id == id)
+                .ok_or_else(|| ConnectionError::Protocol(format!("no port forward {id}")))?;
+            forwards.remove(index)
+        };
+        forward.stop();
+
+        if let PortForwardSpec::Remote { bind_address, .. } = &forward.spec {
+            let port = u32::from(forward.bound_port);
+            self.session.remote_forwards.remove(bind_address, port);
+            let handle = self.handle.lock().await;
+            handle
+                .cancel_tcpip_forward(bind_address.as_str(), port)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
 // ---------------------------------------------------------------------------
 // TerminalAdapter impl
 // ---------------------------------------------------------------------------
@@ -437,73 +2086,80 @@ impl ConnectionAdapter for SshAdapter {
 #[async_trait]
 impl TerminalAdapter for SshAdapter {
     async fn send_input(&self, data: &[u8]) -> Result<(), ConnectionError> {
-        self.shell_tx
-            .send(ShellCmd::Data(data.to_vec()))
-            .await
-            .map_err(|_| {
-                // Defensively mark dead in case the background task's Release
-                // store hasn't propagated to this thread yet.
-                self.alive.store(false, Ordering::Release);
-                ConnectionError::Protocol("shell channel closed".to_owned())
-            })
+        self.primary_shell()?.send_input(data).await
     }
 
     fn output_stream(&mut self) -> Option<mpsc::Receiver<Vec<u8>>> {
-        self.output_rx.take()
+        self.shell.as_mut()?.output_stream()
     }
 
     async fn resize(&self, cols: u16, rows: u16) -> Result<(), ConnectionError> {
-        self.shell_tx
-            .send(ShellCmd::Resize { cols, rows })
-            .await
-            .map_err(|_| {
-                // Defensive alive marking — same rationale as send_input.
-                self.alive.store(false, Ordering::Release);
-                ConnectionError::Protocol("shell channel closed".to_owned())
-            })
+        self.primary_shell()?.resize(cols, rows).await
     }
 
     async fn exec(&self, command: &str) -> Result<ExecResult, ConnectionError> {
+        self.exec_stream(ExecRequest::new(command))
+            .await?
+            .collect()
+            .await
+    }
+
+    async fn exec_stream(&self, request: ExecRequest) -> Result<ExecHandle, ConnectionError> {
         let channel = {
             let handle = self.handle.lock().await;
-            handle.channel_open_session().await?
+            handle
+                .channel_open_session()
+                .await
+                .map_err(channel_open_error)?
         };
+        request_agent_forwarding(&channel, &self.profile).await?;
 
-        channel.exec(true, command).await?;
+        for (name, value) in &request.env {
+            channel
+                .set_env(false, name.as_str(), value.as_str())
+                .await?;
+        }
+        if let Some(pty) = &request.pty {
+            channel
+                .request_pty(
+                    false,
+                    &pty.term,
+                    u32::from(pty.cols),
+                    u32::from(pty.rows),
+                    0,
+                    0,
+                    &[],
+                )
+                .await?;
+        }
 
-        let mut stdout = Vec::new();
-        let mut stderr = Vec::new();
-        let mut exit_code = None;
-        let mut ch = channel;
+        channel.exec(true, request.command.as_str()).await?;
 
-        // Collect output until the channel signals it is done.
-        //
-        // Standard server ordering is: Data* → ExitStatus → Eof → Close.
-        // Breaking on `Eof` is safe because all data and the exit status
-        // will have arrived before the server sends EOF.  Waiting for
-        // `Close` instead risks hanging indefinitely if the server sends
-        // `Eof` but then delays or omits `Close`.
-        loop {
-            match ch.wait().await {
-                Some(russh::ChannelMsg::Data { data }) => {
-                    stdout.extend_from_slice(&data);
-                }
-                Some(russh::ChannelMsg::ExtendedData { data, ext: 1 }) => {
-                    stderr.extend_from_slice(&data);
-                }
-                Some(russh::ChannelMsg::ExitStatus { exit_status }) => {
-                    exit_code = Some(exit_status);
-                }
-                Some(russh::ChannelMsg::Eof) | Some(russh::ChannelMsg::Close) | None => break,
-                _ => {}
-            }
-        }
+        let (stdout_tx, stdout) = mpsc::channel(64);
+        let (stderr_tx, stderr) = mpsc::channel(64);
+        // An overflow fails the command, so the events are not needed.
+        let (events_tx, _) = mpsc::channel(1);
+        let output = ExecOutput {
+            stdout: OutputSink::spawn(OutputMode::Backpressure, stdout_tx, events_tx.clone()),
+            stderr: OutputSink::spawn(OutputMode::Backpressure, stderr_tx, events_tx),
+        };
+        let (control, control_rx) = mpsc::channel(16);
+        let (exit_tx, exit) = oneshot::channel();
+        let cancel = CancellationToken::new();
 
-        Ok(ExecResult {
+        let task_cancel = cancel.clone();
+        tokio::spawn(async move {
+            let result = run_exec(channel, output, control_rx, request.timeout, task_cancel).await;
+            let _ = exit_tx.send(result);
+        });
+
+        Ok(ExecHandle::new(ExecParts {
             stdout,
             stderr,
-            exit_code,
-        })
+            control,
+            exit,
+            cancel,
+        }))
     }
 }
 
@@ -513,15 +2169,22 @@ impl TerminalAdapter for SshAdapter {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
     use std::sync::Arc;
 
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
     use russh::keys::key::KeyPair;
     use russh::keys::PublicKeyBase64 as _;
 
-    use crate::profile::types::{ConnectionProfile, HostKeyPolicy};
+    use crate::connection::host_key_verifier::{HostKeyDecision, MockHostKeyVerifier};
+    use crate::connection::jump::MockJumpHostResolver;
+    use crate::connection::known_hosts::{
+        sha256_fingerprint, KnownHostsStore, MemoryKnownHostsStore,
+    };
+    use crate::profile::types::{ConnectionProfile, HostKeyPolicy, KnownHost};
 
-    use super::{ConnectionError, SshClientHandler};
+    use super::{
+        ConnectionError, Credential, SessionState, SshAdapter, SshClientHandler, SshConnectOptions,
+    };
 
     // -----------------------------------------------------------------------
     // Helper: build a handler for testing the TOFU logic directly.
@@ -531,18 +2194,21 @@ mod tests {
         host: &str,
         port: u16,
         policy: HostKeyPolicy,
-        known_keys: Arc<tokio::sync::Mutex<HashMap<String, Vec<u8>>>>,
+        known_hosts: Arc<MemoryKnownHostsStore>,
     ) -> SshClientHandler {
         SshClientHandler {
             host: host.to_owned(),
             port,
             host_key_policy: policy,
-            known_keys,
+            known_hosts,
+            verifier: None,
+            session: SessionState::default(),
+            agent: None,
         }
     }
 
-    fn fresh_known_keys() -> Arc<tokio::sync::Mutex<HashMap<String, Vec<u8>>>> {
-        Arc::new(tokio::sync::Mutex::new(HashMap::new()))
+    fn fresh_known_keys() -> Arc<MemoryKnownHostsStore> {
+        Arc::new(MemoryKnownHostsStore::new())
     }
 
     // -----------------------------------------------------------------------
@@ -569,9 +2235,9 @@ mod tests {
         let accepted = handler.check_server_key(&pub_key).await.unwrap();
         assert!(accepted, "first connect should be accepted (TOFU)");
 
-        let stored = known.lock().await;
+        let stored = known.get("host.example.com", 22).await.unwrap();
         assert!(
-            stored.contains_key("host.example.com:22"),
+            stored.is_some(),
             "key should be stored keyed by host:port after first connect"
         );
     }
@@ -641,9 +2307,22 @@ mod tests {
         let result = h2.check_server_key(&pub2).await;
 
         match result {
-            Err(ConnectionError::HostKeyMismatch { host, port }) => {
+            Err(ConnectionError::HostKeyMismatch {
+                host,
+                port,
+                expected_fingerprint,
+                presented_fingerprint,
+            }) => {
                 assert_eq!(host, "host.example.com");
                 assert_eq!(port, 22);
+                assert_eq!(
+                    expected_fingerprint,
+                    sha256_fingerprint(&pub1.public_key_bytes())
+                );
+                assert_eq!(
+                    presented_fingerprint,
+                    sha256_fingerprint(&pub2.public_key_bytes())
+                );
             }
             other => panic!("expected HostKeyMismatch, got {other:?}"),
         }
@@ -663,9 +2342,14 @@ mod tests {
             let key2 = KeyPair::generate_ed25519().expect("keygen");
             let pub2 = key2.clone_public_key().expect("pub2");
             known
-                .lock()
+                .insert(KnownHost::new(
+                    "host.example.com",
+                    22,
+                    pub2.name(),
+                    BASE64.encode(pub2.public_key_bytes()),
+                ))
                 .await
-                .insert("host.example.com:22".to_owned(), pub2.public_key_bytes());
+                .unwrap();
         }
 
         // AcceptAll should ignore the mismatch.
@@ -741,6 +2425,266 @@ mod tests {
         );
     }
 
+    // -----------------------------------------------------------------------
+    // OpenSSH known_hosts policy tests
+    // -----------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn known_hosts_file_policy_trusts_listed_and_rejects_changed_key() {
+        use russh::client::Handler as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known_hosts");
+
+        let trusted = KeyPair::generate_ed25519().expect("keygen");
+        let trusted_pub = trusted.clone_public_key().expect("pub");
+        std::fs::write(
+            &path,
+            format!(
+                "host.example.com ssh-ed25519 {}\n",
+                trusted_pub.public_key_base64()
+            ),
+        )
+        .unwrap();
+
+        let policy = HostKeyPolicy::KnownHostsFile {
+            path: path.clone(),
+            append_unknown: false,
+        };
+        let mut h = make_handler("host.example.com", 22, policy, fresh_known_keys());
+        assert!(h.check_server_key(&trusted_pub).await.unwrap());
+
+        let other = KeyPair::generate_ed25519().expect("keygen");
+        let other_pub = other.clone_public_key().expect("pub");
+        let err = h.check_server_key(&other_pub).await.unwrap_err();
+        assert!(matches!(err, ConnectionError::HostKeyMismatch { .. }));
+    }
+
+    #[tokio::test]
+    async fn known_hosts_file_policy_unknown_host_rejected_or_appended() {
+        use russh::client::Handler as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known_hosts");
+        let key = KeyPair::generate_ed25519().expect("keygen");
+        let key_pub = key.clone_public_key().expect("pub");
+
+        let strict = HostKeyPolicy::KnownHostsFile {
+            path: path.clone(),
+            append_unknown: false,
+        };
+        let mut h = make_handler("new.example.com", 2222, strict, fresh_known_keys());
+        let err = h.check_server_key(&key_pub).await.unwrap_err();
+        assert!(matches!(err, ConnectionError::HostKeyUnknown { .. }));
+
+        let appending = HostKeyPolicy::KnownHostsFile {
+            path: path.clone(),
+            append_unknown: true,
+        };
+        let mut h = make_handler("new.example.com", 2222, appending, fresh_known_keys());
+        assert!(h.check_server_key(&key_pub).await.unwrap());
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(written.starts_with("[new.example.com]:2222 ssh-ed25519 "));
+
+        // The appended entry is trusted on the next strict check.
+        let strict = HostKeyPolicy::KnownHostsFile {
+            path,
+            append_unknown: false,
+        };
+        let mut h = make_handler("new.example.com", 2222, strict, fresh_known_keys());
+        assert!(h.check_server_key(&key_pub).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn known_hosts_files_are_read_off_the_runtime() {
+        use russh::client::Handler as _;
+
+        // Reading a FIFO blocks until a writer opens it. On this
+        // single-threaded runtime the writer task could never run if the
+        // read blocked the runtime thread.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known_hosts");
+        let made = std::process::Command::new("mkfifo")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(made.success());
+        let key = KeyPair::generate_ed25519().expect("keygen");
+        let key_pub = key.clone_public_key().expect("pub");
+        let line = format!(
+            "host.example.com ssh-ed25519 {}\n",
+            key_pub.public_key_base64()
+        );
+        let writer = tokio::spawn(tokio::fs::write(path.clone(), line));
+
+        let policy = HostKeyPolicy::KnownHostsFile {
+            path,
+            append_unknown: false,
+        };
+        let mut h = make_handler("host.example.com", 22, policy, fresh_known_keys());
+        assert!(h.check_server_key(&key_pub).await.unwrap());
+        writer.await.unwrap().unwrap();
+    }
+
+    // -----------------------------------------------------------------------
+    // HostKeyVerifier tests
+    // -----------------------------------------------------------------------
+
+    fn handler_with_verifier(
+        policy: HostKeyPolicy,
+        known_hosts: Arc<MemoryKnownHostsStore>,
+        decision: HostKeyDecision,
+    ) -> SshClientHandler {
+        let mut verifier = MockHostKeyVerifier::new();
+        verifier
+            .expect_verify()
+            .withf(|info| {
+                info.host == "host.example.com"
+                    && info.port == 22
+                    && info.algorithm == "ssh-ed25519"
+                    && info.md5_fingerprint.starts_with("MD5:")
+            })
+            .times(1)
+            .returning(move |_| decision);
+        let mut h = make_handler("host.example.com", 22, policy, known_hosts);
+        h.verifier = Some(Arc::new(verifier));
+        h
+    }
+
+    #[tokio::test]
+    async fn verifier_accept_once_does_not_store_key() {
+        use russh::client::Handler as _;
+
+        let known = fresh_known_keys();
+        let key = KeyPair::generate_ed25519().expect("keygen");
+        let pub_key = key.clone_public_key().expect("pub");
+
+        let mut h = handler_with_verifier(
+            HostKeyPolicy::StrictFirstConnect,
+            Arc::clone(&known),
+            HostKeyDecision::AcceptOnce,
+        );
+        assert!(h.check_server_key(&pub_key).await.unwrap());
+        assert!(known.get("host.example.com", 22).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn verifier_accept_and_remember_stores_key() {
+        use russh::client::Handler as _;
+
+        let known = fresh_known_keys();
+        let key = KeyPair::generate_ed25519().expect("keygen");
+        let pub_key = key.clone_public_key().expect("pub");
+
+        let mut h = handler_with_verifier(
+            HostKeyPolicy::StrictFirstConnect,
+            Arc::clone(&known),
+            HostKeyDecision::AcceptAndRemember,
+        );
+        assert!(h.check_server_key(&pub_key).await.unwrap());
+        let stored = known.get("host.example.com", 22).await.unwrap().unwrap();
+        assert_eq!(stored.public_key, BASE64.encode(pub_key.public_key_bytes()));
+    }
+
+    #[tokio::test]
+    async fn verifier_reject_fails_with_fingerprint() {
+        use russh::client::Handler as _;
+
+        let key = KeyPair::generate_ed25519().expect("keygen");
+        let pub_key = key.clone_public_key().expect("pub");
+
+        let mut h = handler_with_verifier(
+            HostKeyPolicy::StrictFirstConnect,
+            fresh_known_keys(),
+            HostKeyDecision::Reject,
+        );
+        match h.check_server_key(&pub_key).await.unwrap_err() {
+            ConnectionError::HostKeyRejected { fingerprint, .. } => {
+                assert_eq!(fingerprint, sha256_fingerprint(&pub_key.public_key_bytes()));
+            }
+            other => panic!("expected HostKeyRejected, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn verifier_remember_appends_to_known_hosts_file() {
+        use russh::client::Handler as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known_hosts");
+        let key = KeyPair::generate_ed25519().expect("keygen");
+        let pub_key = key.clone_public_key().expect("pub");
+
+        let policy = HostKeyPolicy::KnownHostsFile {
+            path: path.clone(),
+            append_unknown: false,
+        };
+        let mut h = handler_with_verifier(
+            policy,
+            fresh_known_keys(),
+            HostKeyDecision::AcceptAndRemember,
+        );
+        assert!(h.check_server_key(&pub_key).await.unwrap());
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(written.starts_with("host.example.com ssh-ed25519 "));
+    }
+
+    // -----------------------------------------------------------------------
+    // ProxyJump tests
+    // -----------------------------------------------------------------------
+
+    fn profile_with_jumps(jump_host_ids: &[&str]) -> ConnectionProfile {
+        let mut profile = ConnectionProfile::new_ssh("target", "10.0.0.5", 22, "ops");
+        if let Some(ssh) = profile.ssh.as_mut() {
+            ssh.jump_host_ids = jump_host_ids.iter().map(|id| id.to_string()).collect();
+        }
+        profile
+    }
+
+    #[tokio::test]
+    async fn jump_chain_without_resolver_is_rejected() {
+        let profile = profile_with_jumps(&["bastion"]);
+        let err = match SshAdapter::connect_with(
+            &profile,
+            Credential::SshAgent,
+            SshConnectOptions::default(),
+        )
+        .await
+        {
+            Ok(_) => panic!("connect must fail without a resolver"),
+            Err(e) => e,
+        };
+        assert!(err.to_string().contains("JumpHostResolver"));
+    }
+
+    #[tokio::test]
+    async fn jump_chain_reports_failing_hop() {
+        let mut resolver = MockJumpHostResolver::new();
+        resolver
+            .expect_resolve()
+            .withf(|id| id == "bastion")
+            .times(1)
+            .returning(|_| Err(ConnectionError::Protocol("no such profile".to_owned())));
+
+        let options = SshConnectOptions {
+            jump_resolver: Some(Arc::new(resolver)),
+            ..SshConnectOptions::default()
+        };
+        let profile = profile_with_jumps(&["bastion", "inner"]);
+        let err = match SshAdapter::connect_with(&profile, Credential::SshAgent, options).await {
+            Ok(_) => panic!("connect must fail when a hop cannot be resolved"),
+            Err(e) => e,
+        };
+        match err {
+            ConnectionError::JumpHost { hop, name, source } => {
+                assert_eq!(hop, 1);
+                assert_eq!(name, "bastion");
+                assert!(matches!(*source, ConnectionError::Protocol(_)));
+            }
+            other => panic!("expected JumpHost, got {other:?}"),
+        }
+    }
+
     // -----------------------------------------------------------------------
     // ExecResult helpers
     // -----------------------------------------------------------------------
@@ -753,6 +2697,7 @@ mod tests {
             stdout: b"hello\n".to_vec(),
             stderr: vec![],
             exit_code: Some(0),
+            exit_signal: None,
         };
         assert_eq!(r.stdout_str(), "hello\n");
     }
@@ -765,6 +2710,7 @@ mod tests {
             stdout: vec![],
             stderr: b"error\n".to_vec(),
             exit_code: Some(1),
+            exit_signal: None,
         };
         assert_eq!(r.stderr_str(), "error\n");
     }
@@ -789,6 +2735,7 @@ mod tests {
         let key_cred = Credential::PublicKey {
             private_key_pem: SecretString::new("-----BEGIN OPENSSH".to_owned()),
             passphrase: None,
+            certificate: None,
         };
         let debug = format!("{key_cred:?}");
         assert!(
@@ -797,6 +2744,76 @@ mod tests {
         );
     }
 
+    #[test]
+    fn credential_chains_flatten_in_order() {
+        use secrecy::SecretString;
+
+        use super::{flatten_credential, method_name, Credential};
+        use crate::connection::keyboard_interactive::CredentialPromptResponder;
+
+        let password = Credential::Password(SecretString::new("pw".to_owned()));
+        let kbd = Credential::KeyboardInteractive(Arc::new(CredentialPromptResponder::new()));
+        let chain = Credential::Chain(vec![
+            Credential::SshAgent,
+            Credential::Chain(vec![kbd, password]),
+        ]);
+
+        let mut methods = Vec::new();
+        flatten_credential(&chain, &mut methods);
+        let names: Vec<_> = methods.iter().map(|m| method_name(m)).collect();
+        assert_eq!(names, ["publickey", "keyboard-interactive", "password"]);
+
+        let debug = format!("{chain:?}");
+        assert!(
+            !debug.contains("pw\""),
+            "chain debug must stay redacted: {debug}"
+        );
+        assert!(debug.contains("Credential::KeyboardInteractive"));
+    }
+
+    #[test]
+    fn preferred_fingerprint_moves_to_front() {
+        use super::prefer_fingerprint;
+
+        let keys = vec!["SHA256:aaa", "SHA256:bbb", "SHA256:ccc"];
+        let fp = |k: &&str| k.to_string();
+
+        assert_eq!(
+            prefer_fingerprint(keys.clone(), Some("SHA256:ccc"), fp),
+            ["SHA256:ccc", "SHA256:aaa", "SHA256:bbb"]
+        );
+        // The prefix is optional and unknown fingerprints keep the order.
+        assert_eq!(
+            prefer_fingerprint(keys.clone(), Some("bbb"), fp),
+            ["SHA256:bbb", "SHA256:aaa", "SHA256:ccc"]
+        );
+        assert_eq!(prefer_fingerprint(keys.clone(), Some("zzz"), fp), keys);
+        assert_eq!(prefer_fingerprint(keys.clone(), None, fp), keys);
+    }
+
+    #[test]
+    fn auth_failure_reason_lists_attempts() {
+        use super::auth_failure_reason;
+        use crate::connection::AuthAttempt;
+
+        let attempts = vec![
+            AuthAttempt::new("publickey").with_identity("ssh-ed25519 SHA256:aaa"),
+            AuthAttempt::new("password"),
+        ];
+        assert_eq!(
+            auth_failure_reason(&attempts, false),
+            "server rejected publickey (ssh-ed25519 SHA256:aaa), password"
+        );
+        assert!(auth_failure_reason(&attempts, true).contains("MaxAuthTries"));
+        assert!(auth_failure_reason(&attempts, true).contains("after 2 attempts"));
+        assert!(auth_failure_reason(&attempts[1..], true).contains("after 1 attempt,"));
+        assert_eq!(
+            auth_failure_reason(&[AuthAttempt::new("keyboard-interactive")], false),
+            "server rejected keyboard-interactive"
+        );
+        assert!(auth_failure_reason(&[], false).contains("nothing to offer"));
+    }
+
     // -----------------------------------------------------------------------
     // ConnectionProfile helper
     // -----------------------------------------------------------------------
@@ -820,4 +2837,115 @@ mod tests {
             "keepalive should be enabled by default"
         );
     }
+
+    #[test]
+    fn agent_forwarding_is_opt_in() {
+        use super::{client_handler, SessionState};
+
+        let mut p = ConnectionProfile::new_ssh("Test", "host.example.com", 22, "alice");
+        let options = SshConnectOptions::default();
+        assert!(client_handler(&p, &options, SessionState::default())
+            .agent
+            .is_none());
+
+        p.ssh.as_mut().unwrap().forward_agent = true;
+        assert!(client_handler(&p, &options, SessionState::default())
+            .agent
+            .is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_steps_time_out_with_their_stage() {
+        use super::{stage_timeout, within};
+        use crate::connection::ConnectStage;
+
+        let p = ConnectionProfile::new_ssh("Test", "host.example.com", 22, "alice");
+        let limit = stage_timeout(&p, ConnectStage::KeyExchange);
+        assert_eq!(limit, Some(std::time::Duration::from_secs(60)));
+
+        let err = within(
+            ConnectStage::KeyExchange,
+            limit,
+            std::future::pending::<Result<(), ConnectionError>>(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ConnectionError::ConnectTimeout {
+                stage: ConnectStage::KeyExchange,
+                ..
+            }
+        ));
+        assert_eq!(err.to_string(), "Timed out during key exchange after 60s");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn host_key_prompts_do_not_count_against_the_handshake_timeout() {
+        use super::{within_handshake, PromptClock};
+        use crate::connection::ConnectStage;
+        use std::time::Duration;
+        use tokio::time::sleep;
+
+        let limit = Some(Duration::from_secs(10));
+        let prompts = PromptClock::default();
+        // 4s of handshake, a minute at the prompt, then 4s more.
+        within_handshake(limit, &prompts, async {
+            sleep(Duration::from_secs(4)).await;
+            prompts.open();
+            sleep(Duration::from_secs(60)).await;
+            prompts.close();
+            sleep(Duration::from_secs(4)).await;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let prompts = PromptClock::default();
+        let started = tokio::time::Instant::now();
+        let err = within_handshake(limit, &prompts, async {
+            sleep(Duration::from_secs(4)).await;
+            prompts.open();
+            sleep(Duration::from_secs(60)).await;
+            prompts.close();
+            std::future::pending::<Result<(), ConnectionError>>().await
+        })
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ConnectionError::ConnectTimeout {
+                stage: ConnectStage::KeyExchange,
+                ..
+            }
+        ));
+        assert_eq!(started.elapsed(), Duration::from_secs(70));
+    }
+
+    #[test]
+    fn handshake_failures_are_attributed_to_key_exchange() {
+        use super::key_exchange_error;
+        use crate::connection::ConnectStage;
+
+        let p = ConnectionProfile::new_ssh("Test", "host.example.com", 22, "alice");
+        let err = key_exchange_error(&p, ConnectionError::Protocol("no common kex".into()));
+        assert!(matches!(err, ConnectionError::KeyExchange { .. }));
+
+        let rejected = key_exchange_error(
+            &p,
+            ConnectionError::HostKeyRejected {
+                host: "host.example.com".into(),
+                port: 22,
+                fingerprint: "SHA256:x".into(),
+            },
+        );
+        assert!(matches!(rejected, ConnectionError::HostKeyRejected { .. }));
+
+        let through_jump = ConnectionError::JumpHost {
+            hop: 1,
+            name: "bastion:22".into(),
+            source: Box::new(err),
+        };
+        assert_eq!(through_jump.stage(), Some(ConnectStage::KeyExchange));
+    }
 }
@@ -0,0 +1,1138 @@
+//! Directory sync between a local tree and a remote one, rsync style.
+//!
+//! [`DirSync`] walks both trees and compares the files they share by size
+//! and modification time, or by SHA-256 with remote files hashed by
+//! `sha256sum` over an exec channel. The result is a [`SyncPlan`]. Running
+//! the plan creates missing directories, sends the copies through a
+//! [`TransferManager`], gives each copy its source's modification time so
+//! the next comparison sees it as unchanged, and in delete mode removes what
+//! the source lacks.
+//!
+//! Symlinks and special files are skipped on both sides.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+
+use super::files::{file_name, join, shell_quote, FileKind};
+use super::transfer::{TransferManager, TransferRequest};
+use super::{ConnectionError, FileTransferAdapter, TerminalAdapter};
+
+/// Remote paths hashed per `sha256sum` invocation, to stay well under the
+/// server's command-line limit.
+const HASH_BATCH: usize = 64;
+
+/// Which way changes flow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncDirection {
+    /// Make the remote tree match the local one.
+    #[default]
+    Upload,
+    /// Make the local tree match the remote one.
+    Download,
+    /// Copy changes both ways.
+    Bidirectional,
+}
+
+/// How [`DirSync`] compares and what it may change.
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    pub direction: SyncDirection,
+    /// Globs a file must match to be synced; empty syncs every file. A glob
+    /// without `/` matches file names (`*.rs`); one with `/` matches the
+    /// path from the sync root (`src/**/*.rs`).
+    pub include: Vec<String>,
+    /// Globs for files and directories to leave alone, matched like
+    /// `include`. Nothing under an excluded directory is synced.
+    pub exclude: Vec<String>,
+    /// Compare files of equal size by SHA-256 rather than modification
+    /// time. Needs [`DirSync::with_checksums`].
+    pub checksum: bool,
+    /// Delete what the destination has and the source lacks. Ignored in
+    /// bidirectional mode, which cannot tell a deletion from a creation. A
+    /// directory that still holds files the globs leave out is kept.
+    pub delete_extraneous: bool,
+    /// Plan without changing anything.
+    pub dry_run: bool,
+    /// Modification times this close count as equal; FTP servers and FAT
+    /// file systems keep coarser times than local disks.
+    pub mtime_tolerance: Duration,
+    /// Bidirectional mode: when the trees were last synced. A file changed
+    /// on both sides since then is a conflict. Without it the newer copy
+    /// wins.
+    pub last_synced: Option<DateTime<Utc>>,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            direction: SyncDirection::Upload,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            checksum: false,
+            delete_extraneous: false,
+            dry_run: false,
+            mtime_tolerance: Duration::from_secs(1),
+            last_synced: None,
+        }
+    }
+}
+
+/// One change a sync makes. Paths are relative to the sync roots, with `/`
+/// separators; the empty path is the root itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum SyncAction {
+    CreateRemoteDir {
+        path: String,
+    },
+    CreateLocalDir {
+        path: String,
+    },
+    Upload {
+        path: String,
+        size: u64,
+        modified: Option<DateTime<Utc>>,
+    },
+    Download {
+        path: String,
+        size: u64,
+        modified: Option<DateTime<Utc>>,
+    },
+    DeleteRemote {
+        path: String,
+    },
+    DeleteLocal {
+        path: String,
+    },
+}
+
+impl SyncAction {
+    pub fn path(&self) -> &str {
+        match self {
+            SyncAction::CreateRemoteDir { path }
+            | SyncAction::CreateLocalDir { path }
+            | SyncAction::Upload { path, .. }
+            | SyncAction::Download { path, .. }
+            | SyncAction::DeleteRemote { path }
+            | SyncAction::DeleteLocal { path } => path,
+        }
+    }
+}
+
+/// A path the sync left alone because it could not tell which side is
+/// right.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyncConflict {
+    pub path: String,
+    pub reason: ConflictReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictReason {
+    /// A file on one side, a directory on the other. Nothing below it is
+    /// synced.
+    FileAndDirectory,
+    /// Bidirectional mode: both copies changed since `last_synced`.
+    ChangedOnBothSides,
+    /// Bidirectional mode: the copies differ but were modified at the same
+    /// time, so neither is newer.
+    SameModificationTime,
+}
+
+/// What a sync will do, in the order it does it: directories are created
+/// parents first, then files copied, then deletions made children first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SyncPlan {
+    pub actions: Vec<SyncAction>,
+    pub conflicts: Vec<SyncConflict>,
+}
+
+impl SyncPlan {
+    /// Bytes the plan copies.
+    pub fn bytes(&self) -> u64 {
+        self.actions
+            .iter()
+            .map(|action| match action {
+                SyncAction::Upload { size, .. } | SyncAction::Download { size, .. } => *size,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+/// An action that failed while the rest of the plan went ahead.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyncFailure {
+    pub action: SyncAction,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyncReport {
+    pub plan: SyncPlan,
+    /// Set for [`SyncOptions::dry_run`]: nothing was changed.
+    pub dry_run: bool,
+    pub failures: Vec<SyncFailure>,
+}
+
+// ---------------------------------------------------------------------------
+// DirSync
+// ---------------------------------------------------------------------------
+
+/// Syncs local directory `local` with remote directory `remote`.
+pub struct DirSync {
+    local: PathBuf,
+    remote: String,
+    options: SyncOptions,
+    checksums: Option<Arc<dyn TerminalAdapter>>,
+}
+
+impl DirSync {
+    pub fn new(local: impl Into<PathBuf>, remote: impl Into<String>, options: SyncOptions) -> Self {
+        DirSync {
+            local: local.into(),
+            remote: remote.into(),
+            options,
+            checksums: None,
+        }
+    }
+
+    /// Run `sha256sum` through `exec` for [`SyncOptions::checksum`]. It
+    /// must reach the same host, and see the same paths, as the file
+    /// transfer adapter, e.g. the `SshAdapter` an `SftpAdapter` shares.
+    pub fn with_checksums(mut self, exec: Arc<dyn TerminalAdapter>) -> Self {
+        self.checksums = Some(exec);
+        self
+    }
+
+    /// Compare the trees without changing anything.
+    pub async fn plan(
+        &self,
+        adapter: &dyn FileTransferAdapter,
+    ) -> Result<SyncPlan, ConnectionError> {
+        let filter = Filter::new(&self.options)?;
+        let local = walk_local(&self.local, &filter).await?;
+        let remote = walk_remote(adapter, &self.remote, &filter).await?;
+        let hashes = if self.options.checksum {
+            let Some(exec) = &self.checksums else {
+                return Err(ConnectionError::NotSupported {
+                    protocol: adapter.protocol(),
+                });
+            };
+            self.hashes(exec.as_ref(), &local, &remote).await?
+        } else {
+            HashMap::new()
+        };
+        Ok(Planner {
+            options: &self.options,
+            hashes: &hashes,
+        }
+        .plan(&local, &remote))
+    }
+
+    /// Plan, then carry the plan out through `manager`, unless this is a dry
+    /// run. A failed action is reported and the rest of the plan goes ahead.
+    pub async fn run(&self, manager: &TransferManager) -> Result<SyncReport, ConnectionError> {
+        let adapter = manager.adapter();
+        let plan = self.plan(adapter.as_ref()).await?;
+        if self.options.dry_run {
+            return Ok(SyncReport {
+                plan,
+                dry_run: true,
+                failures: Vec::new(),
+            });
+        }
+
+        let mut failures = Vec::new();
+        let mut copies = Vec::new();
+        for action in &plan.actions {
+            let result = match action {
+                SyncAction::CreateRemoteDir { path } => {
+                    adapter.mkdir(&self.remote_path(path)).await
+                }
+                SyncAction::CreateLocalDir { path } => tokio::fs::create_dir(self.local_path(path))
+                    .await
+                    .map_err(ConnectionError::from),
+                SyncAction::Upload { path, .. } => {
+                    let request =
+                        TransferRequest::upload(self.local_path(path), self.remote_path(path));
+                    copies.push((action, manager.enqueue(request)));
+                    Ok(())
+                }
+                SyncAction::Download { path, .. } => {
+                    let request =
+                        TransferRequest::download(self.remote_path(path), self.local_path(path));
+                    copies.push((action, manager.enqueue(request)));
+                    Ok(())
+                }
+                // Deletions wait until every copy is done.
+                SyncAction::DeleteRemote { .. } | SyncAction::DeleteLocal { .. } => continue,
+            };
+            if let Err(e) = result {
+                failures.push(SyncFailure {
+                    action: action.clone(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+
+        for (action, mut handle) in copies {
+            let result = match handle.wait().await {
+                Ok(_) => self.keep_modified(adapter.as_ref(), action).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                failures.push(SyncFailure {
+                    action: action.clone(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+
+        for action in &plan.actions {
+            let result = match action {
+                SyncAction::DeleteRemote { path } => {
+                    remove_remote(adapter.as_ref(), &self.remote_path(path)).await
+                }
+                SyncAction::DeleteLocal { path } => remove_local(&self.local_path(path)).await,
+                _ => continue,
+            };
+            if let Err(e) = result {
+                failures.push(SyncFailure {
+                    action: action.clone(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+
+        Ok(SyncReport {
+            plan,
+            dry_run: false,
+            failures,
+        })
+    }
+
+    fn local_path(&self, path: &str) -> PathBuf {
+        path.split('/')
+            .filter(|part| !part.is_empty())
+            .fold(self.local.clone(), |dir, part| dir.join(part))
+    }
+
+    fn remote_path(&self, path: &str) -> String {
+        if path.is_empty() {
+            self.remote.clone()
+        } else {
+            join(&self.remote, path)
+        }
+    }
+
+    /// Give a finished copy its source's modification time.
+    async fn keep_modified(
+        &self,
+        adapter: &dyn FileTransferAdapter,
+        action: &SyncAction,
+    ) -> Result<(), ConnectionError> {
+        match action {
+            SyncAction::Upload {
+                path,
+                modified: Some(time),
+                ..
+            } => adapter.set_modified(&self.remote_path(path), *time).await,
+            SyncAction::Download {
+                path,
+                modified: Some(time),
+                ..
+            } => {
+                let (path, time) = (self.local_path(path), SystemTime::from(*time));
+                tokio::task::spawn_blocking(move || {
+                    std::fs::File::options()
+                        .write(true)
+                        .open(path)?
+                        .set_modified(time)
+                })
+                .await
+                .map_err(std::io::Error::other)??;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// SHA-256 of both copies of every file whose sizes agree, keyed by
+    /// relative path.
+    async fn hashes(
+        &self,
+        exec: &dyn TerminalAdapter,
+        local: &Tree,
+        remote: &Tree,
+    ) -> Result<HashMap<String, (String, String)>, ConnectionError> {
+        let shared: Vec<&String> = local
+            .iter()
+            .filter(|(path, l)| {
+                remote
+                    .get(*path)
+                    .is_some_and(|r| !l.dir && !r.dir && l.size == r.size)
+            })
+            .map(|(path, _)| path)
+            .collect();
+
+        let mut remote_hashes = HashMap::new();
+        for batch in shared.chunks(HASH_BATCH) {
+            let paths: Vec<String> = batch.iter().map(|path| self.remote_path(path)).collect();
+            let mut command = "sha256sum --".to_owned();
+            for path in &paths {
+                command.push(' ');
+                command.push_str(&shell_quote(path));
+            }
+            let result = exec.exec(&command).await?;
+            let stdout = result.stdout_str();
+            let found = parse_sha256sum(&stdout);
+            if found.is_empty() && result.exit_code != Some(0) {
+                return Err(ConnectionError::Protocol(format!(
+                    "sha256sum failed: {}",
+                    String::from_utf8_lossy(&result.stderr).trim()
+                )));
+            }
+            for (rel, full) in batch.iter().zip(&paths) {
+                if let Some(hash) = found.get(full.as_str()) {
+                    remote_hashes.insert((*rel).clone(), hash.clone());
+                }
+            }
+        }
+
+        let mut hashes = HashMap::new();
+        for (path, remote_hash) in remote_hashes {
+            let local_hash = sha256_file(&self.local_path(&path)).await?;
+            hashes.insert(path, (local_hash, remote_hash));
+        }
+        Ok(hashes)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Planning
+// ---------------------------------------------------------------------------
+
+/// A file or directory in one of the trees.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    dir: bool,
+    size: u64,
+    modified: Option<DateTime<Utc>>,
+}
+
+/// Relative path → node. Sorted, so a directory comes before its contents.
+type Tree = BTreeMap<String, Node>;
+
+struct Planner<'a> {
+    options: &'a SyncOptions,
+    /// Local and remote SHA-256, for files compared by checksum.
+    hashes: &'a HashMap<String, (String, String)>,
+}
+
+impl Planner<'_> {
+    fn plan(&self, local: &Tree, remote: &Tree) -> SyncPlan {
+        let direction = self.options.direction;
+        let delete = self.options.delete_extraneous && direction != SyncDirection::Bidirectional;
+        let mut creates = Vec::new();
+        let mut copies = Vec::new();
+        let mut deletes = Vec::new();
+        let mut conflicts = Vec::new();
+        // Paths whose contents are left alone because of a conflict.
+        let mut blocked = BTreeSet::new();
+
+        let paths: BTreeSet<&String> = local.keys().chain(remote.keys()).collect();
+        for path in paths {
+            if ancestors(path).any(|dir| blocked.contains(dir)) {
+                continue;
+            }
+            let upload = || SyncAction::Upload {
+                path: path.clone(),
+                size: local[path].size,
+                modified: local[path].modified,
+            };
+            let download = || SyncAction::Download {
+                path: path.clone(),
+                size: remote[path].size,
+                modified: remote[path].modified,
+            };
+            match (local.get(path), remote.get(path)) {
+                (Some(l), None) => match direction {
+                    SyncDirection::Upload | SyncDirection::Bidirectional if l.dir => {
+                        creates.push(SyncAction::CreateRemoteDir { path: path.clone() });
+                    }
+                    SyncDirection::Upload | SyncDirection::Bidirectional => copies.push(upload()),
+                    SyncDirection::Download if delete => {
+                        deletes.push(SyncAction::DeleteLocal { path: path.clone() });
+                    }
+                    SyncDirection::Download => {}
+                },
+                (None, Some(r)) => match direction {
+                    SyncDirection::Download | SyncDirection::Bidirectional if r.dir => {
+                        creates.push(SyncAction::CreateLocalDir { path: path.clone() });
+                    }
+                    SyncDirection::Download | SyncDirection::Bidirectional => {
+                        copies.push(download());
+                    }
+                    SyncDirection::Upload if delete => {
+                        deletes.push(SyncAction::DeleteRemote { path: path.clone() });
+                    }
+                    SyncDirection::Upload => {}
+                },
+                (Some(l), Some(r)) if l.dir != r.dir => {
+                    blocked.insert(path.as_str());
+                    conflicts.push(SyncConflict {
+                        path: path.clone(),
+                        reason: ConflictReason::FileAndDirectory,
+                    });
+                }
+                (Some(l), Some(r)) if l.dir || self.same(path, l, r) => {}
+                (Some(l), Some(r)) => match direction {
+                    SyncDirection::Upload => copies.push(upload()),
+                    SyncDirection::Download => copies.push(download()),
+                    SyncDirection::Bidirectional => match self.newer(l, r) {
+                        Ok(Side::Local) => copies.push(upload()),
+                        Ok(Side::Remote) => copies.push(download()),
+                        Err(reason) => conflicts.push(SyncConflict {
+                            path: path.clone(),
+                            reason,
+                        }),
+                    },
+                },
+                (None, None) => {}
+            }
+        }
+
+        // Children before the directories that hold them.
+        deletes.reverse();
+        let mut actions = creates;
+        actions.append(&mut copies);
+        actions.append(&mut deletes);
+        SyncPlan { actions, conflicts }
+    }
+
+    /// Whether two files of the same path are copies of each other.
+    fn same(&self, path: &str, local: &Node, remote: &Node) -> bool {
+        if local.size != remote.size {
+            return false;
+        }
+        if let Some((l, r)) = self.hashes.get(path) {
+            return l == r;
+        }
+        match (local.modified, remote.modified) {
+            (Some(l), Some(r)) => self.same_time(l, r),
+            // Without times, only a checksum could tell.
+            _ => false,
+        }
+    }
+
+    fn same_time(&self, a: DateTime<Utc>, b: DateTime<Utc>) -> bool {
+        (a - b)
+            .abs()
+            .to_std()
+            .is_ok_and(|gap| gap <= self.options.mtime_tolerance)
+    }
+
+    /// Which copy of a file that differs should win in bidirectional mode.
+    fn newer(&self, local: &Node, remote: &Node) -> Result<Side, ConflictReason> {
+        if let Some(since) = self.options.last_synced {
+            let changed = |node: &Node| node.modified.is_none_or(|t| t > since);
+            match (changed(local), changed(remote)) {
+                (true, true) => return Err(ConflictReason::ChangedOnBothSides),
+                (true, false) => return Ok(Side::Local),
+                (false, true) => return Ok(Side::Remote),
+                // Neither changed since the last sync; fall back to times.
+                (false, false) => {}
+            }
+        }
+        match (local.modified, remote.modified) {
+            (Some(l), Some(r)) if self.same_time(l, r) => Err(ConflictReason::SameModificationTime),
+            (Some(l), Some(r)) if l > r => Ok(Side::Local),
+            (Some(_), Some(_)) => Ok(Side::Remote),
+            _ => Err(ConflictReason::SameModificationTime),
+        }
+    }
+}
+
+enum Side {
+    Local,
+    Remote,
+}
+
+/// `a/b/c` → `a/b`, `a`.
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').map(move |(at, _)| &path[..at])
+}
+
+// ---------------------------------------------------------------------------
+// Include / exclude globs
+// ---------------------------------------------------------------------------
+
+struct Filter {
+    include: Option<Globs>,
+    exclude: Globs,
+}
+
+impl Filter {
+    fn new(options: &SyncOptions) -> Result<Self, ConnectionError> {
+        let include = if options.include.is_empty() {
+            None
+        } else {
+            Some(Globs::new(&options.include)?)
+        };
+        Ok(Filter {
+            include,
+            exclude: Globs::new(&options.exclude)?,
+        })
+    }
+
+    /// Whether `path` (relative) is synced. Directories are not subject to
+    /// `include`, so their matching files are still found.
+    fn admits(&self, path: &str, dir: bool) -> bool {
+        if self.exclude.matches(path) {
+            return false;
+        }
+        dir || self
+            .include
+            .as_ref()
+            .is_none_or(|globs| globs.matches(path))
+    }
+}
+
+/// Globs split into file-name patterns and path patterns.
+struct Globs {
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl Globs {
+    fn new(patterns: &[String]) -> Result<Self, ConnectionError> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in patterns {
+            let trimmed = pattern.trim_start_matches('/');
+            let glob = GlobBuilder::new(trimmed)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| ConnectionError::InvalidGlob {
+                    pattern: pattern.clone(),
+                    reason: e.kind().to_string(),
+                })?;
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+        let build = |set: GlobSetBuilder| {
+            set.build().map_err(|e| ConnectionError::InvalidGlob {
+                pattern: e.glob().unwrap_or_default().to_owned(),
+                reason: e.kind().to_string(),
+            })
+        };
+        Ok(Globs {
+            names: build(names)?,
+            paths: build(paths)?,
+        })
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        self.names.is_match(file_name(path)) || self.paths.is_match(path)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tree walks
+// ---------------------------------------------------------------------------
+
+async fn walk_local(root: &Path, filter: &Filter) -> Result<Tree, ConnectionError> {
+    let mut tree = Tree::new();
+    if tokio::fs::metadata(root).await.is_err() {
+        return Ok(tree);
+    }
+    tree.insert(String::new(), DIR);
+    let mut pending = vec![(root.to_path_buf(), String::new())];
+    while let Some((dir, prefix)) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let path = join(&prefix, &name);
+            let meta = entry.metadata().await?;
+            let kind = meta.file_type();
+            if !(kind.is_file() || kind.is_dir()) || !filter.admits(&path, kind.is_dir()) {
+                continue;
+            }
+            if kind.is_dir() {
+                pending.push((entry.path(), path.clone()));
+            }
+            tree.insert(
+                path,
+                Node {
+                    dir: kind.is_dir(),
+                    size: if kind.is_dir() { 0 } else { meta.len() },
+                    modified: meta.modified().ok().map(DateTime::from),
+                },
+            );
+        }
+    }
+    Ok(tree)
+}
+
+async fn walk_remote(
+    adapter: &dyn FileTransferAdapter,
+    root: &str,
+    filter: &Filter,
+) -> Result<Tree, ConnectionError> {
+    let mut tree = Tree::new();
+    match adapter.stat(root).await {
+        Ok(entry) if entry.is_dir() => {}
+        Ok(_) | Err(ConnectionError::NotFound { .. }) => return Ok(tree),
+        Err(e) => return Err(e),
+    }
+    tree.insert(String::new(), DIR);
+    let mut pending = vec![(root.to_owned(), String::new())];
+    while let Some((dir, prefix)) = pending.pop() {
+        for entry in adapter.list_dir(&dir).await? {
+            let path = join(&prefix, &entry.name);
+            let is_dir = entry.kind == FileKind::Dir;
+            if !matches!(entry.kind, FileKind::File | FileKind::Dir)
+                || !filter.admits(&path, is_dir)
+            {
+                continue;
+            }
+            if is_dir {
+                pending.push((entry.path.clone(), path.clone()));
+            }
+            tree.insert(
+                path,
+                Node {
+                    dir: is_dir,
+                    size: if is_dir { 0 } else { entry.size },
+                    modified: entry.modified_at,
+                },
+            );
+        }
+    }
+    Ok(tree)
+}
+
+/// The sync root.
+const DIR: Node = Node {
+    dir: true,
+    size: 0,
+    modified: None,
+};
+
+/// Remove a file, or a directory the sync has emptied. A directory that
+/// still holds something, i.e. files the globs leave out, is kept.
+async fn remove_local(path: &Path) -> Result<(), ConnectionError> {
+    if tokio::fs::symlink_metadata(path).await?.is_dir() {
+        if tokio::fs::read_dir(path)
+            .await?
+            .next_entry()
+            .await?
+            .is_none()
+        {
+            tokio::fs::remove_dir(path).await?;
+        }
+    } else {
+        tokio::fs::remove_file(path).await?;
+    }
+    Ok(())
+}
+
+/// Like [`remove_local`], on the remote side.
+async fn remove_remote(
+    adapter: &dyn FileTransferAdapter,
+    path: &str,
+) -> Result<(), ConnectionError> {
+    if adapter.stat(path).await?.is_dir() && !adapter.list_dir(path).await?.is_empty() {
+        return Ok(());
+    }
+    adapter.delete(path).await
+}
+
+// ---------------------------------------------------------------------------
+// Checksums
+// ---------------------------------------------------------------------------
+
+async fn sha256_file(path: &Path) -> Result<String, ConnectionError> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        context.update(&buf[..n]);
+    }
+    Ok(context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// `sha256sum` output as path → hash. Lines for names with a newline or
+/// backslash are escaped by sha256sum and skipped here; those files fall
+/// back to comparing times.
+fn parse_sha256sum(output: &str) -> HashMap<&str, String> {
+    output
+        .lines()
+        .filter(|line| !line.starts_with('\\'))
+        .filter_map(|line| {
+            let (hash, path) = line.split_once("  ").or_else(|| line.split_once(" *"))?;
+            Some((path, hash.to_owned()))
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::testing::LocalFs;
+
+    /// A local and a remote root, both temporary directories.
+    struct Roots {
+        _dir: tempfile::TempDir,
+        local: PathBuf,
+        remote: PathBuf,
+    }
+
+    impl Roots {
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let local = dir.path().join("local");
+            let remote = dir.path().join("remote");
+            std::fs::create_dir(&local).unwrap();
+            std::fs::create_dir(&remote).unwrap();
+            Roots {
+                _dir: dir,
+                local,
+                remote,
+            }
+        }
+
+        fn sync(&self, options: SyncOptions) -> DirSync {
+            DirSync::new(&self.local, self.remote.to_str().unwrap(), options)
+        }
+    }
+
+    /// Write `path` under `root` with the given age in seconds.
+    fn write(root: &Path, path: &str, data: &str, age: u64) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, data).unwrap();
+        let time = SystemTime::now() - Duration::from_secs(age);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    fn read(root: &Path, path: &str) -> Option<String> {
+        std::fs::read_to_string(root.join(path)).ok()
+    }
+
+    fn paths(plan: &SyncPlan) -> Vec<String> {
+        plan.actions
+            .iter()
+            .map(|action| {
+                let verb = match action {
+                    SyncAction::CreateRemoteDir { .. } => "mkdir remote",
+                    SyncAction::CreateLocalDir { .. } => "mkdir local",
+                    SyncAction::Upload { .. } => "upload",
+                    SyncAction::Download { .. } => "download",
+                    SyncAction::DeleteRemote { .. } => "delete remote",
+                    SyncAction::DeleteLocal { .. } => "delete local",
+                };
+                format!("{verb} {}", action.path())
+            })
+            .collect()
+    }
+
+    fn manager() -> TransferManager {
+        TransferManager::new(Arc::new(LocalFs::default()), 2)
+    }
+
+    #[tokio::test]
+    async fn uploads_copy_new_and_changed_files_only() {
+        let roots = Roots::new();
+        write(&roots.local, "same.txt", "same", 100);
+        write(&roots.remote, "same.txt", "same", 100);
+        write(&roots.local, "grown.txt", "longer now", 100);
+        write(&roots.remote, "grown.txt", "short", 100);
+        write(&roots.local, "touched.txt", "abc", 10);
+        write(&roots.remote, "touched.txt", "abc", 100);
+        write(&roots.local, "src/main.rs", "fn main() {}", 100);
+        write(&roots.remote, "stale.txt", "old", 100);
+
+        let plan = roots
+            .sync(SyncOptions::default())
+            .plan(&LocalFs::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            paths(&plan),
+            [
+                "mkdir remote src",
+                "upload grown.txt",
+                "upload src/main.rs",
+                "upload touched.txt"
+            ]
+        );
+        assert_eq!(plan.bytes(), 10 + 12 + 3);
+    }
+
+    #[tokio::test]
+    async fn running_a_sync_makes_the_trees_match() {
+        let roots = Roots::new();
+        write(&roots.local, "a/b/c.txt", "deep", 100);
+        write(&roots.local, "top.txt", "top", 100);
+        write(&roots.remote, "gone/old.txt", "old", 100);
+        let options = SyncOptions {
+            delete_extraneous: true,
+            ..SyncOptions::default()
+        };
+
+        let report = roots.sync(options.clone()).run(&manager()).await.unwrap();
+        assert!(report.failures.is_empty(), "{:?}", report.failures);
+        assert_eq!(
+            paths(&report.plan),
+            [
+                "mkdir remote a",
+                "mkdir remote a/b",
+                "upload a/b/c.txt",
+                "upload top.txt",
+                "delete remote gone/old.txt",
+                "delete remote gone"
+            ]
+        );
+        assert_eq!(read(&roots.remote, "a/b/c.txt").as_deref(), Some("deep"));
+        assert!(!roots.remote.join("gone").exists());
+
+        // Copies keep their source's time, so nothing is left to do.
+        let again = roots.sync(options).plan(&LocalFs::default()).await.unwrap();
+        assert_eq!(again, SyncPlan::default());
+    }
+
+    #[tokio::test]
+    async fn dry_runs_change_nothing() {
+        let roots = Roots::new();
+        write(&roots.local, "new.txt", "new", 100);
+        write(&roots.remote, "extra.txt", "extra", 100);
+        let options = SyncOptions {
+            delete_extraneous: true,
+            dry_run: true,
+            ..SyncOptions::default()
+        };
+
+        let report = roots.sync(options).run(&manager()).await.unwrap();
+        assert!(report.dry_run);
+        assert_eq!(
+            paths(&report.plan),
+            ["upload new.txt", "delete remote extra.txt"]
+        );
+        assert_eq!(read(&roots.remote, "new.txt"), None);
+        assert_eq!(read(&roots.remote, "extra.txt").as_deref(), Some("extra"));
+    }
+
+    #[tokio::test]
+    async fn globs_pick_files_by_name_or_by_path() {
+        let roots = Roots::new();
+        write(&roots.local, "src/lib.rs", "lib", 100);
+        write(&roots.local, "src/notes.md", "notes", 100);
+        write(&roots.local, "target/debug/app.rs", "built", 100);
+        write(&roots.local, "logs/today.rs", "log", 100);
+        write(&roots.remote, "target/keep.txt", "remote only", 100);
+        let options = SyncOptions {
+            include: vec!["*.rs".into()],
+            exclude: vec!["target".into(), "/logs/*.rs".into()],
+            delete_extraneous: true,
+            ..SyncOptions::default()
+        };
+
+        let plan = roots.sync(options).plan(&LocalFs::default()).await.unwrap();
+        // `logs` itself is created: directories are not subject to include.
+        assert_eq!(
+            paths(&plan),
+            ["mkdir remote logs", "mkdir remote src", "upload src/lib.rs"]
+        );
+
+        let bad = SyncOptions {
+            exclude: vec!["a{b".into()],
+            ..SyncOptions::default()
+        };
+        let err = roots.sync(bad).plan(&LocalFs::default()).await.unwrap_err();
+        assert!(
+            matches!(err, ConnectionError::InvalidGlob { ref pattern, .. } if pattern == "a{b")
+        );
+    }
+
+    #[tokio::test]
+    async fn downloads_mirror_the_remote_tree() {
+        let roots = Roots::new();
+        write(&roots.remote, "docs/guide.md", "guide", 100);
+        write(&roots.local, "local-only.txt", "mine", 100);
+        let options = SyncOptions {
+            direction: SyncDirection::Download,
+            delete_extraneous: true,
+            ..SyncOptions::default()
+        };
+
+        let report = roots.sync(options).run(&manager()).await.unwrap();
+        assert_eq!(
+            paths(&report.plan),
+            [
+                "mkdir local docs",
+                "download docs/guide.md",
+                "delete local local-only.txt"
+            ]
+        );
+        assert_eq!(
+            read(&roots.local, "docs/guide.md").as_deref(),
+            Some("guide")
+        );
+        assert_eq!(read(&roots.local, "local-only.txt"), None);
+    }
+
+    #[tokio::test]
+    async fn directories_holding_excluded_files_are_kept() {
+        let roots = Roots::new();
+        write(&roots.local, "build/out.o", "object", 100);
+        write(&roots.local, "build/stale.txt", "stale", 100);
+        write(&roots.local, "empty/stale.txt", "stale", 100);
+        let download = SyncOptions {
+            direction: SyncDirection::Download,
+            exclude: vec!["*.o".into()],
+            delete_extraneous: true,
+            ..SyncOptions::default()
+        };
+
+        let report = roots.sync(download).run(&manager()).await.unwrap();
+        assert!(report.failures.is_empty(), "{:?}", report.failures);
+        assert!(report.plan.actions.contains(&SyncAction::DeleteLocal {
+            path: "build".into()
+        }));
+        assert_eq!(read(&roots.local, "build/out.o").as_deref(), Some("object"));
+        assert_eq!(read(&roots.local, "build/stale.txt"), None);
+        assert!(!roots.local.join("empty").exists());
+
+        let roots = Roots::new();
+        write(&roots.remote, "logs/today.log", "log", 100);
+        write(&roots.remote, "logs/old.txt", "old", 100);
+        let upload = SyncOptions {
+            exclude: vec!["*.log".into()],
+            delete_extraneous: true,
+            ..SyncOptions::default()
+        };
+        let report = roots.sync(upload).run(&manager()).await.unwrap();
+        assert!(report.failures.is_empty(), "{:?}", report.failures);
+        assert_eq!(
+            read(&roots.remote, "logs/today.log").as_deref(),
+            Some("log")
+        );
+        assert_eq!(read(&roots.remote, "logs/old.txt"), None);
+    }
+
+    #[tokio::test]
+    async fn bidirectional_syncs_copy_the_newer_side_and_report_conflicts() {
+        let roots = Roots::new();
+        write(&roots.local, "mine.txt", "local only", 100);
+        write(&roots.remote, "theirs.txt", "remote only", 100);
+        write(&roots.local, "edited-here.txt", "new", 10);
+        write(&roots.remote, "edited-here.txt", "old!", 100);
+        write(&roots.local, "edited-there.txt", "old", 100);
+        write(&roots.remote, "edited-there.txt", "new!", 10);
+        write(&roots.local, "both.txt", "mine", 10);
+        write(&roots.remote, "both.txt", "theirs", 20);
+        write(&roots.local, "shape/file.txt", "x", 100);
+        write(&roots.remote, "shape", "a file", 100);
+        let options = SyncOptions {
+            direction: SyncDirection::Bidirectional,
+            delete_extraneous: true,
+            last_synced: Some(Utc::now() - chrono::Duration::seconds(50)),
+            ..SyncOptions::default()
+        };
+
+        let plan = roots.sync(options).plan(&LocalFs::default()).await.unwrap();
+        assert_eq!(
+            paths(&plan),
+            [
+                "upload edited-here.txt",
+                "download edited-there.txt",
+                "upload mine.txt",
+                "download theirs.txt"
+            ]
+        );
+        assert_eq!(
+            plan.conflicts,
+            [
+                SyncConflict {
+                    path: "both.txt".into(),
+                    reason: ConflictReason::ChangedOnBothSides,
+                },
+                SyncConflict {
+                    path: "shape".into(),
+                    reason: ConflictReason::FileAndDirectory,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn checksums_decide_between_files_of_equal_size() {
+        let roots = Roots::new();
+        write(&roots.local, "touched.txt", "same", 10);
+        write(&roots.remote, "touched.txt", "same", 100);
+        write(&roots.local, "it's edited.txt", "new!", 100);
+        write(&roots.remote, "it's edited.txt", "old!", 100);
+        let options = SyncOptions {
+            checksum: true,
+            ..SyncOptions::default()
+        };
+
+        let sync = roots.sync(options);
+        assert!(matches!(
+            sync.plan(&LocalFs::default()).await,
+            Err(ConnectionError::NotSupported { .. })
+        ));
+        let plan = sync
+            .with_checksums(Arc::new(LocalFs::default()))
+            .plan(&LocalFs::default())
+            .await
+            .unwrap();
+        assert_eq!(paths(&plan), ["upload it's edited.txt"]);
+    }
+
+    #[test]
+    fn sha256sum_output_is_parsed_by_path() {
+        let output = "\
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  /srv/empty
+\\0123  /srv/odd\\nname
+ab12 */srv/binary";
+        let parsed = parse_sha256sum(output);
+        assert_eq!(parsed.len(), 2);
+        assert!(parsed["/srv/empty"].starts_with("e3b0c442"));
+        assert_eq!(parsed["/srv/binary"], "ab12");
+    }
+}
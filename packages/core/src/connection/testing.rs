@@ -0,0 +1,295 @@
+//! Test doubles shared by the connection modules' unit tests.
+
+use std::fs::Permissions;
+use std::io::SeekFrom;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+use super::exec::{ExecHandle, ExecParts, ExecRequest};
+use super::files::{file_name, join, FileEntry, FileKind, ServerIdentity};
+use super::transfer::OnProgress;
+use super::{
+    ConnectionAdapter, ConnectionError, Credential, ExecResult, FileTransferAdapter,
+    TerminalAdapter,
+};
+use crate::profile::types::{ConnectionProfile, Protocol};
+
+/// A "remote" side that is the local file system: remote paths are local
+/// absolute paths, and commands run with `sh`.
+#[derive(Default)]
+pub(crate) struct LocalFs {
+    /// What [`FileTransferAdapter::server_identity`] reports.
+    pub identity: Option<ServerIdentity>,
+    /// Every command run through [`TerminalAdapter::exec`].
+    pub commands: Mutex<Vec<String>>,
+    /// When set, streams copy [`CHUNK`] bytes at a time and each chunk takes
+    /// a permit, so tests can step transfers.
+    pub gate: Option<Arc<Semaphore>>,
+    /// Chunks to copy before a stream fails; `None` never fails.
+    pub fail_after: Option<usize>,
+    /// The offset of every stream started.
+    pub starts: Arc<Mutex<Vec<u64>>>,
+    /// When set, [`TerminalAdapter::exec_stream`] starts commands that print
+    /// nothing and run until cancelled; otherwise it is unsupported.
+    pub silent_exec: bool,
+}
+
+/// The chunk size of streams stepped through [`LocalFs::gate`].
+pub(crate) const CHUNK: usize = 4;
+
+impl LocalFs {
+    /// Streams wait for `gate` before each chunk.
+    pub fn gated(gate: &Arc<Semaphore>) -> Self {
+        LocalFs {
+            gate: Some(gate.clone()),
+            ..LocalFs::default()
+        }
+    }
+
+    /// Copy `source` to `sink`, reporting `offset` plus the bytes copied.
+    async fn copy(
+        &self,
+        source: &mut (dyn AsyncRead + Send + Unpin),
+        sink: &mut (dyn AsyncWrite + Send + Unpin),
+        offset: u64,
+        on_progress: &OnProgress<'_>,
+    ) -> Result<u64, ConnectionError> {
+        lock(&self.starts).push(offset);
+        let mut buf = vec![
+            0;
+            if self.gate.is_some() {
+                CHUNK
+            } else {
+                64 * 1024
+            }
+        ];
+        let mut size = offset;
+        for n in 0.. {
+            let read = source.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            if self.fail_after == Some(n) {
+                return Err(ConnectionError::Io(std::io::ErrorKind::BrokenPipe.into()));
+            }
+            if let Some(gate) = &self.gate {
+                gate.acquire().await.unwrap().forget();
+            }
+            sink.write_all(&buf[..read]).await?;
+            sink.flush().await?;
+            size += read as u64;
+            on_progress(size);
+        }
+        Ok(size)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn unsupported<T>() -> Result<T, ConnectionError> {
+    Err(ConnectionError::NotSupported {
+        protocol: Protocol::Sftp,
+    })
+}
+
+#[async_trait]
+impl ConnectionAdapter for LocalFs {
+    async fn connect(_: &ConnectionProfile, _: Credential) -> Result<Self, ConnectionError> {
+        Ok(LocalFs::default())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        Ok(())
+    }
+
+    fn is_alive(&self) -> bool {
+        true
+    }
+
+    async fn reconnect(&mut self) -> Result<(), ConnectionError> {
+        Ok(())
+    }
+
+    fn protocol(&self) -> Protocol {
+        Protocol::Sftp
+    }
+}
+
+#[async_trait]
+impl FileTransferAdapter for LocalFs {
+    async fn list_dir(&self, path: &str) -> Result<Vec<FileEntry>, ConnectionError> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let name = entry?.file_name().into_string().unwrap();
+            entries.push(self.stat(&join(path, &name)).await?);
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    async fn stat(&self, path: &str) -> Result<FileEntry, ConnectionError> {
+        let meta = std::fs::symlink_metadata(path)
+            .map_err(|_| ConnectionError::NotFound { path: path.into() })?;
+        let kind = if meta.is_dir() {
+            FileKind::Dir
+        } else if meta.is_file() {
+            FileKind::File
+        } else {
+            FileKind::Symlink
+        };
+        Ok(FileEntry {
+            name: file_name(path).into(),
+            path: path.into(),
+            kind,
+            size: meta.len(),
+            permissions: meta.mode() & 0o7777,
+            modified_at: meta.modified().ok().map(DateTime::from),
+            accessed_at: None,
+            uid: None,
+            gid: None,
+            owner: None,
+            group: None,
+            symlink_target: None,
+            target_kind: None,
+        })
+    }
+
+    async fn upload_stream(
+        &self,
+        source: &mut (dyn AsyncRead + Send + Unpin),
+        remote: &str,
+        offset: u64,
+        on_progress: &OnProgress<'_>,
+    ) -> Result<u64, ConnectionError> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(offset == 0)
+            .open(remote)
+            .await?;
+        file.set_len(offset).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        self.copy(source, &mut file, offset, on_progress).await
+    }
+
+    async fn download_stream(
+        &self,
+        remote: &str,
+        sink: &mut (dyn AsyncWrite + Send + Unpin),
+        offset: u64,
+        on_progress: &OnProgress<'_>,
+    ) -> Result<u64, ConnectionError> {
+        let mut file = tokio::fs::File::open(remote).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        self.copy(&mut file, sink, offset, on_progress).await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), ConnectionError> {
+        if std::fs::symlink_metadata(path)?.is_dir() {
+            std::fs::remove_dir(path)?;
+        } else {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    async fn mkdir(&self, path: &str) -> Result<(), ConnectionError> {
+        Ok(std::fs::create_dir(path)?)
+    }
+
+    async fn rename(&self, _: &str, _: &str) -> Result<(), ConnectionError> {
+        unsupported()
+    }
+
+    async fn chmod(&self, path: &str, mode: u32) -> Result<(), ConnectionError> {
+        Ok(std::fs::set_permissions(
+            path,
+            Permissions::from_mode(mode),
+        )?)
+    }
+
+    async fn set_modified(&self, path: &str, time: DateTime<Utc>) -> Result<(), ConnectionError> {
+        let file = std::fs::File::open(path)?;
+        Ok(file.set_modified(SystemTime::from(time))?)
+    }
+
+    async fn symlink(&self, _: &str, _: &str) -> Result<(), ConnectionError> {
+        unsupported()
+    }
+
+    async fn readlink(&self, _: &str) -> Result<String, ConnectionError> {
+        unsupported()
+    }
+
+    async fn canonicalize(&self, path: &str) -> Result<String, ConnectionError> {
+        let path = std::fs::canonicalize(path)
+            .map_err(|_| ConnectionError::NotFound { path: path.into() })?;
+        Ok(path.to_str().unwrap().to_owned())
+    }
+
+    fn server_identity(&self) -> Option<ServerIdentity> {
+        self.identity.clone()
+    }
+}
+
+#[async_trait]
+impl TerminalAdapter for LocalFs {
+    async fn send_input(&self, _: &[u8]) -> Result<(), ConnectionError> {
+        unsupported()
+    }
+
+    fn output_stream(&mut self) -> Option<tokio::sync::mpsc::Receiver<Vec<u8>>> {
+        None
+    }
+
+    async fn resize(&self, _: u16, _: u16) -> Result<(), ConnectionError> {
+        unsupported()
+    }
+
+    async fn exec(&self, command: &str) -> Result<ExecResult, ConnectionError> {
+        lock(&self.commands).push(command.to_owned());
+        let output = std::process::Command::new("sh")
+            .args(["-c", command])
+            .output()?;
+        Ok(ExecResult {
+            stdout: output.stdout,
+            stderr: output.stderr,
+            exit_code: output.status.code().map(|code| code as u32),
+            exit_signal: None,
+        })
+    }
+
+    async fn exec_stream(&self, request: ExecRequest) -> Result<ExecHandle, ConnectionError> {
+        if !self.silent_exec {
+            return unsupported();
+        }
+        lock(&self.commands).push(request.command);
+        let (stdout_tx, stdout) = mpsc::channel(1);
+        let (stderr_tx, stderr) = mpsc::channel(1);
+        let (control, control_rx) = mpsc::channel(1);
+        let (exit_tx, exit) = oneshot::channel();
+        let cancel = CancellationToken::new();
+        let running = cancel.clone();
+        tokio::spawn(async move {
+            running.cancelled().await;
+            drop((stdout_tx, stderr_tx, control_rx));
+            let _ = exit_tx.send(Err(ConnectionError::Cancelled));
+        });
+        Ok(ExecHandle::new(ExecParts {
+            stdout,
+            stderr,
+            control,
+            exit,
+            cancel,
+        }))
+    }
+}
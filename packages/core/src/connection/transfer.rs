@@ -0,0 +1,916 @@
+//! Queued file transfers.
+//!
+//! [`TransferManager`] copies files over any [`FileTransferAdapter`] — SFTP
+//! or FTP — running at most `max_concurrent` transfers at once. Each one
+//! publishes [`TransferProgress`] on a `watch` channel and can be paused,
+//! resumed or cancelled.
+//!
+//! A transfer that is paused or fails part-way keeps what it has copied: its
+//! next run continues from the size of the destination file. With a queue
+//! file, unfinished transfers survive a restart. After a dropped connection,
+//! hand the manager the reconnected adapter with
+//! [`TransferManager::set_adapter`] and resume the transfers that failed.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, Notify, Semaphore};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use super::files::FileKind;
+use super::{ConnectionError, FileTransferAdapter};
+
+/// Receives the number of bytes a transfer has reached, after each chunk.
+pub type OnProgress<'a> = dyn Fn(u64) + Send + Sync + 'a;
+
+/// Transfers run at once unless the caller says otherwise.
+pub const DEFAULT_MAX_CONCURRENT: usize = 3;
+
+/// How long the queue file waits for more changes before it is written.
+const SAVE_DELAY: Duration = Duration::from_millis(250);
+
+/// How far back the transfer speed is averaged.
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+pub type TransferId = String;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+/// One file to copy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferRequest {
+    pub direction: TransferDirection,
+    pub local: PathBuf,
+    pub remote: String,
+}
+
+impl TransferRequest {
+    pub fn upload(local: impl Into<PathBuf>, remote: impl Into<String>) -> Self {
+        TransferRequest {
+            direction: TransferDirection::Upload,
+            local: local.into(),
+            remote: remote.into(),
+        }
+    }
+
+    pub fn download(remote: impl Into<String>, local: impl Into<PathBuf>) -> Self {
+        TransferRequest {
+            direction: TransferDirection::Download,
+            local: local.into(),
+            remote: remote.into(),
+        }
+    }
+}
+
+/// Where a transfer stands.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransferProgress {
+    pub bytes_transferred: u64,
+    /// Size of the source; 0 if unknown.
+    pub total_bytes: u64,
+    /// Bytes per second over the last few seconds.
+    pub speed_bps: f64,
+    pub eta: Option<Duration>,
+    pub status: TransferStatus,
+}
+
+impl TransferProgress {
+    pub(crate) fn queued() -> Self {
+        TransferProgress {
+            bytes_transferred: 0,
+            total_bytes: 0,
+            speed_bps: 0.0,
+            eta: None,
+            status: TransferStatus::Queued,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferStatus {
+    Queued,
+    InProgress,
+    Paused,
+    Completed,
+    /// Stopped on an error; can be resumed.
+    Failed(String),
+    Cancelled,
+}
+
+/// A queued transfer, as seen by the UI.
+pub struct TransferHandle {
+    pub id: TransferId,
+    pub request: TransferRequest,
+    pub progress: watch::Receiver<TransferProgress>,
+    /// Cancelling this cancels the transfer.
+    pub cancel: CancellationToken,
+}
+
+impl TransferHandle {
+    /// Wait until the transfer completes, fails or is cancelled, sitting out
+    /// any pause. Returns the size of the copied file.
+    pub async fn wait(&mut self) -> Result<u64, ConnectionError> {
+        let progress = self
+            .progress
+            .wait_for(|p| {
+                matches!(
+                    p.status,
+                    TransferStatus::Completed
+                        | TransferStatus::Failed(_)
+                        | TransferStatus::Cancelled
+                )
+            })
+            .await
+            // The manager was dropped.
+            .map_err(|_| ConnectionError::Cancelled)?;
+        match &progress.status {
+            TransferStatus::Completed => Ok(progress.bytes_transferred),
+            TransferStatus::Failed(reason) => Err(ConnectionError::TransferFailed {
+                reason: reason.clone(),
+            }),
+            _ => Err(ConnectionError::Cancelled),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Manager
+// ---------------------------------------------------------------------------
+
+/// Runs transfers in the order they were queued, a few at a time.
+///
+/// Dropping the manager stops its transfers where they are, without marking
+/// them cancelled, so a queue file still lists them. The queue file is
+/// written in the background shortly after each change; [`flush`] writes it
+/// at once.
+///
+/// [`flush`]: TransferManager::flush
+pub struct TransferManager {
+    inner: Arc<Inner>,
+}
+
+impl TransferManager {
+    pub fn new(adapter: Arc<dyn FileTransferAdapter>, max_concurrent: usize) -> Self {
+        Self::build(adapter, max_concurrent, None)
+    }
+
+    /// Keep the queue in `path`, and queue again the transfers it lists
+    /// from a previous run. Must be called within a Tokio runtime.
+    pub fn with_queue_file(
+        adapter: Arc<dyn FileTransferAdapter>,
+        max_concurrent: usize,
+        path: impl Into<PathBuf>,
+    ) -> Result<Self, ConnectionError> {
+        let path = path.into();
+        let saved = read_queue(&path)?;
+        let manager = Self::build(adapter, max_concurrent, Some(path));
+        for entry in saved {
+            let job = Job::new(entry.id, entry.request, entry.started);
+            manager.start(job);
+        }
+        Ok(manager)
+    }
+
+    /// Queue `request`; it starts once a slot is free.
+    pub fn enqueue(&self, request: TransferRequest) -> TransferHandle {
+        let job = Job::new(uuid::Uuid::new_v4().to_string(), request, false);
+        let handle = job.handle();
+        self.start(job);
+        handle
+    }
+
+    /// Every transfer still listed, in queue order.
+    pub fn transfers(&self) -> Vec<TransferHandle> {
+        self.inner.jobs().iter().map(|job| job.handle()).collect()
+    }
+
+    pub fn transfer(&self, id: &str) -> Option<TransferHandle> {
+        self.inner.find(id).map(|job| job.handle())
+    }
+
+    /// Stop a queued or running transfer, keeping what it has copied.
+    /// Returns `false` if it is not queued or running.
+    pub fn pause(&self, id: &str) -> bool {
+        let Some(job) = self.inner.find(id) else {
+            return false;
+        };
+        if !matches!(
+            job.status(),
+            TransferStatus::Queued | TransferStatus::InProgress
+        ) {
+            return false;
+        }
+        job.pause_token().cancel();
+        true
+    }
+
+    /// Queue a paused or failed transfer again. It continues from what the
+    /// destination already holds. Returns `false` for any other state.
+    pub fn resume(&self, id: &str) -> bool {
+        let Some(job) = self.inner.find(id) else {
+            return false;
+        };
+        if !matches!(
+            job.status(),
+            TransferStatus::Paused | TransferStatus::Failed(_)
+        ) {
+            return false;
+        }
+        *lock(&job.pause) = CancellationToken::new();
+        job.set_status(TransferStatus::Queued);
+        job.resumed.notify_one();
+        true
+    }
+
+    /// Returns `false` if the transfer has already completed or been
+    /// cancelled.
+    pub fn cancel(&self, id: &str) -> bool {
+        let Some(job) = self.inner.find(id) else {
+            return false;
+        };
+        if matches!(
+            job.status(),
+            TransferStatus::Completed | TransferStatus::Cancelled
+        ) {
+            return false;
+        }
+        job.cancel.cancel();
+        true
+    }
+
+    /// The adapter new runs use.
+    pub fn adapter(&self) -> Arc<dyn FileTransferAdapter> {
+        lock(&self.inner.adapter).clone()
+    }
+
+    /// Use `adapter` for every run from now on, e.g. after a reconnect.
+    /// Running transfers keep the adapter they started with.
+    pub fn set_adapter(&self, adapter: Arc<dyn FileTransferAdapter>) {
+        *lock(&self.inner.adapter) = adapter;
+    }
+
+    /// Forget completed and cancelled transfers.
+    pub fn clear_finished(&self) {
+        self.inner.jobs().retain(|job| !job.is_finished());
+    }
+
+    /// Write the queue file now rather than after [`SAVE_DELAY`].
+    pub async fn flush(&self) {
+        if let Some(queue) = &self.inner.queue_file {
+            queue.dirty.store(true, Ordering::SeqCst);
+            self.inner.write_queue_file().await;
+        }
+    }
+
+    fn build(
+        adapter: Arc<dyn FileTransferAdapter>,
+        max_concurrent: usize,
+        queue_file: Option<PathBuf>,
+    ) -> Self {
+        let inner = Arc::new(Inner {
+            adapter: Mutex::new(adapter),
+            slots: Semaphore::new(max_concurrent.max(1)),
+            jobs: Mutex::new(Vec::new()),
+            queue_file: queue_file.map(QueueFile::new),
+            shutdown: CancellationToken::new(),
+        });
+        if inner.queue_file.is_some() {
+            tokio::spawn(keep_saved(inner.clone()));
+        }
+        TransferManager { inner }
+    }
+
+    fn start(&self, job: Job) {
+        let job = Arc::new(job);
+        self.inner.jobs().push(job.clone());
+        self.inner.save();
+        tokio::spawn(drive(self.inner.clone(), job));
+    }
+}
+
+impl Drop for TransferManager {
+    fn drop(&mut self) {
+        self.inner.shutdown.cancel();
+    }
+}
+
+struct Inner {
+    adapter: Mutex<Arc<dyn FileTransferAdapter>>,
+    slots: Semaphore,
+    jobs: Mutex<Vec<Arc<Job>>>,
+    queue_file: Option<QueueFile>,
+    shutdown: CancellationToken,
+}
+
+struct QueueFile {
+    path: PathBuf,
+    /// The jobs changed since the file was last written.
+    dirty: AtomicBool,
+    changed: Notify,
+    /// Held while writing, so writes land in order.
+    writing: tokio::sync::Mutex<()>,
+}
+
+impl QueueFile {
+    fn new(path: PathBuf) -> Self {
+        QueueFile {
+            path,
+            dirty: AtomicBool::new(false),
+            changed: Notify::new(),
+            writing: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+impl Inner {
+    fn jobs(&self) -> MutexGuard<'_, Vec<Arc<Job>>> {
+        lock(&self.jobs)
+    }
+
+    fn find(&self, id: &str) -> Option<Arc<Job>> {
+        self.jobs().iter().find(|job| job.id == id).cloned()
+    }
+
+    /// Have [`keep_saved`] write the queue file, if there is one.
+    fn save(&self) {
+        if let Some(queue) = &self.queue_file {
+            queue.dirty.store(true, Ordering::SeqCst);
+            queue.changed.notify_one();
+        }
+    }
+
+    /// Write the unfinished transfers to the queue file if they changed
+    /// since the last write.
+    async fn write_queue_file(&self) {
+        let Some(queue) = &self.queue_file else {
+            return;
+        };
+        let _writing = queue.writing.lock().await;
+        if !queue.dirty.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let pending: Vec<_> = self
+            .jobs()
+            .iter()
+            .filter(|job| !job.is_finished())
+            .map(|job| SavedTransfer {
+                id: job.id.clone(),
+                request: job.request.clone(),
+                started: job.started.load(Ordering::SeqCst),
+            })
+            .collect();
+        let path = queue.path.clone();
+        let written = tokio::task::spawn_blocking(move || write_queue(&path, &pending))
+            .await
+            .unwrap_or_else(|e| Err(ConnectionError::Io(std::io::Error::other(e))));
+        if let Err(e) = written {
+            warn!("cannot save transfer queue {}: {e}", queue.path.display());
+        }
+    }
+
+    /// One run of `job`: wait for a slot, then copy from where the
+    /// destination ends.
+    async fn run(&self, job: &Job) -> Result<u64, ConnectionError> {
+        let _slot = self
+            .slots
+            .acquire()
+            .await
+            .map_err(|_| ConnectionError::Cancelled)?;
+        let adapter = lock(&self.adapter).clone();
+        job.set_status(TransferStatus::InProgress);
+        let (offset, total) = job.plan(adapter.as_ref()).await?;
+        job.started.store(true, Ordering::SeqCst);
+        self.save();
+        job.progress.send_modify(|p| {
+            p.bytes_transferred = offset;
+            p.total_bytes = total;
+        });
+
+        let meter = Mutex::new(Meter::new(Instant::now(), offset));
+        let on_progress = |bytes: u64| {
+            let speed = lock(&meter).record(Instant::now(), bytes);
+            job.progress.send_modify(|p| {
+                p.bytes_transferred = bytes;
+                p.speed_bps = speed;
+                p.eta = eta(total.saturating_sub(bytes), speed);
+            });
+        };
+        let request = &job.request;
+        match request.direction {
+            TransferDirection::Upload => {
+                adapter
+                    .upload_from(&request.local, &request.remote, offset, &on_progress)
+                    .await
+            }
+            TransferDirection::Download => {
+                adapter
+                    .download_from(&request.remote, &request.local, offset, &on_progress)
+                    .await
+            }
+        }
+    }
+}
+
+/// How a run of a transfer ended.
+enum Outcome {
+    Completed(u64),
+    Failed(ConnectionError),
+    Paused,
+    Cancelled,
+}
+
+/// Write the queue file [`SAVE_DELAY`] after a change, so a burst of
+/// changes costs one write, and once more when the manager is dropped.
+async fn keep_saved(inner: Arc<Inner>) {
+    let Some(queue) = &inner.queue_file else {
+        return;
+    };
+    loop {
+        tokio::select! {
+            _ = inner.shutdown.cancelled() => break,
+            _ = queue.changed.notified() => {}
+        }
+        tokio::select! {
+            _ = inner.shutdown.cancelled() => break,
+            _ = tokio::time::sleep(SAVE_DELAY) => {}
+        }
+        inner.write_queue_file().await;
+    }
+    inner.write_queue_file().await;
+}
+
+/// Run `job` until it completes or is cancelled, waiting for a resume after
+/// each pause or failure.
+async fn drive(inner: Arc<Inner>, job: Arc<Job>) {
+    loop {
+        let pause = job.pause_token();
+        let outcome = tokio::select! {
+            biased;
+            _ = inner.shutdown.cancelled() => return,
+            _ = job.cancel.cancelled() => Outcome::Cancelled,
+            _ = pause.cancelled() => Outcome::Paused,
+            result = inner.run(&job) => match result {
+                Ok(size) => Outcome::Completed(size),
+                Err(e) => Outcome::Failed(e),
+            },
+        };
+        match outcome {
+            Outcome::Completed(size) => {
+                job.progress.send_modify(|p| {
+                    p.bytes_transferred = size;
+                    p.total_bytes = size;
+                });
+                job.set_status(TransferStatus::Completed);
+                inner.save();
+                return;
+            }
+            Outcome::Cancelled => {
+                job.set_status(TransferStatus::Cancelled);
+                inner.save();
+                return;
+            }
+            Outcome::Paused => job.set_status(TransferStatus::Paused),
+            Outcome::Failed(e) => job.set_status(TransferStatus::Failed(e.to_string())),
+        }
+        tokio::select! {
+            biased;
+            _ = inner.shutdown.cancelled() => return,
+            _ = job.cancel.cancelled() => {
+                job.set_status(TransferStatus::Cancelled);
+                inner.save();
+                return;
+            }
+            _ = job.resumed.notified() => {}
+        }
+    }
+}
+
+struct Job {
+    id: TransferId,
+    request: TransferRequest,
+    progress: watch::Sender<TransferProgress>,
+    cancel: CancellationToken,
+    /// Cancelled to pause the current run; replaced on resume.
+    pause: Mutex<CancellationToken>,
+    resumed: Notify,
+    /// A run has written to the destination, so later runs continue it
+    /// rather than replacing it.
+    started: AtomicBool,
+}
+
+impl Job {
+    fn new(id: TransferId, request: TransferRequest, started: bool) -> Self {
+        let (progress, _) = watch::channel(TransferProgress::queued());
+        Job {
+            id,
+            request,
+            progress,
+            cancel: CancellationToken::new(),
+            pause: Mutex::new(CancellationToken::new()),
+            resumed: Notify::new(),
+            started: AtomicBool::new(started),
+        }
+    }
+
+    fn handle(&self) -> TransferHandle {
+        TransferHandle {
+            id: self.id.clone(),
+            request: self.request.clone(),
+            progress: self.progress.subscribe(),
+            cancel: self.cancel.clone(),
+        }
+    }
+
+    fn status(&self) -> TransferStatus {
+        self.progress.borrow().status.clone()
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(
+            self.status(),
+            TransferStatus::Completed | TransferStatus::Cancelled
+        )
+    }
+
+    fn pause_token(&self) -> CancellationToken {
+        lock(&self.pause).clone()
+    }
+
+    /// Speed and ETA only mean something while the transfer runs.
+    fn set_status(&self, status: TransferStatus) {
+        self.progress.send_modify(|p| {
+            p.speed_bps = 0.0;
+            p.eta = None;
+            p.status = status;
+        });
+    }
+
+    /// The offset to start from and the size of the source (0 if unknown).
+    async fn plan(&self, adapter: &dyn FileTransferAdapter) -> Result<(u64, u64), ConnectionError> {
+        let request = &self.request;
+        let resuming = self.started.load(Ordering::SeqCst);
+        let (done, total) = match request.direction {
+            TransferDirection::Upload => {
+                let total = tokio::fs::metadata(&request.local).await?.len();
+                let done = if resuming {
+                    match adapter.stat(&request.remote).await {
+                        Ok(entry) if entry.kind == FileKind::File => entry.size,
+                        Ok(_) | Err(ConnectionError::NotFound { .. }) => 0,
+                        Err(e) => return Err(e),
+                    }
+                } else {
+                    0
+                };
+                (done, total)
+            }
+            TransferDirection::Download => {
+                let entry = adapter.stat(&request.remote).await?;
+                // A symlink's own size says nothing about its target's.
+                let total = if entry.kind == FileKind::File {
+                    entry.size
+                } else {
+                    0
+                };
+                let done = if resuming {
+                    local_size(&request.local).await
+                } else {
+                    0
+                };
+                (done, total)
+            }
+        };
+        // A destination longer than the source is not a partial copy of it.
+        let offset = if total > 0 && done > total { 0 } else { done };
+        Ok((offset, total))
+    }
+}
+
+async fn local_size(path: &Path) -> u64 {
+    tokio::fs::metadata(path).await.map_or(0, |m| m.len())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// ---------------------------------------------------------------------------
+// Speed
+// ---------------------------------------------------------------------------
+
+/// Rolling transfer speed.
+pub(crate) struct Meter {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl Meter {
+    pub(crate) fn new(now: Instant, bytes: u64) -> Self {
+        Meter {
+            samples: VecDeque::from([(now, bytes)]),
+        }
+    }
+
+    /// Note that the transfer reached `bytes` at `now`; returns the bytes
+    /// per second over the last [`SPEED_WINDOW`].
+    pub(crate) fn record(&mut self, now: Instant, bytes: u64) -> f64 {
+        self.samples.push_back((now, bytes));
+        while self.samples.len() > 2
+            && self
+                .samples
+                .get(1)
+                .is_some_and(|&(at, _)| now.duration_since(at) >= SPEED_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+        let Some(&(since, from)) = self.samples.front() else {
+            return 0.0;
+        };
+        let elapsed = now.duration_since(since).as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+        bytes.saturating_sub(from) as f64 / elapsed
+    }
+}
+
+pub(crate) fn eta(remaining: u64, speed_bps: f64) -> Option<Duration> {
+    (speed_bps > 0.0).then(|| Duration::from_secs_f64(remaining as f64 / speed_bps))
+}
+
+// ---------------------------------------------------------------------------
+// Queue file
+// ---------------------------------------------------------------------------
+
+/// A transfer as kept in the queue file.
+#[derive(Serialize, Deserialize)]
+struct SavedTransfer {
+    id: TransferId,
+    #[serde(flatten)]
+    request: TransferRequest,
+    started: bool,
+}
+
+fn read_queue(path: &Path) -> Result<Vec<SavedTransfer>, ConnectionError> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let bytes = std::fs::read(path)?;
+    serde_json::from_slice(&bytes).map_err(|e| invalid_data(path, e))
+}
+
+/// Atomic, like `FileKnownHostsStore`: write a `.tmp` sibling, then rename.
+fn write_queue(path: &Path, transfers: &[SavedTransfer]) -> Result<(), ConnectionError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let json = serde_json::to_vec_pretty(transfers).map_err(|e| invalid_data(path, e))?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, &json)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn invalid_data(path: &Path, e: serde_json::Error) -> ConnectionError {
+    ConnectionError::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("transfer queue {}: {e}", path.display()),
+    ))
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::testing::LocalFs;
+
+    fn local_file(dir: &tempfile::TempDir, name: &str, data: &[u8]) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    /// A "remote" path in `dir`.
+    fn remote_path(dir: &tempfile::TempDir, name: &str) -> String {
+        dir.path().join(name).to_str().unwrap().to_owned()
+    }
+
+    fn remote(path: &str) -> Vec<u8> {
+        std::fs::read(path).unwrap_or_default()
+    }
+
+    async fn reach(handle: &mut TransferHandle, done: impl Fn(&TransferProgress) -> bool) {
+        handle.progress.wait_for(done).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn transfers_complete_in_both_directions() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = TransferManager::new(Arc::new(LocalFs::default()), DEFAULT_MAX_CONCURRENT);
+        let local = local_file(&dir, "up.txt", b"hello, world");
+        let target = remote_path(&dir, "srv-up.txt");
+
+        let mut up = manager.enqueue(TransferRequest::upload(&local, &target));
+        assert_eq!(up.wait().await.unwrap(), 12);
+        assert_eq!(remote(&target), b"hello, world");
+        let progress = up.progress.borrow().clone();
+        assert_eq!(progress.status, TransferStatus::Completed);
+        assert_eq!((progress.bytes_transferred, progress.total_bytes), (12, 12));
+
+        let copy = dir.path().join("down.txt");
+        let mut down = manager.enqueue(TransferRequest::download(&target, &copy));
+        assert_eq!(down.wait().await.unwrap(), 12);
+        assert_eq!(std::fs::read(&copy).unwrap(), b"hello, world");
+    }
+
+    #[tokio::test]
+    async fn no_more_than_max_concurrent_transfers_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let gate = Arc::new(Semaphore::new(0));
+        let manager = TransferManager::new(Arc::new(LocalFs::gated(&gate)), 1);
+        let local = local_file(&dir, "a", b"12345678");
+        let b = remote_path(&dir, "b");
+
+        let mut first = manager.enqueue(TransferRequest::upload(&local, remote_path(&dir, "r")));
+        let mut second = manager.enqueue(TransferRequest::upload(&local, &b));
+        reach(&mut first, |p| p.status == TransferStatus::InProgress).await;
+        tokio::task::yield_now().await;
+        assert_eq!(second.progress.borrow().status, TransferStatus::Queued);
+
+        gate.add_permits(4);
+        first.wait().await.unwrap();
+        second.wait().await.unwrap();
+        assert_eq!(remote(&b), b"12345678");
+    }
+
+    #[tokio::test]
+    async fn cancelled_transfers_report_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let gate = Arc::new(Semaphore::new(0));
+        let manager = TransferManager::new(Arc::new(LocalFs::gated(&gate)), 1);
+        let local = local_file(&dir, "a", b"12345678");
+
+        let mut running = manager.enqueue(TransferRequest::upload(&local, remote_path(&dir, "r")));
+        let mut queued = manager.enqueue(TransferRequest::upload(&local, remote_path(&dir, "b")));
+        reach(&mut running, |p| p.status == TransferStatus::InProgress).await;
+        running.cancel.cancel();
+        assert!(manager.cancel(&queued.id));
+
+        assert!(matches!(
+            running.wait().await,
+            Err(ConnectionError::Cancelled)
+        ));
+        assert!(matches!(
+            queued.wait().await,
+            Err(ConnectionError::Cancelled)
+        ));
+        assert!(!manager.cancel(&queued.id));
+        manager.clear_finished();
+        assert!(manager.transfers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn paused_transfers_resume_where_they_stopped() {
+        let dir = tempfile::tempdir().unwrap();
+        let gate = Arc::new(Semaphore::new(1));
+        let adapter = LocalFs::gated(&gate);
+        let starts = adapter.starts.clone();
+        let manager = TransferManager::new(Arc::new(adapter), 1);
+        let local = local_file(&dir, "a", b"0123456789");
+        let target = remote_path(&dir, "r");
+
+        let mut handle = manager.enqueue(TransferRequest::upload(&local, &target));
+        reach(&mut handle, |p| p.bytes_transferred == 4).await;
+        assert!(manager.pause(&handle.id));
+        reach(&mut handle, |p| p.status == TransferStatus::Paused).await;
+        assert!(!manager.pause(&handle.id));
+
+        gate.add_permits(Semaphore::MAX_PERMITS - 1);
+        assert!(manager.resume(&handle.id));
+        assert_eq!(handle.wait().await.unwrap(), 10);
+        assert_eq!(remote(&target), b"0123456789");
+        assert_eq!(*lock(&starts), [0, 4]);
+    }
+
+    #[tokio::test]
+    async fn failed_transfers_resume_on_a_new_adapter() {
+        let dir = tempfile::tempdir().unwrap();
+        let gate = Arc::new(Semaphore::new(Semaphore::MAX_PERMITS));
+        let dropped = LocalFs {
+            fail_after: Some(2),
+            ..LocalFs::gated(&gate)
+        };
+        let manager = TransferManager::new(Arc::new(dropped), 1);
+        local_file(&dir, "big", b"abcdefghijkl");
+        let local = dir.path().join("copy");
+
+        let source = remote_path(&dir, "big");
+        let mut handle = manager.enqueue(TransferRequest::download(&source, &local));
+        let err = handle.wait().await.unwrap_err();
+        assert!(
+            matches!(err, ConnectionError::TransferFailed { .. }),
+            "{err}"
+        );
+
+        let reconnected = LocalFs::gated(&gate);
+        let starts = reconnected.starts.clone();
+        manager.set_adapter(Arc::new(reconnected));
+        assert!(manager.resume(&handle.id));
+        assert_eq!(handle.wait().await.unwrap(), 12);
+        assert_eq!(std::fs::read(&local).unwrap(), b"abcdefghijkl");
+        assert_eq!(*lock(&starts), [8]);
+    }
+
+    #[tokio::test]
+    async fn new_transfers_replace_an_existing_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let adapter = LocalFs::default();
+        let starts = adapter.starts.clone();
+        let manager = TransferManager::new(Arc::new(adapter), 1);
+        let target = remote_path(&dir, "r");
+        std::fs::write(&target, b"0123").unwrap();
+        let local = local_file(&dir, "a", b"version two");
+
+        let mut handle = manager.enqueue(TransferRequest::upload(&local, &target));
+        handle.wait().await.unwrap();
+        assert_eq!(remote(&target), b"version two");
+        assert_eq!(*lock(&starts), [0]);
+    }
+
+    #[tokio::test]
+    async fn unfinished_transfers_continue_after_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = dir.path().join("state/transfers.json");
+        let local = local_file(&dir, "a", b"0123456789");
+        let target = remote_path(&dir, "r");
+
+        let gate = Arc::new(Semaphore::new(1));
+        let manager =
+            TransferManager::with_queue_file(Arc::new(LocalFs::gated(&gate)), 1, &queue).unwrap();
+        let mut handle = manager.enqueue(TransferRequest::upload(&local, &target));
+        let done = manager.enqueue(TransferRequest::upload(&local, remote_path(&dir, "b")));
+        assert!(manager.cancel(&done.id));
+        reach(&mut handle, |p| p.bytes_transferred == 4).await;
+        manager.flush().await;
+        drop(manager);
+
+        let restarted = LocalFs::gated(&Arc::new(Semaphore::new(8)));
+        let starts = restarted.starts.clone();
+        let manager = TransferManager::with_queue_file(Arc::new(restarted), 1, &queue).unwrap();
+        let mut restored = manager.transfers();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].id, handle.id);
+        assert_eq!(restored[0].wait().await.unwrap(), 10);
+        assert_eq!(remote(&target), b"0123456789");
+        assert_eq!(*lock(&starts), [4]);
+        manager.flush().await;
+        assert_eq!(std::fs::read_to_string(&queue).unwrap().trim(), "[]");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bursts_of_changes_are_saved_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = dir.path().join("transfers.json");
+        let gate = Arc::new(Semaphore::new(0));
+        let manager =
+            TransferManager::with_queue_file(Arc::new(LocalFs::gated(&gate)), 1, &queue).unwrap();
+        let local = local_file(&dir, "a", b"0123");
+
+        let first = manager.enqueue(TransferRequest::upload(&local, remote_path(&dir, "r")));
+        manager.enqueue(TransferRequest::upload(&local, remote_path(&dir, "b")));
+        tokio::task::yield_now().await;
+        assert!(!queue.exists());
+
+        tokio::time::sleep(SAVE_DELAY * 2).await;
+        let saved = read_queue(&queue).unwrap();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[0].id, first.id);
+    }
+
+    #[test]
+    fn speed_is_averaged_over_the_recent_window() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut meter = Meter::new(start, 0);
+        assert_eq!(meter.record(start, 0), 0.0);
+        assert_eq!(meter.record(at(1), 1000), 1000.0);
+        assert_eq!(meter.record(at(2), 3000), 1500.0);
+        // The first second falls out of the window.
+        assert_eq!(meter.record(at(7), 13_000), 2000.0);
+        assert_eq!(eta(4000, 2000.0), Some(Duration::from_secs(2)));
+        assert_eq!(eta(4000, 0.0), None);
+    }
+}
@@ -0,0 +1,879 @@
+//! Change notifications for remote files.
+//!
+//! [`RemoteWatcher`] reports files created, modified and deleted at a remote
+//! path, or anywhere under it. It polls the path with the
+//! [`FileTransferAdapter`] every [`WatchOptions::interval`], comparing size
+//! and mtime. Given a shell on the same server
+//! ([`with_inotify`](RemoteWatcher::with_inotify)) it runs `inotifywait`
+//! there instead and hears about changes as they happen, falling back to
+//! polling if `inotifywait` is missing, is not watching within
+//! [`INOTIFY_START_TIMEOUT`], or exits.
+//!
+//! Events for the same path within [`WatchOptions::debounce`] of each other
+//! are merged, so an editor's burst of writes arrives as one `Modified`.
+//! Changes that never pause still come out every [`WatchOptions::max_wait`].
+//! The watch never waits for the listener: changes it has no room for stay
+//! pending and merge with later ones.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use super::exec::{ExecHandle, ExecRequest};
+use super::files::{file_name, parent, shell_quote, FileEntry, FileKind};
+use super::{ConnectionError, FileTransferAdapter, TerminalAdapter};
+
+/// Events buffered for a [`WatchHandle`] that is not being read. Further
+/// changes wait, merged, until there is room.
+const EVENT_BUFFER: usize = 256;
+
+/// What `inotifywait` prints on stderr once it is watching.
+const INOTIFY_READY: &str = "Watches established.";
+
+/// How long `inotifywait` may take to start watching before the watch polls
+/// instead. Watching a large tree recursively takes a while.
+pub const INOTIFY_START_TIMEOUT: Duration = Duration::from_secs(10);
+
+// ---------------------------------------------------------------------------
+// Options and events
+// ---------------------------------------------------------------------------
+
+/// How a [`RemoteWatcher`] watches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchOptions {
+    /// Watch everything under a directory, not just its entries.
+    pub recursive: bool,
+    /// Time between polls. Unused while `inotifywait` is running.
+    pub interval: Duration,
+    /// Wait this long after a change for further changes before reporting
+    /// them together.
+    pub debounce: Duration,
+    /// Report changes at the latest this long after the first of them, even
+    /// if more keep arriving within `debounce`.
+    pub max_wait: Duration,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            recursive: true,
+            interval: Duration::from_secs(2),
+            debounce: Duration::from_millis(300),
+            max_wait: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchEventKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// A change to one file or directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEvent {
+    pub path: String,
+    pub kind: WatchEventKind,
+}
+
+/// How changes are being detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMode {
+    Polling,
+    Inotify,
+}
+
+// ---------------------------------------------------------------------------
+// Watcher
+// ---------------------------------------------------------------------------
+
+/// Watches one remote path.
+pub struct RemoteWatcher {
+    files: Arc<dyn FileTransferAdapter>,
+    shell: Option<Arc<dyn TerminalAdapter>>,
+    path: String,
+    options: WatchOptions,
+}
+
+impl RemoteWatcher {
+    pub fn new(
+        files: Arc<dyn FileTransferAdapter>,
+        path: impl Into<String>,
+        options: WatchOptions,
+    ) -> Self {
+        RemoteWatcher {
+            files,
+            shell: None,
+            path: path.into(),
+            options,
+        }
+    }
+
+    /// Try `inotifywait` through `shell`, which must be on the same server
+    /// as the file-transfer session.
+    pub fn with_inotify(mut self, shell: Arc<dyn TerminalAdapter>) -> Self {
+        self.shell = Some(shell);
+        self
+    }
+
+    /// Start watching. Fails if the path does not exist; changes made after
+    /// this returns are reported.
+    pub async fn start(self) -> Result<WatchHandle, ConnectionError> {
+        let root = self.files.stat(&self.path).await?;
+        let target = Target {
+            files: self.files,
+            is_dir: root.is_dir(),
+            path: self.path,
+            recursive: self.options.recursive,
+            interval: self.options.interval,
+        };
+        let inotify = match &self.shell {
+            Some(shell) => match Inotify::start(shell.as_ref(), &target).await {
+                Ok(inotify) => Some(inotify),
+                Err(e) => {
+                    warn!("watching {} by polling: {e}", target.path);
+                    None
+                }
+            },
+            None => None,
+        };
+        let (mode, feed) = match inotify {
+            Some(inotify) => (WatchMode::Inotify, Feed::Inotify(inotify)),
+            None => (WatchMode::Polling, Feed::poll(&target).await?),
+        };
+
+        let (events_tx, events) = mpsc::channel(EVENT_BUFFER);
+        let cancel = CancellationToken::new();
+        tokio::spawn(watch(
+            target,
+            feed,
+            Debouncer::new(self.options.debounce, self.options.max_wait),
+            events_tx,
+            cancel.clone(),
+        ));
+        Ok(WatchHandle {
+            events,
+            mode,
+            cancel,
+        })
+    }
+}
+
+/// A running watch. Dropping it stops the watch.
+pub struct WatchHandle {
+    events: mpsc::Receiver<Result<WatchEvent, ConnectionError>>,
+    mode: WatchMode,
+    cancel: CancellationToken,
+}
+
+impl WatchHandle {
+    /// The next change. `None` once the watch has stopped; an error is the
+    /// last item when it stopped because the session failed.
+    pub async fn recv(&mut self) -> Option<Result<WatchEvent, ConnectionError>> {
+        self.events.recv().await
+    }
+
+    /// How the watch started. An `Inotify` watch whose `inotifywait` exits
+    /// carries on by polling.
+    pub fn mode(&self) -> WatchMode {
+        self.mode
+    }
+
+    pub fn stop(&self) {
+        self.cancel.cancel();
+    }
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+/// What is being watched.
+struct Target {
+    files: Arc<dyn FileTransferAdapter>,
+    path: String,
+    is_dir: bool,
+    recursive: bool,
+    interval: Duration,
+}
+
+type Events = mpsc::Sender<Result<WatchEvent, ConnectionError>>;
+
+async fn watch(
+    target: Target,
+    mut feed: Feed,
+    mut debouncer: Debouncer,
+    events: Events,
+    cancel: CancellationToken,
+) {
+    let error = loop {
+        let deadline = debouncer.deadline;
+        tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return,
+            _ = events.closed() => return,
+            _ = sleep_until(deadline), if deadline.is_some() => {
+                if !deliver(&events, &mut debouncer, Instant::now()) {
+                    return;
+                }
+            }
+            changes = feed.next(&target) => match changes {
+                Ok(changes) => debouncer.push(changes, Instant::now()),
+                Err(e) => break e,
+            },
+        }
+    };
+    // Nothing is left to watch, so waiting for the listener costs nothing.
+    let last = async {
+        for event in debouncer.take() {
+            if events.send(Ok(event)).await.is_err() {
+                return;
+            }
+        }
+        let _ = events.send(Err(error)).await;
+    };
+    tokio::select! {
+        _ = cancel.cancelled() => {}
+        _ = last => {}
+    }
+}
+
+/// Hand the pending changes to the listener without waiting for room.
+/// Those that do not fit stay pending and are tried again after the
+/// debounce delay. `false` once the listener is gone.
+fn deliver(events: &Events, debouncer: &mut Debouncer, now: Instant) -> bool {
+    let mut pending = debouncer.take().into_iter();
+    while let Some(event) = pending.next() {
+        match events.try_send(Ok(event)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(event)) => {
+                debouncer.push(event.into_iter().chain(pending).collect(), now);
+                return true;
+            }
+            Err(TrySendError::Closed(_)) => return false,
+        }
+    }
+    true
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    if let Some(deadline) = deadline {
+        tokio::time::sleep_until(deadline).await;
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Change sources
+// ---------------------------------------------------------------------------
+
+enum Feed {
+    Poll { snapshot: Snapshot, ticks: Interval },
+    Inotify(Inotify),
+}
+
+impl Feed {
+    async fn poll(target: &Target) -> Result<Self, ConnectionError> {
+        let start = Instant::now() + target.interval;
+        let mut ticks = tokio::time::interval_at(start, target.interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Ok(Feed::Poll {
+            snapshot: Snapshot::take(target).await?,
+            ticks,
+        })
+    }
+
+    /// The next batch of changes, possibly empty.
+    async fn next(&mut self, target: &Target) -> Result<Vec<WatchEvent>, ConnectionError> {
+        match self {
+            Feed::Poll { snapshot, ticks } => {
+                ticks.tick().await;
+                let current = Snapshot::take(target).await?;
+                Ok(std::mem::replace(snapshot, current).changes_to(snapshot))
+            }
+            Feed::Inotify(inotify) => match inotify.next(target).await {
+                Some(changes) => Ok(changes),
+                None => {
+                    warn!("inotifywait for {} exited; polling instead", target.path);
+                    *self = Feed::poll(target).await?;
+                    Ok(Vec::new())
+                }
+            },
+        }
+    }
+}
+
+/// What a poll compares.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stamp {
+    kind: FileKind,
+    size: u64,
+    modified: Option<DateTime<Utc>>,
+}
+
+impl Stamp {
+    fn new(entry: &FileEntry) -> Self {
+        Stamp {
+            kind: entry.kind,
+            size: entry.size,
+            modified: entry.modified_at,
+        }
+    }
+}
+
+/// Every watched path with its stamp. A missing root is an empty snapshot,
+/// so deleting and recreating it is reported like any other change.
+#[derive(Debug, Default)]
+struct Snapshot(BTreeMap<String, Stamp>);
+
+impl Snapshot {
+    async fn take(target: &Target) -> Result<Self, ConnectionError> {
+        let mut snapshot = Snapshot::default();
+        if !target.is_dir {
+            match target.files.stat(&target.path).await {
+                Ok(entry) => {
+                    snapshot.0.insert(target.path.clone(), Stamp::new(&entry));
+                }
+                Err(ConnectionError::NotFound { .. }) => {}
+                Err(e) => return Err(e),
+            }
+            return Ok(snapshot);
+        }
+        let mut pending = vec![target.path.clone()];
+        while let Some(dir) = pending.pop() {
+            let entries = match target.files.list_dir(&dir).await {
+                Ok(entries) => entries,
+                // Removed while we were looking; the next poll sees it gone.
+                Err(ConnectionError::NotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            };
+            for entry in entries {
+                if target.recursive && entry.kind == FileKind::Dir {
+                    pending.push(entry.path.clone());
+                }
+                snapshot.0.insert(entry.path.clone(), Stamp::new(&entry));
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// What happened between `self` and `later`. A directory's own mtime
+    /// changes with its entries, so directories are only created or deleted.
+    fn changes_to(&self, later: &Snapshot) -> Vec<WatchEvent> {
+        let mut events = Vec::new();
+        for (path, stamp) in &later.0 {
+            let kind = match self.0.get(path) {
+                None => WatchEventKind::Created,
+                Some(before) if before.kind == FileKind::Dir && stamp.kind == FileKind::Dir => {
+                    continue
+                }
+                Some(before) if before == stamp => continue,
+                Some(_) => WatchEventKind::Modified,
+            };
+            events.push(WatchEvent {
+                path: path.clone(),
+                kind,
+            });
+        }
+        for path in self.0.keys().filter(|path| !later.0.contains_key(*path)) {
+            events.push(WatchEvent {
+                path: path.clone(),
+                kind: WatchEventKind::Deleted,
+            });
+        }
+        events
+    }
+}
+
+/// A running `inotifywait -m`. A watched file is watched through its
+/// directory, so an editor replacing it by rename is still seen.
+struct Inotify {
+    // Dropping the handle stops the command.
+    _handle: ExecHandle,
+    stdout: mpsc::Receiver<Vec<u8>>,
+    partial: Vec<u8>,
+}
+
+impl Inotify {
+    async fn start(shell: &dyn TerminalAdapter, target: &Target) -> Result<Self, ConnectionError> {
+        let mut handle = shell
+            .exec_stream(ExecRequest::new(inotify_command(target)))
+            .await?;
+        let (Some(stdout), Some(mut stderr)) = (handle.stdout(), handle.stderr()) else {
+            return Err(ConnectionError::Protocol(
+                "exec output already taken".to_owned(),
+            ));
+        };
+        let ready = async {
+            let mut messages = Vec::new();
+            loop {
+                match stderr.recv().await {
+                    Some(chunk) => messages.extend_from_slice(&chunk),
+                    None => {
+                        let messages = String::from_utf8_lossy(&messages);
+                        return Err(ConnectionError::Protocol(format!(
+                            "inotifywait failed: {}",
+                            messages.trim()
+                        )));
+                    }
+                }
+                if String::from_utf8_lossy(&messages).contains(INOTIFY_READY) {
+                    return Ok(());
+                }
+            }
+        };
+        // Dropping the handle on a timeout stops the command.
+        tokio::time::timeout(INOTIFY_START_TIMEOUT, ready)
+            .await
+            .map_err(|_| ConnectionError::Timeout {
+                timeout: INOTIFY_START_TIMEOUT,
+            })??;
+        Ok(Inotify {
+            _handle: handle,
+            stdout,
+            partial: Vec::new(),
+        })
+    }
+
+    /// The changes in the next chunk of output; `None` once `inotifywait`
+    /// has exited.
+    async fn next(&mut self, target: &Target) -> Option<Vec<WatchEvent>> {
+        let chunk = self.stdout.recv().await?;
+        self.partial.extend_from_slice(&chunk);
+        let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') else {
+            return Some(Vec::new());
+        };
+        let lines: Vec<u8> = self.partial.drain(..=end).collect();
+        Some(
+            String::from_utf8_lossy(&lines)
+                .lines()
+                .filter_map(|line| parse_inotify_line(line, target))
+                .collect(),
+        )
+    }
+}
+
+fn inotify_command(target: &Target) -> String {
+    let (path, recursive) = if target.is_dir {
+        (target.path.as_str(), target.recursive)
+    } else {
+        (parent(&target.path), false)
+    };
+    format!(
+        "inotifywait -m {}-e modify,create,delete,move --format '%e %w%f' -- {}",
+        if recursive { "-r " } else { "" },
+        shell_quote(path)
+    )
+}
+
+/// One `%e %w%f` line, e.g. `CREATE,ISDIR /srv/app/logs`.
+fn parse_inotify_line(line: &str, target: &Target) -> Option<WatchEvent> {
+    let (events, path) = line.split_once(' ')?;
+    let kind = events.split(',').find_map(|event| match event {
+        "CREATE" | "MOVED_TO" => Some(WatchEventKind::Created),
+        "MODIFY" => Some(WatchEventKind::Modified),
+        "DELETE" | "MOVED_FROM" => Some(WatchEventKind::Deleted),
+        _ => None,
+    })?;
+    if target.is_dir {
+        return Some(WatchEvent {
+            path: path.to_owned(),
+            kind,
+        });
+    }
+    (file_name(path) == file_name(&target.path)).then(|| WatchEvent {
+        path: target.path.clone(),
+        kind,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Debouncing
+// ---------------------------------------------------------------------------
+
+/// Holds changes until none have arrived for `delay`, or `max_wait` after
+/// the first of them, merging those to the same path.
+struct Debouncer {
+    delay: Duration,
+    max_wait: Duration,
+    pending: BTreeMap<String, WatchEventKind>,
+    /// When the first change still pending arrived.
+    first: Option<Instant>,
+    deadline: Option<Instant>,
+}
+
+impl Debouncer {
+    fn new(delay: Duration, max_wait: Duration) -> Self {
+        Debouncer {
+            delay,
+            max_wait,
+            pending: BTreeMap::new(),
+            first: None,
+            deadline: None,
+        }
+    }
+
+    fn push(&mut self, events: Vec<WatchEvent>, now: Instant) {
+        if events.is_empty() {
+            return;
+        }
+        for event in events {
+            let merged = match (self.pending.get(&event.path), event.kind) {
+                (None, kind) => Some(kind),
+                // Never existed as far as the listener knows.
+                (Some(WatchEventKind::Created), WatchEventKind::Deleted) => None,
+                (Some(WatchEventKind::Created), _) => Some(WatchEventKind::Created),
+                (Some(WatchEventKind::Deleted), WatchEventKind::Deleted) => {
+                    Some(WatchEventKind::Deleted)
+                }
+                (Some(WatchEventKind::Deleted), _) => Some(WatchEventKind::Modified),
+                (Some(WatchEventKind::Modified), kind) => Some(match kind {
+                    WatchEventKind::Deleted => WatchEventKind::Deleted,
+                    _ => WatchEventKind::Modified,
+                }),
+            };
+            match merged {
+                Some(kind) => self.pending.insert(event.path, kind),
+                None => self.pending.remove(&event.path),
+            };
+        }
+        let first = *self.first.get_or_insert(now);
+        self.deadline = Some((now + self.delay).min(first + self.max_wait));
+    }
+
+    fn take(&mut self) -> Vec<WatchEvent> {
+        self.first = None;
+        self.deadline = None;
+        std::mem::take(&mut self.pending)
+            .into_iter()
+            .map(|(path, kind)| WatchEvent { path, kind })
+            .collect()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::testing::LocalFs;
+
+    fn options() -> WatchOptions {
+        WatchOptions {
+            recursive: true,
+            interval: Duration::from_millis(20),
+            debounce: Duration::from_millis(10),
+            max_wait: Duration::from_millis(50),
+        }
+    }
+
+    fn path(dir: &tempfile::TempDir, name: &str) -> String {
+        dir.path().join(name).to_str().unwrap().to_owned()
+    }
+
+    fn target(path: &str, is_dir: bool) -> Target {
+        Target {
+            files: Arc::new(LocalFs::default()),
+            path: path.to_owned(),
+            is_dir,
+            recursive: true,
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    fn event(path: &str, kind: WatchEventKind) -> WatchEvent {
+        WatchEvent {
+            path: path.to_owned(),
+            kind,
+        }
+    }
+
+    async fn next(handle: &mut WatchHandle) -> WatchEvent {
+        tokio::time::timeout(Duration::from_secs(5), handle.recv())
+            .await
+            .expect("no event")
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn polling_reports_changes_under_a_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let files = Arc::new(LocalFs::default());
+        let mut handle = RemoteWatcher::new(files, path(&dir, ""), options())
+            .start()
+            .await
+            .unwrap();
+        assert_eq!(handle.mode(), WatchMode::Polling);
+        let file = path(&dir, "sub/a.txt");
+
+        std::fs::write(&file, "one").unwrap();
+        assert_eq!(
+            next(&mut handle).await,
+            event(&file, WatchEventKind::Created)
+        );
+        std::fs::write(&file, "three").unwrap();
+        assert_eq!(
+            next(&mut handle).await,
+            event(&file, WatchEventKind::Modified)
+        );
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(
+            next(&mut handle).await,
+            event(&file, WatchEventKind::Deleted)
+        );
+    }
+
+    #[tokio::test]
+    async fn a_watched_file_can_go_and_come_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = path(&dir, "notes.txt");
+        std::fs::write(&file, "draft").unwrap();
+        let mut handle = RemoteWatcher::new(Arc::new(LocalFs::default()), &file, options())
+            .start()
+            .await
+            .unwrap();
+
+        std::fs::write(dir.path().join("other.txt"), "ignored").unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(
+            next(&mut handle).await,
+            event(&file, WatchEventKind::Deleted)
+        );
+        std::fs::write(&file, "final").unwrap();
+        assert_eq!(
+            next(&mut handle).await,
+            event(&file, WatchEventKind::Created)
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_paths_fail_to_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let watcher =
+            RemoteWatcher::new(Arc::new(LocalFs::default()), path(&dir, "gone"), options());
+        assert!(matches!(
+            watcher.start().await,
+            Err(ConnectionError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn shells_without_inotifywait_fall_back_to_polling() {
+        let dir = tempfile::tempdir().unwrap();
+        let handle = RemoteWatcher::new(Arc::new(LocalFs::default()), path(&dir, ""), options())
+            .with_inotify(Arc::new(LocalFs::default()))
+            .start()
+            .await
+            .unwrap();
+        assert_eq!(handle.mode(), WatchMode::Polling);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn inotifywait_that_never_starts_watching_falls_back_to_polling() {
+        let dir = tempfile::tempdir().unwrap();
+        let shell = Arc::new(LocalFs {
+            silent_exec: true,
+            ..LocalFs::default()
+        });
+        let started = Instant::now();
+        let handle = RemoteWatcher::new(Arc::new(LocalFs::default()), path(&dir, ""), options())
+            .with_inotify(shell.clone())
+            .start()
+            .await
+            .unwrap();
+        assert_eq!(handle.mode(), WatchMode::Polling);
+        assert_eq!(started.elapsed(), INOTIFY_START_TIMEOUT);
+        assert!(shell.commands.lock().unwrap()[0].starts_with("inotifywait "));
+    }
+
+    #[test]
+    fn directory_timestamps_are_not_modifications() {
+        let stamp = |kind, size, secs| Stamp {
+            kind,
+            size,
+            modified: DateTime::from_timestamp(secs, 0),
+        };
+        let before = Snapshot(BTreeMap::from([
+            ("/d".to_owned(), stamp(FileKind::Dir, 4096, 1)),
+            ("/f".to_owned(), stamp(FileKind::File, 1, 1)),
+            ("/g".to_owned(), stamp(FileKind::File, 1, 1)),
+        ]));
+        let after = Snapshot(BTreeMap::from([
+            ("/d".to_owned(), stamp(FileKind::Dir, 4096, 2)),
+            ("/f".to_owned(), stamp(FileKind::File, 1, 2)),
+            ("/h".to_owned(), stamp(FileKind::File, 1, 1)),
+        ]));
+        assert_eq!(
+            before.changes_to(&after),
+            [
+                event("/f", WatchEventKind::Modified),
+                event("/h", WatchEventKind::Created),
+                event("/g", WatchEventKind::Deleted),
+            ]
+        );
+    }
+
+    #[test]
+    fn bursts_of_changes_merge_per_path() {
+        use WatchEventKind::*;
+        let now = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(100), Duration::from_secs(1));
+        debouncer.push(
+            vec![
+                event("/new", Created),
+                event("/new", Modified),
+                event("/tmp", Created),
+                event("/tmp", Deleted),
+                event("/saved", Deleted),
+                event("/saved", Created),
+                event("/gone", Modified),
+                event("/gone", Deleted),
+            ],
+            now,
+        );
+        debouncer.push(Vec::new(), now + Duration::from_secs(1));
+        assert_eq!(debouncer.deadline, Some(now + Duration::from_millis(100)));
+        assert_eq!(
+            debouncer.take(),
+            [
+                event("/gone", Deleted),
+                event("/new", Created),
+                event("/saved", Modified),
+            ]
+        );
+        assert_eq!(debouncer.deadline, None);
+    }
+
+    #[test]
+    fn steady_changes_are_flushed_after_max_wait() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut debouncer = Debouncer::new(Duration::from_millis(100), Duration::from_millis(250));
+        debouncer.push(vec![event("/log", WatchEventKind::Modified)], at(0));
+        assert_eq!(debouncer.deadline, Some(at(100)));
+        debouncer.push(vec![event("/log", WatchEventKind::Modified)], at(80));
+        assert_eq!(debouncer.deadline, Some(at(180)));
+        debouncer.push(vec![event("/log", WatchEventKind::Modified)], at(160));
+        assert_eq!(debouncer.deadline, Some(at(250)));
+
+        debouncer.take();
+        debouncer.push(vec![event("/log", WatchEventKind::Modified)], at(300));
+        assert_eq!(debouncer.deadline, Some(at(400)));
+    }
+
+    #[tokio::test]
+    async fn files_that_never_settle_still_report_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = path(&dir, "busy.log");
+        std::fs::write(&file, "").unwrap();
+        let options = WatchOptions {
+            debounce: Duration::from_secs(60),
+            max_wait: Duration::from_millis(100),
+            ..options()
+        };
+        let mut handle = RemoteWatcher::new(Arc::new(LocalFs::default()), &file, options)
+            .start()
+            .await
+            .unwrap();
+
+        let writing = CancellationToken::new();
+        let writer = tokio::spawn({
+            let (file, writing) = (file.clone(), writing.clone());
+            async move {
+                for n in 1.. {
+                    std::fs::write(&file, "x".repeat(n)).unwrap();
+                    tokio::select! {
+                        _ = writing.cancelled() => return,
+                        _ = tokio::time::sleep(Duration::from_millis(5)) => {}
+                    }
+                }
+            }
+        });
+        assert_eq!(
+            next(&mut handle).await,
+            event(&file, WatchEventKind::Modified)
+        );
+        assert!(!writer.is_finished());
+        writing.cancel();
+    }
+
+    #[tokio::test]
+    async fn a_full_listener_gets_the_rest_later() {
+        let (events, mut listener) = mpsc::channel(1);
+        let mut debouncer = Debouncer::new(Duration::from_millis(100), Duration::from_secs(1));
+        let now = Instant::now();
+        debouncer.push(
+            vec![
+                event("/a", WatchEventKind::Created),
+                event("/b", WatchEventKind::Created),
+            ],
+            now,
+        );
+
+        assert!(deliver(&events, &mut debouncer, now));
+        assert_eq!(debouncer.deadline, Some(now + Duration::from_millis(100)));
+        // `/b` merges with a later change instead of waiting in line.
+        debouncer.push(vec![event("/b", WatchEventKind::Modified)], now);
+        assert_eq!(
+            listener.recv().await.unwrap().unwrap(),
+            event("/a", WatchEventKind::Created)
+        );
+        assert!(deliver(&events, &mut debouncer, now));
+        assert_eq!(
+            listener.recv().await.unwrap().unwrap(),
+            event("/b", WatchEventKind::Created)
+        );
+        assert_eq!(debouncer.deadline, None);
+
+        drop(listener);
+        debouncer.push(vec![event("/c", WatchEventKind::Created)], now);
+        assert!(!deliver(&events, &mut debouncer, now));
+    }
+
+    #[test]
+    fn inotify_lines_become_events() {
+        let tree = target("/srv/app", true);
+        assert_eq!(
+            parse_inotify_line("CREATE,ISDIR /srv/app/logs", &tree),
+            Some(event("/srv/app/logs", WatchEventKind::Created))
+        );
+        assert_eq!(
+            parse_inotify_line("MOVED_FROM /srv/app/a b.txt", &tree),
+            Some(event("/srv/app/a b.txt", WatchEventKind::Deleted))
+        );
+        assert_eq!(parse_inotify_line("ATTRIB /srv/app/x", &tree), None);
+
+        let file = target("notes.txt", false);
+        assert_eq!(
+            parse_inotify_line("MOVED_TO ./notes.txt", &file),
+            Some(event("notes.txt", WatchEventKind::Created))
+        );
+        assert_eq!(parse_inotify_line("MODIFY ./other.txt", &file), None);
+    }
+
+    #[test]
+    fn files_are_watched_through_their_directory() {
+        assert_eq!(
+            inotify_command(&target("/srv/app", true)),
+            "inotifywait -m -r -e modify,create,delete,move --format '%e %w%f' -- '/srv/app'"
+        );
+        assert_eq!(
+            inotify_command(&target("/srv/app/notes.txt", false)),
+            "inotifywait -m -e modify,create,delete,move --format '%e %w%f' -- '/srv/app'"
+        );
+    }
+}
@@ -1,4 +1,7 @@
+pub mod agent;
 pub mod connection;
 pub mod crypto;
 pub mod profile;
+pub mod recording;
 pub mod storage;
+pub mod terminal;
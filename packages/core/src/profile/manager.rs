@@ -3,7 +3,7 @@ use zeroize::Zeroizing;
 
 use crate::crypto::vault::{EncryptedItem, VaultError, VaultFile};
 use crate::profile::types::{
-    ConnectionProfile, KubeConfigItem, Password, ProfileId, SshKey, VaultPayload,
+    ConnectionProfile, KnownHost, KubeConfigItem, Password, ProfileId, SshKey, VaultPayload,
 };
 
 // ---------------------------------------------------------------------------
@@ -61,6 +61,11 @@ impl ProfileManager {
         &self.vault
     }
 
+    /// The master key, for data encrypted outside the vault (recordings).
+    pub(crate) fn master_key(&self) -> &[u8; 32] {
+        &self.master_key
+    }
+
     // -----------------------------------------------------------------------
     // Generic CRUD
     // -----------------------------------------------------------------------
@@ -261,6 +266,67 @@ impl ProfileManager {
         })
     }
 
+    // -----------------------------------------------------------------------
+    // Typed helpers — KnownHost
+    // -----------------------------------------------------------------------
+
+    pub fn add_known_host(&mut self, host: KnownHost) -> Result<ProfileId, ProfileError> {
+        self.add(VaultPayload::KnownHost(host))
+    }
+
+    pub fn get_known_host(&self, id: &str) -> Result<KnownHost, ProfileError> {
+        match self.get(id)? {
+            VaultPayload::KnownHost(h) => Ok(h),
+            other => Err(ProfileError::WrongType {
+                expected: "known_host",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    pub fn update_known_host(&mut self, host: KnownHost) -> Result<(), ProfileError> {
+        self.update(VaultPayload::KnownHost(host))
+    }
+
+    /// The vault item ID for the known host at `host:port`: a keyed hash of
+    /// the pair, so every device derives the same ID, a lookup decrypts one
+    /// item, and the stored IDs do not reveal which hosts are trusted.
+    pub fn known_host_id(&self, host: &str, port: u16) -> ProfileId {
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, self.master_key.as_slice());
+        let tag = ring::hmac::sign(&key, format!("known_host\0{host}:{port}").as_bytes());
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&tag.as_ref()[..16]);
+        uuid::Builder::from_random_bytes(bytes)
+            .into_uuid()
+            .to_string()
+    }
+
+    /// The known host stored under [`known_host_id`](Self::known_host_id),
+    /// if any.
+    pub fn find_known_host(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> Result<Option<KnownHost>, ProfileError> {
+        match self.get_known_host(&self.known_host_id(host, port)) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(ProfileError::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn list_known_hosts(&self) -> Result<Vec<KnownHost>, ProfileError> {
+        self.list_all().map(|items| {
+            items
+                .into_iter()
+                .filter_map(|p| match p {
+                    VaultPayload::KnownHost(h) => Some(h),
+                    _ => None,
+                })
+                .collect()
+        })
+    }
+
     // -----------------------------------------------------------------------
     // Private helpers
     // -----------------------------------------------------------------------
@@ -460,6 +526,31 @@ mod tests {
         );
     }
 
+    // --- KnownHost CRUD ---
+
+    #[test]
+    fn add_and_list_known_hosts() {
+        let mut mgr = make_manager();
+        let host = KnownHost::new("bastion.example.com", 22, "ssh-ed25519", "AAAA");
+        let id = mgr.add_known_host(host.clone()).unwrap();
+        mgr.add_profile(ConnectionProfile::new_ssh("P", "h", 22, "u"))
+            .unwrap();
+        assert_eq!(mgr.get_known_host(&id).unwrap(), host);
+        assert_eq!(mgr.list_known_hosts().unwrap(), vec![host]);
+    }
+
+    #[test]
+    fn known_host_ids_depend_on_host_port_and_key() {
+        let mgr = make_manager();
+        let id = mgr.known_host_id("h", 22);
+        assert_eq!(id, make_manager().known_host_id("h", 22));
+        assert_ne!(id, mgr.known_host_id("h", 2222));
+        assert_ne!(
+            id,
+            ProfileManager::new(VaultFile::new(), [0; 32]).known_host_id("h", 22)
+        );
+    }
+
     // --- Type isolation ---
 
     #[test]
@@ -1,3 +1,6 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
@@ -26,6 +29,45 @@ pub enum HostKeyPolicy {
     StrictFirstConnect,
     /// Accept any host key without verification. User must explicitly opt in.
     AcceptAll,
+    /// Verify against an OpenSSH-format `known_hosts` file.
+    KnownHostsFile {
+        path: PathBuf,
+        /// Append unknown hosts to `path` instead of rejecting them.
+        #[serde(default)]
+        append_unknown: bool,
+    },
+    /// Verify against `~/.ssh/known_hosts` and `/etc/ssh/ssh_known_hosts`,
+    /// as the OpenSSH client does. Unknown hosts are appended to the user file
+    /// when `append_unknown` is set.
+    SystemKnownHosts {
+        #[serde(default)]
+        append_unknown: bool,
+    },
+}
+
+/// A port forward started automatically when an SSH profile connects.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PortForwardSpec {
+    /// `ssh -L`: listen locally, connect to `remote_host:remote_port` from the server.
+    Local {
+        bind_address: String,
+        bind_port: u16,
+        remote_host: String,
+        remote_port: u16,
+    },
+    /// `ssh -R`: listen on the server, connect to `local_host:local_port` from here.
+    Remote {
+        bind_address: String,
+        bind_port: u16,
+        local_host: String,
+        local_port: u16,
+    },
+    /// `ssh -D`: local SOCKS5 proxy whose connections exit from the server.
+    Dynamic {
+        bind_address: String,
+        bind_port: u16,
+    },
 }
 
 /// FTP connection security mode.
@@ -73,6 +115,60 @@ pub struct SshSettings {
     pub keepalive_secs: Option<u64>,
     /// Ordered list of jump host profile IDs (ProxyJump chain).
     pub jump_host_ids: Vec<ProfileId>,
+    /// Port forwards to start on connect.
+    #[serde(default)]
+    pub port_forwards: Vec<PortForwardSpec>,
+    /// SHA-256 fingerprint (`SHA256:…`) of the SSH agent identity to offer
+    /// first, ahead of the server's `MaxAuthTries` limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_fingerprint: Option<String>,
+    /// Forward the local SSH agent to the server (`ssh -A`). Only enable
+    /// for hosts you trust: their root can use your keys while you are
+    /// connected.
+    #[serde(default)]
+    pub forward_agent: bool,
+    /// Seconds allowed for DNS resolution and the TCP connection together.
+    /// `None` waits as long as the operating system does.
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout_secs: Option<u64>,
+    /// Seconds allowed for the SSH key exchange, not counting time spent
+    /// waiting for the user to answer a host-key prompt. `None` disables
+    /// the limit.
+    #[serde(default = "default_handshake_timeout")]
+    pub handshake_timeout_secs: Option<u64>,
+    /// Seconds allowed for authentication, including time spent answering
+    /// keyboard-interactive prompts. `None` disables the limit.
+    #[serde(default = "default_auth_timeout")]
+    pub auth_timeout_secs: Option<u64>,
+    /// Lines of shell output kept in the core's scrollback
+    /// ([`TerminalState`](crate::terminal::TerminalState)).
+    #[serde(default = "default_scrollback_lines")]
+    pub scrollback_lines: usize,
+    /// Whether the session opens a shell, and with or without a PTY.
+    #[serde(default)]
+    pub shell_mode: ShellMode,
+    /// The PTY and environment requested for shells.
+    #[serde(default)]
+    pub pty: PtySettings,
+    /// Algorithms to offer, replacing the built-in lists.
+    #[serde(default)]
+    pub algorithms: AlgorithmPreferences,
+}
+
+fn default_connect_timeout() -> Option<u64> {
+    Some(15)
+}
+
+fn default_handshake_timeout() -> Option<u64> {
+    Some(60)
+}
+
+fn default_auth_timeout() -> Option<u64> {
+    Some(120)
+}
+
+fn default_scrollback_lines() -> usize {
+    crate::terminal::DEFAULT_SCROLLBACK
 }
 
 impl Default for SshSettings {
@@ -81,6 +177,87 @@ impl Default for SshSettings {
             host_key_policy: HostKeyPolicy::default(),
             keepalive_secs: Some(30),
             jump_host_ids: Vec::new(),
+            port_forwards: Vec::new(),
+            agent_fingerprint: None,
+            forward_agent: false,
+            connect_timeout_secs: default_connect_timeout(),
+            handshake_timeout_secs: default_handshake_timeout(),
+            auth_timeout_secs: default_auth_timeout(),
+            scrollback_lines: default_scrollback_lines(),
+            shell_mode: ShellMode::default(),
+            pty: PtySettings::default(),
+            algorithms: AlgorithmPreferences::default(),
+        }
+    }
+}
+
+/// SSH algorithm names to offer, most preferred first. An empty list keeps
+/// the built-in default, which leaves out SHA-1 key exchange, `ssh-rsa`
+/// and CBC ciphers; list them here to reach hosts that offer nothing else.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlgorithmPreferences {
+    /// Key exchange, e.g. `curve25519-sha256`, `diffie-hellman-group14-sha1`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub kex: Vec<String>,
+    /// Host key, e.g. `ssh-ed25519`, `rsa-sha2-256`, `ssh-rsa`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub host_key: Vec<String>,
+    /// Ciphers, e.g. `chacha20-poly1305@openssh.com`, `aes128-cbc`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cipher: Vec<String>,
+    /// MACs, e.g. `hmac-sha2-256-etm@openssh.com`, `hmac-sha1`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mac: Vec<String>,
+    /// Compression, e.g. `none`, `zlib@openssh.com`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub compression: Vec<String>,
+}
+
+/// What an SSH session opens at connect time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShellMode {
+    /// An interactive shell on a PTY.
+    #[default]
+    Pty,
+    /// A shell without a PTY (`ssh -T`): no echo, no line editing, no
+    /// window size.
+    NoPty,
+    /// No shell at all, for hosts that only allow exec, SFTP or forwarding.
+    /// The terminal methods of the adapter fail.
+    ExecOnly,
+}
+
+/// PTY request for SSH shells (RFC 4254 §6.2).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PtySettings {
+    /// `TERM` for the remote side.
+    pub term: String,
+    pub cols: u16,
+    pub rows: u16,
+    /// Size in pixels, `0` when unknown.
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+    /// Terminal modes by their RFC 4254 §8 name (`ECHO`, `VERASE`,
+    /// `IUTF8`, …). Flags take `0` or `1`, characters their byte value.
+    pub modes: BTreeMap<String, u32>,
+    /// Variables set before the shell starts, typically `LANG` and `LC_*`.
+    /// Servers ignore names they do not accept (OpenSSH: `AcceptEnv`).
+    pub env: BTreeMap<String, String>,
+}
+
+impl Default for PtySettings {
+    fn default() -> Self {
+        PtySettings {
+            term: "xterm-256color".to_owned(),
+            cols: 80,
+            rows: 24,
+            pixel_width: 0,
+            pixel_height: 0,
+            modes: BTreeMap::new(),
+            env: BTreeMap::new(),
         }
     }
 }
@@ -129,6 +306,9 @@ pub struct ConnectionProfile {
     /// Present and non-None for FTP protocols.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ftp: Option<FtpSettings>,
+    /// Steps run in the shell right after login, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub login_actions: Vec<LoginAction>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -152,12 +332,50 @@ impl ConnectionProfile {
             credential_id: None,
             ssh: Some(SshSettings::default()),
             ftp: None,
+            login_actions: Vec::new(),
             created_at: now,
             updated_at: now,
         }
     }
 }
 
+/// One step of a profile's post-login script.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoginAction {
+    #[serde(flatten)]
+    pub kind: LoginActionKind,
+    /// Seconds the step may take before the script is abandoned; `None`
+    /// uses the default of 10. Ignored for [`LoginActionKind::Delay`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// What a [`LoginAction`] does. Text is sent exactly as given, so end a
+/// command with `\r` to press Enter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum LoginActionKind {
+    /// Type `text` into the shell.
+    SendText { text: String },
+    /// Wait until the output since the previous match matches the regex
+    /// `pattern`, then type `text`.
+    WaitFor { pattern: String, text: String },
+    /// Pause for `millis` milliseconds.
+    Delay { millis: u64 },
+}
+
+/// How the built-in SSH agent ([`crate::agent`]) may use an [`SshKey`],
+/// mirroring `ssh-add -c` and `ssh-add -t`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentKeyConstraints {
+    /// Ask before every signature.
+    #[serde(default)]
+    pub confirm: bool,
+    /// Stop serving the key this many seconds after the vault is unlocked.
+    #[serde(default)]
+    pub lifetime_secs: Option<u64>,
+}
+
 /// An SSH private key stored in the vault.
 ///
 /// The private key material is stored encrypted inside a vault item. The
@@ -172,6 +390,13 @@ pub struct SshKey {
     /// OpenSSH-format public key. Not sensitive.
     pub public_key: String,
     pub key_type: SshKeyType,
+    /// OpenSSH user certificate for this key (`-cert.pub` contents), if the
+    /// key is signed by a CA. Not sensitive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<String>,
+    /// Restrictions the built-in SSH agent applies to this key.
+    #[serde(default)]
+    pub agent_constraints: AgentKeyConstraints,
     /// Profile IDs that reference this key via `credential_id`.
     pub associated_profile_ids: Vec<ProfileId>,
     pub created_at: DateTime<Utc>,
@@ -192,6 +417,8 @@ impl SshKey {
             private_key_pem: private_key_pem.into(),
             public_key: public_key.into(),
             key_type,
+            certificate: None,
+            agent_constraints: AgentKeyConstraints::default(),
             associated_profile_ids: Vec::new(),
             created_at: now,
             updated_at: now,
@@ -207,6 +434,10 @@ pub struct Password {
     pub username: String,
     /// Plaintext password. Sensitive — stored encrypted in the vault.
     pub password: String,
+    /// Base32 TOTP seed for hosts that also ask for a one-time code.
+    /// Sensitive — stored encrypted in the vault.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_secret: Option<String>,
     /// Profile IDs that reference this credential via `credential_id`.
     pub associated_profile_ids: Vec<ProfileId>,
     pub created_at: DateTime<Utc>,
@@ -225,6 +456,7 @@ impl Password {
             display_name: display_name.into(),
             username: username.into(),
             password: password.into(),
+            totp_secret: None,
             associated_profile_ids: Vec::new(),
             created_at: now,
             updated_at: now,
@@ -277,6 +509,45 @@ impl KubeConfigItem {
     }
 }
 
+/// A trusted SSH host key, remembered after the first successful connection.
+///
+/// Stored in the vault (rather than in adapter memory) so that TOFU trust
+/// survives app restarts and follows the user to other devices via
+/// `SyncEngine`. Entries are keyed by the full `host:port` pair.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownHost {
+    pub id: ProfileId,
+    pub host: String,
+    pub port: u16,
+    /// SSH key algorithm reported by the server (e.g., `ssh-ed25519`).
+    pub key_type: String,
+    /// Base64-encoded SSH wire-format public key (the second field of an
+    /// OpenSSH public key line). Not sensitive.
+    pub public_key: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl KnownHost {
+    pub fn new(
+        host: impl Into<String>,
+        port: u16,
+        key_type: impl Into<String>,
+        public_key: impl Into<String>,
+    ) -> Self {
+        let now = Utc::now();
+        KnownHost {
+            id: uuid::Uuid::new_v4().to_string(),
+            host: host.into(),
+            port,
+            key_type: key_type.into(),
+            public_key: public_key.into(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Vault payload wrapper
 // ---------------------------------------------------------------------------
@@ -288,11 +559,13 @@ impl KubeConfigItem {
 /// JSON enables the correct deserialization path on load.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]
 pub enum VaultPayload {
     ConnectionProfile(ConnectionProfile),
     SshKey(SshKey),
     Password(Password),
     KubeConfig(KubeConfigItem),
+    KnownHost(KnownHost),
 }
 
 impl VaultPayload {
@@ -302,6 +575,7 @@ impl VaultPayload {
             VaultPayload::SshKey(_) => "ssh_key",
             VaultPayload::Password(_) => "password",
             VaultPayload::KubeConfig(_) => "kube_config",
+            VaultPayload::KnownHost(_) => "known_host",
         }
     }
 
@@ -311,6 +585,7 @@ impl VaultPayload {
             VaultPayload::SshKey(k) => &k.id,
             VaultPayload::Password(p) => &p.id,
             VaultPayload::KubeConfig(k) => &k.id,
+            VaultPayload::KnownHost(h) => &h.id,
         }
     }
 }
@@ -339,6 +614,12 @@ impl From<KubeConfigItem> for VaultPayload {
     }
 }
 
+impl From<KnownHost> for VaultPayload {
+    fn from(h: KnownHost) -> Self {
+        VaultPayload::KnownHost(h)
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Tests
 // ---------------------------------------------------------------------------
@@ -392,6 +673,15 @@ mod tests {
         assert!(k.default_namespace.is_none());
     }
 
+    #[test]
+    fn known_host_new_sets_fields() {
+        let h = KnownHost::new("example.com", 2222, "ssh-ed25519", "AAAA");
+        assert_eq!(h.host, "example.com");
+        assert_eq!(h.port, 2222);
+        assert_eq!(h.key_type, "ssh-ed25519");
+        assert_eq!(VaultPayload::from(h).type_name(), "known_host");
+    }
+
     #[test]
     fn vault_payload_type_name_is_stable() {
         let profile = ConnectionProfile::new_ssh("Test", "host", 22, "user");
@@ -425,6 +715,116 @@ mod tests {
         assert_eq!(restored, payload);
     }
 
+    #[test]
+    fn credentials_saved_before_optional_fields_still_load() {
+        let mut key = serde_json::to_value(SshKey::new(
+            "k",
+            "PEM",
+            "ssh-ed25519 AAAA",
+            SshKeyType::Ed25519,
+        ))
+        .unwrap();
+        key.as_object_mut().unwrap().remove("certificate");
+        key.as_object_mut().unwrap().remove("agent_constraints");
+        let key: SshKey = serde_json::from_value(key).unwrap();
+        assert!(key.certificate.is_none());
+        assert_eq!(key.agent_constraints, AgentKeyConstraints::default());
+
+        let mut password = serde_json::to_value(Password::new("p", "u", "pw")).unwrap();
+        password.as_object_mut().unwrap().remove("totp_secret");
+        let password: Password = serde_json::from_value(password).unwrap();
+        assert!(password.totp_secret.is_none());
+    }
+
+    #[test]
+    fn host_key_policy_variants_round_trip() {
+        let variants = vec![
+            HostKeyPolicy::StrictFirstConnect,
+            HostKeyPolicy::AcceptAll,
+            HostKeyPolicy::KnownHostsFile {
+                path: PathBuf::from("/etc/tacoshell/known_hosts"),
+                append_unknown: false,
+            },
+            HostKeyPolicy::SystemKnownHosts {
+                append_unknown: true,
+            },
+        ];
+        for policy in variants {
+            let json = serde_json::to_string(&policy).unwrap();
+            let restored: HostKeyPolicy = serde_json::from_str(&json).unwrap();
+            assert_eq!(restored, policy);
+        }
+    }
+
+    #[test]
+    fn ssh_settings_without_port_forwards_deserializes() {
+        let json = r#"{"host_key_policy":"accept_all","keepalive_secs":null,"jump_host_ids":[]}"#;
+        let settings: SshSettings = serde_json::from_str(json).unwrap();
+        assert!(settings.port_forwards.is_empty());
+        assert!(settings.agent_fingerprint.is_none());
+        assert!(!settings.forward_agent);
+        assert_eq!(settings.connect_timeout_secs, Some(15));
+        assert_eq!(settings.auth_timeout_secs, Some(120));
+
+        let mut settings = SshSettings::default();
+        settings.port_forwards.push(PortForwardSpec::Dynamic {
+            bind_address: "127.0.0.1".into(),
+            bind_port: 1080,
+        });
+        let restored: SshSettings =
+            serde_json::from_str(&serde_json::to_string(&settings).unwrap()).unwrap();
+        assert_eq!(restored, settings);
+    }
+
+    #[test]
+    fn pty_settings_fill_in_missing_fields() {
+        let json = r#"{"host_key_policy":"accept_all","keepalive_secs":null,"jump_host_ids":[],
+            "shell_mode":"exec_only","pty":{"term":"vt100","modes":{"IUTF8":1}}}"#;
+        let settings: SshSettings = serde_json::from_str(json).unwrap();
+        assert_eq!(settings.shell_mode, ShellMode::ExecOnly);
+        assert_eq!(settings.pty.term, "vt100");
+        assert_eq!((settings.pty.cols, settings.pty.rows), (80, 24));
+        assert_eq!(settings.pty.modes.get("IUTF8"), Some(&1));
+        assert!(settings.pty.env.is_empty());
+
+        let defaults: SshSettings = serde_json::from_str(
+            r#"{"host_key_policy":"accept_all","keepalive_secs":null,"jump_host_ids":[]}"#,
+        )
+        .unwrap();
+        assert_eq!(defaults.shell_mode, ShellMode::Pty);
+        assert_eq!(defaults.pty, PtySettings::default());
+    }
+
+    #[test]
+    fn login_actions_use_a_flat_action_tag() {
+        let json = r#"[
+            {"action":"wait_for","pattern":"\\$ $","text":"sudo -i\r","timeout_secs":30},
+            {"action":"delay","millis":500},
+            {"action":"send_text","text":"cd /srv/app\r"}
+        ]"#;
+        let actions: Vec<LoginAction> = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            actions[0].kind,
+            LoginActionKind::WaitFor {
+                pattern: "\\$ $".into(),
+                text: "sudo -i\r".into(),
+            }
+        );
+        assert_eq!(actions[0].timeout_secs, Some(30));
+        assert_eq!(actions[1].kind, LoginActionKind::Delay { millis: 500 });
+        assert_eq!(actions[1].timeout_secs, None);
+
+        let mut profile = ConnectionProfile::new_ssh("Test", "host", 22, "user");
+        let json = serde_json::to_value(&profile).unwrap();
+        assert!(json.get("login_actions").is_none());
+        profile.login_actions = actions;
+        let json = serde_json::to_string(&profile).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ConnectionProfile>(&json).unwrap(),
+            profile
+        );
+    }
+
     #[test]
     fn kube_auth_variants_round_trip() {
         let variants = vec![
@@ -0,0 +1,183 @@
+//! asciicast v2 lines.
+//!
+//! A recording is newline-delimited JSON: one header object, then one
+//! `[time, code, data]` array per event, `time` in seconds since the start
+//! of the file. See <https://docs.asciinema.org/manual/asciicast/v2/>.
+
+use serde::{Deserialize, Serialize};
+
+use super::RecordingError;
+
+/// The first line of an asciicast v2 file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub version: u8,
+    pub width: u16,
+    pub height: u16,
+    /// Unix time the recording started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+impl Header {
+    pub fn new(width: u16, height: u16) -> Self {
+        Header {
+            version: 2,
+            width,
+            height,
+            timestamp: None,
+            title: None,
+        }
+    }
+}
+
+/// What an event line records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    /// Terminal output (`"o"`).
+    Output(String),
+    /// Keyboard input (`"i"`), only present when input capture was on.
+    Input(String),
+    /// The terminal was resized (`"r"`).
+    Resize { cols: u16, rows: u16 },
+    /// A named marker (`"m"`).
+    Marker(String),
+}
+
+/// Serialise one event line, without the trailing newline.
+pub(crate) fn encode_event(time: f64, kind: &EventKind) -> String {
+    let (code, data) = match kind {
+        EventKind::Output(data) => ("o", data.clone()),
+        EventKind::Input(data) => ("i", data.clone()),
+        EventKind::Resize { cols, rows } => ("r", format!("{cols}x{rows}")),
+        EventKind::Marker(label) => ("m", label.clone()),
+    };
+    // Microsecond precision, as asciinema writes it.
+    let time = (time * 1e6).round() / 1e6;
+    serde_json::Value::from(vec![
+        serde_json::Value::from(time),
+        serde_json::Value::from(code),
+        serde_json::Value::from(data),
+    ])
+    .to_string()
+}
+
+/// Parse one event line. Unknown event codes are skipped (`Ok(None)`), as
+/// the format allows.
+pub(crate) fn decode_event(line: &str) -> Result<Option<(f64, EventKind)>, RecordingError> {
+    let invalid = |what: &str| RecordingError::Format(format!("{what}: {line}"));
+    let (time, code, data): (f64, String, String) =
+        serde_json::from_str(line).map_err(|_| invalid("malformed event"))?;
+    if !time.is_finite() || time < 0.0 {
+        return Err(invalid("bad event time"));
+    }
+    let kind = match code.as_str() {
+        "o" => EventKind::Output(data),
+        "i" => EventKind::Input(data),
+        "m" => EventKind::Marker(data),
+        "r" => {
+            let (cols, rows) = data
+                .split_once('x')
+                .and_then(|(c, r)| Some((c.parse().ok()?, r.parse().ok()?)))
+                .ok_or_else(|| invalid("bad resize"))?;
+            EventKind::Resize { cols, rows }
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some((time, kind)))
+}
+
+/// Turns a byte stream into text without splitting UTF-8 sequences across
+/// events: an incomplete sequence at the end of a chunk waits for the next
+/// one. Invalid bytes become U+FFFD.
+#[derive(Debug, Default)]
+pub(crate) struct Utf8Stream {
+    carry: Vec<u8>,
+}
+
+impl Utf8Stream {
+    pub fn push(&mut self, data: &[u8]) -> String {
+        let mut bytes = std::mem::take(&mut self.carry);
+        bytes.extend_from_slice(data);
+
+        let mut out = String::with_capacity(bytes.len());
+        let mut rest = bytes.as_slice();
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    out.push_str(valid);
+                    return out;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    out.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                    match e.error_len() {
+                        Some(len) => {
+                            out.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[len..];
+                        }
+                        None => {
+                            self.carry = after.to_vec();
+                            return out;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_round_trip() {
+        let kinds = [
+            EventKind::Output("ls\r\n\u{1b}[0m".into()),
+            EventKind::Input("q".into()),
+            EventKind::Resize {
+                cols: 132,
+                rows: 43,
+            },
+            EventKind::Marker("deploy".into()),
+        ];
+        for kind in kinds {
+            let line = encode_event(1.5, &kind);
+            assert_eq!(decode_event(&line).unwrap(), Some((1.5, kind)));
+        }
+        assert_eq!(
+            encode_event(0.1234567, &EventKind::Resize { cols: 80, rows: 24 }),
+            r#"[0.123457,"r","80x24"]"#
+        );
+    }
+
+    #[test]
+    fn unknown_codes_are_skipped_and_garbage_rejected() {
+        assert_eq!(decode_event(r#"[1.0,"x","?"]"#).unwrap(), None);
+        assert!(decode_event(r#"{"version":2}"#).is_err());
+        assert!(decode_event(r#"[-1.0,"o","a"]"#).is_err());
+        assert!(decode_event(r#"[1.0,"r","wide"]"#).is_err());
+    }
+
+    #[test]
+    fn header_omits_unset_fields() {
+        let json = serde_json::to_string(&Header::new(80, 24)).unwrap();
+        assert_eq!(json, r#"{"version":2,"width":80,"height":24}"#);
+    }
+
+    #[test]
+    fn utf8_sequences_survive_chunk_boundaries() {
+        let mut stream = Utf8Stream::default();
+        let bytes = "héllo".as_bytes();
+        assert_eq!(stream.push(&bytes[..2]), "h");
+        assert_eq!(stream.push(&bytes[2..]), "éllo");
+        assert_eq!(stream.push(&[b'a', 0xff, b'b']), "a\u{fffd}b");
+    }
+}
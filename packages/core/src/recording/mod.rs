@@ -0,0 +1,85 @@
+//! Terminal session recording in asciicast v2 format.
+//!
+//! A [`Recorder`] attached to a shell with
+//! [`SshAdapter::record`](crate::connection::ssh::SshAdapter::record)
+//! receives everything the shell prints, every resize and, when
+//! [`RecordingOptions::capture_input`] is set, every keystroke, and writes
+//! them as [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/)
+//! files that `asciinema play` understands. Files rotate by size or age
+//! ([`Rotation`]); each one is a complete recording with its own header.
+//!
+//! With [`RecordingOptions::key`] set, recordings are encrypted with the vault
+//! master key before they reach the disk (see [`sealed`]) and are written as
+//! `.cast.enc`. [`Replay`] reads either kind back as timed [`Frame`]s.
+
+pub mod asciicast;
+mod recorder;
+mod replay;
+pub mod sealed;
+
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::crypto::CipherError;
+use crate::profile::manager::ProfileManager;
+
+pub use asciicast::{EventKind, Header};
+pub use recorder::{Recorder, RecordingOptions, Rotation};
+pub use replay::{Frame, Replay};
+
+// ---------------------------------------------------------------------------
+// Errors
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    #[error("recording I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("invalid recording: {0}")]
+    Format(String),
+
+    #[error("recording is encrypted and no key was given")]
+    KeyRequired,
+
+    #[error("encryption error: {0}")]
+    Cipher(#[from] CipherError),
+
+    #[error("recording ends before its final chunk (truncated or still being written)")]
+    Truncated,
+
+    #[error("recorder has stopped")]
+    Stopped,
+}
+
+// ---------------------------------------------------------------------------
+// RecordingKey
+// ---------------------------------------------------------------------------
+
+/// The key recordings are encrypted with: the vault master key, so only
+/// someone who can unlock the vault can replay them.
+#[derive(Clone)]
+pub struct RecordingKey(Zeroizing<[u8; 32]>);
+
+impl RecordingKey {
+    pub fn from_vault(manager: &ProfileManager) -> Self {
+        RecordingKey(Zeroizing::new(*manager.master_key()))
+    }
+
+    fn bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for RecordingKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RecordingKey([redacted])")
+    }
+}
+
+#[cfg(test)]
+impl RecordingKey {
+    pub(crate) fn for_tests(byte: u8) -> Self {
+        RecordingKey(Zeroizing::new([byte; 32]))
+    }
+}
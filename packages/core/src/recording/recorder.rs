@@ -0,0 +1,531 @@
+//! The recording side: [`Recorder`] and the thread that writes its files.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+use tracing::warn;
+
+use super::asciicast::{encode_event, EventKind, Header, Utf8Stream};
+use super::sealed::{Sealer, CHUNK_SIZE};
+use super::{RecordingError, RecordingKey};
+
+/// How often buffered events are written out (and, when encrypting, sealed)
+/// while the session is quiet.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Events queued for the writer thread. A session that gets this far ahead
+/// of the disk waits for it.
+const QUEUE_SIZE: usize = 256;
+
+// ---------------------------------------------------------------------------
+// Options
+// ---------------------------------------------------------------------------
+
+/// When to close the current file and start the next.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    /// Start a new file once this many bytes of events have been written.
+    pub max_bytes: Option<u64>,
+    /// Start a new file once the current one covers this much time.
+    pub max_duration: Option<Duration>,
+}
+
+/// Where and how to record.
+#[derive(Debug, Clone)]
+pub struct RecordingOptions {
+    /// Directory for the recording files; created if missing.
+    pub dir: PathBuf,
+    /// File name stem. Files are `<stem>-001.cast`, `<stem>-002.cast`, …
+    pub stem: String,
+    /// Terminal size for the first header, until a resize says otherwise.
+    pub cols: u16,
+    pub rows: u16,
+    pub title: Option<String>,
+    /// Record keystrokes too. Off by default: input includes passwords
+    /// typed at prompts.
+    pub capture_input: bool,
+    pub rotation: Rotation,
+    /// Encrypt the files; see [`sealed`](super::sealed).
+    pub key: Option<RecordingKey>,
+}
+
+impl RecordingOptions {
+    pub fn new(dir: impl Into<PathBuf>, stem: impl Into<String>) -> Self {
+        RecordingOptions {
+            dir: dir.into(),
+            stem: stem.into(),
+            cols: 80,
+            rows: 24,
+            title: None,
+            capture_input: false,
+            rotation: Rotation::default(),
+            key: None,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Recorder
+// ---------------------------------------------------------------------------
+
+/// An event and when it happened, or the request to stop.
+enum Record {
+    Output(Instant, Vec<u8>),
+    Input(Instant, Vec<u8>),
+    Resize { at: Instant, cols: u16, rows: u16 },
+    Marker(Instant, String),
+    Finish(oneshot::Sender<Result<Vec<PathBuf>, RecordingError>>),
+}
+
+/// Handle to a running recording.
+///
+/// Clones feed the same recording. Events are timed when they are recorded
+/// and handed to a writer thread, which the session only waits for when the
+/// disk falls far behind; write errors surface from [`Recorder::finish`].
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    tx: mpsc::SyncSender<Record>,
+    capture_input: bool,
+}
+
+impl Recorder {
+    /// Create the first file and start recording.
+    pub fn start(options: RecordingOptions) -> Result<Self, RecordingError> {
+        std::fs::create_dir_all(&options.dir)?;
+        let capture_input = options.capture_input;
+        let mut writer = Writer::new(options);
+        writer.open_segment(Instant::now())?;
+
+        let (tx, rx) = mpsc::sync_channel(QUEUE_SIZE);
+        std::thread::Builder::new()
+            .name("session-recorder".to_owned())
+            .spawn(move || writer.run(rx))?;
+        Ok(Recorder { tx, capture_input })
+    }
+
+    /// Record terminal output.
+    pub fn output(&self, data: &[u8]) {
+        self.send(Record::Output(Instant::now(), data.to_vec()));
+    }
+
+    /// Record keystrokes, if input capture is on.
+    pub fn input(&self, data: &[u8]) {
+        if self.capture_input {
+            self.send(Record::Input(Instant::now(), data.to_vec()));
+        }
+    }
+
+    /// Record a terminal resize. Repeats of the current size are ignored.
+    pub fn resize(&self, cols: u16, rows: u16) {
+        self.send(Record::Resize {
+            at: Instant::now(),
+            cols,
+            rows,
+        });
+    }
+
+    /// Add a named marker, e.g. where a reconnect happened.
+    pub fn marker(&self, label: impl Into<String>) {
+        self.send(Record::Marker(Instant::now(), label.into()));
+    }
+
+    /// Close the recording and return its files, in order. Events sent from
+    /// other clones afterwards are dropped.
+    pub async fn finish(self) -> Result<Vec<PathBuf>, RecordingError> {
+        let (reply, done) = oneshot::channel();
+        self.tx
+            .send(Record::Finish(reply))
+            .map_err(|_| RecordingError::Stopped)?;
+        done.await.map_err(|_| RecordingError::Stopped)?
+    }
+
+    fn send(&self, record: Record) {
+        // The writer only goes away after `finish`; later events are moot.
+        let _ = self.tx.send(record);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Writer thread
+// ---------------------------------------------------------------------------
+
+/// One recording file.
+struct Segment {
+    out: BufWriter<File>,
+    sealer: Option<Sealer>,
+    /// Lines waiting to be sealed, when encrypting.
+    pending: Vec<u8>,
+    started: Instant,
+    bytes: u64,
+}
+
+impl Segment {
+    fn write_line(&mut self, line: &str) -> Result<(), RecordingError> {
+        self.bytes += line.len() as u64 + 1;
+        match &mut self.sealer {
+            Some(sealer) => {
+                self.pending.extend_from_slice(line.as_bytes());
+                self.pending.push(b'\n');
+                if self.pending.len() >= CHUNK_SIZE {
+                    let chunk = sealer.seal(&self.pending, false)?;
+                    self.pending.clear();
+                    self.out.write_all(&chunk)?;
+                }
+            }
+            None => {
+                self.out.write_all(line.as_bytes())?;
+                self.out.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), RecordingError> {
+        if let Some(sealer) = &mut self.sealer {
+            if !self.pending.is_empty() {
+                let chunk = sealer.seal(&self.pending, false)?;
+                self.pending.clear();
+                self.out.write_all(&chunk)?;
+            }
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), RecordingError> {
+        if let Some(sealer) = &mut self.sealer {
+            let chunk = sealer.seal(&self.pending, true)?;
+            self.out.write_all(&chunk)?;
+        }
+        self.out.flush()?;
+        self.out.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+struct Writer {
+    options: RecordingOptions,
+    segment: Option<Segment>,
+    files: Vec<PathBuf>,
+    cols: u16,
+    rows: u16,
+    output: Utf8Stream,
+    input: Utf8Stream,
+    /// The first write error; recording stops there.
+    error: Option<RecordingError>,
+}
+
+impl Writer {
+    fn new(options: RecordingOptions) -> Self {
+        Writer {
+            cols: options.cols,
+            rows: options.rows,
+            options,
+            segment: None,
+            files: Vec::new(),
+            output: Utf8Stream::default(),
+            input: Utf8Stream::default(),
+            error: None,
+        }
+    }
+
+    fn run(mut self, rx: mpsc::Receiver<Record>) {
+        loop {
+            match rx.recv_timeout(FLUSH_INTERVAL) {
+                Ok(Record::Finish(reply)) => {
+                    let _ = reply.send(self.close());
+                    return;
+                }
+                Ok(record) => {
+                    let result = self.record(record);
+                    self.fail_on(result);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let result = self.segment.as_mut().map_or(Ok(()), Segment::flush);
+                    self.fail_on(result);
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    if let Err(e) = self.close() {
+                        warn!("session recording failed: {e}");
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    fn fail_on(&mut self, result: Result<(), RecordingError>) {
+        if let Err(e) = result {
+            if self.error.is_none() {
+                warn!("session recording stopped: {e}");
+                self.segment = None;
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn record(&mut self, record: Record) -> Result<(), RecordingError> {
+        if self.error.is_some() {
+            return Ok(());
+        }
+        let (at, kind) = match record {
+            Record::Output(at, data) => (at, EventKind::Output(self.output.push(&data))),
+            Record::Input(at, data) => (at, EventKind::Input(self.input.push(&data))),
+            Record::Resize { at, cols, rows } => {
+                if (cols, rows) == (self.cols, self.rows) {
+                    return Ok(());
+                }
+                (self.cols, self.rows) = (cols, rows);
+                (at, EventKind::Resize { cols, rows })
+            }
+            Record::Marker(at, label) => (at, EventKind::Marker(label)),
+            Record::Finish(_) => return Ok(()),
+        };
+        if matches!(&kind, EventKind::Output(s) | EventKind::Input(s) if s.is_empty()) {
+            return Ok(());
+        }
+
+        if self.segment_is_full(at) {
+            if let Some(segment) = self.segment.take() {
+                segment.finish()?;
+            }
+            self.open_segment(at)?;
+        }
+        let Some(segment) = self.segment.as_mut() else {
+            return Ok(());
+        };
+        let offset = at.saturating_duration_since(segment.started);
+        let line = encode_event(offset.as_secs_f64(), &kind);
+        segment.write_line(&line)
+    }
+
+    /// Whether an event at `at` belongs in a new file.
+    fn segment_is_full(&self, at: Instant) -> bool {
+        let Some(segment) = &self.segment else {
+            return false;
+        };
+        let rotation = self.options.rotation;
+        rotation.max_bytes.is_some_and(|max| segment.bytes >= max)
+            || rotation
+                .max_duration
+                .is_some_and(|max| at.saturating_duration_since(segment.started) >= max)
+    }
+
+    /// Create the next file, starting at `started`, and write its header.
+    fn open_segment(&mut self, started: Instant) -> Result<(), RecordingError> {
+        let extension = if self.options.key.is_some() {
+            "cast.enc"
+        } else {
+            "cast"
+        };
+        let name = format!(
+            "{}-{:03}.{extension}",
+            self.options.stem,
+            self.files.len() + 1
+        );
+        let path = self.options.dir.join(name);
+        let out = BufWriter::new(create_private(&path)?);
+
+        let (sealer, container) = match &self.options.key {
+            Some(key) => {
+                let (sealer, container) = Sealer::new(key.clone())?;
+                (Some(sealer), container)
+            }
+            None => (None, Vec::new()),
+        };
+        let mut segment = Segment {
+            out,
+            sealer,
+            pending: Vec::new(),
+            started,
+            bytes: 0,
+        };
+        segment.out.write_all(&container)?;
+
+        let header = Header {
+            timestamp: Some(chrono::Utc::now().timestamp()),
+            title: self.options.title.clone(),
+            ..Header::new(self.cols, self.rows)
+        };
+        let header =
+            serde_json::to_string(&header).map_err(|e| RecordingError::Format(e.to_string()))?;
+        segment.write_line(&header)?;
+
+        self.files.push(path);
+        self.segment = Some(segment);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<Vec<PathBuf>, RecordingError> {
+        if let Some(segment) = self.segment.take() {
+            let result = segment.finish();
+            self.fail_on(result);
+        }
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(std::mem::take(&mut self.files)),
+        }
+    }
+}
+
+/// Create a new file readable only by the user; never overwrite one.
+fn create_private(path: &std::path::Path) -> std::io::Result<File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{Frame, Replay};
+
+    fn frames(path: &std::path::Path, key: Option<&RecordingKey>) -> Vec<EventKind> {
+        Replay::open(path, key)
+            .unwrap()
+            .map(|frame| frame.map(|Frame { kind, .. }| kind).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn records_output_resizes_and_optional_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = Recorder::start(RecordingOptions::new(dir.path(), "plain")).unwrap();
+        recorder.output("h\u{e9}".as_bytes().split_at(2).0);
+        recorder.output(&"h\u{e9}".as_bytes()[2..]);
+        recorder.input(b"secret");
+        recorder.resize(80, 24);
+        recorder.resize(120, 40);
+        let files = recorder.finish().await.unwrap();
+
+        assert_eq!(files, [dir.path().join("plain-001.cast")]);
+        let text = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(text.starts_with(r#"{"version":2,"width":80,"height":24,"timestamp":"#));
+        assert_eq!(
+            frames(&files[0], None),
+            [
+                EventKind::Output("h".into()),
+                EventKind::Output("\u{e9}".into()),
+                EventKind::Resize {
+                    cols: 120,
+                    rows: 40
+                },
+            ]
+        );
+
+        let options = RecordingOptions {
+            capture_input: true,
+            ..RecordingOptions::new(dir.path(), "keys")
+        };
+        let recorder = Recorder::start(options).unwrap();
+        recorder.input(b"ls\r");
+        let files = recorder.finish().await.unwrap();
+        assert_eq!(frames(&files[0], None), [EventKind::Input("ls\r".into())]);
+    }
+
+    #[tokio::test]
+    async fn rotates_by_size_with_a_fresh_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = RecordingOptions {
+            rotation: Rotation {
+                max_bytes: Some(100),
+                max_duration: None,
+            },
+            ..RecordingOptions::new(dir.path(), "rot")
+        };
+        let recorder = Recorder::start(options).unwrap();
+        recorder.resize(100, 30);
+        recorder.output(&[b'x'; 100]);
+        recorder.output(b"next");
+        let files = recorder.finish().await.unwrap();
+
+        assert_eq!(files.len(), 2);
+        let second = Replay::open(&files[1], None).unwrap();
+        assert_eq!((second.header().width, second.header().height), (100, 30));
+        assert_eq!(frames(&files[1], None), [EventKind::Output("next".into())]);
+    }
+
+    #[tokio::test]
+    async fn encrypted_recordings_need_the_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = RecordingKey::for_tests(1);
+        let options = RecordingOptions {
+            key: Some(key.clone()),
+            ..RecordingOptions::new(dir.path(), "enc")
+        };
+        let recorder = Recorder::start(options).unwrap();
+        recorder.output(b"top secret output");
+        let files = recorder.finish().await.unwrap();
+
+        assert_eq!(files, [dir.path().join("enc-001.cast.enc")]);
+        let raw = std::fs::read(&files[0]).unwrap();
+        assert!(!raw.windows(10).any(|w| w == b"top secret"));
+        assert_eq!(
+            frames(&files[0], Some(&key)),
+            [EventKind::Output("top secret output".into())]
+        );
+        assert!(matches!(
+            Replay::open(&files[0], None),
+            Err(RecordingError::KeyRequired)
+        ));
+    }
+
+    #[tokio::test]
+    async fn events_are_timed_when_recorded_not_when_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = RecordingOptions {
+            rotation: Rotation {
+                max_bytes: None,
+                max_duration: Some(Duration::from_secs(10)),
+            },
+            ..RecordingOptions::new(dir.path(), "timed")
+        };
+        let mut writer = Writer::new(options);
+        let start = Instant::now();
+        writer.open_segment(start).unwrap();
+        let at = |secs| start + Duration::from_secs(secs);
+        for (secs, data) in [(2, "a"), (3, "b"), (12, "c")] {
+            writer
+                .record(Record::Output(at(secs), data.as_bytes().to_vec()))
+                .unwrap();
+        }
+        let files = writer.close().unwrap();
+
+        let times = |path| {
+            Replay::open(path, None)
+                .unwrap()
+                .map(|frame| frame.unwrap().time)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            times(&files[0]),
+            [Duration::from_secs(2), Duration::from_secs(3)]
+        );
+        assert_eq!(times(&files[1]), [Duration::ZERO]);
+    }
+
+    #[tokio::test]
+    async fn existing_recordings_are_not_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("dup-001.cast"), "keep").unwrap();
+        assert!(Recorder::start(RecordingOptions::new(dir.path(), "dup")).is_err());
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("dup-001.cast")).unwrap(),
+            "keep"
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Reading recordings back.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::time::Duration;
+
+use super::asciicast::{decode_event, EventKind, Header};
+use super::sealed::{Opener, MAGIC};
+use super::{RecordingError, RecordingKey};
+
+/// One recorded event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Offset from the start of the file.
+    pub time: Duration,
+    pub kind: EventKind,
+}
+
+/// Where lines come from: a plain file, or decrypted chunks.
+enum Lines {
+    Plain(BufReader<Box<dyn Read + Send>>),
+    Sealed {
+        opener: Opener<Box<dyn Read + Send>>,
+        pending: Vec<u8>,
+        done: bool,
+    },
+}
+
+impl Lines {
+    fn next_line(&mut self) -> Result<Option<String>, RecordingError> {
+        match self {
+            Lines::Plain(reader) => {
+                let mut line = String::new();
+                if reader.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                Ok(Some(line))
+            }
+            Lines::Sealed {
+                opener,
+                pending,
+                done,
+            } => loop {
+                if let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = pending.drain(..=end).collect();
+                    return utf8_line(line).map(Some);
+                }
+                if *done {
+                    if pending.is_empty() {
+                        return Ok(None);
+                    }
+                    return utf8_line(std::mem::take(pending)).map(Some);
+                }
+                match opener.next_chunk()? {
+                    Some(chunk) => pending.extend_from_slice(&chunk),
+                    None => *done = true,
+                }
+            },
+        }
+    }
+}
+
+fn utf8_line(line: Vec<u8>) -> Result<String, RecordingError> {
+    String::from_utf8(line).map_err(|_| RecordingError::Format("line is not UTF-8".to_owned()))
+}
+
+/// Reads one recording file as a sequence of [`Frame`]s, in order.
+///
+/// Plain and encrypted files are told apart by their first bytes. Frames
+/// carry their offset from the start of the file; sleeping for the
+/// difference between consecutive frames plays the session back at its
+/// original speed.
+pub struct Replay {
+    header: Header,
+    lines: Lines,
+    /// Set after an error, which ends the iteration.
+    failed: bool,
+}
+
+impl Replay {
+    /// Open `path`. `key` is needed for encrypted recordings only.
+    pub fn open(path: &Path, key: Option<&RecordingKey>) -> Result<Self, RecordingError> {
+        Self::from_reader(File::open(path)?, key)
+    }
+
+    pub fn from_reader(
+        reader: impl Read + Send + 'static,
+        key: Option<&RecordingKey>,
+    ) -> Result<Self, RecordingError> {
+        let mut reader: Box<dyn Read + Send> = Box::new(reader);
+        let mut magic = Vec::with_capacity(MAGIC.len());
+        (&mut reader)
+            .take(MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+
+        let mut lines = if magic == MAGIC {
+            let key = key.ok_or(RecordingError::KeyRequired)?;
+            Lines::Sealed {
+                opener: Opener::new(reader, key.clone())?,
+                pending: Vec::new(),
+                done: false,
+            }
+        } else {
+            let rest: Box<dyn Read + Send> = Box::new(std::io::Cursor::new(magic).chain(reader));
+            Lines::Plain(BufReader::new(rest))
+        };
+
+        let header = lines
+            .next_line()?
+            .ok_or_else(|| RecordingError::Format("empty recording".to_owned()))?;
+        let header: Header = serde_json::from_str(&header)
+            .map_err(|e| RecordingError::Format(format!("bad header: {e}")))?;
+        if header.version != 2 {
+            return Err(RecordingError::Format(format!(
+                "unsupported asciicast version {}",
+                header.version
+            )));
+        }
+        Ok(Replay {
+            header,
+            lines,
+            failed: false,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    fn next_frame(&mut self) -> Result<Option<Frame>, RecordingError> {
+        while let Some(line) = self.lines.next_line()? {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some((time, kind)) = decode_event(line)? {
+                return Ok(Some(Frame {
+                    time: Duration::from_secs_f64(time),
+                    kind,
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for Replay {
+    type Item = Result<Frame, RecordingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let next = self.next_frame().transpose();
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::sealed::Sealer;
+
+    const CAST: &str = concat!(
+        r#"{"version":2,"width":80,"height":24,"timestamp":1700000000}"#,
+        "\n",
+        r#"[0.5,"o","hello\r\n"]"#,
+        "\n\n",
+        r#"[1.25,"r","100x30"]"#,
+        "\n",
+        r#"[2.0,"x","ignored"]"#,
+        "\n",
+        r#"[3.0,"m","end"]"#,
+    );
+
+    #[test]
+    fn plain_recordings_yield_timed_frames() {
+        let replay = Replay::from_reader(CAST.as_bytes(), None).unwrap();
+        assert_eq!(replay.header().timestamp, Some(1_700_000_000));
+
+        let frames: Vec<Frame> = replay.map(Result::unwrap).collect();
+        assert_eq!(
+            frames,
+            [
+                Frame {
+                    time: Duration::from_millis(500),
+                    kind: EventKind::Output("hello\r\n".into()),
+                },
+                Frame {
+                    time: Duration::from_millis(1250),
+                    kind: EventKind::Resize {
+                        cols: 100,
+                        rows: 30
+                    },
+                },
+                Frame {
+                    time: Duration::from_secs(3),
+                    kind: EventKind::Marker("end".into()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn lines_may_span_sealed_chunks() {
+        let key = RecordingKey::for_tests(3);
+        let (mut sealer, mut bytes) = Sealer::new(key.clone()).unwrap();
+        let (a, b) = CAST.as_bytes().split_at(70);
+        bytes.extend(sealer.seal(a, false).unwrap());
+        bytes.extend(sealer.seal(b, true).unwrap());
+
+        let frames: Vec<Frame> = Replay::from_reader(std::io::Cursor::new(bytes), Some(&key))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(frames.len(), 3);
+    }
+
+    #[test]
+    fn truncated_recordings_end_with_one_error() {
+        let key = RecordingKey::for_tests(3);
+        let (mut sealer, mut bytes) = Sealer::new(key.clone()).unwrap();
+        bytes.extend(sealer.seal(format!("{CAST}\n").as_bytes(), false).unwrap());
+
+        let mut replay = Replay::from_reader(std::io::Cursor::new(bytes), Some(&key)).unwrap();
+        assert_eq!(replay.by_ref().take(3).filter(Result::is_ok).count(), 3);
+        assert!(matches!(
+            replay.next(),
+            Some(Err(RecordingError::Truncated))
+        ));
+        assert!(replay.next().is_none());
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let v1 = r#"{"version":1,"width":80,"height":24}"#;
+        assert!(matches!(
+            Replay::from_reader(v1.as_bytes(), None),
+            Err(RecordingError::Format(_))
+        ));
+    }
+}
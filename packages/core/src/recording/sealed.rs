@@ -0,0 +1,232 @@
+//! Encrypted recording container.
+//!
+//! An encrypted recording is [`MAGIC`], a random file ID, then a sequence of
+//! chunks. Each chunk is a big-endian `u32` length followed by a flag byte
+//! (`1` on the last chunk), the AES-256-GCM nonce, the ciphertext and the
+//! tag. A chunk seals a run of whole asciicast lines; its AAD binds the file
+//! ID, the chunk's position and the flag, so chunks cannot be reordered,
+//! moved between recordings or cut off the end unnoticed.
+//!
+//! Chunks are sealed as the recording goes, so a recorder that dies leaves
+//! every chunk written so far readable; [`Replay`](super::Replay) then
+//! reports [`RecordingError::Truncated`] at the end.
+
+use std::io::Read;
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use super::{RecordingError, RecordingKey};
+use crate::crypto::{decrypt, encrypt, CipherError, EncryptedEnvelope, NONCE_LEN, TAG_LEN};
+
+/// First bytes of every encrypted recording.
+pub const MAGIC: &[u8; 8] = b"TSCAST\x00\x01";
+
+const ID_LEN: usize = 16;
+
+/// Plaintext collected before a chunk is sealed.
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Largest chunk a reader accepts. One output event can exceed
+/// [`CHUNK_SIZE`] once JSON-escaped.
+const MAX_CHUNK: usize = 16 * 1024 * 1024;
+
+fn aad(id: &[u8; ID_LEN], index: u64, last: bool) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(MAGIC.len() + ID_LEN + 9);
+    aad.extend_from_slice(MAGIC);
+    aad.extend_from_slice(id);
+    aad.extend_from_slice(&index.to_be_bytes());
+    aad.push(u8::from(last));
+    aad
+}
+
+/// Seals the chunks of one file.
+pub(crate) struct Sealer {
+    key: RecordingKey,
+    id: [u8; ID_LEN],
+    index: u64,
+}
+
+impl Sealer {
+    /// A sealer for a new file, and the file header to write first.
+    pub fn new(key: RecordingKey) -> Result<(Self, Vec<u8>), RecordingError> {
+        let mut id = [0u8; ID_LEN];
+        SystemRandom::new()
+            .fill(&mut id)
+            .map_err(|_| CipherError::Rng)?;
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&id);
+        Ok((Sealer { key, id, index: 0 }, header))
+    }
+
+    /// Encrypt `plaintext` as the next chunk, framed for writing.
+    pub fn seal(&mut self, plaintext: &[u8], last: bool) -> Result<Vec<u8>, RecordingError> {
+        let envelope = encrypt(
+            self.key.bytes(),
+            plaintext,
+            &aad(&self.id, self.index, last),
+        )?;
+        self.index += 1;
+
+        let len = 1 + NONCE_LEN + envelope.ciphertext.len() + TAG_LEN;
+        let mut out = Vec::with_capacity(4 + len);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+        out.push(u8::from(last));
+        out.extend_from_slice(&envelope.nonce);
+        out.extend_from_slice(&envelope.ciphertext);
+        out.extend_from_slice(&envelope.tag);
+        Ok(out)
+    }
+}
+
+/// Reads and decrypts the chunks of one file.
+pub(crate) struct Opener<R> {
+    reader: R,
+    key: RecordingKey,
+    id: [u8; ID_LEN],
+    index: u64,
+    finished: bool,
+}
+
+impl<R: Read> Opener<R> {
+    /// Start reading after [`MAGIC`], which the caller has consumed.
+    pub fn new(mut reader: R, key: RecordingKey) -> Result<Self, RecordingError> {
+        let mut id = [0u8; ID_LEN];
+        reader
+            .read_exact(&mut id)
+            .map_err(|_| RecordingError::Truncated)?;
+        Ok(Opener {
+            reader,
+            key,
+            id,
+            index: 0,
+            finished: false,
+        })
+    }
+
+    /// The next chunk's plaintext; `None` after the last chunk.
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, RecordingError> {
+        let mut len = [0u8; 4];
+        match read_full(&mut self.reader, &mut len)? {
+            0 if self.finished => return Ok(None),
+            0 => return Err(RecordingError::Truncated),
+            4 => {}
+            _ => return Err(RecordingError::Truncated),
+        }
+        if self.finished {
+            return Err(RecordingError::Format(
+                "data after the final chunk".to_owned(),
+            ));
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if !(1 + NONCE_LEN + TAG_LEN..=MAX_CHUNK).contains(&len) {
+            return Err(RecordingError::Format(format!("bad chunk length {len}")));
+        }
+        let mut frame = vec![0u8; len];
+        self.reader
+            .read_exact(&mut frame)
+            .map_err(|_| RecordingError::Truncated)?;
+
+        let last = match frame[0] {
+            0 => false,
+            1 => true,
+            flag => return Err(RecordingError::Format(format!("bad chunk flag {flag}"))),
+        };
+        let (nonce, rest) = frame[1..].split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        let mut envelope = EncryptedEnvelope {
+            nonce: [0u8; NONCE_LEN],
+            ciphertext: ciphertext.to_vec(),
+            tag: [0u8; TAG_LEN],
+        };
+        envelope.nonce.copy_from_slice(nonce);
+        envelope.tag.copy_from_slice(tag);
+
+        let plaintext = decrypt(
+            self.key.bytes(),
+            &envelope,
+            &aad(&self.id, self.index, last),
+        )?;
+        self.index += 1;
+        self.finished = last;
+        Ok(Some(plaintext.to_vec()))
+    }
+}
+
+/// Fill `buf` unless the stream ends first; returns the bytes read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealed(chunks: &[(&[u8], bool)]) -> Vec<u8> {
+        let (mut sealer, mut out) = Sealer::new(RecordingKey::for_tests(7)).unwrap();
+        for (plaintext, last) in chunks {
+            out.extend(sealer.seal(plaintext, *last).unwrap());
+        }
+        out
+    }
+
+    fn open(bytes: &[u8], key: u8) -> Result<Opener<&[u8]>, RecordingError> {
+        assert_eq!(&bytes[..MAGIC.len()], MAGIC);
+        Opener::new(&bytes[MAGIC.len()..], RecordingKey::for_tests(key))
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let bytes = sealed(&[(b"one\n", false), (b"two\n", true)]);
+        let mut opener = open(&bytes, 7).unwrap();
+        assert_eq!(opener.next_chunk().unwrap().unwrap(), b"one\n");
+        assert_eq!(opener.next_chunk().unwrap().unwrap(), b"two\n");
+        assert!(opener.next_chunk().unwrap().is_none());
+    }
+
+    #[test]
+    fn wrong_key_fails() {
+        let bytes = sealed(&[(b"one\n", true)]);
+        let mut opener = open(&bytes, 8).unwrap();
+        assert!(matches!(
+            opener.next_chunk(),
+            Err(RecordingError::Cipher(CipherError::Decryption))
+        ));
+    }
+
+    #[test]
+    fn missing_final_chunk_is_reported() {
+        let bytes = sealed(&[(b"one\n", false)]);
+        let mut opener = open(&bytes, 7).unwrap();
+        assert!(opener.next_chunk().unwrap().is_some());
+        assert!(matches!(
+            opener.next_chunk(),
+            Err(RecordingError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn reordered_chunks_fail() {
+        let (mut sealer, header) = Sealer::new(RecordingKey::for_tests(7)).unwrap();
+        let first = sealer.seal(b"one\n", false).unwrap();
+        let second = sealer.seal(b"two\n", true).unwrap();
+        let mut bytes = header;
+        bytes.extend(second);
+        bytes.extend(first);
+
+        let mut opener = open(&bytes, 7).unwrap();
+        assert!(opener.next_chunk().is_err());
+    }
+}
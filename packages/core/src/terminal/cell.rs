@@ -0,0 +1,243 @@
+//! Cells, styles and lines of the terminal grid.
+
+use serde::{Deserialize, Serialize};
+
+/// A foreground or background colour.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Color {
+    /// The terminal's default colour.
+    #[default]
+    Default,
+    /// One of the 256 palette colours (0–15 are the ANSI colours).
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// Text attributes of a cell.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Style {
+    #[serde(default, skip_serializing_if = "is_default_color")]
+    pub fg: Color,
+    #[serde(default, skip_serializing_if = "is_default_color")]
+    pub bg: Color,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub bold: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub dim: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub italic: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub underline: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub blink: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub inverse: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub hidden: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub strikethrough: bool,
+}
+
+fn is_default_color(color: &Color) -> bool {
+    *color == Color::Default
+}
+
+fn is_false(flag: &bool) -> bool {
+    !*flag
+}
+
+impl Style {
+    pub fn is_default(&self) -> bool {
+        *self == Style::default()
+    }
+
+    /// The SGR sequence that selects this style from any other.
+    pub fn sgr(&self) -> String {
+        let mut codes = vec!["0".to_owned()];
+        let flags = [
+            (self.bold, "1"),
+            (self.dim, "2"),
+            (self.italic, "3"),
+            (self.underline, "4"),
+            (self.blink, "5"),
+            (self.inverse, "7"),
+            (self.hidden, "8"),
+            (self.strikethrough, "9"),
+        ];
+        codes.extend(
+            flags
+                .iter()
+                .filter(|(on, _)| *on)
+                .map(|(_, c)| (*c).to_owned()),
+        );
+        push_color(&mut codes, self.fg, 30, 90, 38);
+        push_color(&mut codes, self.bg, 40, 100, 48);
+        format!("\x1b[{}m", codes.join(";"))
+    }
+}
+
+fn push_color(codes: &mut Vec<String>, color: Color, base: u8, bright: u8, extended: u8) {
+    match color {
+        Color::Default => {}
+        Color::Indexed(n @ 0..=7) => codes.push((base + n).to_string()),
+        Color::Indexed(n @ 8..=15) => codes.push((bright + n - 8).to_string()),
+        Color::Indexed(n) => codes.push(format!("{extended};5;{n}")),
+        Color::Rgb(r, g, b) => codes.push(format!("{extended};2;{r};{g};{b}")),
+    }
+}
+
+/// One character position. Every character takes one cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            ch: ' ',
+            style: Style::default(),
+        }
+    }
+}
+
+impl Cell {
+    /// A blank cell as left by an erase: erased cells keep the current
+    /// background colour and nothing else.
+    pub fn blank(bg: Color) -> Self {
+        Cell {
+            ch: ' ',
+            style: Style {
+                bg,
+                ..Style::default()
+            },
+        }
+    }
+}
+
+/// One row of cells.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub cells: Vec<Cell>,
+    /// The text continues on the next row because it hit the right margin.
+    pub wrapped: bool,
+}
+
+impl Line {
+    pub fn new(cols: usize, bg: Color) -> Self {
+        Line {
+            cells: vec![Cell::blank(bg); cols],
+            wrapped: false,
+        }
+    }
+
+    /// Truncate or pad to `cols`. No reflow: a row that grows is no longer
+    /// full, so it no longer wraps.
+    pub fn resize(&mut self, cols: usize) {
+        if cols > self.cells.len() {
+            self.wrapped = false;
+        }
+        self.cells.resize(cols, Cell::default());
+    }
+
+    /// The text of the row without trailing blanks.
+    pub fn text(&self) -> String {
+        let text: String = self.cells.iter().map(|c| c.ch).collect();
+        text.trim_end_matches(' ').to_owned()
+    }
+
+    /// Runs of identically styled text, without trailing unstyled blanks.
+    pub fn spans(&self) -> Vec<Span> {
+        let end = self
+            .cells
+            .iter()
+            .rposition(|c| c.ch != ' ' || !c.style.is_default())
+            .map_or(0, |i| i + 1);
+        let mut spans: Vec<Span> = Vec::new();
+        for cell in &self.cells[..end] {
+            match spans.last_mut() {
+                Some(span) if span.style == cell.style => span.text.push(cell.ch),
+                _ => spans.push(Span {
+                    text: cell.ch.to_string(),
+                    style: cell.style,
+                }),
+            }
+        }
+        spans
+    }
+}
+
+/// A run of text in one style, as serialised in a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Style::is_default")]
+    pub style: Style,
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sgr_covers_every_color_form() {
+        let style = Style {
+            fg: Color::Indexed(9),
+            bg: Color::Rgb(1, 2, 3),
+            bold: true,
+            ..Style::default()
+        };
+        assert_eq!(style.sgr(), "\x1b[0;1;91;48;2;1;2;3m");
+        assert_eq!(Style::default().sgr(), "\x1b[0m");
+        let indexed = Style {
+            fg: Color::Indexed(200),
+            bg: Color::Indexed(4),
+            ..Style::default()
+        };
+        assert_eq!(indexed.sgr(), "\x1b[0;38;5;200;44m");
+    }
+
+    #[test]
+    fn spans_merge_runs_and_drop_trailing_blanks() {
+        let red = Style {
+            fg: Color::Indexed(1),
+            ..Style::default()
+        };
+        let mut line = Line::new(8, Color::Default);
+        for (i, ch) in "ab c".chars().enumerate() {
+            line.cells[i] = Cell {
+                ch,
+                style: if i < 2 { red } else { Style::default() },
+            };
+        }
+        assert_eq!(
+            line.spans(),
+            [
+                Span {
+                    text: "ab".into(),
+                    style: red
+                },
+                Span {
+                    text: " c".into(),
+                    style: Style::default()
+                },
+            ]
+        );
+        assert_eq!(line.text(), "ab c");
+    }
+
+    #[test]
+    fn default_styles_serialise_compactly() {
+        let span = Span {
+            text: "x".into(),
+            style: Style::default(),
+        };
+        assert_eq!(serde_json::to_string(&span).unwrap(), r#"{"text":"x"}"#);
+    }
+}
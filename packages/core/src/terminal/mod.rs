@@ -0,0 +1,357 @@
+//! Server-side terminal emulation.
+//!
+//! [`TerminalState`] runs a shell's output through a VT100/xterm parser
+//! into a screen grid with a bounded scrollback, so the core always knows
+//! what the user's terminal shows. A client that reconnects or reloads asks
+//! for a [`Snapshot`] instead of replaying raw output, and scrollback can be
+//! searched without the client holding it.
+//!
+//! The emulation covers what shells and full-screen programs rely on:
+//! cursor movement, erasing, insert/delete, scrolling regions, SGR colours
+//! (16, 256 and true colour), the alternate screen, autowrap and the window
+//! title. Every character takes one cell; wide and combining characters are
+//! not measured.
+
+mod cell;
+mod parser;
+mod screen;
+mod snapshot;
+
+pub use cell::{Color, Span, Style};
+pub use snapshot::Snapshot;
+
+use parser::Parser;
+use screen::Screen;
+
+/// Scrollback lines kept by [`TerminalState::default`].
+pub const DEFAULT_SCROLLBACK: usize = 10_000;
+
+/// A match from [`TerminalState::search`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchMatch {
+    /// Line index over scrollback then screen: `0` is the oldest scrollback
+    /// line, `scrollback_len()` the top screen row.
+    pub line: usize,
+    /// Character column of the first matching character.
+    pub column: usize,
+    /// Length of the match in characters.
+    pub len: usize,
+}
+
+/// The emulated terminal of one shell.
+#[derive(Debug)]
+pub struct TerminalState {
+    parser: Parser,
+    screen: Screen,
+}
+
+impl Default for TerminalState {
+    fn default() -> Self {
+        TerminalState::new(80, 24, DEFAULT_SCROLLBACK)
+    }
+}
+
+impl TerminalState {
+    /// A blank `cols`×`rows` terminal keeping up to `scrollback` lines that
+    /// scroll off the top.
+    pub fn new(cols: u16, rows: u16, scrollback: usize) -> Self {
+        TerminalState {
+            parser: Parser::default(),
+            screen: Screen::new(usize::from(cols), usize::from(rows), scrollback),
+        }
+    }
+
+    /// Process shell output. Sequences may be split across calls.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.parser.advance(&mut self.screen, bytes);
+    }
+
+    /// Follow a window-size change. Rows are truncated or padded, not
+    /// reflowed; rows pushed off the top to keep the cursor visible go to
+    /// the scrollback.
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.screen.resize(usize::from(cols), usize::from(rows));
+    }
+
+    /// `(cols, rows)`.
+    pub fn size(&self) -> (u16, u16) {
+        (to_u16(self.screen.cols), to_u16(self.screen.rows))
+    }
+
+    /// Zero-based `(row, col)` of the cursor.
+    pub fn cursor(&self) -> (u16, u16) {
+        (
+            to_u16(self.screen.cursor.row),
+            to_u16(self.screen.cursor.col),
+        )
+    }
+
+    /// The title last set with OSC 0 or 2.
+    pub fn title(&self) -> &str {
+        &self.screen.title
+    }
+
+    /// Whether a full-screen program has switched to the alternate screen.
+    pub fn alternate_screen(&self) -> bool {
+        self.screen.alternate_active()
+    }
+
+    /// Whether the program asked for bracketed paste (`CSI ? 2004 h`).
+    pub fn bracketed_paste(&self) -> bool {
+        self.screen.bracketed_paste
+    }
+
+    /// Whether arrow keys should be sent in application mode (`CSI ? 1 h`).
+    pub fn application_cursor(&self) -> bool {
+        self.screen.application_cursor
+    }
+
+    /// The visible rows as text, without trailing blanks.
+    pub fn screen_text(&self) -> Vec<String> {
+        self.screen.lines.iter().map(|line| line.text()).collect()
+    }
+
+    pub fn scrollback_len(&self) -> usize {
+        self.screen.scrollback.len()
+    }
+
+    /// The text of line `index` (see [`SearchMatch::line`]).
+    pub fn line_text(&self, index: usize) -> Option<String> {
+        self.lines().nth(index).map(|line| line.text())
+    }
+
+    /// Every occurrence of `needle` in the scrollback and on screen, oldest
+    /// first. Matches do not span rows.
+    pub fn search(&self, needle: &str, ignore_case: bool) -> Vec<SearchMatch> {
+        let needle: Vec<char> = needle.chars().map(|ch| fold(ch, ignore_case)).collect();
+        if needle.is_empty() {
+            return Vec::new();
+        }
+        let mut matches = Vec::new();
+        for (index, line) in self.lines().enumerate() {
+            let text: Vec<char> = line
+                .cells
+                .iter()
+                .map(|cell| fold(cell.ch, ignore_case))
+                .collect();
+            let mut column = 0;
+            while column + needle.len() <= text.len() {
+                if text[column..column + needle.len()] == needle[..] {
+                    matches.push(SearchMatch {
+                        line: index,
+                        column,
+                        len: needle.len(),
+                    });
+                    column += needle.len();
+                } else {
+                    column += 1;
+                }
+            }
+        }
+        matches
+    }
+
+    /// The screen plus the newest `scrollback` lines of scrollback.
+    pub fn snapshot(&self, scrollback: usize) -> Snapshot {
+        let skip = self.screen.scrollback.len().saturating_sub(scrollback);
+        Snapshot {
+            cols: to_u16(self.screen.cols),
+            rows: to_u16(self.screen.rows),
+            scrollback: self
+                .screen
+                .scrollback
+                .iter()
+                .skip(skip)
+                .map(|line| line.spans())
+                .collect(),
+            lines: self.screen.lines.iter().map(|line| line.spans()).collect(),
+            cursor_row: to_u16(self.screen.cursor.row),
+            cursor_col: to_u16(self.screen.cursor.col),
+            cursor_visible: self.screen.cursor_visible,
+            alternate_screen: self.screen.alternate_active(),
+            title: self.screen.title.clone(),
+        }
+    }
+
+    pub fn clear_scrollback(&mut self) {
+        self.screen.scrollback.clear();
+    }
+
+    fn lines(&self) -> impl Iterator<Item = &cell::Line> {
+        self.screen.scrollback.iter().chain(&self.screen.lines)
+    }
+}
+
+fn to_u16(n: usize) -> u16 {
+    u16::try_from(n).unwrap_or(u16::MAX)
+}
+
+/// Lower-casing that keeps one character per cell, so columns still line
+/// up (characters with multi-character lower-case forms are left alone).
+fn fold(ch: char, ignore_case: bool) -> char {
+    let mut lower = ch.to_lowercase();
+    match (ignore_case, lower.len()) {
+        (true, 1) => lower.next().unwrap_or(ch),
+        _ => ch,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(cols: u16, rows: u16, input: &str) -> TerminalState {
+        let mut term = TerminalState::new(cols, rows, 100);
+        term.feed(input.as_bytes());
+        term
+    }
+
+    #[test]
+    fn text_wraps_and_scrolls_into_scrollback() {
+        let term = term(4, 2, "abcdefg\r\nhi");
+        assert_eq!(term.scrollback_len(), 1);
+        assert_eq!(term.line_text(0).as_deref(), Some("abcd"));
+        assert_eq!(term.screen_text(), ["efg", "hi"]);
+        assert_eq!(term.cursor(), (1, 2));
+    }
+
+    #[test]
+    fn deferred_wrap_waits_for_the_next_character() {
+        let mut term = term(3, 2, "abc");
+        assert_eq!(term.cursor(), (0, 2));
+        term.feed(b"\r\nx");
+        assert_eq!(term.screen_text(), ["abc", "x"]);
+    }
+
+    #[test]
+    fn cursor_movement_and_erasing() {
+        let term = term(
+            10,
+            3,
+            "hello\x1b[2;3Hxy\x1b[1;2H\x1b[K\x1b[3;1Hend\x1b[1D\x1b[1P",
+        );
+        assert_eq!(term.screen_text(), ["h", "  xy", "en"]);
+    }
+
+    #[test]
+    fn erase_display_and_insert_delete_lines() {
+        let mut term = term(5, 3, "a\r\nb\r\nc");
+        term.feed(b"\x1b[1;1H\x1b[L");
+        assert_eq!(term.screen_text(), ["", "a", "b"]);
+        term.feed(b"\x1b[2M");
+        assert_eq!(term.screen_text(), ["b", "", ""]);
+        term.feed(b"\x1b[2J");
+        assert_eq!(term.screen_text(), ["", "", ""]);
+    }
+
+    #[test]
+    fn scrolling_region_keeps_rows_outside_it() {
+        let term = term(5, 4, "top\x1b[2;3r\x1b[3;1Hx\r\ny\r\nz\x1b[r\x1b[4;1Hbot");
+        assert_eq!(term.screen_text(), ["top", "y", "z", "bot"]);
+        // Only full-screen scrolls feed the scrollback... and this region
+        // does not start at the top.
+        assert_eq!(term.scrollback_len(), 0);
+    }
+
+    #[test]
+    fn sgr_colours_reach_the_snapshot() {
+        let term = term(
+            20,
+            2,
+            "\x1b[1;31mred\x1b[0m \x1b[38;5;200mx\x1b[38:2::1:2:3my",
+        );
+        let line = &term.snapshot(0).lines[0];
+        assert_eq!(line[0].text, "red");
+        assert!(line[0].style.bold);
+        assert_eq!(line[0].style.fg, Color::Indexed(1));
+        assert_eq!(line[1].style, Style::default());
+        assert_eq!(line[2].style.fg, Color::Indexed(200));
+        assert_eq!(line[3].style.fg, Color::Rgb(1, 2, 3));
+    }
+
+    #[test]
+    fn alternate_screen_restores_the_primary_and_skips_scrollback() {
+        let mut term = term(5, 2, "shell");
+        term.feed(b"\x1b[?1049h\x1b[Hvim\r\n\r\n\r\n");
+        assert!(term.alternate_screen());
+        assert_eq!(term.scrollback_len(), 0);
+        term.feed(b"\x1b[?1049l");
+        assert!(!term.alternate_screen());
+        assert_eq!(term.screen_text(), ["shell", ""]);
+        assert_eq!(term.cursor(), (0, 4));
+    }
+
+    #[test]
+    fn scrollback_is_bounded() {
+        let mut term = TerminalState::new(10, 2, 3);
+        for i in 0..10 {
+            term.feed(format!("{i}\r\n").as_bytes());
+        }
+        assert_eq!(term.scrollback_len(), 3);
+        assert_eq!(term.line_text(0).as_deref(), Some("6"));
+    }
+
+    #[test]
+    fn search_covers_scrollback_and_screen() {
+        let term = term(10, 2, "Error one\r\nok\r\nerror two");
+        let hits = term.search("error", true);
+        assert_eq!(
+            hits,
+            [
+                SearchMatch {
+                    line: 0,
+                    column: 0,
+                    len: 5
+                },
+                SearchMatch {
+                    line: 2,
+                    column: 0,
+                    len: 5
+                },
+            ]
+        );
+        assert_eq!(term.search("error", false).len(), 1);
+        assert!(term.search("", true).is_empty());
+    }
+
+    #[test]
+    fn resize_pushes_rows_above_the_cursor_into_scrollback() {
+        let mut term = term(5, 3, "a\r\nb\r\nc");
+        term.resize(3, 2);
+        assert_eq!(term.screen_text(), ["b", "c"]);
+        assert_eq!(term.scrollback_len(), 1);
+        assert_eq!(term.cursor(), (1, 1));
+        term.resize(6, 4);
+        assert_eq!(term.size(), (6, 4));
+        assert_eq!(term.screen_text(), ["b", "c", "", ""]);
+    }
+
+    #[test]
+    fn title_and_modes() {
+        let term = term(5, 2, "\x1b]2;build; logs\x07\x1b[?25l\x1b[?2004h\x1b[?1h");
+        assert_eq!(term.title(), "build; logs");
+        assert!(!term.snapshot(0).cursor_visible);
+        assert!(term.bracketed_paste());
+        assert!(term.application_cursor());
+    }
+
+    #[test]
+    fn snapshot_replays_to_the_same_screen() {
+        let source = term(8, 3, "one\r\n\x1b[32mtwo\x1b[0m\r\nthree\r\nfour\x1b[2;2H");
+        let snapshot = source.snapshot(10);
+        assert_eq!(snapshot.scrollback.len(), 1);
+
+        let mut copy = TerminalState::new(8, 3, 100);
+        copy.feed(&snapshot.to_ansi());
+        assert_eq!(copy.screen_text(), source.screen_text());
+        assert_eq!(copy.cursor(), source.cursor());
+        assert_eq!(copy.snapshot(10), snapshot);
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(serde_json::from_str::<Snapshot>(&json).unwrap(), snapshot);
+    }
+}
@@ -0,0 +1,411 @@
+//! VT escape-sequence parser.
+//!
+//! A byte-at-a-time state machine after Paul Williams' DEC parser
+//! (<https://vt100.net/emu/dec_ansi_parser>), reduced to what an xterm-style
+//! screen needs: UTF-8 text, C0 controls, ESC, CSI (with `:` sub-parameters)
+//! and OSC sequences. DCS, SOS, PM and APC strings are consumed and ignored.
+//! Sequences may be split across [`Parser::advance`] calls at any byte.
+
+/// Receives what the parser recognises.
+pub(crate) trait Perform {
+    /// A printable character.
+    fn print(&mut self, c: char);
+    /// A C0 control byte (BEL, BS, HT, LF, CR, …).
+    fn execute(&mut self, byte: u8);
+    /// `ESC [ private? params intermediates final`.
+    fn csi_dispatch(&mut self, csi: &Csi<'_>);
+    /// `ESC intermediates final`.
+    fn esc_dispatch(&mut self, intermediates: &[u8], byte: u8);
+    /// `ESC ] params BEL` or `ESC ] params ESC \`, split on `;`.
+    fn osc_dispatch(&mut self, params: &[&[u8]]);
+}
+
+/// A complete CSI sequence.
+#[derive(Debug)]
+pub(crate) struct Csi<'a> {
+    /// `?`, `>`, `<` or `=` directly after the `[`.
+    pub private: Option<u8>,
+    pub params: &'a [Param],
+    pub intermediates: &'a [u8],
+    pub action: u8,
+}
+
+/// One numeric parameter. `sub` marks a `:`-separated sub-parameter of the
+/// one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Param {
+    pub value: u16,
+    pub sub: bool,
+}
+
+impl Csi<'_> {
+    /// Parameter `index`, with 0 or absence meaning `default`.
+    pub fn arg(&self, index: usize, default: u16) -> u16 {
+        match self.params.get(index) {
+            Some(p) if p.value != 0 => p.value,
+            _ => default,
+        }
+    }
+}
+
+const MAX_PARAMS: usize = 32;
+const MAX_INTERMEDIATES: usize = 2;
+const MAX_OSC: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    OscString,
+    /// DCS, SOS, PM and APC bodies, skipped until ST.
+    StringIgnore,
+}
+
+#[derive(Debug)]
+pub(crate) struct Parser {
+    state: State,
+    private: Option<u8>,
+    params: Vec<Param>,
+    /// The parameter being read, if any digit or separator started it.
+    current: Option<u16>,
+    next_is_sub: bool,
+    intermediates: Vec<u8>,
+    osc: Vec<u8>,
+    /// ESC seen inside a string; a `\` completes ST.
+    string_escape: bool,
+    utf8: Vec<u8>,
+    utf8_len: usize,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser {
+            state: State::Ground,
+            private: None,
+            params: Vec::with_capacity(MAX_PARAMS),
+            current: None,
+            next_is_sub: false,
+            intermediates: Vec::with_capacity(MAX_INTERMEDIATES),
+            osc: Vec::new(),
+            string_escape: false,
+            utf8: Vec::with_capacity(4),
+            utf8_len: 0,
+        }
+    }
+}
+
+impl Parser {
+    pub fn advance(&mut self, performer: &mut impl Perform, bytes: &[u8]) {
+        for &byte in bytes {
+            self.byte(performer, byte);
+        }
+    }
+
+    fn byte(&mut self, performer: &mut impl Perform, byte: u8) {
+        if matches!(self.state, State::OscString | State::StringIgnore) {
+            self.string_byte(performer, byte);
+            return;
+        }
+        match byte {
+            // CAN and SUB abort any sequence.
+            0x18 | 0x1a => {
+                self.utf8.clear();
+                self.state = State::Ground;
+                return;
+            }
+            0x1b => {
+                if !self.utf8.is_empty() {
+                    self.utf8.clear();
+                    performer.print(char::REPLACEMENT_CHARACTER);
+                }
+                self.enter_escape();
+                return;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => self.ground(performer, byte),
+            State::Escape => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x20..=0x2f => {
+                    self.intermediate(byte);
+                    self.state = State::EscapeIntermediate;
+                }
+                b'[' => {
+                    self.clear_params();
+                    self.state = State::CsiEntry;
+                }
+                b']' => {
+                    self.osc.clear();
+                    self.state = State::OscString;
+                }
+                b'P' | b'X' | b'^' | b'_' => self.state = State::StringIgnore,
+                0x30..=0x7e => {
+                    performer.esc_dispatch(&self.intermediates, byte);
+                    self.state = State::Ground;
+                }
+                _ => {}
+            },
+            State::EscapeIntermediate => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x20..=0x2f => self.intermediate(byte),
+                0x30..=0x7e => {
+                    performer.esc_dispatch(&self.intermediates, byte);
+                    self.state = State::Ground;
+                }
+                _ => {}
+            },
+            State::CsiEntry | State::CsiParam => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                b'0'..=b'9' => {
+                    let digit = u16::from(byte - b'0');
+                    let value = self.current.unwrap_or(0);
+                    self.current = Some(value.saturating_mul(10).saturating_add(digit));
+                    self.state = State::CsiParam;
+                }
+                b';' | b':' => {
+                    self.push_param();
+                    self.next_is_sub = byte == b':';
+                    self.current = Some(0);
+                    self.state = State::CsiParam;
+                }
+                0x3c..=0x3f if self.state == State::CsiEntry => {
+                    self.private = Some(byte);
+                    self.state = State::CsiParam;
+                }
+                0x3c..=0x3f => self.state = State::CsiIgnore,
+                0x20..=0x2f => {
+                    self.intermediate(byte);
+                    self.state = State::CsiIntermediate;
+                }
+                0x40..=0x7e => self.csi_dispatch(performer, byte),
+                _ => {}
+            },
+            State::CsiIntermediate => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x20..=0x2f => self.intermediate(byte),
+                0x30..=0x3f => self.state = State::CsiIgnore,
+                0x40..=0x7e => self.csi_dispatch(performer, byte),
+                _ => {}
+            },
+            State::CsiIgnore => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x40..=0x7e => self.state = State::Ground,
+                _ => {}
+            },
+            State::OscString | State::StringIgnore => {}
+        }
+    }
+
+    fn ground(&mut self, performer: &mut impl Perform, byte: u8) {
+        if !self.utf8.is_empty() {
+            if (0x80..=0xbf).contains(&byte) {
+                self.utf8.push(byte);
+                if self.utf8.len() == self.utf8_len {
+                    let c = std::str::from_utf8(&self.utf8)
+                        .ok()
+                        .and_then(|s| s.chars().next())
+                        .unwrap_or(char::REPLACEMENT_CHARACTER);
+                    self.utf8.clear();
+                    performer.print(c);
+                }
+                return;
+            }
+            // Truncated sequence: replace it, then take this byte afresh.
+            self.utf8.clear();
+            performer.print(char::REPLACEMENT_CHARACTER);
+        }
+        match byte {
+            0x00..=0x1f => performer.execute(byte),
+            0x20..=0x7e => performer.print(char::from(byte)),
+            0x7f => {}
+            0xc2..=0xdf => self.start_utf8(byte, 2),
+            0xe0..=0xef => self.start_utf8(byte, 3),
+            0xf0..=0xf4 => self.start_utf8(byte, 4),
+            _ => performer.print(char::REPLACEMENT_CHARACTER),
+        }
+    }
+
+    fn start_utf8(&mut self, byte: u8, len: usize) {
+        self.utf8.push(byte);
+        self.utf8_len = len;
+    }
+
+    fn string_byte(&mut self, performer: &mut impl Perform, byte: u8) {
+        let is_osc = self.state == State::OscString;
+        if self.string_escape {
+            self.string_escape = false;
+            if is_osc {
+                self.osc_dispatch(performer);
+            }
+            self.state = State::Ground;
+            if byte != b'\\' {
+                // Not ST: the ESC started a new sequence.
+                self.enter_escape();
+                self.byte(performer, byte);
+            }
+            return;
+        }
+        match byte {
+            0x1b => self.string_escape = true,
+            0x07 if is_osc => {
+                self.osc_dispatch(performer);
+                self.state = State::Ground;
+            }
+            0x18 | 0x1a => self.state = State::Ground,
+            0x20..=0xff if is_osc && self.osc.len() < MAX_OSC => self.osc.push(byte),
+            _ => {}
+        }
+    }
+
+    fn enter_escape(&mut self) {
+        self.intermediates.clear();
+        self.state = State::Escape;
+    }
+
+    fn intermediate(&mut self, byte: u8) {
+        if self.intermediates.len() < MAX_INTERMEDIATES {
+            self.intermediates.push(byte);
+        }
+    }
+
+    fn clear_params(&mut self) {
+        self.private = None;
+        self.params.clear();
+        self.current = None;
+        self.next_is_sub = false;
+        self.intermediates.clear();
+    }
+
+    fn push_param(&mut self) {
+        if self.params.len() < MAX_PARAMS {
+            self.params.push(Param {
+                value: self.current.unwrap_or(0),
+                sub: self.next_is_sub,
+            });
+        }
+        self.current = None;
+    }
+
+    fn csi_dispatch(&mut self, performer: &mut impl Perform, action: u8) {
+        if self.current.is_some() {
+            self.push_param();
+        }
+        performer.csi_dispatch(&Csi {
+            private: self.private,
+            params: &self.params,
+            intermediates: &self.intermediates,
+            action,
+        });
+        self.state = State::Ground;
+    }
+
+    fn osc_dispatch(&mut self, performer: &mut impl Perform) {
+        let params: Vec<&[u8]> = self.osc.split(|&b| b == b';').collect();
+        performer.osc_dispatch(&params);
+        self.osc.clear();
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records everything as readable strings.
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    impl Perform for Log {
+        fn print(&mut self, c: char) {
+            self.0.push(format!("print {c}"));
+        }
+        fn execute(&mut self, byte: u8) {
+            self.0.push(format!("exec {byte:#04x}"));
+        }
+        fn csi_dispatch(&mut self, csi: &Csi<'_>) {
+            let params: Vec<String> = csi
+                .params
+                .iter()
+                .map(|p| format!("{}{}", if p.sub { ":" } else { "" }, p.value))
+                .collect();
+            self.0.push(format!(
+                "csi {}{} {}",
+                csi.private
+                    .map(char::from)
+                    .map(String::from)
+                    .unwrap_or_default(),
+                params.join(","),
+                char::from(csi.action)
+            ));
+        }
+        fn esc_dispatch(&mut self, intermediates: &[u8], byte: u8) {
+            self.0.push(format!(
+                "esc {}{}",
+                String::from_utf8_lossy(intermediates),
+                char::from(byte)
+            ));
+        }
+        fn osc_dispatch(&mut self, params: &[&[u8]]) {
+            let params: Vec<_> = params.iter().map(|p| String::from_utf8_lossy(p)).collect();
+            self.0.push(format!("osc {}", params.join("|")));
+        }
+    }
+
+    fn parse(chunks: &[&[u8]]) -> Vec<String> {
+        let mut parser = Parser::default();
+        let mut log = Log::default();
+        for chunk in chunks {
+            parser.advance(&mut log, chunk);
+        }
+        log.0
+    }
+
+    #[test]
+    fn csi_parameters_defaults_and_sub_parameters() {
+        assert_eq!(parse(&[b"\x1b[1;31m"]), ["csi 1,31 m"]);
+        assert_eq!(parse(&[b"\x1b[;5H"]), ["csi 0,5 H"]);
+        assert_eq!(parse(&[b"\x1b[?1049h"]), ["csi ?1049 h"]);
+        assert_eq!(parse(&[b"\x1b[38:2::1:2:3m"]), ["csi 38,:2,:0,:1,:2,:3 m"]);
+        assert_eq!(parse(&[b"\x1b[K"]), ["csi  K"]);
+    }
+
+    #[test]
+    fn sequences_survive_any_split() {
+        let input = "a\x1b[12;3Hé\x1b]0;title\x07\x1b7".as_bytes();
+        let whole = parse(&[input]);
+        for split in 1..input.len() {
+            let (head, tail) = input.split_at(split);
+            assert_eq!(parse(&[head, tail]), whole, "split at {split}");
+        }
+        assert_eq!(
+            whole,
+            ["print a", "csi 12,3 H", "print é", "osc 0|title", "esc 7"]
+        );
+    }
+
+    #[test]
+    fn strings_end_at_st_and_controls_execute_mid_sequence() {
+        assert_eq!(parse(&[b"\x1b]2;x\x1b\\y"]), ["osc 2|x", "print y"]);
+        assert_eq!(parse(&[b"\x1bPq#0;2\x1b\\z"]), ["print z"]);
+        assert_eq!(parse(&[b"\x1b[2\rJ"]), ["exec 0x0d", "csi 2 J"]);
+        assert_eq!(parse(&[b"\x1b[5\x18A"]), ["print A"]);
+    }
+
+    #[test]
+    fn invalid_utf8_becomes_replacement_characters() {
+        assert_eq!(
+            parse(&[b"\xc3(\xff"]),
+            ["print \u{fffd}", "print (", "print \u{fffd}"]
+        );
+    }
+}
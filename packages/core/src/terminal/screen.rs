@@ -0,0 +1,564 @@
+//! The screen model driven by the parser.
+
+use std::collections::VecDeque;
+
+use super::cell::{Cell, Color, Line, Style};
+use super::parser::{Csi, Param, Perform};
+
+/// Cursor position and the attributes it writes with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Cursor {
+    pub row: usize,
+    pub col: usize,
+    pub style: Style,
+    /// A character was written in the last column; the next one wraps
+    /// first (xterm's deferred wrap).
+    pub pending_wrap: bool,
+}
+
+/// The primary screen while the alternate one is shown.
+#[derive(Debug, Clone)]
+struct SavedScreen {
+    lines: Vec<Line>,
+    cursor: Cursor,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Screen {
+    pub cols: usize,
+    pub rows: usize,
+    /// The visible rows of the active buffer.
+    pub lines: Vec<Line>,
+    pub scrollback: VecDeque<Line>,
+    pub scrollback_limit: usize,
+    pub cursor: Cursor,
+    saved_cursor: Option<Cursor>,
+    /// Set while the alternate screen is active.
+    primary: Option<SavedScreen>,
+    /// Scrolling region, inclusive rows.
+    scroll_top: usize,
+    scroll_bottom: usize,
+    pub autowrap: bool,
+    pub cursor_visible: bool,
+    pub application_cursor: bool,
+    pub bracketed_paste: bool,
+    pub title: String,
+}
+
+impl Screen {
+    pub fn new(cols: usize, rows: usize, scrollback_limit: usize) -> Self {
+        let cols = cols.max(1);
+        let rows = rows.max(1);
+        Screen {
+            cols,
+            rows,
+            lines: vec![Line::new(cols, Color::Default); rows],
+            scrollback: VecDeque::new(),
+            scrollback_limit,
+            cursor: Cursor::default(),
+            saved_cursor: None,
+            primary: None,
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            autowrap: true,
+            cursor_visible: true,
+            application_cursor: false,
+            bracketed_paste: false,
+            title: String::new(),
+        }
+    }
+
+    pub fn alternate_active(&self) -> bool {
+        self.primary.is_some()
+    }
+
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        let cols = cols.max(1);
+        let rows = rows.max(1);
+
+        if rows < self.rows {
+            // Keep the cursor on screen by pushing rows above it into the
+            // scrollback; drop blank rows from the bottom for the rest.
+            let excess = (self.cursor.row + 1).saturating_sub(rows);
+            let pushed: Vec<Line> = self.lines.drain(..excess).collect();
+            for line in pushed {
+                self.push_scrollback(line);
+            }
+            self.lines.truncate(rows);
+            self.cursor.row -= excess;
+        }
+        self.lines.resize(rows, Line::new(cols, Color::Default));
+        for line in &mut self.lines {
+            line.resize(cols);
+        }
+        if let Some(primary) = &mut self.primary {
+            primary.lines.resize(rows, Line::new(cols, Color::Default));
+            for line in &mut primary.lines {
+                line.resize(cols);
+            }
+            clamp(&mut primary.cursor, cols, rows);
+        }
+
+        self.cols = cols;
+        self.rows = rows;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        clamp(&mut self.cursor, cols, rows);
+        if let Some(saved) = &mut self.saved_cursor {
+            clamp(saved, cols, rows);
+        }
+    }
+
+    /// Back to power-on state. The scrollback is kept.
+    fn reset(&mut self) {
+        let scrollback = std::mem::take(&mut self.scrollback);
+        *self = Screen {
+            scrollback,
+            ..Screen::new(self.cols, self.rows, self.scrollback_limit)
+        };
+    }
+
+    fn push_scrollback(&mut self, line: Line) {
+        if self.primary.is_some() || self.scrollback_limit == 0 {
+            return;
+        }
+        if self.scrollback.len() == self.scrollback_limit {
+            self.scrollback.pop_front();
+        }
+        self.scrollback.push_back(line);
+    }
+
+    fn blank(&self) -> Line {
+        Line::new(self.cols, self.cursor.style.bg)
+    }
+
+    // -- Scrolling ----------------------------------------------------------
+
+    /// Scroll the region up by `n`; rows leaving the top of a full-screen
+    /// region go to the scrollback.
+    fn scroll_up(&mut self, n: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        for _ in 0..n.min(bottom - top + 1) {
+            let line = self.lines.remove(top);
+            if top == 0 {
+                self.push_scrollback(line);
+            }
+            let blank = self.blank();
+            self.lines.insert(bottom, blank);
+        }
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        for _ in 0..n.min(bottom - top + 1) {
+            self.lines.remove(bottom);
+            let blank = self.blank();
+            self.lines.insert(top, blank);
+        }
+    }
+
+    fn line_feed(&mut self) {
+        self.cursor.pending_wrap = false;
+        if self.cursor.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor.row + 1 < self.rows {
+            self.cursor.row += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        self.cursor.pending_wrap = false;
+        if self.cursor.row == self.scroll_top {
+            self.scroll_down(1);
+        } else if self.cursor.row > 0 {
+            self.cursor.row -= 1;
+        }
+    }
+
+    // -- Cursor -------------------------------------------------------------
+
+    fn goto(&mut self, row: usize, col: usize) {
+        self.cursor.row = row.min(self.rows - 1);
+        self.cursor.col = col.min(self.cols - 1);
+        self.cursor.pending_wrap = false;
+    }
+
+    fn tab(&mut self) {
+        let next = (self.cursor.col / 8 + 1) * 8;
+        self.cursor.col = next.min(self.cols - 1);
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some(self.cursor);
+    }
+
+    fn restore_cursor(&mut self) {
+        self.cursor = self.saved_cursor.unwrap_or_default();
+        clamp(&mut self.cursor, self.cols, self.rows);
+    }
+
+    // -- Erasing and editing ------------------------------------------------
+
+    fn erase_cells(&mut self, row: usize, from: usize, to: usize) {
+        let blank = Cell::blank(self.cursor.style.bg);
+        let line = &mut self.lines[row];
+        for cell in &mut line.cells[from.min(self.cols)..to.min(self.cols)] {
+            *cell = blank;
+        }
+        if to >= self.cols {
+            line.wrapped = false;
+        }
+    }
+
+    fn erase_display(&mut self, mode: u16) {
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        match mode {
+            0 => {
+                self.erase_cells(row, col, self.cols);
+                for r in row + 1..self.rows {
+                    self.erase_cells(r, 0, self.cols);
+                }
+            }
+            1 => {
+                for r in 0..row {
+                    self.erase_cells(r, 0, self.cols);
+                }
+                self.erase_cells(row, 0, col + 1);
+            }
+            2 => {
+                for r in 0..self.rows {
+                    self.erase_cells(r, 0, self.cols);
+                }
+            }
+            3 => self.scrollback.clear(),
+            _ => {}
+        }
+    }
+
+    fn erase_line(&mut self, mode: u16) {
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        match mode {
+            0 => self.erase_cells(row, col, self.cols),
+            1 => self.erase_cells(row, 0, col + 1),
+            2 => self.erase_cells(row, 0, self.cols),
+            _ => {}
+        }
+    }
+
+    fn insert_chars(&mut self, n: usize) {
+        let blank = Cell::blank(self.cursor.style.bg);
+        let col = self.cursor.col;
+        let cells = &mut self.lines[self.cursor.row].cells;
+        let n = n.min(cells.len() - col);
+        cells.truncate(cells.len() - n);
+        cells.splice(col..col, std::iter::repeat_n(blank, n));
+    }
+
+    fn delete_chars(&mut self, n: usize) {
+        let blank = Cell::blank(self.cursor.style.bg);
+        let col = self.cursor.col;
+        let cells = &mut self.lines[self.cursor.row].cells;
+        let n = n.min(cells.len() - col);
+        cells.drain(col..col + n);
+        cells.extend(std::iter::repeat_n(blank, n));
+    }
+
+    fn insert_lines(&mut self, n: usize) {
+        let row = self.cursor.row;
+        if row < self.scroll_top || row > self.scroll_bottom {
+            return;
+        }
+        for _ in 0..n.min(self.scroll_bottom - row + 1) {
+            self.lines.remove(self.scroll_bottom);
+            let blank = self.blank();
+            self.lines.insert(row, blank);
+        }
+        self.cursor.col = 0;
+    }
+
+    fn delete_lines(&mut self, n: usize) {
+        let row = self.cursor.row;
+        if row < self.scroll_top || row > self.scroll_bottom {
+            return;
+        }
+        for _ in 0..n.min(self.scroll_bottom - row + 1) {
+            self.lines.remove(row);
+            let blank = self.blank();
+            self.lines.insert(self.scroll_bottom, blank);
+        }
+        self.cursor.col = 0;
+    }
+
+    // -- Modes --------------------------------------------------------------
+
+    fn set_private_mode(&mut self, mode: u16, on: bool) {
+        match mode {
+            1 => self.application_cursor = on,
+            7 => self.autowrap = on,
+            25 => self.cursor_visible = on,
+            47 | 1047 => self.alternate_screen(on, false),
+            1049 => self.alternate_screen(on, true),
+            2004 => self.bracketed_paste = on,
+            _ => {}
+        }
+    }
+
+    fn alternate_screen(&mut self, on: bool, save_cursor: bool) {
+        match (on, self.primary.take()) {
+            (true, None) => {
+                let lines = std::mem::replace(
+                    &mut self.lines,
+                    vec![Line::new(self.cols, Color::Default); self.rows],
+                );
+                self.primary = Some(SavedScreen {
+                    lines,
+                    cursor: self.cursor,
+                });
+                if !save_cursor {
+                    return;
+                }
+                self.cursor.pending_wrap = false;
+            }
+            (false, Some(primary)) => {
+                self.lines = primary.lines;
+                if save_cursor {
+                    self.cursor = primary.cursor;
+                }
+            }
+            (_, primary) => self.primary = primary,
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, params: &[Param]) {
+        if params.is_empty() {
+            self.cursor.style = Style::default();
+            return;
+        }
+        let style = &mut self.cursor.style;
+        let mut i = 0;
+        while i < params.len() {
+            let code = params[i].value;
+            i += 1;
+            match code {
+                0 => *style = Style::default(),
+                1 => style.bold = true,
+                2 => style.dim = true,
+                3 => style.italic = true,
+                4 => style.underline = true,
+                5 | 6 => style.blink = true,
+                7 => style.inverse = true,
+                8 => style.hidden = true,
+                9 => style.strikethrough = true,
+                21 | 24 => style.underline = false,
+                22 => {
+                    style.bold = false;
+                    style.dim = false;
+                }
+                23 => style.italic = false,
+                25 => style.blink = false,
+                27 => style.inverse = false,
+                28 => style.hidden = false,
+                29 => style.strikethrough = false,
+                30..=37 => style.fg = Color::Indexed((code - 30) as u8),
+                39 => style.fg = Color::Default,
+                40..=47 => style.bg = Color::Indexed((code - 40) as u8),
+                49 => style.bg = Color::Default,
+                90..=97 => style.fg = Color::Indexed((code - 90 + 8) as u8),
+                100..=107 => style.bg = Color::Indexed((code - 100 + 8) as u8),
+                38 | 48 => {
+                    let (color, used) = extended_color(&params[i..]);
+                    i += used;
+                    if let Some(color) = color {
+                        if code == 38 {
+                            style.fg = color;
+                        } else {
+                            style.bg = color;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            // Unhandled sub-parameters (e.g. `4:3` curly underline) belong
+            // to the code before them.
+            while params.get(i).is_some_and(|p| p.sub) {
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Parse the colour after SGR 38/48 in `5;n`, `2;r;g;b` or the colon forms
+/// (`5:n`, `2::r:g:b`, `2:r:g:b`). Returns the colour and the parameters
+/// consumed.
+fn extended_color(params: &[Param]) -> (Option<Color>, usize) {
+    let byte = |p: &Param| u8::try_from(p.value).unwrap_or(u8::MAX);
+    let Some(kind) = params.first() else {
+        return (None, 0);
+    };
+    if kind.sub {
+        // Colon form: everything up to the next non-sub parameter.
+        let subs = params.iter().take_while(|p| p.sub).count();
+        let values = &params[1..subs];
+        let color = match (kind.value, values.len()) {
+            (5, 1) => Some(Color::Indexed(byte(&values[0]))),
+            (2, 3) => Some(Color::Rgb(
+                byte(&values[0]),
+                byte(&values[1]),
+                byte(&values[2]),
+            )),
+            // With the colour-space ID.
+            (2, n) if n >= 4 => Some(Color::Rgb(
+                byte(&values[1]),
+                byte(&values[2]),
+                byte(&values[3]),
+            )),
+            _ => None,
+        };
+        return (color, subs);
+    }
+    match (kind.value, params.len()) {
+        (5, n) if n >= 2 => (Some(Color::Indexed(byte(&params[1]))), 2),
+        (2, n) if n >= 4 => (
+            Some(Color::Rgb(
+                byte(&params[1]),
+                byte(&params[2]),
+                byte(&params[3]),
+            )),
+            4,
+        ),
+        _ => (None, params.len()),
+    }
+}
+
+fn clamp(cursor: &mut Cursor, cols: usize, rows: usize) {
+    cursor.row = cursor.row.min(rows - 1);
+    cursor.col = cursor.col.min(cols - 1);
+    cursor.pending_wrap = false;
+}
+
+impl Perform for Screen {
+    fn print(&mut self, c: char) {
+        if self.cursor.pending_wrap {
+            if self.autowrap {
+                self.lines[self.cursor.row].wrapped = true;
+                self.cursor.col = 0;
+                self.line_feed();
+            }
+            self.cursor.pending_wrap = false;
+        }
+        let Cursor {
+            row, col, style, ..
+        } = self.cursor;
+        self.lines[row].cells[col] = Cell { ch: c, style };
+        if col + 1 < self.cols {
+            self.cursor.col += 1;
+        } else {
+            self.cursor.pending_wrap = self.autowrap;
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            0x08 => {
+                self.cursor.col = self.cursor.col.saturating_sub(1);
+                self.cursor.pending_wrap = false;
+            }
+            0x09 => self.tab(),
+            0x0a..=0x0c => self.line_feed(),
+            0x0d => {
+                self.cursor.col = 0;
+                self.cursor.pending_wrap = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, csi: &Csi<'_>) {
+        let n = usize::from(csi.arg(0, 1));
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        match (csi.private, csi.intermediates, csi.action) {
+            (None, [], b'A') => self.goto(row.saturating_sub(n).max(self.top_for(row)), col),
+            (None, [], b'B') => self.goto((row + n).min(self.bottom_for(row)), col),
+            (None, [], b'C') => self.goto(row, col + n),
+            (None, [], b'D') => self.goto(row, col.saturating_sub(n)),
+            (None, [], b'E') => self.goto((row + n).min(self.bottom_for(row)), 0),
+            (None, [], b'F') => self.goto(row.saturating_sub(n).max(self.top_for(row)), 0),
+            (None, [], b'G' | b'`') => self.goto(row, n - 1),
+            (None, [], b'H' | b'f') => {
+                let col = usize::from(csi.arg(1, 1));
+                self.goto(n - 1, col - 1);
+            }
+            (None, [], b'd') => self.goto(n - 1, col),
+            (None, [], b'J') => self.erase_display(csi.arg(0, 0)),
+            (None, [], b'K') => self.erase_line(csi.arg(0, 0)),
+            (None, [], b'@') => self.insert_chars(n),
+            (None, [], b'P') => self.delete_chars(n),
+            (None, [], b'X') => {
+                let end = col + n;
+                self.erase_cells(row, col, end);
+            }
+            (None, [], b'L') => self.insert_lines(n),
+            (None, [], b'M') => self.delete_lines(n),
+            (None, [], b'S') => self.scroll_up(n),
+            (None, [], b'T') => self.scroll_down(n),
+            (None, [], b'm') => self.select_graphic_rendition(csi.params),
+            (None, [], b'r') => {
+                let top = usize::from(csi.arg(0, 1)) - 1;
+                let bottom = usize::from(csi.arg(1, self.rows as u16)).min(self.rows) - 1;
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.goto(0, 0);
+                }
+            }
+            (None, [], b's') => self.save_cursor(),
+            (None, [], b'u') => self.restore_cursor(),
+            (Some(b'?'), [], b'h' | b'l') => {
+                for param in csi.params {
+                    self.set_private_mode(param.value, csi.action == b'h');
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], byte: u8) {
+        match (intermediates, byte) {
+            ([], b'7') => self.save_cursor(),
+            ([], b'8') => self.restore_cursor(),
+            ([], b'D') => self.line_feed(),
+            ([], b'E') => {
+                self.cursor.col = 0;
+                self.line_feed();
+            }
+            ([], b'M') => self.reverse_index(),
+            ([], b'c') => self.reset(),
+            _ => {}
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]]) {
+        if let [b"0" | b"2", title @ ..] = params {
+            self.title = String::from_utf8_lossy(&title.join(&b';')).into_owned();
+        }
+    }
+}
+
+impl Screen {
+    /// Cursor-up stops at the top margin when starting inside the region.
+    fn top_for(&self, row: usize) -> usize {
+        if row >= self.scroll_top {
+            self.scroll_top
+        } else {
+            0
+        }
+    }
+
+    fn bottom_for(&self, row: usize) -> usize {
+        if row <= self.scroll_bottom {
+            self.scroll_bottom
+        } else {
+            self.rows - 1
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! Serialisable copies of the terminal state.
+
+use serde::{Deserialize, Serialize};
+
+use super::cell::{Span, Style};
+
+/// The screen, part of the scrollback and the cursor at one moment.
+///
+/// Serialises to JSON for a client that renders cells itself; [`to_ansi`]
+/// turns it back into a byte stream for one that only understands a
+/// terminal (e.g. xterm.js after a page reload).
+///
+/// [`to_ansi`]: Snapshot::to_ansi
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub cols: u16,
+    pub rows: u16,
+    /// Scrollback lines, oldest first, as styled runs.
+    pub scrollback: Vec<Vec<Span>>,
+    /// The visible rows, top to bottom.
+    pub lines: Vec<Vec<Span>>,
+    /// Zero-based cursor position on the screen.
+    pub cursor_row: u16,
+    pub cursor_col: u16,
+    pub cursor_visible: bool,
+    /// A full-screen program (vim, less, …) is using the alternate screen.
+    pub alternate_screen: bool,
+    pub title: String,
+}
+
+impl Snapshot {
+    /// Escape sequences that redraw this snapshot on a fresh terminal of
+    /// the same size, scrollback included, with the cursor and title
+    /// restored.
+    pub fn to_ansi(&self) -> Vec<u8> {
+        let mut out = String::from("\x1b[0m\x1b[H\x1b[2J");
+        // Printing every row in order leaves the screen rows on screen and
+        // pushes the scrollback rows into the client's own scrollback.
+        for (i, line) in self.scrollback.iter().chain(&self.lines).enumerate() {
+            if i > 0 {
+                out.push_str("\r\n");
+            }
+            push_spans(&mut out, line);
+        }
+        out.push_str(&format!(
+            "\x1b[{};{}H",
+            self.cursor_row + 1,
+            self.cursor_col + 1
+        ));
+        out.push_str(if self.cursor_visible {
+            "\x1b[?25h"
+        } else {
+            "\x1b[?25l"
+        });
+        if !self.title.is_empty() {
+            out.push_str(&format!("\x1b]2;{}\x07", self.title));
+        }
+        out.into_bytes()
+    }
+}
+
+fn push_spans(out: &mut String, spans: &[Span]) {
+    for span in spans {
+        out.push_str(&span.style.sgr());
+        out.push_str(&span.text);
+    }
+    if spans.iter().any(|s| s.style != Style::default()) {
+        out.push_str("\x1b[0m");
+    }
+}
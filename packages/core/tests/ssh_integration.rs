@@ -9,6 +9,7 @@
 
 #![cfg(feature = "integration")]
 
+use std::sync::Arc;
 use std::time::Duration;
 
 use secrecy::SecretString;
@@ -18,9 +19,21 @@ use testcontainers::{
     GenericImage, ImageExt,
 };
 
+use tacoshell_core::connection::exec::{ExecRequest, Signal};
+use tacoshell_core::connection::files::FileKind;
+use tacoshell_core::connection::login::LoginFailure;
+use tacoshell_core::connection::remote_copy::RemoteCopy;
+use tacoshell_core::connection::sftp::SftpAdapter;
 use tacoshell_core::connection::ssh::{ConnectionAdapter, SshAdapter, TerminalAdapter};
-use tacoshell_core::connection::Credential;
-use tacoshell_core::profile::types::ConnectionProfile;
+use tacoshell_core::connection::sync::{DirSync, SyncOptions, SyncPlan};
+use tacoshell_core::connection::transfer::{
+    TransferManager, TransferRequest, DEFAULT_MAX_CONCURRENT,
+};
+use tacoshell_core::connection::watch::{RemoteWatcher, WatchEventKind, WatchOptions};
+use tacoshell_core::connection::{ConnectionError, Credential, FileTransferAdapter};
+use tacoshell_core::profile::types::{
+    ConnectionProfile, LoginAction, LoginActionKind, Protocol, ShellMode,
+};
 
 // ---------------------------------------------------------------------------
 // Constants
@@ -166,6 +179,7 @@ async fn ssh_pubkey_auth_ed25519_succeeds() {
     let credential = Credential::PublicKey {
         private_key_pem: SecretString::new(pem.into()),
         passphrase: None,
+        certificate: None,
     };
 
     let mut adapter = SshAdapter::connect(&profile, credential)
@@ -197,6 +211,7 @@ async fn ssh_pubkey_auth_rsa_succeeds() {
     let credential = Credential::PublicKey {
         private_key_pem: SecretString::new(pem.into()),
         passphrase: None,
+        certificate: None,
     };
 
     let mut adapter = SshAdapter::connect(&profile, credential)
@@ -303,6 +318,101 @@ async fn ssh_exec_captures_stderr() {
     );
 }
 
+#[tokio::test]
+async fn ssh_exec_stream_pipes_stdin_to_stdout() {
+    let (_container, profile) = start_sshd_password().await;
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+
+    let adapter = SshAdapter::connect(&profile, credential)
+        .await
+        .expect("connect");
+
+    let mut handle = adapter
+        .exec_stream(ExecRequest::new("cat"))
+        .await
+        .expect("exec_stream");
+    let mut stdout = handle.stdout().expect("stdout");
+    let stdin = handle.stdin();
+    stdin.write(b"ping\n").await.expect("write stdin");
+
+    let chunk = tokio::time::timeout(Duration::from_secs(5), stdout.recv())
+        .await
+        .expect("echo within 5 s")
+        .expect("stdout open");
+    assert_eq!(chunk, b"ping\n");
+
+    stdin.close().await.expect("close stdin");
+    let status = handle.wait().await.expect("wait");
+    assert!(status.success(), "cat should exit 0, got {status:?}");
+}
+
+#[tokio::test]
+async fn ssh_exec_stream_timeout_kills_command() {
+    let (_container, profile) = start_sshd_password().await;
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+
+    let adapter = SshAdapter::connect(&profile, credential)
+        .await
+        .expect("connect");
+
+    let request = ExecRequest {
+        timeout: Some(Duration::from_millis(500)),
+        ..ExecRequest::new("sleep 30")
+    };
+    let handle = adapter.exec_stream(request).await.expect("exec_stream");
+    let result = tokio::time::timeout(Duration::from_secs(5), handle.wait())
+        .await
+        .expect("timeout must fire well before the command ends");
+    assert!(
+        matches!(result, Err(ConnectionError::Timeout { .. })),
+        "expected Timeout, got {result:?}"
+    );
+}
+
+#[tokio::test]
+async fn ssh_exec_stream_fails_once_unread_output_overflows() {
+    let (_container, profile) = start_sshd_password().await;
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+
+    let adapter = SshAdapter::connect(&profile, credential)
+        .await
+        .expect("connect");
+
+    let mut handle = adapter
+        .exec_stream(ExecRequest::new("head -c 40000000 /dev/zero"))
+        .await
+        .expect("exec_stream");
+    // Held but never read.
+    let _stdout = handle.stdout().expect("stdout");
+    let result = tokio::time::timeout(Duration::from_secs(30), handle.wait())
+        .await
+        .expect("an overflow ends the command");
+    assert!(
+        matches!(result, Err(ConnectionError::OutputOverflow { .. })),
+        "expected OutputOverflow, got {result:?}"
+    );
+}
+
+#[tokio::test]
+async fn ssh_exec_stream_reports_exit_signal() {
+    let (_container, profile) = start_sshd_password().await;
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+
+    let adapter = SshAdapter::connect(&profile, credential)
+        .await
+        .expect("connect");
+
+    let result = adapter
+        .exec_stream(ExecRequest::new("kill -TERM $$"))
+        .await
+        .expect("exec_stream")
+        .collect()
+        .await
+        .expect("collect");
+    assert_eq!(result.exit_code, None);
+    assert_eq!(result.exit_signal.map(|s| s.signal), Some(Signal::Term));
+}
+
 // ---------------------------------------------------------------------------
 // send_input / output_stream
 // ---------------------------------------------------------------------------
@@ -352,6 +462,191 @@ async fn ssh_input_output_stream_round_trip() {
     assert!(collected.contains("roundtrip"));
 }
 
+#[tokio::test]
+async fn ssh_pty_settings_reach_the_shell() {
+    let (_container, mut profile) = start_sshd_password().await;
+    if let Some(ref mut ssh) = profile.ssh {
+        ssh.pty.term = "vt220".into();
+        ssh.pty.cols = 132;
+        ssh.pty.rows = 43;
+        ssh.pty.modes.insert("ECHO".into(), 0);
+    }
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+
+    let mut adapter = SshAdapter::connect(&profile, credential)
+        .await
+        .expect("connect");
+    let mut rx = adapter.output_stream().expect("output_stream");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    adapter
+        .send_input(b"echo \"term=$TERM size=$(stty size)\"\n")
+        .await
+        .expect("send_input");
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    let mut collected = String::new();
+    while !collected.contains("term=vt220 size=43 132") {
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        match tokio::time::timeout(remaining, rx.recv()).await {
+            Ok(Some(bytes)) => collected.push_str(&String::from_utf8_lossy(&bytes)),
+            _ => panic!("PTY settings not applied; got: {collected:?}"),
+        }
+    }
+    // ECHO off: the command line itself is not echoed back.
+    assert!(!collected.contains("echo \"term="));
+}
+
+#[tokio::test]
+async fn ssh_exec_only_profile_has_no_shell() {
+    let (_container, mut profile) = start_sshd_password().await;
+    if let Some(ref mut ssh) = profile.ssh {
+        ssh.shell_mode = ShellMode::ExecOnly;
+    }
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+
+    let mut adapter = SshAdapter::connect(&profile, credential)
+        .await
+        .expect("connect");
+    assert!(adapter.output_stream().is_none());
+    assert!(adapter.send_input(b"ls\n").await.is_err());
+    assert!(adapter.open_shell("extra").await.is_err());
+    assert!(adapter.is_alive());
+
+    let result = adapter.exec("echo exec-only").await.expect("exec");
+    assert_eq!(result.stdout_str().trim(), "exec-only");
+}
+
+#[tokio::test]
+async fn ssh_login_actions_run_after_the_shell_starts() {
+    let (_container, mut profile) = start_sshd_password().await;
+    profile.login_actions = vec![
+        LoginAction {
+            kind: LoginActionKind::WaitFor {
+                pattern: r"[$#] $".into(),
+                text: "cd /tmp\n".into(),
+            },
+            timeout_secs: None,
+        },
+        LoginAction {
+            kind: LoginActionKind::SendText {
+                text: "echo \"login-done:$PWD\"\n".into(),
+            },
+            timeout_secs: None,
+        },
+    ];
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+
+    let mut adapter = SshAdapter::connect(&profile, credential)
+        .await
+        .expect("connect");
+    assert!(adapter.login_failure().is_none());
+    let mut rx = adapter.output_stream().expect("output_stream");
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    let mut collected = String::new();
+    while !collected.contains("login-done:/tmp") {
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        match tokio::time::timeout(remaining, rx.recv()).await {
+            Ok(Some(bytes)) => collected.push_str(&String::from_utf8_lossy(&bytes)),
+            _ => panic!("login actions did not run; got: {collected:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn ssh_failed_login_step_is_reported_and_keeps_the_shell() {
+    let (_container, mut profile) = start_sshd_password().await;
+    profile.login_actions = vec![LoginAction {
+        kind: LoginActionKind::WaitFor {
+            pattern: "no such prompt".into(),
+            text: "exit\n".into(),
+        },
+        timeout_secs: Some(1),
+    }];
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+
+    let adapter = SshAdapter::connect(&profile, credential)
+        .await
+        .expect("connect");
+    let failure = adapter.login_failure().expect("the step should fail");
+    assert_eq!(failure.step, 1);
+    assert_eq!(
+        failure.reason,
+        LoginFailure::TimedOut(Duration::from_secs(1))
+    );
+    assert!(adapter.is_alive());
+}
+
+// ---------------------------------------------------------------------------
+// Multiple shells
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn ssh_named_shells_run_and_close_independently() {
+    let (_container, profile) = start_sshd_password().await;
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+
+    let adapter = SshAdapter::connect(&profile, credential)
+        .await
+        .expect("connect");
+
+    let mut left = adapter.open_shell("left").await.expect("open left");
+    let right = adapter.open_shell("right").await.expect("open right");
+    assert!(
+        adapter.open_shell("left").await.is_err(),
+        "names of open shells must be unique"
+    );
+    assert_eq!(adapter.shell_names(), vec!["left", "right"]);
+
+    let mut rx = left.output_stream().expect("output_stream");
+    left.send_input(b"echo left-pane\n")
+        .await
+        .expect("send_input");
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    let mut collected = String::new();
+    while !collected.contains("left-pane") {
+        let chunk = tokio::time::timeout_at(deadline, rx.recv())
+            .await
+            .unwrap_or_else(|_| panic!("timed out waiting for output; got: {collected:?}"))
+            .expect("left shell output open");
+        collected.push_str(&String::from_utf8_lossy(&chunk));
+    }
+
+    adapter.close_shell("right").await.expect("close right");
+    assert!(!right.is_alive());
+    assert!(left.is_alive(), "closing one shell must not affect another");
+    assert!(
+        adapter.is_alive(),
+        "closing a shell must not close the session"
+    );
+    assert_eq!(adapter.shell_names(), vec!["left"]);
+}
+
+#[tokio::test]
+async fn ssh_unread_shell_output_does_not_stall_the_session() {
+    let (_container, profile) = start_sshd_password().await;
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+
+    let adapter = SshAdapter::connect(&profile, credential)
+        .await
+        .expect("connect");
+
+    // Never read, but under OUTPUT_LIMIT, so the shell stays open.
+    let flood = adapter.open_shell("flood").await.expect("open flood");
+    flood
+        .send_input(b"yes | head -c 8000000\n")
+        .await
+        .expect("send_input");
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let result = tokio::time::timeout(Duration::from_secs(10), adapter.exec("echo still-here"))
+        .await
+        .expect("exec must not wait for the unread shell")
+        .expect("exec");
+    assert_eq!(result.stdout_str().trim(), "still-here");
+    assert!(flood.is_alive());
+}
+
 #[tokio::test]
 async fn ssh_output_stream_returns_none_on_second_call() {
     let (_container, profile) = start_sshd_password().await;
@@ -532,3 +827,260 @@ async fn ssh_keepalive_configured_does_not_drop_idle_connection() {
         "connection should stay alive under keepalive"
     );
 }
+
+// ---------------------------------------------------------------------------
+// SFTP
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn sftp_transfers_and_manages_files() {
+    let (_container, mut profile) = start_sshd_password().await;
+    profile.protocol = Protocol::Sftp;
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+
+    let sftp = SftpAdapter::connect(&profile, credential)
+        .await
+        .expect("sftp connect");
+    assert!(sftp.is_alive());
+
+    let local = tempfile::tempdir().expect("tempdir");
+    let source = local.path().join("source.bin");
+    let payload: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    std::fs::write(&source, &payload).expect("write source");
+
+    sftp.mkdir("work").await.expect("mkdir");
+    let written = sftp.upload(&source, "work/data.bin").await.expect("upload");
+    assert_eq!(written, payload.len() as u64);
+    sftp.chmod("work/data.bin", 0o640).await.expect("chmod");
+    sftp.symlink("data.bin", "work/link")
+        .await
+        .expect("symlink");
+    assert_eq!(
+        sftp.readlink("work/link").await.expect("readlink"),
+        "data.bin"
+    );
+
+    let entries = sftp.list_dir("work").await.expect("list_dir");
+    let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["data.bin", "link"]);
+    assert_eq!(entries[0].kind, FileKind::File);
+    assert_eq!(entries[0].size, payload.len() as u64);
+    assert_eq!(entries[0].permissions, 0o640);
+    assert_eq!(entries[0].owner.as_deref(), Some(TEST_USER));
+    assert!(entries[0].path.ends_with("/work/data.bin"));
+    assert!(entries[1].is_symlink());
+    assert_eq!(entries[1].symlink_target.as_deref(), Some("data.bin"));
+    assert_eq!(entries[1].target_kind, Some(FileKind::File));
+
+    sftp.rename("work/data.bin", "work/renamed.bin")
+        .await
+        .expect("rename");
+    let copy = local.path().join("copy.bin");
+    let read = sftp
+        .download("work/renamed.bin", &copy)
+        .await
+        .expect("download");
+    assert_eq!(read, payload.len() as u64);
+    assert_eq!(std::fs::read(&copy).expect("read copy"), payload);
+
+    let dangling = sftp.stat("work/link").await.expect("stat link");
+    assert_eq!(dangling.target_kind, None);
+    assert!(matches!(
+        sftp.stat("work/data.bin").await,
+        Err(ConnectionError::NotFound { .. })
+    ));
+
+    sftp.delete("work/link").await.expect("delete link");
+    sftp.delete("work/renamed.bin").await.expect("delete file");
+    sftp.delete("work").await.expect("delete dir");
+    assert!(sftp.list_dir("work").await.is_err());
+}
+
+#[tokio::test]
+async fn sftp_shares_an_ssh_session() {
+    let (_container, profile) = start_sshd_password().await;
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+
+    let ssh = SshAdapter::connect(&profile, credential)
+        .await
+        .expect("connect");
+    ssh.exec("mkdir -p shared && echo hi > shared/file.txt")
+        .await
+        .expect("exec");
+
+    let sftp = SftpAdapter::from_ssh(&ssh).await.expect("open sftp");
+    let entry = sftp.stat("shared/file.txt").await.expect("stat");
+    assert_eq!(entry.name, "file.txt");
+    assert_eq!(entry.size, 3);
+    assert!(ssh.is_alive(), "the shell must survive the SFTP channel");
+
+    drop(ssh);
+    assert!(sftp.is_alive(), "the SFTP adapter keeps the session up");
+    assert_eq!(sftp.list_dir("shared").await.expect("list").len(), 1);
+}
+
+#[tokio::test]
+async fn sftp_transfers_resume_from_an_offset() {
+    let (_container, mut profile) = start_sshd_password().await;
+    profile.protocol = Protocol::Sftp;
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+    let sftp = Arc::new(
+        SftpAdapter::connect(&profile, credential)
+            .await
+            .expect("sftp connect"),
+    );
+
+    let local = tempfile::tempdir().expect("tempdir");
+    let payload: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let head = local.path().join("head.bin");
+    let source = local.path().join("source.bin");
+    std::fs::write(&head, &payload[..50_000]).expect("write head");
+    std::fs::write(&source, &payload).expect("write source");
+
+    // A partial upload, then the rest from where it stopped.
+    sftp.upload(&head, "data.bin").await.expect("upload head");
+    let size = sftp
+        .upload_from(&source, "data.bin", 50_000, &|_| {})
+        .await
+        .expect("resume upload");
+    assert_eq!(size, payload.len() as u64);
+
+    let copy = local.path().join("copy.bin");
+    std::fs::write(&copy, &payload[..70_000]).expect("write partial copy");
+    let reached = std::sync::Mutex::new(Vec::new());
+    sftp.download_from("data.bin", &copy, 70_000, &|n| {
+        reached.lock().unwrap().push(n)
+    })
+    .await
+    .expect("resume download");
+    assert_eq!(std::fs::read(&copy).expect("read copy"), payload);
+    let reached = reached.into_inner().unwrap();
+    assert!(reached.first().is_some_and(|&n| n > 70_000));
+    assert_eq!(reached.last(), Some(&(payload.len() as u64)));
+
+    let manager = TransferManager::new(sftp, DEFAULT_MAX_CONCURRENT);
+    let mut handle = manager.enqueue(TransferRequest::download(
+        "data.bin",
+        local.path().join("queued.bin"),
+    ));
+    assert_eq!(handle.wait().await.expect("queued download"), 200_000);
+    assert_eq!(handle.progress.borrow().total_bytes, 200_000);
+}
+
+#[tokio::test]
+async fn sftp_directory_sync_uploads_then_finds_nothing_to_do() {
+    let (_container, profile) = start_sshd_password().await;
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+    let ssh = SshAdapter::connect(&profile, credential)
+        .await
+        .expect("connect");
+    let sftp = SftpAdapter::from_ssh(&ssh).await.expect("open sftp");
+    let manager = TransferManager::new(Arc::new(sftp), DEFAULT_MAX_CONCURRENT);
+
+    let local = tempfile::tempdir().expect("tempdir");
+    std::fs::create_dir_all(local.path().join("dist/assets")).expect("mkdir");
+    std::fs::write(local.path().join("dist/index.html"), "<html>").expect("write");
+    std::fs::write(local.path().join("dist/assets/app.js"), "app()").expect("write");
+    std::fs::write(local.path().join("dist/debug.log"), "noise").expect("write");
+    ssh.exec("mkdir -p site && echo stale > site/old.html")
+        .await
+        .expect("exec");
+
+    let options = SyncOptions {
+        exclude: vec!["*.log".into()],
+        delete_extraneous: true,
+        checksum: true,
+        ..SyncOptions::default()
+    };
+    let ssh: Arc<dyn TerminalAdapter> = Arc::new(ssh);
+    let sync = DirSync::new(local.path().join("dist"), "site", options).with_checksums(ssh.clone());
+    let report = sync.run(&manager).await.expect("sync");
+    assert!(report.failures.is_empty(), "{:?}", report.failures);
+    assert_eq!(report.plan.actions.len(), 4, "{:?}", report.plan.actions);
+
+    let listing = ssh.exec("cd site && find . | sort").await.expect("find");
+    assert_eq!(
+        listing.stdout_str(),
+        ".\n./assets\n./assets/app.js\n./index.html\n"
+    );
+    let again = sync.plan(manager.adapter().as_ref()).await.expect("plan");
+    assert_eq!(again, SyncPlan::default());
+}
+
+#[tokio::test]
+async fn sftp_copies_between_sessions_stream_or_run_on_the_server() {
+    let (_container, profile) = start_sshd_password().await;
+    let credential = || Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+    let ssh = SshAdapter::connect(&profile, credential())
+        .await
+        .expect("connect");
+    let other = SshAdapter::connect(&profile, credential())
+        .await
+        .expect("connect");
+    let source: Arc<dyn FileTransferAdapter> =
+        Arc::new(SftpAdapter::from_ssh(&ssh).await.expect("open sftp"));
+    let dest: Arc<dyn FileTransferAdapter> =
+        Arc::new(SftpAdapter::from_ssh(&other).await.expect("open sftp"));
+    assert_eq!(source.server_identity(), dest.server_identity());
+    ssh.exec("mkdir -p src/sub && head -c 300000 /dev/urandom > src/sub/blob && echo hi > src/a")
+        .await
+        .expect("exec");
+
+    let streamed = RemoteCopy::new(source.clone(), dest.clone());
+    assert_eq!(
+        streamed.run("src", "streamed").await.expect("copy"),
+        300_003
+    );
+
+    let ssh: Arc<dyn TerminalAdapter> = Arc::new(ssh);
+    let on_server = RemoteCopy::new(source, dest).with_server_shell(ssh.clone());
+    assert_eq!(on_server.run("src", "copied").await.expect("copy"), 300_003);
+
+    let diff = ssh
+        .exec("diff -r src streamed && diff -r src copied")
+        .await
+        .expect("diff");
+    assert_eq!(diff.exit_code, Some(0), "{}", diff.stdout_str());
+}
+
+#[tokio::test]
+async fn sftp_watcher_reports_changes_to_a_remote_file() {
+    let (_container, profile) = start_sshd_password().await;
+    let credential = Credential::Password(SecretString::new(TEST_PASSWORD.to_owned()));
+    let ssh = SshAdapter::connect(&profile, credential)
+        .await
+        .expect("connect");
+    let sftp = SftpAdapter::from_ssh(&ssh).await.expect("open sftp");
+    ssh.exec("echo draft > notes.txt").await.expect("exec");
+
+    let ssh: Arc<dyn TerminalAdapter> = Arc::new(ssh);
+    let options = WatchOptions {
+        interval: Duration::from_millis(200),
+        ..WatchOptions::default()
+    };
+    // Polling unless the image has inotify-tools; the events are the same.
+    let mut handle = RemoteWatcher::new(Arc::new(sftp), "notes.txt", options)
+        .with_inotify(ssh.clone())
+        .start()
+        .await
+        .expect("watch");
+
+    ssh.exec("echo 'final version' > notes.txt")
+        .await
+        .expect("exec");
+    let event = tokio::time::timeout(Duration::from_secs(10), handle.recv())
+        .await
+        .expect("no event")
+        .expect("watch ended")
+        .expect("watch failed");
+    assert_eq!(event.path, "notes.txt");
+    assert_eq!(event.kind, WatchEventKind::Modified);
+
+    ssh.exec("rm notes.txt").await.expect("exec");
+    let event = tokio::time::timeout(Duration::from_secs(10), handle.recv())
+        .await
+        .expect("no event")
+        .expect("watch ended")
+        .expect("watch failed");
+    assert_eq!(event.kind, WatchEventKind::Deleted);
+}
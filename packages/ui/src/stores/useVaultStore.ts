@@ -1,6 +1,11 @@
 import { create } from 'zustand'
 
-export type VaultItemType = 'connection_profile' | 'ssh_key' | 'password' | 'kube_config'
+export type VaultItemType =
+  | 'connection_profile'
+  | 'ssh_key'
+  | 'password'
+  | 'kube_config'
+  | 'known_host'
 
 export interface VaultItem {
   id: string